@@ -1 +1,9 @@
+pub mod memory;
+pub mod console;
+pub mod discord;
+#[cfg(test)]
+pub mod fake;
+pub mod irc;
+pub mod matrix;
+pub mod multi;
 pub mod telegram;
@@ -0,0 +1,234 @@
+//! Terminal front end of the in-memory platform, for trying the bot without
+//! a real chat platform.
+
+use super::memory::{MemoryChannel, Sent};
+use crate::{domain::Keyboard, shutdown::Shutdown};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+};
+use tokio::io::{self, AsyncBufReadExt, BufReader};
+
+pub const HANDLE: &str = "console_bot";
+const FIRST_USER: u64 = 1;
+
+const HELP: &str = "\
+Lines are sent as messages of the current user in the current chat.
+    >ID TEXT      sends TEXT replying to message ID
+    :chat ID      switches to chat ID
+    :user ID      switches to user ID
+    :press ID N   presses button N under message ID
+    :help         shows this help
+A doubled : or > at the start of a line is sent as a single one.
+Ctrl+D quits.";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Line<'line> {
+    Say { reply_to: Option<u64>, text: &'line str },
+    Chat(u64),
+    User(u64),
+    Press { message_id: u64, button: usize },
+    Help,
+}
+
+impl<'line> Line<'line> {
+    fn parse(line: &'line str) -> Result<Self, &'static str> {
+        if line.starts_with("::") || line.starts_with(">>") {
+            return Ok(Self::Say { reply_to: None, text: &line[1..] });
+        }
+        if let Some(rest) = line.strip_prefix('>') {
+            let (id, text) = rest.split_once(' ').unwrap_or((rest, ""));
+            let reply_to = id.parse().map_err(|_| "expected >ID TEXT")?;
+            return Ok(Self::Say { reply_to: Some(reply_to), text });
+        }
+        let command = match line.strip_prefix(':') {
+            Some(command) => command,
+            None => return Ok(Self::Say { reply_to: None, text: line }),
+        };
+        let mut words = command.split_whitespace();
+        match (words.next(), words.next(), words.next(), words.next()) {
+            (Some("chat"), Some(id), None, None) => {
+                id.parse().map(Self::Chat).map_err(|_| "expected :chat ID")
+            },
+            (Some("user"), Some(id), None, None) => {
+                id.parse().map(Self::User).map_err(|_| "expected :user ID")
+            },
+            (Some("press"), Some(id), Some(button), None) => {
+                match (id.parse(), button.parse()) {
+                    (Ok(message_id), Ok(button)) => {
+                        Ok(Self::Press { message_id, button })
+                    },
+                    _ => Err("expected :press ID N"),
+                }
+            },
+            (Some("help"), None, None, None) => Ok(Self::Help),
+            _ => Err("unknown command, see :help"),
+        }
+    }
+}
+
+/// Keyboards of the messages sent by the bot, by message id.
+type Keyboards = Arc<Mutex<HashMap<u64, Keyboard>>>;
+
+/// Sends the lines typed on stdin through the channel and prints the messages
+/// of the bot, triggering shutdown once stdin is closed.
+pub fn attach(channel: MemoryChannel, shutdown: Shutdown) {
+    let keyboards = Keyboards::default();
+    tokio::spawn(print_sent(channel.clone(), keyboards.clone()));
+    tokio::spawn(print_deleted(channel.clone(), keyboards.clone()));
+    tokio::spawn(async move {
+        read_lines(channel, keyboards).await;
+        shutdown.trigger();
+    });
+}
+
+async fn read_lines(channel: MemoryChannel, keyboards: Keyboards) {
+    let mut user_id = FIRST_USER;
+    let mut chat_id = FIRST_USER;
+    println!("Talking as user {} in chat {}, see :help.", user_id, chat_id);
+
+    let mut lines = BufReader::new(io::stdin()).lines();
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(error) => {
+                eprintln!("Error reading from the console...");
+                eprintln!("    {}", error);
+                break;
+            },
+        };
+        // Only the line terminator is stripped, which reading already did.
+        if line.is_empty() {
+            continue;
+        }
+        match Line::parse(&line) {
+            Ok(Line::Say { reply_to, text }) => {
+                let message_id = channel.say(user_id, chat_id, text, reply_to);
+                println!(
+                    "#{} sent by user {} in chat {}",
+                    message_id, user_id, chat_id
+                );
+            },
+            Ok(Line::Chat(id)) => chat_id = id,
+            Ok(Line::User(id)) => user_id = id,
+            Ok(Line::Press { message_id, button }) => {
+                let data = keyboards
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .get(&message_id)
+                    .and_then(|keyboard| {
+                        keyboard
+                            .rows
+                            .iter()
+                            .flatten()
+                            .nth(button.checked_sub(1)?)
+                    })
+                    .map(|button| button.data.clone());
+                match data {
+                    Some(data) => channel.press(message_id, &data),
+                    None => {
+                        println!("No button {} under #{}", button, message_id)
+                    },
+                }
+            },
+            Ok(Line::Help) => println!("{}", HELP),
+            Err(message) => println!("{}", message),
+        }
+    }
+}
+
+async fn print_sent(channel: MemoryChannel, keyboards: Keyboards) {
+    loop {
+        let sent = channel.next_sent().await;
+        print_message(&sent);
+        if let Some(keyboard) = sent.message.keyboard {
+            keyboards
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(sent.id, keyboard);
+        }
+    }
+}
+
+async fn print_deleted(channel: MemoryChannel, keyboards: Keyboards) {
+    loop {
+        let message_id = channel.next_deleted().await;
+        println!("#{} deleted by the bot", message_id);
+        keyboards
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&message_id);
+    }
+}
+
+fn print_message(sent: &Sent) {
+    match sent.reply_to() {
+        Some(reply_to) => println!(
+            "#{} sent by the bot in chat {}, replying to #{}:",
+            sent.id, sent.message.data.chat_id, reply_to
+        ),
+        None => println!(
+            "#{} sent by the bot in chat {}:",
+            sent.id, sent.message.data.chat_id
+        ),
+    }
+    for line in sent.text().lines() {
+        println!("    {}", line);
+    }
+    if let Some(attachment) = &sent.message.attachment {
+        println!("    (attached {})", attachment.file_name);
+    }
+    if let Some(keyboard) = &sent.message.keyboard {
+        let buttons = keyboard
+            .rows
+            .iter()
+            .flatten()
+            .enumerate()
+            .map(|(index, button)| format!("[{}: {}]", index + 1, button.label))
+            .collect::<Vec<_>>();
+        println!("    {}", buttons.join(" "));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Line;
+
+    #[test]
+    fn parses_lines() {
+        assert_eq!(
+            Line::parse("s/a/b/"),
+            Ok(Line::Say { reply_to: None, text: "s/a/b/" })
+        );
+        assert_eq!(
+            Line::parse(">3 s/a/b/"),
+            Ok(Line::Say { reply_to: Some(3), text: "s/a/b/" })
+        );
+        assert_eq!(Line::parse(":chat 5"), Ok(Line::Chat(5)));
+        assert_eq!(Line::parse(":user 2"), Ok(Line::User(2)));
+        assert_eq!(
+            Line::parse(":press 4 1"),
+            Ok(Line::Press { message_id: 4, button: 1 })
+        );
+        assert!(Line::parse(">x hi").is_err());
+        assert!(Line::parse(":chat").is_err());
+        assert!(Line::parse(":quit").is_err());
+    }
+
+    #[test]
+    fn sends_doubled_prefixes_as_text() {
+        assert_eq!(
+            Line::parse("::chat 5"),
+            Ok(Line::Say { reply_to: None, text: ":chat 5" })
+        );
+        assert_eq!(
+            Line::parse(">> quoted"),
+            Ok(Line::Say { reply_to: None, text: "> quoted" })
+        );
+        assert_eq!(
+            Line::parse("  indented "),
+            Ok(Line::Say { reply_to: None, text: "  indented " })
+        );
+    }
+}
@@ -0,0 +1,632 @@
+//! Discord bots, receiving over the gateway and sending over the REST API.
+//! Snowflakes serve as message, channel and user ids.
+
+mod client;
+mod error;
+#[cfg(test)]
+pub mod fake;
+mod gateway;
+
+use self::gateway::{
+    Gateway,
+    Payload,
+    DISPATCH,
+    IDENTIFY,
+    INVALID_SESSION,
+    RECONNECT,
+    RESUME,
+};
+pub use self::{
+    client::{DiscordClient, DiscordConfig},
+    error::DiscordError,
+};
+use crate::{
+    domain::{self, Author, ContentKind, MediaKind, MessageData, ReplyTarget},
+    future::DynFuture,
+    history::History,
+    middleware::{
+        rate_limit::{Quota, RateLimits},
+        split::{LengthUnit, PlatformLimits, TextLimit},
+    },
+    port::{Connector, Deleter, Disconnected, Receiver, Receiving, Sender},
+};
+use hyper::Method;
+use serde_json::{json, Value};
+use std::{
+    fmt,
+    sync::{self, Arc, PoisonError},
+    time::Duration,
+};
+use tokio::{sync::Mutex, time};
+
+/// Discord bots are addressed by mention rather than by handle.
+pub const HANDLE: &str = "discord_bot";
+const DEFAULT_REPLY_DEPTH: usize = 1;
+const HISTORY_CAPACITY: usize = 4096;
+/// Gateway close codes after which the session cannot be resumed.
+const SESSION_CLOSE_CODES: [u16; 2] = [4007, 4009];
+
+const TYPE_DEFAULT: u64 = 0;
+const TYPE_REPLY: u64 = 19;
+
+pub const LIMITS: PlatformLimits = PlatformLimits {
+    text: TextLimit { max_len: 2000, unit: LengthUnit::Chars },
+    caption: TextLimit { max_len: 2000, unit: LengthUnit::Chars },
+};
+
+/// Discord allows five messages every five seconds in each channel, and
+/// fifty requests a second overall.
+pub const RATE_LIMITS: RateLimits = RateLimits {
+    per_chat: &[Quota { burst: 5, period: Duration::from_secs(5) }],
+    global: &[Quota { burst: 50, period: Duration::from_secs(1) }],
+};
+
+pub type Message = domain::Message<u64, u64, u64>;
+
+fn snowflake(value: &Value) -> Option<u64> {
+    value.as_str()?.parse().ok()
+}
+
+fn media_kind(content_type: &str) -> MediaKind {
+    match content_type.split('/').next() {
+        Some("image") => MediaKind::Photo,
+        Some("video") => MediaKind::Video,
+        Some("audio") => MediaKind::Audio,
+        _ => MediaKind::Document,
+    }
+}
+
+/// Converts a message object, yielding nothing for system messages and
+/// messages without text. Only the id of the message replied to is kept.
+fn message_to_domain(message: &Value) -> Option<Message> {
+    let kind = message["type"].as_u64()?;
+    if kind != TYPE_DEFAULT && kind != TYPE_REPLY {
+        return None;
+    }
+    let text = message["content"].as_str().filter(|text| !text.is_empty())?;
+    let content_kind = match message["attachments"][0]["content_type"].as_str()
+    {
+        Some(content_type) => ContentKind::Caption(media_kind(content_type)),
+        None if message["attachments"][0].is_object() => {
+            ContentKind::Caption(MediaKind::Document)
+        },
+        None => ContentKind::Text,
+    };
+    let author = &message["author"];
+    let username = author["username"].as_str().map(String::from);
+    let author = Author {
+        id: snowflake(&author["id"]),
+        display_name: author["global_name"]
+            .as_str()
+            .map(String::from)
+            .or_else(|| username.clone())
+            .unwrap_or_default(),
+        username,
+        is_bot: author["bot"].as_bool().unwrap_or(false),
+    };
+    let reply_target =
+        match snowflake(&message["message_reference"]["message_id"]) {
+            Some(message_id) if kind == TYPE_REPLY => {
+                ReplyTarget::MessageId(message_id)
+            },
+            _ => ReplyTarget::NotReplying,
+        };
+    Some(Message {
+        id: snowflake(&message["id"])?,
+        author: Some(author),
+        content_kind,
+        data: MessageData {
+            chat_id: snowflake(&message["channel_id"])?,
+            thread_id: None,
+            content: String::from(text).into(),
+            reply_target,
+        },
+    })
+}
+
+/// Gateway session, kept across connections so that they resume it.
+#[derive(Debug, Clone)]
+struct Session {
+    id: String,
+    resume_url: String,
+}
+
+#[derive(Debug)]
+struct State {
+    /// Id of the bot user, learned when identifying.
+    user_id: Option<u64>,
+    session: Option<Session>,
+    /// Sequence number of the last dispatch received.
+    seq: Option<u64>,
+    history: History<u64, u64, u64>,
+}
+
+/// Bot connected to Discord. Replies do not ping their target unless asked
+/// to, and mentions written in the text never ping anyone.
+#[derive(Clone)]
+pub struct DiscordChannel {
+    config: Arc<DiscordConfig>,
+    client: DiscordClient,
+    gateway: Arc<Mutex<Option<Gateway>>>,
+    state: Arc<sync::Mutex<State>>,
+    reply_depth: usize,
+    pings_replied_users: bool,
+}
+
+impl DiscordChannel {
+    pub fn new(config: DiscordConfig) -> Self {
+        let state = State {
+            user_id: None,
+            session: None,
+            seq: None,
+            history: History::new(HISTORY_CAPACITY),
+        };
+        Self {
+            client: DiscordClient::new(&config),
+            config: Arc::new(config),
+            gateway: Arc::new(Mutex::new(None)),
+            state: Arc::new(sync::Mutex::new(state)),
+            reply_depth: DEFAULT_REPLY_DEPTH,
+            pings_replied_users: false,
+        }
+    }
+
+    /// Sets how many levels of replied messages are embedded in received
+    /// messages.
+    pub fn reply_depth(mut self, depth: usize) -> Self {
+        self.reply_depth = depth;
+        self
+    }
+
+    /// Sets whether replies ping the author of the message replied to.
+    pub fn ping_replied_users(mut self, pings: bool) -> Self {
+        self.pings_replied_users = pings;
+        self
+    }
+
+    async fn gateway_url(&self) -> Result<String, DiscordError> {
+        if let Some(url) = &self.config.gateway_url {
+            return Ok(url.clone());
+        }
+        let gateway =
+            self.client.call(Method::GET, "gateway/bot", None).await?;
+        gateway["url"]
+            .as_str()
+            .map(String::from)
+            .ok_or(DiscordError::MalformedResponse)
+    }
+
+    /// Opens a gateway connection, resuming the previous session if any, or
+    /// else identifying and waiting for the new session to be ready.
+    async fn open(&self) -> Result<Gateway, DiscordError> {
+        let (session, seq) = {
+            let state = self.lock_state();
+            (state.session.clone(), state.seq)
+        };
+        if let Some(session) = session {
+            let mut gateway = Gateway::open(&session.resume_url, seq).await?;
+            let resume = json!({
+                "token": self.config.token,
+                "session_id": session.id,
+                "seq": seq,
+            });
+            gateway.send(&Payload::new(RESUME, resume)).await?;
+            return Ok(gateway);
+        }
+
+        let mut gateway =
+            Gateway::open(&self.gateway_url().await?, None).await?;
+        let identify = json!({
+            "token": self.config.token,
+            "intents": self.config.intents,
+            "properties": {
+                "os": std::env::consts::OS,
+                "browser": env!("CARGO_PKG_NAME"),
+                "device": env!("CARGO_PKG_NAME"),
+            },
+        });
+        gateway.send(&Payload::new(IDENTIFY, identify)).await?;
+        loop {
+            let payload = gateway.next().await?;
+            match (payload.op, payload.t.as_deref()) {
+                (DISPATCH, Some("READY")) => {
+                    let ready = &payload.d;
+                    let session = Session {
+                        id: ready["session_id"]
+                            .as_str()
+                            .map(String::from)
+                            .ok_or(DiscordError::MalformedResponse)?,
+                        resume_url: ready["resume_gateway_url"]
+                            .as_str()
+                            .map(String::from)
+                            .ok_or(DiscordError::MalformedResponse)?,
+                    };
+                    let mut state = self.lock_state();
+                    state.user_id = snowflake(&ready["user"]["id"]);
+                    state.session = Some(session);
+                    state.seq = gateway.seq;
+                    return Ok(gateway);
+                },
+                (INVALID_SESSION, _) => {
+                    return Err(DiscordError::InvalidSession)
+                },
+                _ => (),
+            }
+        }
+    }
+
+    /// Converts a created message, yielding nothing for messages of the bot
+    /// itself.
+    fn message_create_to_domain(&self, created: &Value) -> Option<Message> {
+        let message = message_to_domain(created)?;
+        let mut state = self.lock_state();
+        let author_id = message.author.as_ref().and_then(|author| author.id);
+        if author_id.is_some() && author_id == state.user_id {
+            state.history.record(&message);
+            return None;
+        }
+        if let Some(replied) = message_to_domain(&created["referenced_message"])
+        {
+            state.history.record(&replied);
+        }
+        state.history.record(&message);
+        Some(state.history.resolve(message, self.reply_depth))
+    }
+
+    fn lock_state(&self) -> sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl fmt::Debug for DiscordChannel {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("DiscordChannel")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Text is sent as is, since Discord formatting is markdown written in the
+/// text itself. Keyboards are left out.
+impl Sender for DiscordChannel {
+    type MessageId = u64;
+    type ChatId = u64;
+    type UserId = u64;
+    type Error = DiscordError;
+
+    fn send<'fut>(
+        &'fut self,
+        message: &'fut domain::NewMessage<
+            Self::MessageId,
+            Self::ChatId,
+            Self::UserId,
+        >,
+    ) -> DynFuture<'fut, Result<Self::MessageId, Self::Error>> {
+        Box::pin(async move {
+            let data = &message.data;
+            let mut payload = json!({
+                "content": data.content.text,
+                "allowed_mentions": {
+                    "parse": [],
+                    "replied_user": self.pings_replied_users,
+                },
+            });
+            let replied_id = match &data.reply_target {
+                ReplyTarget::Message(target) => Some(target.id),
+                ReplyTarget::MessageId(target_id) => Some(*target_id),
+                _ => None,
+            };
+            if let Some(replied_id) = replied_id {
+                payload["message_reference"] = json!({
+                    "message_id": replied_id.to_string(),
+                    "channel_id": data.chat_id.to_string(),
+                    "fail_if_not_exists": false,
+                });
+            }
+
+            let path = format!("channels/{}/messages", data.chat_id);
+            let sent = match &message.attachment {
+                Some(attachment) => {
+                    payload["attachments"] = json!([
+                        { "id": 0, "filename": attachment.file_name },
+                    ]);
+                    self.client.upload(&path, &payload, attachment).await?
+                },
+                None => {
+                    self.client
+                        .call(Method::POST, &path, Some(&payload))
+                        .await?
+                },
+            };
+            let id = snowflake(&sent["id"])
+                .ok_or(DiscordError::MalformedResponse)?;
+            // Messages with an attachment alone have no text to record.
+            if let Some(sent) = message_to_domain(&sent) {
+                self.lock_state().history.record(&sent);
+            }
+            Ok(id)
+        })
+    }
+}
+
+impl Deleter for DiscordChannel {
+    fn can_delete(&self, _chat_id: Self::ChatId) -> bool {
+        true
+    }
+
+    fn delete(
+        &self,
+        chat_id: Self::ChatId,
+        message_id: Self::MessageId,
+    ) -> DynFuture<'_, Result<(), Self::Error>> {
+        Box::pin(async move {
+            let path = format!("channels/{}/messages/{}", chat_id, message_id);
+            self.client.call(Method::DELETE, &path, None).await?;
+            Ok(())
+        })
+    }
+}
+
+impl Receiver for DiscordChannel {
+    type MessageId = u64;
+    type ChatId = u64;
+    type UserId = u64;
+    type Error = DiscordError;
+
+    fn receive<'fut>(
+        &'fut self,
+    ) -> Receiving<'fut, Self::MessageId, Self::ChatId, Self::UserId, Self::Error>
+    {
+        Box::pin(async move {
+            let mut gateway = self.gateway.lock().await;
+            loop {
+                let payload = match gateway.as_mut() {
+                    Some(gateway) => gateway.next().await,
+                    None => return Ok(Err(Disconnected)),
+                };
+                let payload = match payload {
+                    Ok(payload) => payload,
+                    Err(error) => {
+                        *gateway = None;
+                        if let DiscordError::Closed {
+                            code: Some(code), ..
+                        } = error
+                        {
+                            if SESSION_CLOSE_CODES.contains(&code) {
+                                self.lock_state().session = None;
+                            }
+                        }
+                        return Err(error);
+                    },
+                };
+                match payload.op {
+                    DISPATCH => {
+                        if payload.s.is_some() {
+                            self.lock_state().seq = payload.s;
+                        }
+                        if payload.t.as_deref() != Some("MESSAGE_CREATE") {
+                            continue;
+                        }
+                        if let Some(message) =
+                            self.message_create_to_domain(&payload.d)
+                        {
+                            return Ok(Ok(domain::Update::Message(message)));
+                        }
+                    },
+                    RECONNECT => {
+                        if let Some(gateway) = gateway.take() {
+                            gateway.close().await;
+                        }
+                        return Ok(Err(Disconnected));
+                    },
+                    INVALID_SESSION => {
+                        if payload.d != Value::Bool(true) {
+                            self.lock_state().session = None;
+                        }
+                        if let Some(gateway) = gateway.take() {
+                            gateway.close().await;
+                        }
+                        return Ok(Err(Disconnected));
+                    },
+                    _ => (),
+                }
+            }
+        })
+    }
+
+    /// Dispatches are acknowledged by heartbeats, so there is nothing to
+    /// commit.
+    fn commit(&self) -> DynFuture<'_, Result<(), Self::Error>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Connecting resumes the previous session when there is one, so that
+/// messages sent in the meantime are not missed.
+impl Connector for DiscordChannel {
+    type Receiver = Self;
+    type Error = DiscordError;
+
+    fn connect(&self) -> DynFuture<'_, Result<Self::Receiver, Self::Error>> {
+        Box::pin(async move {
+            let gateway = time::timeout(self.config.timeout, self.open())
+                .await
+                .map_err(|_| DiscordError::TimedOut)??;
+            *self.gateway.lock().await = Some(gateway);
+            Ok(self.clone())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        fake::{FakeDiscord, CHANNEL_ID, TOKEN, USER_ID},
+        gateway::{HEARTBEAT, IDENTIFY, RESUME},
+        DiscordChannel,
+        DiscordError,
+        HANDLE,
+    };
+    use crate::{
+        app::{App, Stopped},
+        commands::replace::{ReplaceCommand, RequestParser},
+        domain::{self, MessageData, ReplyTarget},
+        handler::DefaultHandler,
+        middleware::retry::Backoff,
+        port::{Connector, Deleter, Receiver, Sender},
+        shutdown::Shutdown,
+    };
+    use serde_json::json;
+
+    async fn receive_message(channel: &DiscordChannel) -> super::Message {
+        match channel.receive().await.unwrap().unwrap() {
+            domain::Update::Message(message) => message,
+            update => panic!("expected a message, got {:?}", update),
+        }
+    }
+
+    #[tokio::test]
+    async fn identifies_and_heartbeats() {
+        let server = FakeDiscord::start().await;
+        let channel =
+            DiscordChannel::new(server.config()).connect().await.unwrap();
+        tokio::spawn(async move { channel.receive().await });
+
+        let identify = &server.wait_for_payloads(IDENTIFY, 1).await[0];
+        assert_eq!(identify.d["token"], TOKEN);
+        let heartbeats = server.wait_for_payloads(HEARTBEAT, 2).await;
+        assert_eq!(heartbeats[0].d, json!(1));
+    }
+
+    #[tokio::test]
+    async fn heartbeats_while_not_receiving() {
+        let server = FakeDiscord::start().await;
+        let channel =
+            DiscordChannel::new(server.config()).connect().await.unwrap();
+
+        server.wait_for_payloads(HEARTBEAT, 3).await;
+        let message = server.push_message("hello", None);
+        assert_eq!(receive_message(&channel).await.id, message);
+    }
+
+    #[tokio::test]
+    async fn fails_when_heartbeats_are_not_acknowledged() {
+        let server = FakeDiscord::start().await;
+        server.ignore_heartbeats();
+        let channel =
+            DiscordChannel::new(server.config()).connect().await.unwrap();
+
+        match channel.receive().await {
+            Err(DiscordError::HeartbeatMissed) => (),
+            received => {
+                panic!("expected a missed heartbeat, got {:?}", received)
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn resumes_without_missing_messages() {
+        let server = FakeDiscord::start().await;
+        let channel = DiscordChannel::new(server.config());
+        channel.connect().await.unwrap();
+        server.push_message("first", None);
+        receive_message(&channel).await;
+
+        server.request_reconnect();
+        assert!(channel.receive().await.unwrap().is_err());
+        let missed = server.push_message("second", None);
+        channel.connect().await.unwrap();
+        assert_eq!(receive_message(&channel).await.id, missed);
+        let resume = &server.wait_for_payloads(RESUME, 1).await[0];
+        assert_eq!(resume.d["session_id"], "session1");
+        assert_eq!(resume.d["seq"], 2);
+        assert_eq!(server.wait_for_payloads(IDENTIFY, 1).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn maps_message_references_to_reply_targets() {
+        let server = FakeDiscord::start().await;
+        let channel =
+            DiscordChannel::new(server.config()).connect().await.unwrap();
+        let first = server.push_message("hello", None);
+        server.push_message("world", Some(first));
+        receive_message(&channel).await;
+        let reply = receive_message(&channel).await;
+
+        assert_eq!(reply.data.chat_id, CHANNEL_ID);
+        let author = reply.author.unwrap();
+        assert_eq!(author.id, Some(USER_ID));
+        assert_eq!(author.display_name, "Alice");
+        match reply.data.reply_target {
+            ReplyTarget::Message(target) => {
+                assert_eq!(target.id, first);
+                assert_eq!(target.data.content.text, "hello");
+            },
+            target => panic!("expected the first message, got {:?}", target),
+        }
+    }
+
+    #[tokio::test]
+    async fn deletes_sent_messages() {
+        let server = FakeDiscord::start().await;
+        let channel = DiscordChannel::new(server.config());
+        let message = domain::NewMessage {
+            data: MessageData {
+                chat_id: CHANNEL_ID,
+                thread_id: None,
+                content: String::from("hello").into(),
+                reply_target: ReplyTarget::NotReplying,
+            },
+            keyboard: None,
+            attachment: None,
+        };
+        let sent_id = channel.send(&message).await.unwrap();
+
+        channel.delete(CHANNEL_ID, sent_id).await.unwrap();
+        let error = channel.delete(CHANNEL_ID, sent_id).await.unwrap_err();
+        assert!(
+            matches!(error, DiscordError::Api { code: Some(10008), .. }),
+            "{:?}",
+            error
+        );
+        let endpoint =
+            format!("DELETE /channels/{}/messages/{}", CHANNEL_ID, sent_id);
+        assert_eq!(server.wait_for_calls(&endpoint, 2).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn app_replies_without_pinging() {
+        let server = FakeDiscord::start().await;
+        let channel = DiscordChannel::new(server.config());
+        let app = App::new(domain::Bot { handle: String::from(HANDLE) })
+            .handler(DefaultHandler {
+                request_parser: RequestParser::new(),
+                command: ReplaceCommand,
+                sender: channel.clone(),
+            });
+        let shutdown = Shutdown::new();
+        let running = tokio::spawn(app.run_supervised(
+            channel,
+            Backoff::default(),
+            shutdown.clone(),
+        ));
+
+        server.wait_for_payloads(IDENTIFY, 1).await;
+        let original = server.push_message("hello world", None);
+        server.push_message("s/world/there/", Some(original));
+        let endpoint = format!("POST /channels/{}/messages", CHANNEL_ID);
+        let sent = &server.wait_for_calls(&endpoint, 1).await[0].body;
+        assert_eq!(sent["content"], "hello there");
+        assert_eq!(
+            sent["message_reference"]["message_id"],
+            original.to_string()
+        );
+        assert_eq!(
+            sent["allowed_mentions"],
+            json!({ "parse": [], "replied_user": false })
+        );
+
+        shutdown.trigger();
+        assert_eq!(running.await.unwrap().unwrap(), Stopped::Drained);
+    }
+}
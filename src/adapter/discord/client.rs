@@ -0,0 +1,193 @@
+use super::error::DiscordError;
+use crate::domain::Attachment;
+use hyper::{
+    client::HttpConnector,
+    header::{AUTHORIZATION, CONTENT_TYPE, USER_AGENT},
+    Body,
+    Client,
+    Method,
+    Uri,
+};
+use hyper_tls::HttpsConnector;
+use serde_json::Value;
+use std::{fmt, sync::Arc, time::Duration};
+use tokio::time;
+
+const DEFAULT_API_URL: &str = "https://discord.com/api/v10/";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Guild and direct messages, along with their content.
+const DEFAULT_INTENTS: u64 = 1 << 9 | 1 << 12 | 1 << 15;
+const AGENT: &str =
+    concat!("DiscordBot (rustgex-bot, ", env!("CARGO_PKG_VERSION"), ")");
+const BOUNDARY: &str = "rustgex-bot-boundary";
+
+/// Where and as which bot Discord is reached.
+#[derive(Clone, PartialEq, Eq)]
+pub struct DiscordConfig {
+    pub token: String,
+    pub api_url: Uri,
+    /// Gateway to connect to, asked to the API when absent.
+    pub gateway_url: Option<String>,
+    /// Gateway intents, selecting which events are received.
+    pub intents: u64,
+    /// How long a request, or connecting to the gateway, may take.
+    pub timeout: Duration,
+}
+
+impl DiscordConfig {
+    pub fn new(token: String) -> Self {
+        Self {
+            token,
+            api_url: Uri::from_static(DEFAULT_API_URL),
+            gateway_url: None,
+            intents: DEFAULT_INTENTS,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl fmt::Debug for DiscordConfig {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("DiscordConfig")
+            .field("api_url", &self.api_url)
+            .field("gateway_url", &self.gateway_url)
+            .field("intents", &self.intents)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Calls endpoints of the REST API.
+#[derive(Clone)]
+pub struct DiscordClient {
+    http: Client<HttpsConnector<HttpConnector>>,
+    /// API URL, ending with a slash.
+    api_url: Arc<str>,
+    token: Arc<str>,
+    timeout: Duration,
+}
+
+impl DiscordClient {
+    pub fn new(config: &DiscordConfig) -> Self {
+        let mut api_url = config.api_url.to_string();
+        if !api_url.ends_with('/') {
+            api_url.push('/');
+        }
+        Self {
+            http: Client::builder().build(HttpsConnector::new()),
+            api_url: Arc::from(api_url),
+            token: Arc::from(config.token.as_str()),
+            timeout: config.timeout,
+        }
+    }
+
+    /// Calls an endpoint, given its path after the API URL.
+    pub async fn call(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<Value, DiscordError> {
+        let body = body.map(|body| {
+            (String::from("application/json"), body.to_string().into_bytes())
+        });
+        self.request(method, path, body).await
+    }
+
+    /// Calls an endpoint with a file attached, the JSON parameters going
+    /// along as `payload_json`.
+    pub async fn upload(
+        &self,
+        path: &str,
+        payload: &Value,
+        attachment: &Attachment,
+    ) -> Result<Value, DiscordError> {
+        let mut body = format!(
+            "--{}\r\nContent-Disposition: form-data; \
+             name=\"payload_json\"\r\nContent-Type: \
+             application/json\r\n\r\n{}\r\n",
+            BOUNDARY, payload
+        )
+        .into_bytes();
+        let file_name = attachment.file_name.replace(['"', '\r', '\n'], "_");
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"files[0]\"; \
+                 filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+                BOUNDARY, file_name, attachment.mime_type
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(&attachment.bytes);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+        let content_type =
+            format!("multipart/form-data; boundary={}", BOUNDARY);
+        self.request(Method::POST, path, Some((content_type, body))).await
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<(String, Vec<u8>)>,
+    ) -> Result<Value, DiscordError> {
+        let uri = format!("{}{}", self.api_url, path)
+            .parse::<Uri>()
+            .map_err(DiscordError::Url)?;
+        let builder = hyper::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(AUTHORIZATION, format!("Bot {}", self.token))
+            .header(USER_AGENT, AGENT);
+        let request = match body {
+            Some((content_type, body)) => builder
+                .header(CONTENT_TYPE, content_type)
+                .body(Body::from(body)),
+            None => builder.body(Body::empty()),
+        }
+        .expect("method, URL and headers are valid");
+
+        let responding = async {
+            let response =
+                self.http.request(request).await.map_err(DiscordError::Http)?;
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body())
+                .await
+                .map_err(DiscordError::Http)?;
+            Ok::<_, DiscordError>((status, body))
+        };
+        let (status, body) = time::timeout(self.timeout, responding)
+            .await
+            .map_err(|_| DiscordError::TimedOut)??;
+
+        // Some endpoints answer with no content at all.
+        let value = if body.is_empty() {
+            Ok(Value::Null)
+        } else {
+            serde_json::from_slice::<Value>(&body)
+        };
+        if status.is_success() {
+            return value.map_err(|_| DiscordError::MalformedResponse);
+        }
+        let value = value.unwrap_or(Value::Null);
+        Err(DiscordError::Api {
+            status: status.as_u16(),
+            code: value["code"].as_u64(),
+            message: value["message"]
+                .as_str()
+                .map(String::from)
+                .unwrap_or_else(|| status.to_string()),
+            retry_after: value["retry_after"]
+                .as_f64()
+                .map(Duration::from_secs_f64),
+        })
+    }
+}
+
+impl fmt::Debug for DiscordClient {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("DiscordClient")
+            .field("api_url", &self.api_url)
+            .finish_non_exhaustive()
+    }
+}
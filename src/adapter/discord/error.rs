@@ -0,0 +1,110 @@
+use crate::middleware::{rate_limit::RetryAfter, retry::Transient};
+use hyper::http::uri::InvalidUri;
+use std::{error::Error, fmt, time::Duration};
+use tokio_tungstenite::tungstenite;
+
+/// Gateway close codes after which reconnecting is pointless, such as an
+/// invalid token or disallowed intents.
+const FATAL_CLOSE_CODES: [u16; 6] = [4004, 4010, 4011, 4012, 4013, 4014];
+
+#[derive(Debug)]
+pub enum DiscordError {
+    /// The endpoint URL built from the API URL is invalid.
+    Url(InvalidUri),
+    Http(hyper::Error),
+    WebSocket(Box<tungstenite::Error>),
+    TimedOut,
+    /// Failure reported by the REST API itself.
+    Api {
+        status: u16,
+        code: Option<u64>,
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    /// The gateway closed the connection.
+    Closed {
+        code: Option<u16>,
+        reason: String,
+    },
+    /// The gateway stopped acknowledging heartbeats, so the connection is
+    /// likely dead.
+    HeartbeatMissed,
+    /// The gateway refused the session it was asked to start.
+    InvalidSession,
+    MalformedResponse,
+}
+
+impl fmt::Display for DiscordError {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Url(cause) => write!(fmtr, "invalid API URL: {}", cause),
+            Self::Http(cause) => write!(fmtr, "{}", cause),
+            Self::WebSocket(cause) => write!(fmtr, "{}", cause),
+            Self::TimedOut => write!(fmtr, "request to Discord timed out"),
+            Self::Api { status, code, message, .. } => match code {
+                Some(code) => write!(fmtr, "error {}: {}", code, message),
+                None => write!(fmtr, "HTTP {}: {}", status, message),
+            },
+            Self::Closed { code: Some(code), reason } => {
+                write!(fmtr, "gateway closed with {}: {}", code, reason)
+            },
+            Self::Closed { code: None, .. } => write!(fmtr, "gateway closed"),
+            Self::HeartbeatMissed => {
+                write!(fmtr, "gateway did not acknowledge a heartbeat")
+            },
+            Self::InvalidSession => write!(fmtr, "gateway session is invalid"),
+            Self::MalformedResponse => {
+                write!(fmtr, "malformed response from Discord")
+            },
+        }
+    }
+}
+
+impl Error for DiscordError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Url(cause) => Some(cause),
+            Self::Http(cause) => Some(cause),
+            Self::WebSocket(cause) => Some(&**cause),
+            _ => None,
+        }
+    }
+}
+
+impl From<tungstenite::Error> for DiscordError {
+    fn from(cause: tungstenite::Error) -> Self {
+        Self::WebSocket(Box::new(cause))
+    }
+}
+
+impl RetryAfter for DiscordError {
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Api { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl Transient for DiscordError {
+    fn is_transient(&self) -> bool {
+        match self {
+            Self::Api { status, retry_after, .. } => {
+                *status == 429 || *status >= 500 || retry_after.is_some()
+            },
+            Self::Closed { code: Some(code), .. } => {
+                !FATAL_CLOSE_CODES.contains(code)
+            },
+            Self::Url(_) => false,
+            _ => true,
+        }
+    }
+
+    fn may_have_succeeded(&self) -> bool {
+        match self {
+            Self::Http(cause) => !cause.is_connect(),
+            Self::TimedOut | Self::MalformedResponse => true,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! Local stand-in for Discord: a gateway handing out resumable sessions and
+//! a REST API recording the calls made to it.
+
+use super::{
+    client::DiscordConfig,
+    gateway::{
+        Payload,
+        DISPATCH,
+        HEARTBEAT,
+        HEARTBEAT_ACK,
+        HELLO,
+        IDENTIFY,
+        INVALID_SESSION,
+        RECONNECT,
+        RESUME,
+    },
+};
+use crate::adapter::fake::{self, Recorder};
+use futures::{SinkExt, StreamExt};
+use hyper::{Body, Request, Response, StatusCode, Uri};
+use serde_json::{json, Value};
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+use tokio::{net::TcpStream, sync::mpsc};
+use tokio_tungstenite::tungstenite::Message;
+
+pub const TOKEN: &str = "fake_token";
+pub const BOT_ID: u64 = 1;
+pub const USER_ID: u64 = 2;
+pub const CHANNEL_ID: u64 = 3;
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// REST call made by the bot, as the method and path, with its body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    pub endpoint: String,
+    pub body: Value,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    next_snowflake: u64,
+    messages: HashMap<u64, Value>,
+    next_session: u64,
+    /// Current session and the dispatches made in it, kept for resuming.
+    session: Option<String>,
+    dispatched: Vec<Payload>,
+    /// Payloads sent by clients, in order.
+    received: Vec<Payload>,
+    calls: Vec<Call>,
+    /// Frames to send to the client connected, if any.
+    client: Option<mpsc::UnboundedSender<Message>>,
+    ignores_heartbeats: bool,
+}
+
+impl State {
+    fn message(
+        &mut self,
+        author: Value,
+        content: &str,
+        reply_to: Option<u64>,
+    ) -> Value {
+        self.next_snowflake += 1;
+        let mut message = json!({
+            "id": self.next_snowflake.to_string(),
+            "type": 0,
+            "channel_id": CHANNEL_ID.to_string(),
+            "author": author,
+            "content": content,
+            "attachments": [],
+        });
+        if let Some(reply_to) = reply_to {
+            message["type"] = json!(19);
+            message["message_reference"] = json!({
+                "message_id": reply_to.to_string(),
+                "channel_id": CHANNEL_ID.to_string(),
+            });
+            // Discord embeds the replied message, without its own reply.
+            let mut replied = self.messages.get(&reply_to).cloned().into();
+            if let Value::Object(replied) = &mut replied {
+                replied.remove("referenced_message");
+            }
+            message["referenced_message"] = replied;
+        }
+        self.messages.insert(self.next_snowflake, message.clone());
+        message
+    }
+
+    /// Dispatches an event to the current session, even while the client is
+    /// away since resuming replays it.
+    fn dispatch(&mut self, event: &str, data: Value) {
+        let payload = Payload {
+            op: DISPATCH,
+            d: data,
+            s: Some(self.dispatched.len() as u64 + 1),
+            t: Some(String::from(event)),
+        };
+        self.dispatched.push(payload.clone());
+        self.send(&payload);
+    }
+
+    fn send(&self, payload: &Payload) {
+        if let Some(client) = &self.client {
+            let text = serde_json::to_string(payload).expect("payload is JSON");
+            let _ = client.send(Message::Text(text));
+        }
+    }
+}
+
+fn user() -> Value {
+    json!({
+        "id": USER_ID.to_string(),
+        "username": "alice",
+        "global_name": "Alice",
+    })
+}
+
+fn bot() -> Value {
+    json!({ "id": BOT_ID.to_string(), "username": "bot", "bot": true })
+}
+
+/// Serves a gateway for one client at a time, along with the
+/// `gateway/bot` and message creation endpoints, for [`TOKEN`].
+#[derive(Debug, Clone)]
+pub struct FakeDiscord {
+    /// Changed whenever a payload is received or a call is made.
+    recorder: Recorder<State>,
+    gateway_addr: SocketAddr,
+    api_addr: SocketAddr,
+}
+
+impl FakeDiscord {
+    pub async fn start() -> Self {
+        let (gateway, gateway_addr) = fake::listen_async();
+        let (api, api_addr) = fake::listen();
+        let server =
+            Self { recorder: Recorder::default(), gateway_addr, api_addr };
+
+        let accepting = server.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = gateway.accept().await {
+                tokio::spawn(accepting.clone().serve_gateway(stream));
+            }
+        });
+        let serving = server.clone();
+        fake::serve_http(api, move |request| {
+            let server = serving.clone();
+            async move { server.serve_api(request).await }
+        });
+        server
+    }
+
+    /// Configuration of a client pointing at this server, asking the API for
+    /// the gateway URL.
+    pub fn config(&self) -> DiscordConfig {
+        let api_url = format!("http://{}/", self.api_addr)
+            .parse::<Uri>()
+            .expect("socket address makes a valid URL");
+        DiscordConfig {
+            api_url,
+            timeout: Duration::from_secs(5),
+            ..DiscordConfig::new(String::from(TOKEN))
+        }
+    }
+
+    /// Scripts a message from the user in [`CHANNEL_ID`], yielding its id.
+    pub fn push_message(&self, content: &str, reply_to: Option<u64>) -> u64 {
+        self.recorder.with_state(|state| {
+            let message = state.message(user(), content, reply_to);
+            state.dispatch("MESSAGE_CREATE", message);
+            state.next_snowflake
+        })
+    }
+
+    /// Asks the client to reconnect and resume.
+    pub fn request_reconnect(&self) {
+        self.recorder.with_state(|state| {
+            state.send(&Payload::new(RECONNECT, Value::Null))
+        });
+    }
+
+    /// Stops acknowledging heartbeats.
+    pub fn ignore_heartbeats(&self) {
+        self.recorder.with_state(|state| state.ignores_heartbeats = true);
+    }
+
+    /// Waits until the client has sent the opcode `count` times, yielding
+    /// those payloads.
+    pub async fn wait_for_payloads(
+        &self,
+        op: u8,
+        count: usize,
+    ) -> Vec<Payload> {
+        let what = format!("{} op {}", count, op);
+        self.recorder
+            .wait_for(what, count, |state| {
+                state
+                    .received
+                    .iter()
+                    .filter(|payload| payload.op == op)
+                    .cloned()
+                    .collect()
+            })
+            .await
+    }
+
+    /// Waits until the bot has called the endpoint `count` times, yielding
+    /// those calls.
+    pub async fn wait_for_calls(
+        &self,
+        endpoint: &str,
+        count: usize,
+    ) -> Vec<Call> {
+        let what = format!("{} call(s) to {}", count, endpoint);
+        self.recorder
+            .wait_for(what, count, |state| {
+                state
+                    .calls
+                    .iter()
+                    .filter(|call| call.endpoint == endpoint)
+                    .cloned()
+                    .collect()
+            })
+            .await
+    }
+
+    async fn serve_gateway(self, stream: TcpStream) {
+        let socket = match tokio_tungstenite::accept_async(stream).await {
+            Ok(socket) => socket,
+            Err(_) => return,
+        };
+        let (mut sink, mut frames) = socket.split();
+        let (client, mut outgoing) = mpsc::unbounded_channel();
+        self.recorder.with_state(|state| {
+            state.client = Some(client);
+            let interval = HEARTBEAT_INTERVAL.as_millis() as u64;
+            let hello = json!({ "heartbeat_interval": interval });
+            state.send(&Payload::new(HELLO, hello));
+        });
+        tokio::spawn(async move {
+            while let Some(frame) = outgoing.recv().await {
+                if sink.send(frame).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let gateway_url = format!("ws://{}", self.gateway_addr);
+        while let Some(Ok(frame)) = frames.next().await {
+            let payload = match frame {
+                Message::Text(text) => serde_json::from_str::<Payload>(&text),
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            let payload = match payload {
+                Ok(payload) => payload,
+                Err(_) => continue,
+            };
+            self.recorder.update(|state| {
+                match payload.op {
+                    HEARTBEAT if !state.ignores_heartbeats => {
+                        state.send(&Payload::new(HEARTBEAT_ACK, Value::Null))
+                    },
+                    IDENTIFY if payload.d["token"] == TOKEN => {
+                        state.next_session += 1;
+                        let session = format!("session{}", state.next_session);
+                        state.session = Some(session.clone());
+                        state.dispatched.clear();
+                        let ready = json!({
+                            "v": 10,
+                            "user": bot(),
+                            "session_id": session,
+                            "resume_gateway_url": gateway_url,
+                        });
+                        state.dispatch("READY", ready);
+                    },
+                    RESUME
+                        if state.session.as_deref()
+                            == payload.d["session_id"].as_str() =>
+                    {
+                        let seq = payload.d["seq"].as_u64().unwrap_or(0);
+                        for missed in state.dispatched.iter().skip(seq as usize)
+                        {
+                            state.send(missed);
+                        }
+                        state.dispatch("RESUMED", json!({}));
+                    },
+                    RESUME => {
+                        state.send(&Payload::new(INVALID_SESSION, json!(false)))
+                    },
+                    _ => (),
+                }
+                state.received.push(payload);
+            });
+        }
+    }
+
+    async fn serve_api(&self, request: Request<Body>) -> Response<Body> {
+        let endpoint = format!("{} {}", request.method(), request.uri().path());
+        let is_authorized = request
+            .headers()
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            == Some(&format!("Bot {}", TOKEN));
+        let body = hyper::body::to_bytes(request.into_body())
+            .await
+            .ok()
+            .and_then(|body| serde_json::from_slice::<Value>(&body).ok())
+            .unwrap_or(Value::Null);
+
+        let messages_endpoint =
+            format!("POST /channels/{}/messages", CHANNEL_ID);
+        let message_endpoint =
+            format!("DELETE /channels/{}/messages/", CHANNEL_ID);
+        let result =
+            self.recorder.with_state(|state| match endpoint.as_str() {
+                _ if !is_authorized => Err((StatusCode::UNAUTHORIZED, 0)),
+                "GET /gateway/bot" => {
+                    let url = format!("ws://{}", self.gateway_addr);
+                    Ok(json!({ "url": url, "shards": 1 }))
+                },
+                endpoint if endpoint == messages_endpoint => {
+                    let reply_to = body["message_reference"]["message_id"]
+                        .as_str()
+                        .and_then(|id| id.parse().ok());
+                    let content = body["content"].as_str().unwrap_or_default();
+                    let message = state.message(bot(), content, reply_to);
+                    state.dispatch("MESSAGE_CREATE", message.clone());
+                    Ok(message)
+                },
+                endpoint if endpoint.starts_with(&message_endpoint) => {
+                    let message_id = endpoint[message_endpoint.len()..]
+                        .parse::<u64>()
+                        .ok()
+                        .and_then(|id| state.messages.remove(&id));
+                    // 10008 is the code of unknown messages.
+                    message_id
+                        .map(|_| Value::Null)
+                        .ok_or((StatusCode::NOT_FOUND, 10008))
+                },
+                _ => Err((StatusCode::NOT_FOUND, 0)),
+            });
+        let (status, response) = match result {
+            Ok(response) => (StatusCode::OK, response),
+            Err((status, code)) => {
+                (status, json!({ "code": code, "message": status.to_string() }))
+            },
+        };
+        self.recorder.update(|state| state.calls.push(Call { endpoint, body }));
+
+        let mut http_response = Response::new(Body::from(response.to_string()));
+        *http_response.status_mut() = status;
+        http_response
+    }
+}
@@ -0,0 +1,261 @@
+//! Gateway payloads and the heartbeating connection carrying them.
+
+use super::error::DiscordError;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::{
+    net::TcpStream,
+    sync::{mpsc, oneshot},
+    time::{self, Instant, Interval, MissedTickBehavior},
+};
+use tokio_tungstenite::{
+    tungstenite::{
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Message,
+    },
+    MaybeTlsStream,
+    WebSocketStream,
+};
+
+pub const DISPATCH: u8 = 0;
+pub const HEARTBEAT: u8 = 1;
+pub const IDENTIFY: u8 = 2;
+pub const RESUME: u8 = 6;
+pub const RECONNECT: u8 = 7;
+pub const INVALID_SESSION: u8 = 9;
+pub const HELLO: u8 = 10;
+pub const HEARTBEAT_ACK: u8 = 11;
+
+/// Closing with any other code than 1000 or 1001 keeps the session
+/// resumable.
+const RESUMABLE_CLOSE_CODE: u16 = 4000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Payload {
+    pub op: u8,
+    #[serde(default)]
+    pub d: Value,
+    /// Sequence number of dispatches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s: Option<u64>,
+    /// Event name of dispatches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub t: Option<String>,
+}
+
+impl Payload {
+    pub fn new(op: u8, d: Value) -> Self {
+        Self { op, d, s: None, t: None }
+    }
+}
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Orders for the task owning the connection.
+#[derive(Debug)]
+enum Command {
+    Send(Payload, oneshot::Sender<Result<(), DiscordError>>),
+    Close(CloseFrame<'static>),
+}
+
+/// Open gateway connection, read and heartbeated in the background whether
+/// or not payloads are taken from it.
+#[derive(Debug)]
+pub struct Gateway {
+    commands: mpsc::UnboundedSender<Command>,
+    /// Payloads read, ending with the error that stopped the connection if
+    /// any.
+    payloads: mpsc::UnboundedReceiver<Result<Payload, DiscordError>>,
+    /// Sequence number of the last dispatch taken.
+    pub seq: Option<u64>,
+}
+
+impl Gateway {
+    /// Connects and waits for the gateway to say how often to heartbeat.
+    pub async fn open(
+        url: &str,
+        seq: Option<u64>,
+    ) -> Result<Self, DiscordError> {
+        let url = format!("{}/?v=10&encoding=json", url.trim_end_matches('/'));
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await?;
+        let hello = loop {
+            let payload = read(&mut socket).await?;
+            if payload.op == HELLO {
+                break payload;
+            }
+        };
+        let period = hello.d["heartbeat_interval"]
+            .as_u64()
+            .map(Duration::from_millis)
+            .filter(|period| !period.is_zero())
+            .ok_or(DiscordError::MalformedResponse)?;
+        let mut heartbeat = time::interval_at(Instant::now() + period, period);
+        heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let (commands, commanded) = mpsc::unbounded_channel();
+        let (reading, payloads) = mpsc::unbounded_channel();
+        let connection = Connection { socket, heartbeat, seq, reading };
+        tokio::spawn(async move {
+            let mut connection = connection;
+            if let Err(error) = connection.run(commanded).await {
+                let _ = connection.reading.send(Err(error));
+            }
+        });
+        Ok(Self { commands, payloads, seq })
+    }
+
+    pub async fn send(
+        &mut self,
+        payload: &Payload,
+    ) -> Result<(), DiscordError> {
+        let (sending, sent) = oneshot::channel();
+        let command = Command::Send(payload.clone(), sending);
+        if self.commands.send(command).is_err() {
+            return Err(self.stopped().await);
+        }
+        match sent.await {
+            Ok(result) => result,
+            Err(_) => Err(self.stopped().await),
+        }
+    }
+
+    /// Takes the next payload read. Dispatches update the sequence number.
+    pub async fn next(&mut self) -> Result<Payload, DiscordError> {
+        let payload =
+            self.payloads.recv().await.unwrap_or_else(|| Err(closed()))?;
+        if payload.op == DISPATCH {
+            self.seq = payload.s.or(self.seq);
+        }
+        Ok(payload)
+    }
+
+    /// Closes the connection, leaving the session to be resumed.
+    pub async fn close(mut self) {
+        let frame = CloseFrame {
+            code: CloseCode::from(RESUMABLE_CLOSE_CODE),
+            reason: "reconnecting".into(),
+        };
+        if self.commands.send(Command::Close(frame)).is_ok() {
+            while self.payloads.recv().await.is_some() {}
+        }
+    }
+
+    /// Error the connection stopped with, skipping the payloads before it.
+    async fn stopped(&mut self) -> DiscordError {
+        while let Some(payload) = self.payloads.recv().await {
+            if let Err(error) = payload {
+                return error;
+            }
+        }
+        closed()
+    }
+}
+
+fn closed() -> DiscordError {
+    DiscordError::Closed { code: None, reason: String::new() }
+}
+
+/// Socket of an open gateway connection, owned by a task of its own.
+struct Connection {
+    socket: Socket,
+    heartbeat: Interval,
+    /// Sequence number of the last dispatch read, sent along with
+    /// heartbeats.
+    seq: Option<u64>,
+    reading: mpsc::UnboundedSender<Result<Payload, DiscordError>>,
+}
+
+impl Connection {
+    /// Reads payloads, answers heartbeat requests, heartbeats and carries
+    /// out commands until closed or dropped, failing when a heartbeat goes
+    /// unacknowledged.
+    async fn run(
+        &mut self,
+        mut commands: mpsc::UnboundedReceiver<Command>,
+    ) -> Result<(), DiscordError> {
+        let mut is_awaiting_ack = false;
+        loop {
+            tokio::select! {
+                _ = self.heartbeat.tick() => {
+                    if is_awaiting_ack {
+                        return Err(DiscordError::HeartbeatMissed);
+                    }
+                    is_awaiting_ack = true;
+                    self.send_heartbeat().await?;
+                },
+                command = commands.recv() => match command {
+                    Some(Command::Send(payload, sending)) => {
+                        let sent = write(&mut self.socket, &payload).await;
+                        let _ = sending.send(sent);
+                    },
+                    Some(Command::Close(frame)) => {
+                        let _ = self.socket.close(Some(frame)).await;
+                        return Ok(());
+                    },
+                    None => return Ok(()),
+                },
+                payload = read(&mut self.socket) => {
+                    let payload = payload?;
+                    match payload.op {
+                        HEARTBEAT => {
+                            is_awaiting_ack = true;
+                            self.send_heartbeat().await?;
+                        },
+                        HEARTBEAT_ACK => is_awaiting_ack = false,
+                        _ => {
+                            if payload.op == DISPATCH {
+                                self.seq = payload.s.or(self.seq);
+                            }
+                            let _ = self.reading.send(Ok(payload));
+                        },
+                    }
+                },
+            }
+        }
+    }
+
+    async fn send_heartbeat(&mut self) -> Result<(), DiscordError> {
+        let payload = Payload::new(HEARTBEAT, json!(self.seq));
+        write(&mut self.socket, &payload).await
+    }
+}
+
+async fn write(
+    socket: &mut Socket,
+    payload: &Payload,
+) -> Result<(), DiscordError> {
+    let text =
+        serde_json::to_string(payload).expect("payloads serialize to JSON");
+    socket.send(Message::Text(text)).await?;
+    Ok(())
+}
+
+async fn read(socket: &mut Socket) -> Result<Payload, DiscordError> {
+    loop {
+        let message = match socket.next().await {
+            Some(message) => message?,
+            None => {
+                return Err(DiscordError::Closed {
+                    code: None,
+                    reason: String::new(),
+                })
+            },
+        };
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Close(frame) => {
+                return Err(DiscordError::Closed {
+                    code: frame.as_ref().map(|frame| u16::from(frame.code)),
+                    reason: frame
+                        .map(|frame| frame.reason.into_owned())
+                        .unwrap_or_default(),
+                })
+            },
+            _ => continue,
+        };
+        return serde_json::from_str(&text)
+            .map_err(|_| DiscordError::MalformedResponse);
+    }
+}
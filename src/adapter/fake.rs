@@ -0,0 +1,134 @@
+//! Pieces shared by the local stand-ins for chat services: a recorder of what
+//! the client did, and listeners on free local ports.
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body,
+    Request,
+    Response,
+    Server,
+};
+use std::{
+    convert::Infallible,
+    fmt::Display,
+    future::Future,
+    net::{SocketAddr, TcpListener},
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+use tokio::{net, sync::watch, time};
+
+/// How long to wait for the client before failing a test.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// State of a stand-in, along with a version bumped on every change so that
+/// waiting for the client takes no polling.
+#[derive(Debug)]
+pub struct Recorder<S> {
+    state: Arc<Mutex<S>>,
+    changes: Arc<watch::Sender<u64>>,
+}
+
+impl<S> Clone for Recorder<S> {
+    fn clone(&self) -> Self {
+        Self { state: self.state.clone(), changes: self.changes.clone() }
+    }
+}
+
+impl<S: Default> Default for Recorder<S> {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(S::default())),
+            changes: Arc::new(watch::channel(0).0),
+        }
+    }
+}
+
+impl<S> Recorder<S> {
+    /// Reads or changes the state without telling waiters.
+    pub fn with_state<F, T>(&self, access: F) -> T
+    where
+        F: FnOnce(&mut S) -> T,
+    {
+        access(&mut self.state.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Changes the state, waking up whoever waits for a change.
+    pub fn update<F, T>(&self, access: F) -> T
+    where
+        F: FnOnce(&mut S) -> T,
+    {
+        let output = self.with_state(access);
+        self.changes.send_modify(|version| *version += 1);
+        output
+    }
+
+    /// Changes to come, for long polls.
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.changes.subscribe()
+    }
+
+    /// Waits until `select` yields at least `count` items, yielding them,
+    /// and panics past a timeout, telling what was waited for.
+    pub async fn wait_for<F, T>(
+        &self,
+        what: impl Display,
+        count: usize,
+        select: F,
+    ) -> Vec<T>
+    where
+        F: Fn(&S) -> Vec<T>,
+    {
+        let mut changes = self.subscribe();
+        let waiting = async {
+            loop {
+                let selected = self.with_state(|state| select(state));
+                if selected.len() >= count {
+                    break selected;
+                }
+                changes.changed().await.expect("sender is kept by self");
+            }
+        };
+        time::timeout(WAIT_TIMEOUT, waiting)
+            .await
+            .unwrap_or_else(|_| panic!("timed out waiting for {}", what))
+    }
+}
+
+/// Listens on a free local port.
+pub fn listen() -> (TcpListener, SocketAddr) {
+    let listener =
+        TcpListener::bind(("127.0.0.1", 0)).expect("localhost has free ports");
+    let local_addr = listener.local_addr().expect("listener is bound");
+    (listener, local_addr)
+}
+
+/// Listens on a free local port, for raw streams.
+pub fn listen_async() -> (net::TcpListener, SocketAddr) {
+    let (listener, local_addr) = listen();
+    listener.set_nonblocking(true).expect("listener is bound");
+    let listener =
+        net::TcpListener::from_std(listener).expect("runtime is running");
+    (listener, local_addr)
+}
+
+/// Serves HTTP on the listener in the background.
+pub fn serve_http<F, R>(listener: TcpListener, serve: F)
+where
+    F: Fn(Request<Body>) -> R + Clone + Send + Sync + 'static,
+    R: Future<Output = Response<Body>> + Send + 'static,
+{
+    let make_service = make_service_fn(move |_| {
+        let serve = serve.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let serving = serve(request);
+                async move { Ok::<_, Infallible>(serving.await) }
+            }))
+        }
+    });
+    let server = Server::from_tcp(listener)
+        .expect("listener is bound")
+        .serve(make_service);
+    tokio::spawn(server);
+}
@@ -0,0 +1,773 @@
+//! Plain IRC, optionally over TLS. Channels and nicknames serve as chat and
+//! user ids, while message ids are assigned locally since IRC has none.
+
+mod error;
+#[cfg(test)]
+pub mod fake;
+mod line;
+
+pub use self::error::IrcError;
+use self::line::Line;
+use crate::{
+    domain::{self, Author, ContentKind, MessageData, ReplyTarget},
+    future::DynFuture,
+    history::History,
+    middleware::{
+        rate_limit::{Quota, RateLimits},
+        split::{LengthUnit, PlatformLimits, TextLimit},
+    },
+    port::{Connector, Deleter, Disconnected, Receiver, Receiving, Sender},
+};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    error::Error,
+    fmt,
+    hash::{Hash, Hasher},
+    mem,
+    str::{self, FromStr},
+    sync::{self, Arc, PoisonError},
+    time::Duration,
+};
+use tokio::{
+    io::{
+        self,
+        AsyncBufReadExt,
+        AsyncRead,
+        AsyncWrite,
+        AsyncWriteExt,
+        BufReader,
+        ReadHalf,
+        WriteHalf,
+    },
+    net::TcpStream,
+    sync::Mutex,
+    time,
+};
+use tokio_native_tls::{native_tls, TlsConnector};
+
+pub const DEFAULT_PORT: u16 = 6667;
+pub const DEFAULT_TLS_PORT: u16 = 6697;
+/// Maximum length of a line, CRLF included.
+const MAX_LINE_LEN: usize = 512;
+const MAX_HOST_LEN: usize = 63;
+const MAX_NAME_LEN: usize = 64;
+const MAX_NICK_ATTEMPTS: usize = 3;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long the server may stay silent before the link is assumed dead.
+/// Servers ping idle clients well within it.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+const DEFAULT_REPLY_DEPTH: usize = 1;
+const HISTORY_CAPACITY: usize = 4096;
+
+const RPL_WELCOME: &str = "001";
+const ERR_ERRONEUSNICKNAME: &str = "432";
+const ERR_NICKNAMEINUSE: &str = "433";
+const ERR_NICKCOLLISION: &str = "436";
+
+/// A single line of text, leaving room for the command and for the prefix
+/// servers add when relaying it.
+pub const LIMITS: PlatformLimits = PlatformLimits {
+    text: TextLimit { max_len: 400, unit: LengthUnit::Bytes },
+    caption: TextLimit { max_len: 400, unit: LengthUnit::Bytes },
+};
+
+/// Servers commonly allow a short burst of lines, then about one every two
+/// seconds before dropping the link for flooding.
+pub const RATE_LIMITS: RateLimits = RateLimits {
+    per_chat: &[],
+    global: &[Quota { burst: 5, period: Duration::from_secs(10) }],
+};
+
+#[derive(Debug, Clone)]
+pub struct InvalidName(String);
+
+impl fmt::Display for InvalidName {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmtr, "{:?} is not a valid IRC nickname or channel", self.0)
+    }
+}
+
+impl Error for InvalidName {}
+
+/// Nickname or channel name, lowercased since IRC compares them regardless
+/// of case, and stored inline so that it can serve as an id.
+#[derive(Clone, Copy)]
+pub struct IrcName {
+    len: u8,
+    bytes: [u8; MAX_NAME_LEN],
+}
+
+impl IrcName {
+    pub fn new(name: &str) -> Result<Self, InvalidName> {
+        let is_valid = !name.is_empty()
+            && name.len() <= MAX_NAME_LEN
+            && !name.contains([' ', ',', '\r', '\n', '\0', '\x07']);
+        if !is_valid {
+            return Err(InvalidName(String::from(name)));
+        }
+        let mut bytes = [0; MAX_NAME_LEN];
+        bytes[..name.len()].copy_from_slice(name.as_bytes());
+        bytes.make_ascii_lowercase();
+        let len = u8::try_from(name.len()).expect("length was checked");
+        Ok(Self { len, bytes })
+    }
+
+    pub fn as_str(&self) -> &str {
+        str::from_utf8(&self.bytes[..usize::from(self.len)])
+            .expect("lowercasing ASCII keeps the name valid UTF-8")
+    }
+}
+
+impl FromStr for IrcName {
+    type Err = InvalidName;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Self::new(input)
+    }
+}
+
+impl PartialEq for IrcName {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for IrcName {}
+
+impl PartialOrd for IrcName {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for IrcName {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl Hash for IrcName {
+    fn hash<H>(&self, state: &mut H)
+    where
+        H: Hasher,
+    {
+        self.as_str().hash(state)
+    }
+}
+
+impl fmt::Debug for IrcName {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_tuple("IrcName").field(&self.as_str()).finish()
+    }
+}
+
+impl fmt::Display for IrcName {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmtr, "{}", self.as_str())
+    }
+}
+
+impl domain::Id for IrcName {}
+
+pub type Message = domain::Message<u64, IrcName, IrcName>;
+
+#[derive(Debug, Clone)]
+pub struct IrcConfig {
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+    pub nick: IrcName,
+    /// Username and real name registered along with the nickname.
+    pub user: String,
+    /// Server password, sent before registering.
+    pub password: Option<String>,
+    /// Channels joined once registered.
+    pub channels: Vec<IrcName>,
+    /// Time allowed to connect and register.
+    pub timeout: Duration,
+}
+
+impl IrcConfig {
+    pub fn new(host: String, nick: IrcName) -> Self {
+        Self {
+            host,
+            port: DEFAULT_PORT,
+            tls: false,
+            nick,
+            user: nick.to_string(),
+            password: None,
+            channels: Vec::new(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T> Stream for T where T: AsyncRead + AsyncWrite + Send + Unpin {}
+
+type Writer = WriteHalf<Box<dyn Stream>>;
+
+/// Half of the connection lines are read from, keeping a line read in part
+/// until the rest comes, so that reading can be interrupted at any point.
+struct Reader {
+    lines: BufReader<ReadHalf<Box<dyn Stream>>>,
+    partial: Vec<u8>,
+}
+
+/// Reads the next line, yielding nothing once the server closed the link.
+async fn read(reader: &mut Reader) -> Result<Option<Line>, IrcError> {
+    loop {
+        let count = reader.lines.read_until(b'\n', &mut reader.partial).await?;
+        let raw = mem::take(&mut reader.partial);
+        if count == 0 {
+            return Ok(None);
+        }
+        if let Some(line) = Line::parse(&String::from_utf8_lossy(&raw)) {
+            return Ok(Some(line));
+        }
+    }
+}
+
+async fn write(writer: &mut Writer, lines: &[Line]) -> Result<(), IrcError> {
+    for line in lines {
+        writer.write_all(format!("{}\r\n", line).as_bytes()).await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+fn pong(ping: &Line) -> Line {
+    Line::new("PONG", ping.params.clone())
+}
+
+/// Splits text into lines within the limit, dropping blank ones since they
+/// cannot be sent.
+fn split_lines(text: &str, limit: TextLimit) -> Vec<String> {
+    let mut lines = Vec::new();
+    for mut rest in text.split(['\r', '\n']) {
+        while !rest.is_empty() {
+            let end = limit.split_point(rest);
+            let line = rest[..end].trim_end().replace('\0', "");
+            if !line.trim_start().is_empty() {
+                lines.push(line);
+            }
+            rest = &rest[end..];
+        }
+    }
+    lines
+}
+
+#[derive(Debug)]
+struct State {
+    /// Nickname the server knows the bot by.
+    nick: IrcName,
+    next_message_id: u64,
+    history: History<u64, IrcName, IrcName>,
+    /// Id of the last line of each nickname in each chat, which the next
+    /// line of the same nickname replies to.
+    last_lines: HashMap<(IrcName, IrcName), u64>,
+}
+
+impl State {
+    fn record(
+        &mut self,
+        author: Author<IrcName>,
+        data: MessageData<u64, IrcName, IrcName>,
+    ) -> Message {
+        self.next_message_id += 1;
+        if let Some(nick) = author.id {
+            self.last_lines.insert((data.chat_id, nick), self.next_message_id);
+        }
+        let message = Message {
+            id: self.next_message_id,
+            author: Some(author),
+            content_kind: ContentKind::Text,
+            data,
+        };
+        self.history.record(&message);
+        message
+    }
+}
+
+/// Connection to an IRC server. Since IRC has no replies, each line replies
+/// to the previous line of the same nickname in the same chat.
+#[derive(Clone)]
+pub struct IrcChannel {
+    config: Arc<IrcConfig>,
+    reader: Arc<Mutex<Option<Reader>>>,
+    writer: Arc<Mutex<Option<Writer>>>,
+    state: Arc<sync::Mutex<State>>,
+    reply_depth: usize,
+}
+
+impl IrcChannel {
+    pub fn new(config: IrcConfig) -> Self {
+        let state = State {
+            nick: config.nick,
+            next_message_id: 0,
+            history: History::new(HISTORY_CAPACITY),
+            last_lines: HashMap::new(),
+        };
+        Self {
+            config: Arc::new(config),
+            reader: Arc::new(Mutex::new(None)),
+            writer: Arc::new(Mutex::new(None)),
+            state: Arc::new(sync::Mutex::new(state)),
+            reply_depth: DEFAULT_REPLY_DEPTH,
+        }
+    }
+
+    /// Sets how many levels of previous lines are embedded in received
+    /// messages.
+    pub fn reply_depth(mut self, depth: usize) -> Self {
+        self.reply_depth = depth;
+        self
+    }
+
+    async fn open(&self) -> Result<Box<dyn Stream>, IrcError> {
+        let address = (self.config.host.as_str(), self.config.port);
+        let tcp = TcpStream::connect(address).await?;
+        if !self.config.tls {
+            return Ok(Box::new(tcp));
+        }
+        let connector = TlsConnector::from(native_tls::TlsConnector::new()?);
+        Ok(Box::new(connector.connect(&self.config.host, tcp).await?))
+    }
+
+    /// Registers the connection, trying alternative nicknames while the one
+    /// asked for is taken, and yields the nickname the server accepted.
+    async fn register(
+        &self,
+        reader: &mut Reader,
+        writer: &mut Writer,
+    ) -> Result<IrcName, IrcError> {
+        let config = &self.config;
+        let mut nick = config.nick.to_string();
+        let mut lines = Vec::new();
+        if let Some(password) = &config.password {
+            lines.push(Line::new("PASS", [password.as_str()]));
+        }
+        lines.push(Line::new("NICK", [nick.as_str()]));
+        let user = config.user.as_str();
+        lines.push(Line::new("USER", [user, "0", "*", user]));
+        write(writer, &lines).await?;
+
+        let mut attempts = 1;
+        loop {
+            let line = read(reader).await?.ok_or(IrcError::Closed(None))?;
+            match line.command.as_str() {
+                "PING" => write(writer, &[pong(&line)]).await?,
+                RPL_WELCOME => {
+                    let accepted = line.param(0).unwrap_or(&nick);
+                    return IrcName::new(accepted)
+                        .map_err(|_| IrcError::NickUnavailable(nick));
+                },
+                ERR_ERRONEUSNICKNAME | ERR_NICKNAMEINUSE
+                | ERR_NICKCOLLISION => {
+                    if attempts >= MAX_NICK_ATTEMPTS {
+                        return Err(IrcError::NickUnavailable(nick));
+                    }
+                    attempts += 1;
+                    nick.push('_');
+                    write(writer, &[Line::new("NICK", [nick.as_str()])])
+                        .await?;
+                },
+                "ERROR" => {
+                    return Err(IrcError::Closed(
+                        line.param(0).map(String::from),
+                    ))
+                },
+                _ => (),
+            }
+        }
+    }
+
+    async fn write(&self, lines: &[Line]) -> Result<(), IrcError> {
+        let mut writer = self.writer.lock().await;
+        write(writer.as_mut().ok_or(IrcError::NotConnected)?, lines).await
+    }
+
+    /// Bytes left for the text of a line sent to the chat, once the server
+    /// prefixes it with `nick!~user@host` to relay it.
+    fn line_budget(&self, nick: IrcName, chat_id: IrcName) -> usize {
+        let prefix = ":!~@ ".len()
+            + nick.as_str().len()
+            + self.config.user.len()
+            + MAX_HOST_LEN;
+        let command = "PRIVMSG  :\r\n".len() + chat_id.as_str().len();
+        MAX_LINE_LEN.saturating_sub(prefix + command).max(1)
+    }
+
+    /// Converts a `PRIVMSG`, yielding nothing for CTCP requests and actions.
+    fn privmsg_to_domain(&self, line: &Line) -> Option<Message> {
+        let nick = line.nick()?;
+        let text = line.param(1)?;
+        if text.starts_with('\x01') {
+            return None;
+        }
+        let author_id = IrcName::new(nick).ok()?;
+        let target = IrcName::new(line.param(0)?).ok()?;
+
+        let mut state = self.lock_state();
+        let chat_id = if target == state.nick { author_id } else { target };
+        let reply_target = match state.last_lines.get(&(chat_id, author_id)) {
+            Some(&message_id) => ReplyTarget::MessageId(message_id),
+            None => ReplyTarget::NotReplying,
+        };
+        let author = Author {
+            id: Some(author_id),
+            display_name: String::from(nick),
+            username: Some(String::from(nick)),
+            is_bot: false,
+        };
+        let data = MessageData {
+            chat_id,
+            thread_id: None,
+            content: String::from(text).into(),
+            reply_target,
+        };
+        let message = state.record(author, data);
+        Some(state.history.resolve(message, self.reply_depth))
+    }
+
+    fn lock_state(&self) -> sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl fmt::Debug for IrcChannel {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("IrcChannel")
+            .field("host", &self.config.host)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Replies to someone else's line address its author by nickname. Keyboards
+/// and attachments have no IRC counterpart and are left out.
+impl Sender for IrcChannel {
+    type MessageId = u64;
+    type ChatId = IrcName;
+    type UserId = IrcName;
+    type Error = IrcError;
+
+    fn send<'fut>(
+        &'fut self,
+        message: &'fut domain::NewMessage<
+            Self::MessageId,
+            Self::ChatId,
+            Self::UserId,
+        >,
+    ) -> DynFuture<'fut, Result<Self::MessageId, Self::Error>> {
+        Box::pin(async move {
+            let data = &message.data;
+            let (nick, addressee) = {
+                let state = self.lock_state();
+                let target_author = match &data.reply_target {
+                    ReplyTarget::Message(target) => target.author.clone(),
+                    ReplyTarget::MessageId(message_id) => state
+                        .history
+                        .get(data.chat_id, *message_id)
+                        .and_then(|target| target.author.clone()),
+                    _ => None,
+                };
+                let addressee = target_author
+                    .filter(|author| {
+                        author.id != Some(state.nick)
+                            && author.id != Some(data.chat_id)
+                    })
+                    .map(|author| author.display_name);
+                (state.nick, addressee)
+            };
+
+            let text = match addressee {
+                Some(name) => format!("{}: {}", name, data.content.text),
+                None => data.content.text.clone(),
+            };
+            let limit = TextLimit {
+                max_len: self.line_budget(nick, data.chat_id),
+                unit: LengthUnit::Bytes,
+            };
+            let lines = split_lines(&text, limit)
+                .into_iter()
+                .map(|text| {
+                    Line::new("PRIVMSG", [data.chat_id.as_str(), &text])
+                })
+                .collect::<Vec<_>>();
+            self.write(&lines).await?;
+
+            let author = Author {
+                id: Some(nick),
+                display_name: nick.to_string(),
+                username: Some(nick.to_string()),
+                is_bot: true,
+            };
+            Ok(self.lock_state().record(author, data.clone()).id)
+        })
+    }
+}
+
+impl Deleter for IrcChannel {
+    fn can_delete(&self, _chat_id: Self::ChatId) -> bool {
+        false
+    }
+
+    fn delete(
+        &self,
+        _chat_id: Self::ChatId,
+        _message_id: Self::MessageId,
+    ) -> DynFuture<'_, Result<(), Self::Error>> {
+        Box::pin(async { Err(IrcError::CannotDelete) })
+    }
+}
+
+impl Receiver for IrcChannel {
+    type MessageId = u64;
+    type ChatId = IrcName;
+    type UserId = IrcName;
+    type Error = IrcError;
+
+    fn receive<'fut>(
+        &'fut self,
+    ) -> Receiving<'fut, Self::MessageId, Self::ChatId, Self::UserId, Self::Error>
+    {
+        Box::pin(async move {
+            let mut reader = self.reader.lock().await;
+            loop {
+                let read = match reader.as_mut() {
+                    Some(reader) => time::timeout(IDLE_TIMEOUT, read(reader)),
+                    None => return Ok(Err(Disconnected)),
+                };
+                let line = match read.await {
+                    Ok(Ok(Some(line))) => line,
+                    Ok(Ok(None)) => {
+                        *reader = None;
+                        *self.writer.lock().await = None;
+                        return Ok(Err(Disconnected));
+                    },
+                    Ok(Err(error)) => return Err(error),
+                    Err(_) => return Err(IrcError::TimedOut),
+                };
+                match line.command.as_str() {
+                    "PING" => self.write(&[pong(&line)]).await?,
+                    "NICK" => {
+                        let old = line
+                            .nick()
+                            .and_then(|nick| IrcName::new(nick).ok());
+                        let new = line
+                            .param(0)
+                            .and_then(|nick| IrcName::new(nick).ok());
+                        let mut state = self.lock_state();
+                        if let (Some(old), Some(new)) = (old, new) {
+                            if old == state.nick {
+                                state.nick = new;
+                            }
+                        }
+                    },
+                    "PRIVMSG" => {
+                        if let Some(message) = self.privmsg_to_domain(&line) {
+                            return Ok(Ok(domain::Update::Message(message)));
+                        }
+                    },
+                    _ => (),
+                }
+            }
+        })
+    }
+
+    /// Lines are consumed as they are read, so there is nothing to commit.
+    fn commit(&self) -> DynFuture<'_, Result<(), Self::Error>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Connecting registers with the server and joins the configured channels.
+impl Connector for IrcChannel {
+    type Receiver = Self;
+    type Error = IrcError;
+
+    fn connect(&self) -> DynFuture<'_, Result<Self::Receiver, Self::Error>> {
+        Box::pin(async move {
+            let connecting = async {
+                let (reader, mut writer) = io::split(self.open().await?);
+                let mut reader = Reader {
+                    lines: BufReader::new(reader),
+                    partial: Vec::new(),
+                };
+                let nick = self.register(&mut reader, &mut writer).await?;
+                let joins = self
+                    .config
+                    .channels
+                    .iter()
+                    .map(|channel| Line::new("JOIN", [channel.as_str()]))
+                    .collect::<Vec<_>>();
+                write(&mut writer, &joins).await?;
+                Ok::<_, IrcError>((reader, writer, nick))
+            };
+            let (reader, writer, nick) =
+                time::timeout(self.config.timeout, connecting)
+                    .await
+                    .map_err(|_| IrcError::TimedOut)??;
+            self.lock_state().nick = nick;
+            *self.writer.lock().await = Some(writer);
+            *self.reader.lock().await = Some(reader);
+            Ok(self.clone())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        fake::{FakeIrcServer, CHANNEL, NICK},
+        IrcChannel,
+        IrcName,
+        MAX_LINE_LEN,
+    };
+    use crate::{
+        app::{App, Stopped},
+        commands::replace::{ReplaceCommand, RequestParser},
+        domain::{self, MessageData, NewMessage, ReplyTarget},
+        handler::DefaultHandler,
+        middleware::retry::Backoff,
+        port::{Connector, Receiver, Sender},
+        shutdown::Shutdown,
+    };
+
+    fn name(name: &str) -> IrcName {
+        IrcName::new(name).unwrap()
+    }
+
+    async fn connected(server: &FakeIrcServer) -> IrcChannel {
+        IrcChannel::new(server.config()).connect().await.unwrap()
+    }
+
+    async fn receive_message(channel: &IrcChannel) -> super::Message {
+        match channel.receive().await.unwrap().unwrap() {
+            domain::Update::Message(message) => message,
+            update => panic!("expected a message, got {:?}", update),
+        }
+    }
+
+    #[tokio::test]
+    async fn registers_and_joins_channels() {
+        let server = FakeIrcServer::start().await;
+        connected(&server).await;
+
+        assert_eq!(server.wait_for("NICK", 1).await[0].params, [NICK]);
+        let user = &server.wait_for("USER", 1).await[0];
+        assert_eq!(user.params, [NICK, "0", "*", NICK]);
+        assert_eq!(server.wait_for("JOIN", 1).await[0].params, [CHANNEL]);
+    }
+
+    #[tokio::test]
+    async fn tries_another_nick_when_taken() {
+        let server = FakeIrcServer::start().await;
+        server.take_nick(NICK);
+        let channel = connected(&server).await;
+
+        let nicks = server.wait_for("NICK", 2).await;
+        assert_eq!(nicks[1].params, [format!("{}_", NICK)]);
+
+        server.say("alice", &format!("{}_", NICK), "hi");
+        let message = receive_message(&channel).await;
+        assert_eq!(message.data.chat_id, name("alice"));
+    }
+
+    #[tokio::test]
+    async fn answers_pings() {
+        let server = FakeIrcServer::start().await;
+        let channel = connected(&server).await;
+
+        server.send("PING :token");
+        server.say("alice", CHANNEL, "hi");
+        receive_message(&channel).await;
+        assert_eq!(server.wait_for("PONG", 1).await[0].params, ["token"]);
+    }
+
+    #[tokio::test]
+    async fn replies_to_the_previous_line_of_the_same_nick() {
+        let server = FakeIrcServer::start().await;
+        let channel = connected(&server).await;
+
+        server.say("Alice", CHANNEL, "hello world");
+        server.say("bob", CHANNEL, "hi");
+        server.say("alice", CHANNEL, "s/world/there/");
+        let first = receive_message(&channel).await;
+        let second = receive_message(&channel).await;
+        let third = receive_message(&channel).await;
+
+        assert_eq!(first.data.reply_target, ReplyTarget::NotReplying);
+        assert_eq!(second.data.reply_target, ReplyTarget::NotReplying);
+        assert_eq!(third.data.chat_id, name(CHANNEL));
+        assert_eq!(third.author.unwrap().id, Some(name("alice")));
+        match third.data.reply_target {
+            ReplyTarget::Message(target) => assert_eq!(target.id, first.id),
+            target => panic!("expected the first line, got {:?}", target),
+        }
+    }
+
+    #[tokio::test]
+    async fn splits_text_into_lines_within_the_limit() {
+        let server = FakeIrcServer::start().await;
+        let channel = connected(&server).await;
+        let long = "word ".repeat(200);
+        let message = NewMessage {
+            data: MessageData {
+                chat_id: name(CHANNEL),
+                thread_id: None,
+                content: format!("{}\n\nlast", long).into(),
+                reply_target: ReplyTarget::NotReplying,
+            },
+            keyboard: None,
+            attachment: None,
+        };
+        channel.send(&message).await.unwrap();
+
+        let lines = server.wait_for("PRIVMSG", 4).await;
+        let relay_prefix = format!(":{}!~{}@{} ", NICK, NICK, "h".repeat(63));
+        for line in &lines {
+            assert_eq!(line.params[0], CHANNEL);
+            let relayed = format!("{}{}\r\n", relay_prefix, line);
+            assert!(relayed.len() <= MAX_LINE_LEN, "{:?} is too long", line);
+        }
+        let texts: Vec<_> = lines.iter().map(|line| &line.params[1]).collect();
+        assert_eq!(texts.last().unwrap().as_str(), "last");
+        let joined = texts[..texts.len() - 1]
+            .iter()
+            .map(|text| text.as_str())
+            .collect::<Vec<_>>()
+            .join(" ");
+        assert_eq!(joined, long.trim_end());
+    }
+
+    #[tokio::test]
+    async fn app_corrects_the_previous_line_addressing_its_author() {
+        let server = FakeIrcServer::start().await;
+        let channel = IrcChannel::new(server.config());
+        let app = App::new(domain::Bot { handle: String::from(NICK) }).handler(
+            DefaultHandler {
+                request_parser: RequestParser::new(),
+                command: ReplaceCommand,
+                sender: channel.clone(),
+            },
+        );
+        let shutdown = Shutdown::new();
+        let running = tokio::spawn(app.run_supervised(
+            channel,
+            Backoff::default(),
+            shutdown.clone(),
+        ));
+
+        server.wait_for("JOIN", 1).await;
+        server.say("Alice", CHANNEL, "hello world");
+        server.say("Alice", CHANNEL, "s/world/there/");
+        let sent = server.wait_for("PRIVMSG", 1).await;
+        assert_eq!(sent[0].params, [CHANNEL, "Alice: hello there"]);
+
+        shutdown.trigger();
+        assert_eq!(running.await.unwrap().unwrap(), Stopped::Drained);
+    }
+}
@@ -0,0 +1,86 @@
+use crate::middleware::{rate_limit::RetryAfter, retry::Transient};
+use std::{error::Error, fmt, io, time::Duration};
+
+#[derive(Debug)]
+pub enum IrcError {
+    Io(io::Error),
+    Tls(tokio_native_tls::native_tls::Error),
+    /// The server closed the link, with the reason it gave if any.
+    Closed(Option<String>),
+    /// Every nickname tried during registration was taken or refused.
+    NickUnavailable(String),
+    NotConnected,
+    TimedOut,
+    /// IRC has no way to take back a line once sent.
+    CannotDelete,
+}
+
+impl From<io::Error> for IrcError {
+    fn from(cause: io::Error) -> Self {
+        Self::Io(cause)
+    }
+}
+
+impl From<tokio_native_tls::native_tls::Error> for IrcError {
+    fn from(cause: tokio_native_tls::native_tls::Error) -> Self {
+        Self::Tls(cause)
+    }
+}
+
+impl fmt::Display for IrcError {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(cause) => write!(fmtr, "{}", cause),
+            Self::Tls(cause) => write!(fmtr, "TLS error: {}", cause),
+            Self::Closed(Some(reason)) => {
+                write!(fmtr, "server closed the link: {}", reason)
+            },
+            Self::Closed(None) => write!(fmtr, "server closed the link"),
+            Self::NickUnavailable(nick) => {
+                write!(fmtr, "nickname {} is unavailable", nick)
+            },
+            Self::NotConnected => write!(fmtr, "not connected to the server"),
+            Self::TimedOut => write!(fmtr, "IRC server timed out"),
+            Self::CannotDelete => {
+                write!(fmtr, "IRC messages cannot be deleted")
+            },
+        }
+    }
+}
+
+impl Error for IrcError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(cause) => Some(cause),
+            Self::Tls(cause) => Some(cause),
+            _ => None,
+        }
+    }
+}
+
+/// IRC servers report flooding by closing the link, never with a delay.
+impl RetryAfter for IrcError {
+    fn retry_after(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Transient for IrcError {
+    fn is_transient(&self) -> bool {
+        match self {
+            Self::Tls(_) => false,
+            // A previous connection may still hold the nickname until it
+            // times out.
+            Self::NickUnavailable(_) => true,
+            Self::Io(_) | Self::Closed(_) | Self::NotConnected => true,
+            Self::TimedOut => true,
+            Self::CannotDelete => false,
+        }
+    }
+
+    fn may_have_succeeded(&self) -> bool {
+        // Whatever was written before the link broke may have been
+        // delivered.
+        matches!(self, Self::Io(_) | Self::Closed(_) | Self::TimedOut)
+    }
+}
@@ -0,0 +1,159 @@
+//! Local stand-in for an IRC server, registering clients, relaying scripted
+//! lines to them and recording the lines they send.
+
+use super::{line::Line, IrcConfig, IrcName};
+use crate::adapter::fake::{self, Recorder};
+use std::{collections::HashSet, net::SocketAddr, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc,
+};
+
+pub const SERVER: &str = "fake.irc";
+pub const NICK: &str = "bot";
+pub const CHANNEL: &str = "#rust";
+
+#[derive(Debug, Default)]
+struct State {
+    /// Lines received from clients, in order.
+    received: Vec<Line>,
+    /// Nicknames refused as already in use.
+    taken: HashSet<String>,
+    /// Lines to send to the last client connected.
+    client: Option<mpsc::UnboundedSender<String>>,
+}
+
+/// Serves a single client at a time, which it welcomes as soon as it has
+/// sent a free nickname and its user.
+#[derive(Debug, Clone)]
+pub struct FakeIrcServer {
+    /// Changed whenever a line is received.
+    recorder: Recorder<State>,
+    local_addr: SocketAddr,
+}
+
+impl FakeIrcServer {
+    pub async fn start() -> Self {
+        let (listener, local_addr) = fake::listen_async();
+        let server = Self { recorder: Recorder::default(), local_addr };
+
+        let accepting = server.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(accepting.clone().serve(stream));
+            }
+        });
+        server
+    }
+
+    /// Configuration of a client joining [`CHANNEL`] as [`NICK`].
+    pub fn config(&self) -> IrcConfig {
+        let nick = IrcName::new(NICK).expect("nick is valid");
+        IrcConfig {
+            port: self.local_addr.port(),
+            channels: vec![IrcName::new(CHANNEL).expect("channel is valid")],
+            timeout: Duration::from_secs(5),
+            ..IrcConfig::new(self.local_addr.ip().to_string(), nick)
+        }
+    }
+
+    /// Refuses the nickname from now on.
+    pub fn take_nick(&self, nick: &str) {
+        self.recorder
+            .with_state(|state| state.taken.insert(String::from(nick)));
+    }
+
+    /// Relays a line from a user to the client.
+    pub fn say(&self, nick: &str, target: &str, text: &str) {
+        let from = format!("{}!{}@user.host", nick, nick);
+        self.send(&format!(":{} PRIVMSG {} :{}", from, target, text));
+    }
+
+    /// Sends a raw line to the client.
+    pub fn send(&self, raw: &str) {
+        self.send_part(&format!("{}\r\n", raw));
+    }
+
+    /// Sends raw text to the client, which may stop amid a line.
+    pub fn send_part(&self, part: &str) {
+        self.recorder.with_state(|state| {
+            let client = state.client.as_ref().expect("a client connected");
+            let _ = client.send(String::from(part));
+        });
+    }
+
+    /// Waits until the client has sent the command `count` times, yielding
+    /// those lines.
+    pub async fn wait_for(&self, command: &str, count: usize) -> Vec<Line> {
+        let what = format!("{} {} line(s)", count, command);
+        self.recorder
+            .wait_for(what, count, |state| {
+                state
+                    .received
+                    .iter()
+                    .filter(|line| line.command == command)
+                    .cloned()
+                    .collect()
+            })
+            .await
+    }
+
+    async fn serve(self, stream: TcpStream) {
+        let (reader, mut writer) = stream.into_split();
+        let (client, mut outgoing) = mpsc::unbounded_channel::<String>();
+        self.recorder.with_state(|state| state.client = Some(client.clone()));
+        tokio::spawn(async move {
+            while let Some(raw) = outgoing.recv().await {
+                if writer.write_all(raw.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut nick = None;
+        let mut has_user = false;
+        let mut is_welcomed = false;
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(raw)) = lines.next_line().await {
+            let line = match Line::parse(&raw) {
+                Some(line) => line,
+                None => continue,
+            };
+            let param = line.param(0).map(String::from).unwrap_or_default();
+            let mut replies = Vec::new();
+            match line.command.as_str() {
+                "NICK"
+                    if self
+                        .recorder
+                        .with_state(|state| state.taken.contains(&param)) =>
+                {
+                    replies.push(format!(
+                        ":{} 433 * {} :Nickname is already in use",
+                        SERVER, param
+                    ));
+                },
+                "NICK" => nick = Some(param.clone()),
+                "USER" => has_user = true,
+                "JOIN" => {
+                    let nick = nick.as_deref().unwrap_or("*");
+                    replies.push(format!(
+                        ":{}!{}@bot.host JOIN {}",
+                        nick, nick, param
+                    ));
+                },
+                "PING" => replies
+                    .push(format!(":{} PONG {} :{}", SERVER, SERVER, param)),
+                _ => (),
+            }
+            if let (false, true, Some(nick)) = (is_welcomed, has_user, &nick) {
+                replies.push(format!(":{} 001 {} :Welcome", SERVER, nick));
+                is_welcomed = true;
+            }
+            for reply in replies {
+                let _ = client.send(format!("{}\r\n", reply));
+            }
+            self.recorder.update(|state| state.received.push(line));
+        }
+    }
+}
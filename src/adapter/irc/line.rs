@@ -0,0 +1,122 @@
+//! Lines of the IRC protocol, as described in RFC 1459 and RFC 2812.
+
+use std::fmt;
+
+/// A single protocol line, without its terminating CRLF.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Line {
+    /// Origin of the line, `nick!user@host` or a server name.
+    pub prefix: Option<String>,
+    pub command: String,
+    pub params: Vec<String>,
+}
+
+impl Line {
+    pub fn new<I, P>(command: &str, params: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<String>,
+    {
+        Self {
+            prefix: None,
+            command: String::from(command),
+            params: params.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Parses a received line, skipping IRCv3 message tags. Yields nothing
+    /// for blank lines.
+    pub fn parse(raw: &str) -> Option<Self> {
+        let mut rest = raw.trim_end_matches(['\r', '\n']);
+        if rest.starts_with('@') {
+            rest = rest.split_once(' ')?.1.trim_start_matches(' ');
+        }
+        let prefix = match rest.strip_prefix(':') {
+            Some(prefixed) => {
+                let (prefix, tail) = prefixed.split_once(' ')?;
+                rest = tail.trim_start_matches(' ');
+                Some(String::from(prefix))
+            },
+            None => None,
+        };
+
+        let (middle, trailing) = match rest.split_once(" :") {
+            Some((middle, trailing)) => (middle, Some(trailing)),
+            None => match rest.strip_prefix(':') {
+                Some(trailing) => ("", Some(trailing)),
+                None => (rest, None),
+            },
+        };
+        let mut words = middle.split(' ').filter(|word| !word.is_empty());
+        let command = words.next()?.to_ascii_uppercase();
+        let mut params: Vec<_> = words.map(String::from).collect();
+        params.extend(trailing.map(String::from));
+        Some(Self { prefix, command, params })
+    }
+
+    /// Nickname of the user the line comes from, if it comes from one.
+    pub fn nick(&self) -> Option<&str> {
+        let prefix = self.prefix.as_deref()?;
+        let (nick, _) = prefix.split_once('!')?;
+        Some(nick)
+    }
+
+    pub fn param(&self, index: usize) -> Option<&str> {
+        self.params.get(index).map(String::as_str)
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(prefix) = &self.prefix {
+            write!(fmtr, ":{} ", prefix)?;
+        }
+        write!(fmtr, "{}", self.command)?;
+        if let Some((last, middle)) = self.params.split_last() {
+            for param in middle {
+                write!(fmtr, " {}", param)?;
+            }
+            if last.is_empty() || last.contains(' ') || last.starts_with(':') {
+                write!(fmtr, " :{}", last)?;
+            } else {
+                write!(fmtr, " {}", last)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Line;
+
+    #[test]
+    fn parses_prefix_and_trailing_param() {
+        let line =
+            Line::parse(":alice!a@host PRIVMSG #rust :s/a/b/ now\r\n").unwrap();
+        assert_eq!(line.nick(), Some("alice"));
+        assert_eq!(line.command, "PRIVMSG");
+        assert_eq!(line.params, ["#rust", "s/a/b/ now"]);
+    }
+
+    #[test]
+    fn skips_tags() {
+        let line = Line::parse("@time=2020 PING :token").unwrap();
+        assert_eq!(line.prefix, None);
+        assert_eq!(line.command, "PING");
+        assert_eq!(line.params, ["token"]);
+    }
+
+    #[test]
+    fn formats_trailing_param_when_needed() {
+        assert_eq!(Line::new("NICK", ["bot"]).to_string(), "NICK bot");
+        assert_eq!(
+            Line::new("PRIVMSG", ["#rust", "hello world"]).to_string(),
+            "PRIVMSG #rust :hello world"
+        );
+        assert_eq!(
+            Line::new("PRIVMSG", ["#rust", ":)"]).to_string(),
+            "PRIVMSG #rust ::)"
+        );
+    }
+}
@@ -0,0 +1,822 @@
+//! Matrix client-server API. Matrix identifies events, rooms and users with
+//! strings, which are mapped to local integers so that they can serve as
+//! ids.
+
+mod client;
+mod error;
+#[cfg(test)]
+pub mod fake;
+mod html;
+
+use self::client::encode;
+pub use self::{
+    client::{MatrixClient, MatrixConfig},
+    error::MatrixError,
+};
+use crate::{
+    domain::{self, Author, ContentKind, MediaKind, MessageData, ReplyTarget},
+    future::DynFuture,
+    history::History,
+    middleware::{
+        rate_limit::{Quota, RateLimits},
+        split::{LengthUnit, PlatformLimits, TextLimit},
+    },
+    port::{Connector, Deleter, Receiver, Receiving, Sender},
+};
+use hyper::Method;
+use serde_json::{json, Value};
+use std::{
+    collections::{hash_map::RandomState, HashMap, VecDeque},
+    fmt,
+    hash::{BuildHasher, Hasher},
+    sync::{
+        self,
+        atomic::{AtomicU64, Ordering},
+        Arc,
+        PoisonError,
+    },
+    time::Duration,
+};
+use tokio::sync::Mutex;
+
+/// Matrix has no bot handles, commands are only ever addressed to the room.
+pub const HANDLE: &str = "matrix_bot";
+const POLL_TIMEOUT: Duration = Duration::from_secs(30);
+/// Only the position in the timeline matters on the first sync, past events
+/// are left unhandled.
+const INITIAL_SYNC_FILTER: &str = r#"{"room":{"timeline":{"limit":1}}}"#;
+const DEFAULT_REPLY_DEPTH: usize = 1;
+const HISTORY_CAPACITY: usize = 4096;
+const MAX_USERS: usize = 4096;
+
+/// Events are limited to 64 KiB, which leaves room for the formatted body
+/// and the reply fallbacks besides the text.
+pub const LIMITS: PlatformLimits = PlatformLimits {
+    text: TextLimit { max_len: 16384, unit: LengthUnit::Bytes },
+    caption: TextLimit { max_len: 16384, unit: LengthUnit::Bytes },
+};
+
+/// Synapse by default allows a burst of ten messages, then one every five
+/// seconds.
+pub const RATE_LIMITS: RateLimits = RateLimits {
+    per_chat: &[],
+    global: &[Quota { burst: 10, period: Duration::from_secs(50) }],
+};
+
+pub type Message = domain::Message<u64, u64, u64>;
+
+/// Local integer ids standing for Matrix ids, the oldest of which are
+/// forgotten beyond a capacity, if any.
+#[derive(Debug)]
+struct Interner {
+    capacity: Option<usize>,
+    next_id: u64,
+    ids: HashMap<String, u64>,
+    names: HashMap<u64, String>,
+    order: VecDeque<u64>,
+}
+
+impl Interner {
+    fn new(capacity: Option<usize>) -> Self {
+        Self {
+            capacity,
+            next_id: 0,
+            ids: HashMap::new(),
+            names: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn intern(&mut self, name: &str) -> u64 {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        self.next_id += 1;
+        self.ids.insert(String::from(name), self.next_id);
+        self.names.insert(self.next_id, String::from(name));
+        self.order.push_back(self.next_id);
+        let capacity = self.capacity.unwrap_or(usize::MAX);
+        while self.order.len() > capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                if let Some(name) = self.names.remove(&oldest) {
+                    self.ids.remove(&name);
+                }
+            }
+        }
+        self.next_id
+    }
+
+    fn name(&self, id: u64) -> Option<&str> {
+        self.names.get(&id).map(String::as_str)
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    /// Matrix id of the bot, learned when connecting.
+    user_id: Option<String>,
+    events: Interner,
+    /// Never forgotten, so that rooms named in the configuration or quiet
+    /// for long can still be sent to.
+    rooms: Interner,
+    users: Interner,
+    history: History<u64, u64, u64>,
+}
+
+#[derive(Debug, Default)]
+struct SyncQueue {
+    /// Token of the next sync, absent until the first one.
+    since: Option<String>,
+    /// Timeline events yet to be handled, with the id of their room.
+    pending: VecDeque<(String, Value)>,
+}
+
+/// Strips the quote of the replied message that clients put at the start of
+/// replies for those not supporting them.
+fn strip_reply_fallback(body: &str) -> &str {
+    let mut rest = body;
+    while rest.starts_with('>') {
+        match rest.split_once('\n') {
+            Some((_, tail)) => rest = tail,
+            None => return body,
+        }
+    }
+    if rest.len() == body.len() {
+        return body;
+    }
+    rest.strip_prefix('\n').unwrap_or(rest)
+}
+
+fn media_kind(msgtype: &str) -> Option<MediaKind> {
+    match msgtype {
+        "m.image" => Some(MediaKind::Photo),
+        "m.video" => Some(MediaKind::Video),
+        "m.file" => Some(MediaKind::Document),
+        "m.audio" => Some(MediaKind::Audio),
+        _ => None,
+    }
+}
+
+/// Room of the Matrix client-server API, or rather all rooms the account is
+/// in. Invites are only accepted if told to.
+#[derive(Clone)]
+pub struct MatrixChannel {
+    client: MatrixClient,
+    updates: Arc<Mutex<SyncQueue>>,
+    state: Arc<sync::Mutex<State>>,
+    /// Prefix of transaction ids, so that they do not clash with those of a
+    /// previous run.
+    txn_prefix: Arc<str>,
+    next_txn: Arc<AtomicU64>,
+    reply_depth: usize,
+    accept_invites: bool,
+}
+
+impl MatrixChannel {
+    pub fn new(client: MatrixClient) -> Self {
+        let state = State {
+            user_id: None,
+            events: Interner::new(Some(HISTORY_CAPACITY)),
+            rooms: Interner::new(None),
+            users: Interner::new(Some(MAX_USERS)),
+            history: History::new(HISTORY_CAPACITY),
+        };
+        let txn_prefix =
+            format!("{:016x}", RandomState::new().build_hasher().finish());
+        Self {
+            client,
+            updates: Arc::new(Mutex::new(SyncQueue::default())),
+            state: Arc::new(sync::Mutex::new(state)),
+            txn_prefix: Arc::from(txn_prefix),
+            next_txn: Arc::new(AtomicU64::new(0)),
+            reply_depth: DEFAULT_REPLY_DEPTH,
+            accept_invites: false,
+        }
+    }
+
+    /// Sets how many levels of replied messages are embedded in received
+    /// messages. Replied events not seen by this channel are fetched.
+    pub fn reply_depth(mut self, depth: usize) -> Self {
+        self.reply_depth = depth;
+        self
+    }
+
+    /// Sets whether the account joins any room it is invited to.
+    pub fn accept_invites(mut self, accept: bool) -> Self {
+        self.accept_invites = accept;
+        self
+    }
+
+    /// Chat id of a room, e.g. one known from the configuration.
+    pub fn room(&self, room_id: &str) -> u64 {
+        self.lock_state().rooms.intern(room_id)
+    }
+
+    async fn sync(
+        &self,
+        since: Option<&str>,
+    ) -> Result<(String, Vec<(String, Value)>), MatrixError> {
+        let (path, wait) = match since {
+            Some(since) => (
+                format!(
+                    "sync?since={}&timeout={}",
+                    encode(since),
+                    POLL_TIMEOUT.as_millis()
+                ),
+                POLL_TIMEOUT,
+            ),
+            None => (
+                format!(
+                    "sync?timeout=0&filter={}",
+                    encode(INITIAL_SYNC_FILTER)
+                ),
+                Duration::ZERO,
+            ),
+        };
+        let batch = self.client.call(Method::GET, &path, None, wait).await?;
+        let next_batch = batch["next_batch"]
+            .as_str()
+            .map(String::from)
+            .ok_or(MatrixError::MalformedResponse)?;
+
+        let invites = batch["rooms"]["invite"]
+            .as_object()
+            .filter(|_| self.accept_invites);
+        if let Some(invites) = invites {
+            for room_id in invites.keys() {
+                let path = format!("join/{}", encode(room_id));
+                let body = json!({});
+                self.client
+                    .call(Method::POST, &path, Some(&body), Duration::ZERO)
+                    .await?;
+            }
+        }
+        let mut events = Vec::new();
+        if let Some(rooms) = batch["rooms"]["join"].as_object() {
+            for (room_id, room) in rooms {
+                if let Some(timeline) = room["timeline"]["events"].as_array() {
+                    events.extend(
+                        timeline
+                            .iter()
+                            .map(|event| (room_id.clone(), event.clone())),
+                    );
+                }
+            }
+        }
+        Ok((next_batch, events))
+    }
+
+    /// Converts a timeline event, yielding nothing for events other than
+    /// text messages, including notices, which bots send.
+    async fn event_to_domain(
+        &self,
+        room_id: &str,
+        event: &Value,
+    ) -> Result<Option<Message>, MatrixError> {
+        let (message, replied_event_id) = match self.convert(room_id, event) {
+            Some(converted) => converted,
+            None => return Ok(None),
+        };
+        if let (ReplyTarget::MessageId(target_id), Some(event_id)) =
+            (&message.data.reply_target, replied_event_id)
+        {
+            let is_known = self
+                .lock_state()
+                .history
+                .get(message.data.chat_id, *target_id)
+                .is_some();
+            if !is_known {
+                self.fetch(room_id, &event_id).await;
+            }
+        }
+
+        let mut state = self.lock_state();
+        state.history.record(&message);
+        Ok(Some(state.history.resolve(message, self.reply_depth)))
+    }
+
+    /// Fetches a replied event into the history. Events the bot may not see
+    /// are left out, the reply then targets an unknown message.
+    async fn fetch(&self, room_id: &str, event_id: &str) {
+        let path =
+            format!("rooms/{}/event/{}", encode(room_id), encode(event_id));
+        if let Ok(event) =
+            self.client.call(Method::GET, &path, None, Duration::ZERO).await
+        {
+            if let Some((mut message, _)) = self.convert(room_id, &event) {
+                if message.data.reply_target != ReplyTarget::NotReplying {
+                    message.data.reply_target = ReplyTarget::Prunned;
+                }
+                self.lock_state().history.record(&message);
+            }
+        }
+    }
+
+    /// Converts a message event, yielding it along with the Matrix id of the
+    /// event it replies to.
+    fn convert(
+        &self,
+        room_id: &str,
+        event: &Value,
+    ) -> Option<(Message, Option<String>)> {
+        if event["type"] != "m.room.message" {
+            return None;
+        }
+        let content = &event["content"];
+        let sender = event["sender"].as_str()?;
+        let event_id = event["event_id"].as_str()?;
+        let msgtype = content["msgtype"].as_str()?;
+        let body = content["body"].as_str()?;
+        let content_kind = match (msgtype, media_kind(msgtype)) {
+            ("m.text", _) => ContentKind::Text,
+            // Media bodies are captions only when they differ from the file
+            // name.
+            (_, Some(media_kind))
+                if content["filename"]
+                    .as_str()
+                    .is_some_and(|name| name != body) =>
+            {
+                ContentKind::Caption(media_kind)
+            },
+            _ => return None,
+        };
+
+        let relation = &content["m.relates_to"];
+        let thread_root = match relation["rel_type"].as_str() {
+            Some("m.thread") => relation["event_id"].as_str(),
+            _ => None,
+        };
+        // Thread messages which are not replies point to the latest event of
+        // the thread for clients without threads, and say so.
+        let replied_event_id = match relation["is_falling_back"].as_bool() {
+            Some(true) => None,
+            _ => relation["m.in_reply_to"]["event_id"].as_str(),
+        };
+        let text = match replied_event_id {
+            Some(_) => strip_reply_fallback(body),
+            None => body,
+        };
+
+        let mut state = self.lock_state();
+        if state.user_id.as_deref() == Some(sender) {
+            return None;
+        }
+        let reply_target = match replied_event_id {
+            Some(replied) => {
+                ReplyTarget::MessageId(state.events.intern(replied))
+            },
+            None => ReplyTarget::NotReplying,
+        };
+        let message = Message {
+            id: state.events.intern(event_id),
+            author: Some(Author {
+                id: Some(state.users.intern(sender)),
+                display_name: String::from(sender),
+                username: Some(String::from(sender)),
+                is_bot: false,
+            }),
+            content_kind,
+            data: MessageData {
+                chat_id: state.rooms.intern(room_id),
+                thread_id: thread_root.map(|root| state.events.intern(root)),
+                content: String::from(text).into(),
+                reply_target,
+            },
+        };
+        Some((message, replied_event_id.map(String::from)))
+    }
+
+    /// Builds the content of a message event, with the reply relation and
+    /// the fallbacks quoting the replied message.
+    fn message_content(
+        &self,
+        message: &domain::NewMessage<u64, u64, u64>,
+    ) -> Result<(String, Value), MatrixError> {
+        let state = self.lock_state();
+        let data = &message.data;
+        let room_id = state
+            .rooms
+            .name(data.chat_id)
+            .ok_or(MatrixError::UnknownId(data.chat_id))?;
+        let mention = |user_id| state.users.name(user_id).map(String::from);
+        let mut body = data.content.text.clone();
+        let mut formatted_body = html::render(&data.content, mention);
+
+        let (target_id, target) = match &data.reply_target {
+            ReplyTarget::Message(target) => (Some(target.id), Some(&**target)),
+            ReplyTarget::MessageId(target_id) => {
+                (Some(*target_id), state.history.get(data.chat_id, *target_id))
+            },
+            _ => (None, None),
+        };
+        let replied_event_id =
+            target_id.and_then(|target_id| state.events.name(target_id));
+        let sender = target
+            .and_then(|target| target.author.as_ref())
+            .and_then(|author| author.id)
+            .and_then(|user_id| state.users.name(user_id));
+        if let (Some(event_id), Some(target), Some(sender)) =
+            (replied_event_id, target, sender)
+        {
+            let mut quote = String::new();
+            for (index, line) in target.data.content.text.lines().enumerate() {
+                match index {
+                    0 => quote.push_str(&format!("> <{}> {}\n", sender, line)),
+                    _ => quote.push_str(&format!("> {}\n", line)),
+                }
+            }
+            body = format!("{}\n{}", quote, body);
+            formatted_body = format!(
+                "<mx-reply><blockquote><a href=\"{}\">In reply to</a> <a \
+                 href=\"{}\">{}</a><br />{}</blockquote></mx-reply>{}",
+                html::permalink(&format!("{}/{}", room_id, event_id)),
+                html::permalink(sender),
+                html::escape(sender),
+                html::render(&target.data.content, mention),
+                formatted_body
+            );
+        }
+
+        let mut content = json!({
+            "msgtype": "m.notice",
+            "body": body,
+            "format": html::FORMAT,
+            "formatted_body": formatted_body,
+        });
+        let thread_root =
+            data.thread_id.and_then(|thread_id| state.events.name(thread_id));
+        match (thread_root, replied_event_id) {
+            (Some(root), replied) => {
+                content["m.relates_to"] = json!({
+                    "rel_type": "m.thread",
+                    "event_id": root,
+                    "is_falling_back": replied.is_none(),
+                    "m.in_reply_to": { "event_id": replied.unwrap_or(root) },
+                });
+            },
+            (None, Some(replied)) => {
+                content["m.relates_to"] =
+                    json!({ "m.in_reply_to": { "event_id": replied } });
+            },
+            (None, None) => (),
+        }
+        Ok((String::from(room_id), content))
+    }
+
+    /// Transaction id of a new event, which the homeserver uses to tell
+    /// retries apart from new events.
+    fn next_txn_id(&self) -> String {
+        format!(
+            "{}-{}",
+            self.txn_prefix,
+            self.next_txn.fetch_add(1, Ordering::Relaxed)
+        )
+    }
+
+    fn lock_state(&self) -> sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl fmt::Debug for MatrixChannel {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("MatrixChannel")
+            .field("client", &self.client)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Messages are sent as notices, which bots are expected to ignore.
+/// Keyboards have no Matrix counterpart and are left out.
+impl Sender for MatrixChannel {
+    type MessageId = u64;
+    type ChatId = u64;
+    type UserId = u64;
+    type Error = MatrixError;
+
+    fn send<'fut>(
+        &'fut self,
+        message: &'fut domain::NewMessage<
+            Self::MessageId,
+            Self::ChatId,
+            Self::UserId,
+        >,
+    ) -> DynFuture<'fut, Result<Self::MessageId, Self::Error>> {
+        Box::pin(async move {
+            let (room_id, mut content) = self.message_content(message)?;
+            if let Some(attachment) = &message.attachment {
+                let url = self
+                    .client
+                    .upload(
+                        &attachment.file_name,
+                        &attachment.mime_type,
+                        &attachment.bytes,
+                    )
+                    .await?;
+                content["msgtype"] = Value::from("m.file");
+                content["filename"] = Value::from(attachment.file_name.clone());
+                content["url"] = Value::from(url);
+                content["info"] = json!({
+                    "mimetype": attachment.mime_type,
+                    "size": attachment.bytes.len(),
+                });
+            }
+
+            let path = format!(
+                "rooms/{}/send/m.room.message/{}",
+                encode(&room_id),
+                encode(&self.next_txn_id())
+            );
+            let sent = self
+                .client
+                .call(Method::PUT, &path, Some(&content), Duration::ZERO)
+                .await?;
+            let event_id = sent["event_id"]
+                .as_str()
+                .ok_or(MatrixError::MalformedResponse)?;
+
+            let mut state = self.lock_state();
+            let sent = Message {
+                id: state.events.intern(event_id),
+                author: Some(Author {
+                    id: state
+                        .user_id
+                        .clone()
+                        .map(|user_id| state.users.intern(&user_id)),
+                    display_name: state.user_id.clone().unwrap_or_default(),
+                    username: state.user_id.clone(),
+                    is_bot: true,
+                }),
+                content_kind: ContentKind::Text,
+                data: message.data.clone(),
+            };
+            state.history.record(&sent);
+            Ok(sent.id)
+        })
+    }
+}
+
+/// Deleting redacts the event, leaving an empty one in its place.
+impl Deleter for MatrixChannel {
+    fn can_delete(&self, _chat_id: Self::ChatId) -> bool {
+        true
+    }
+
+    fn delete(
+        &self,
+        chat_id: Self::ChatId,
+        message_id: Self::MessageId,
+    ) -> DynFuture<'_, Result<(), Self::Error>> {
+        Box::pin(async move {
+            let (room_id, event_id) = {
+                let state = self.lock_state();
+                let room_id = state
+                    .rooms
+                    .name(chat_id)
+                    .ok_or(MatrixError::UnknownId(chat_id))?;
+                let event_id = state
+                    .events
+                    .name(message_id)
+                    .ok_or(MatrixError::UnknownId(message_id))?;
+                (String::from(room_id), String::from(event_id))
+            };
+            let path = format!(
+                "rooms/{}/redact/{}/{}",
+                encode(&room_id),
+                encode(&event_id),
+                encode(&self.next_txn_id())
+            );
+            self.client
+                .call(Method::PUT, &path, Some(&json!({})), Duration::ZERO)
+                .await?;
+            Ok(())
+        })
+    }
+}
+
+impl Receiver for MatrixChannel {
+    type MessageId = u64;
+    type ChatId = u64;
+    type UserId = u64;
+    type Error = MatrixError;
+
+    fn receive<'fut>(
+        &'fut self,
+    ) -> Receiving<'fut, Self::MessageId, Self::ChatId, Self::UserId, Self::Error>
+    {
+        Box::pin(async move {
+            let mut updates = self.updates.lock().await;
+            loop {
+                // Only taken once converted, so that an interrupted receive
+                // leaves it for the next one.
+                let (room_id, event) = match updates.pending.front() {
+                    Some(pending) => pending.clone(),
+                    None => {
+                        let (next_batch, events) =
+                            self.sync(updates.since.as_deref()).await?;
+                        // Events of the first sync are past ones.
+                        if updates.since.is_some() {
+                            updates.pending.extend(events);
+                        }
+                        updates.since = Some(next_batch);
+                        continue;
+                    },
+                };
+                let message = self.event_to_domain(&room_id, &event).await;
+                updates.pending.pop_front();
+                if let Some(message) = message? {
+                    break Ok(Ok(domain::Update::Message(message)));
+                }
+            }
+        })
+    }
+
+    /// The sync token lives in memory only, so there is nothing to commit.
+    fn commit(&self) -> DynFuture<'_, Result<(), Self::Error>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Connecting checks the access token, learning which user it belongs to.
+impl Connector for MatrixChannel {
+    type Receiver = Self;
+    type Error = MatrixError;
+
+    fn connect(&self) -> DynFuture<'_, Result<Self::Receiver, Self::Error>> {
+        Box::pin(async move {
+            let whoami = self
+                .client
+                .call(Method::GET, "account/whoami", None, Duration::ZERO)
+                .await?;
+            let user_id = whoami["user_id"]
+                .as_str()
+                .ok_or(MatrixError::MalformedResponse)?;
+            self.lock_state().user_id = Some(String::from(user_id));
+            Ok(self.clone())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        fake::{FakeHomeserver, ROOM, USER},
+        strip_reply_fallback,
+        MatrixChannel,
+        MatrixClient,
+        HANDLE,
+        MAX_USERS,
+    };
+    use crate::{
+        app::{App, Stopped},
+        commands::replace::{ReplaceCommand, RequestParser},
+        domain::{self, MessageData, ReplyTarget},
+        handler::DefaultHandler,
+        middleware::retry::Backoff,
+        port::{Connector, Deleter, Receiver, Sender},
+        shutdown::Shutdown,
+    };
+
+    fn channel(server: &FakeHomeserver) -> MatrixChannel {
+        MatrixChannel::new(MatrixClient::new(server.config()))
+    }
+
+    #[test]
+    fn strips_reply_fallbacks() {
+        assert_eq!(strip_reply_fallback("> <@a:b> hi\n> there\n\nyes"), "yes");
+        assert_eq!(strip_reply_fallback("> quoted"), "> quoted");
+        assert_eq!(strip_reply_fallback("no quote"), "no quote");
+    }
+
+    #[tokio::test]
+    async fn skips_past_events_and_fetches_replied_ones() {
+        let server = FakeHomeserver::start();
+        let old = server.push_message(ROOM, "hello world", None);
+        let channel = channel(&server).connect().await.unwrap();
+        let scripting = server.clone();
+        tokio::spawn(async move {
+            scripting.wait_for_calls("sync", 1).await;
+            scripting.push_message(ROOM, "s/world/there/", Some(&old));
+        });
+
+        let message = match channel.receive().await.unwrap().unwrap() {
+            domain::Update::Message(message) => message,
+            update => panic!("expected a message, got {:?}", update),
+        };
+        assert_eq!(message.data.content.text, "s/world/there/");
+        assert_eq!(message.author.unwrap().display_name, USER);
+        match message.data.reply_target {
+            ReplyTarget::Message(target) => {
+                assert_eq!(target.data.content.text, "hello world")
+            },
+            target => panic!("expected the old message, got {:?}", target),
+        }
+        assert_eq!(server.wait_for_calls("event", 1).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn joins_rooms_it_is_invited_to() {
+        let server = FakeHomeserver::start();
+        server.invite(ROOM);
+        let channel =
+            channel(&server).accept_invites(true).connect().await.unwrap();
+        tokio::spawn(async move { channel.receive().await });
+
+        let joins = server.wait_for_calls("join", 1).await;
+        assert_eq!(joins[0].path, ["join", ROOM]);
+    }
+
+    #[tokio::test]
+    async fn ignores_invites_unless_told_otherwise() {
+        let server = FakeHomeserver::start();
+        server.invite(ROOM);
+        let channel = channel(&server).connect().await.unwrap();
+        let scripting = server.clone();
+        tokio::spawn(async move {
+            scripting.wait_for_calls("sync", 1).await;
+            scripting.push_message(ROOM, "hello", None);
+        });
+
+        channel.receive().await.unwrap().unwrap();
+        assert!(server.wait_for_calls("join", 0).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn forgets_users_but_not_rooms() {
+        let server = FakeHomeserver::start();
+        let channel = channel(&server);
+        let room = channel.room(ROOM);
+        let mut state = channel.lock_state();
+        let first_user = state.users.intern(USER);
+        for index in 0..MAX_USERS {
+            state.users.intern(&format!("@user{}:localhost", index));
+        }
+        assert_eq!(state.users.name(first_user), None);
+        assert_eq!(state.rooms.name(room), Some(ROOM));
+    }
+
+    #[tokio::test]
+    async fn redacts_deleted_messages() {
+        let server = FakeHomeserver::start();
+        let channel = channel(&server);
+        let message = domain::NewMessage {
+            data: MessageData {
+                chat_id: channel.room(ROOM),
+                thread_id: None,
+                content: String::from("hello").into(),
+                reply_target: ReplyTarget::NotReplying,
+            },
+            keyboard: None,
+            attachment: None,
+        };
+        let sent_id = channel.send(&message).await.unwrap();
+        let sends = server.wait_for_calls("send", 1).await;
+        let event_id = sends[0].response["event_id"].as_str().unwrap();
+
+        channel.delete(message.data.chat_id, sent_id).await.unwrap();
+        let redactions = server.wait_for_calls("redact", 1).await;
+        assert_eq!(
+            redactions[0].path[..4],
+            ["rooms", ROOM, "redact", event_id]
+        );
+    }
+
+    #[tokio::test]
+    async fn app_replies_with_fallbacks() {
+        let server = FakeHomeserver::start();
+        let channel = channel(&server);
+        let app = App::new(domain::Bot { handle: String::from(HANDLE) })
+            .handler(DefaultHandler {
+                request_parser: RequestParser::new(),
+                command: ReplaceCommand,
+                sender: channel.clone(),
+            });
+        let shutdown = Shutdown::new();
+        let running = tokio::spawn(app.run_supervised(
+            channel,
+            Backoff::default(),
+            shutdown.clone(),
+        ));
+
+        server.wait_for_calls("sync", 1).await;
+        let original = server.push_message(ROOM, "hello world", None);
+        server.push_message(ROOM, "s/world/there/", Some(&original));
+        let sent = &server.wait_for_calls("send", 1).await[0].body;
+        assert_eq!(sent["msgtype"], "m.notice");
+        assert_eq!(
+            sent["body"],
+            format!("> <{}> hello world\n\nhello there", USER)
+        );
+        assert_eq!(sent["m.relates_to"]["m.in_reply_to"]["event_id"], original);
+        let formatted = sent["formatted_body"].as_str().unwrap();
+        assert!(
+            formatted.starts_with("<mx-reply><blockquote>"),
+            "{}",
+            formatted
+        );
+        assert!(formatted.ends_with("</mx-reply>hello there"), "{}", formatted);
+
+        shutdown.trigger();
+        assert_eq!(running.await.unwrap().unwrap(), Stopped::Drained);
+    }
+}
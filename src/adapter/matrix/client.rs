@@ -0,0 +1,181 @@
+use super::error::MatrixError;
+use hyper::{
+    client::HttpConnector,
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    Body,
+    Client,
+    Method,
+    Uri,
+};
+use hyper_tls::HttpsConnector;
+use serde_json::Value;
+use std::{fmt, sync::Arc, time::Duration};
+use tokio::time;
+
+const CLIENT_PATH: &str = "_matrix/client/v3/";
+const MEDIA_PATH: &str = "_matrix/media/v3/";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where and as whom the client-server API is reached.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MatrixConfig {
+    pub homeserver_url: Uri,
+    pub access_token: String,
+    /// How long a request may take, on top of the time the homeserver holds
+    /// it while long polling.
+    pub timeout: Duration,
+}
+
+impl MatrixConfig {
+    pub fn new(homeserver_url: Uri, access_token: String) -> Self {
+        Self { homeserver_url, access_token, timeout: DEFAULT_TIMEOUT }
+    }
+}
+
+impl fmt::Debug for MatrixConfig {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("MatrixConfig")
+            .field("homeserver_url", &self.homeserver_url)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Percent-encodes a path segment or query value.
+pub fn encode(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'_'
+            | b'.'
+            | b'~' => encoded.push(char::from(byte)),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Calls endpoints of the client-server API, decoding errors the way the
+/// specification describes them.
+#[derive(Clone)]
+pub struct MatrixClient {
+    http: Client<HttpsConnector<HttpConnector>>,
+    /// Homeserver URL, ending with a slash.
+    base_url: Arc<str>,
+    access_token: Arc<str>,
+    timeout: Duration,
+}
+
+impl MatrixClient {
+    pub fn new(config: MatrixConfig) -> Self {
+        let mut base_url = config.homeserver_url.to_string();
+        if !base_url.ends_with('/') {
+            base_url.push('/');
+        }
+        Self {
+            http: Client::builder().build(HttpsConnector::new()),
+            base_url: Arc::from(base_url),
+            access_token: Arc::from(config.access_token),
+            timeout: config.timeout,
+        }
+    }
+
+    /// Calls a client endpoint, given its path after the version and its
+    /// already encoded query, which the homeserver may hold for up to
+    /// `wait`.
+    pub async fn call(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+        wait: Duration,
+    ) -> Result<Value, MatrixError> {
+        let body = body
+            .map(|body| ("application/json", body.to_string().into_bytes()));
+        self.request(method, &format!("{}{}", CLIENT_PATH, path), body, wait)
+            .await
+    }
+
+    /// Uploads a file to the content repository, yielding its `mxc://` URI.
+    pub async fn upload(
+        &self,
+        file_name: &str,
+        mime_type: &str,
+        bytes: &[u8],
+    ) -> Result<String, MatrixError> {
+        let path =
+            format!("{}upload?filename={}", MEDIA_PATH, encode(file_name));
+        let body = Some((mime_type, bytes.to_vec()));
+        let response =
+            self.request(Method::POST, &path, body, Duration::ZERO).await?;
+        response["content_uri"]
+            .as_str()
+            .map(String::from)
+            .ok_or(MatrixError::MalformedResponse)
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<(&str, Vec<u8>)>,
+        wait: Duration,
+    ) -> Result<Value, MatrixError> {
+        let uri = format!("{}{}", self.base_url, path)
+            .parse::<Uri>()
+            .map_err(MatrixError::Url)?;
+        let builder = hyper::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(AUTHORIZATION, format!("Bearer {}", self.access_token));
+        let request = match body {
+            Some((content_type, body)) => builder
+                .header(CONTENT_TYPE, content_type)
+                .body(Body::from(body)),
+            None => builder.body(Body::empty()),
+        }
+        .expect("method, URL and headers are valid");
+
+        let responding = async {
+            let response =
+                self.http.request(request).await.map_err(MatrixError::Http)?;
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body())
+                .await
+                .map_err(MatrixError::Http)?;
+            Ok::<_, MatrixError>((status, body))
+        };
+        let (status, body) = time::timeout(self.timeout + wait, responding)
+            .await
+            .map_err(|_| MatrixError::TimedOut)??;
+
+        let value = serde_json::from_slice::<Value>(&body);
+        if status.is_success() {
+            return value.map_err(|_| MatrixError::MalformedResponse);
+        }
+        let value = value.unwrap_or(Value::Null);
+        Err(MatrixError::Api {
+            status: status.as_u16(),
+            errcode: value["errcode"].as_str().map(String::from),
+            error: value["error"]
+                .as_str()
+                .map(String::from)
+                .unwrap_or_else(|| status.to_string()),
+            retry_after: value["retry_after_ms"]
+                .as_u64()
+                .map(Duration::from_millis),
+        })
+    }
+}
+
+impl fmt::Debug for MatrixClient {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("MatrixClient")
+            .field("base_url", &self.base_url)
+            .finish_non_exhaustive()
+    }
+}
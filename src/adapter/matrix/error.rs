@@ -0,0 +1,87 @@
+use crate::middleware::{rate_limit::RetryAfter, retry::Transient};
+use hyper::http::uri::InvalidUri;
+use std::{error::Error, fmt, time::Duration};
+
+#[derive(Debug)]
+pub enum MatrixError {
+    /// The endpoint URL built from the homeserver URL is invalid.
+    Url(InvalidUri),
+    Http(hyper::Error),
+    TimedOut,
+    /// Failure reported by the homeserver itself.
+    Api {
+        status: u16,
+        errcode: Option<String>,
+        error: String,
+        retry_after: Option<Duration>,
+    },
+    MalformedResponse,
+    /// A local id whose Matrix id was forgotten, or never known.
+    UnknownId(u64),
+}
+
+impl fmt::Display for MatrixError {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Url(cause) => {
+                write!(fmtr, "invalid homeserver URL: {}", cause)
+            },
+            Self::Http(cause) => write!(fmtr, "{}", cause),
+            Self::TimedOut => {
+                write!(fmtr, "request to the homeserver timed out")
+            },
+            Self::Api { status, errcode, error, .. } => {
+                match errcode {
+                    Some(errcode) => write!(fmtr, "{}: {}", errcode, error)?,
+                    None => write!(fmtr, "HTTP {}: {}", status, error)?,
+                }
+                Ok(())
+            },
+            Self::MalformedResponse => {
+                write!(fmtr, "malformed response from the homeserver")
+            },
+            Self::UnknownId(id) => {
+                write!(fmtr, "no Matrix id known for {}", id)
+            },
+        }
+    }
+}
+
+impl Error for MatrixError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Url(cause) => Some(cause),
+            Self::Http(cause) => Some(cause),
+            _ => None,
+        }
+    }
+}
+
+impl RetryAfter for MatrixError {
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Api { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl Transient for MatrixError {
+    fn is_transient(&self) -> bool {
+        match self {
+            Self::Api { status, retry_after, .. } => {
+                *status == 429 || *status >= 500 || retry_after.is_some()
+            },
+            Self::Http(_) | Self::TimedOut | Self::MalformedResponse => true,
+            Self::Url(_) | Self::UnknownId(_) => false,
+        }
+    }
+
+    fn may_have_succeeded(&self) -> bool {
+        match self {
+            Self::Http(cause) => !cause.is_connect(),
+            Self::TimedOut | Self::MalformedResponse => true,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! Local stand-in for a homeserver, serving a scripted timeline and
+//! recording the calls made to it.
+
+use super::client::MatrixConfig;
+use crate::adapter::fake::{self, Recorder};
+use hyper::{Body, Method, Request, Response, StatusCode, Uri};
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeSet, HashMap},
+    net::SocketAddr,
+    time::Duration,
+};
+use tokio::time;
+
+pub const ACCESS_TOKEN: &str = "fake_token";
+pub const BOT: &str = "@bot:fake";
+pub const USER: &str = "@alice:fake";
+pub const ROOM: &str = "!room:fake";
+
+/// Call made by the bot, named after the endpoint, with its body and the
+/// response it got.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    pub endpoint: String,
+    pub path: Vec<String>,
+    pub body: Value,
+    pub response: Value,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Events of every room, with their room, the sync token being their
+    /// count.
+    timeline: Vec<(String, Value)>,
+    events: HashMap<String, Value>,
+    /// Rooms the bot is invited to and has not joined yet.
+    invites: BTreeSet<String>,
+    calls: Vec<Call>,
+}
+
+impl State {
+    fn push_event(
+        &mut self,
+        room_id: &str,
+        sender: &str,
+        content: Value,
+    ) -> String {
+        let event_id = format!("${}", self.timeline.len() + 1);
+        let event = json!({
+            "type": "m.room.message",
+            "event_id": event_id,
+            "sender": sender,
+            "origin_server_ts": 0,
+            "content": content,
+        });
+        self.events.insert(event_id.clone(), event.clone());
+        self.timeline.push((String::from(room_id), event));
+        event_id
+    }
+
+    /// Empties the content of an event, yielding the id of the redaction.
+    fn redact(&mut self, event_id: &str) -> Option<String> {
+        let event = self.events.get_mut(event_id)?;
+        event["content"] = json!({});
+        Some(format!("{}-redaction", event_id))
+    }
+
+    fn sync_since(&self, since: usize) -> Value {
+        let mut join = json!({});
+        for (room_id, event) in self.timeline.iter().skip(since) {
+            let events = join[room_id]["timeline"]["events"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default();
+            join[room_id]["timeline"]["events"] =
+                Value::from([events, vec![event.clone()]].concat());
+        }
+        let invite = self
+            .invites
+            .iter()
+            .map(|room_id| (room_id.clone(), json!({})))
+            .collect::<serde_json::Map<_, _>>();
+        json!({
+            "next_batch": format!("s{}", self.timeline.len()),
+            "rooms": { "join": join, "invite": invite },
+        })
+    }
+}
+
+/// Decodes a percent-encoded path segment or query value.
+fn decode(input: &str) -> String {
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut rest = input.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (byte, escaped) {
+            (b'%', Some(escaped)) => {
+                bytes.push(escaped);
+                rest = &tail[2..];
+            },
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            },
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Serves `whoami`, `sync`, `join`, message sending and redacting, event
+/// fetching and media uploads for [`ACCESS_TOKEN`], the bot being [`BOT`].
+#[derive(Debug, Clone)]
+pub struct FakeHomeserver {
+    /// Changed whenever an event is pushed or a call is made.
+    recorder: Recorder<State>,
+    local_addr: SocketAddr,
+}
+
+impl FakeHomeserver {
+    pub fn start() -> Self {
+        let (listener, local_addr) = fake::listen();
+        let server = Self { recorder: Recorder::default(), local_addr };
+        let serving = server.clone();
+        fake::serve_http(listener, move |request| {
+            let server = serving.clone();
+            async move { server.serve(request).await }
+        });
+        server
+    }
+
+    /// Configuration of a client logged in as [`BOT`].
+    pub fn config(&self) -> MatrixConfig {
+        let homeserver_url = format!("http://{}", self.local_addr)
+            .parse::<Uri>()
+            .expect("socket address makes a valid URL");
+        MatrixConfig {
+            timeout: Duration::from_secs(5),
+            ..MatrixConfig::new(homeserver_url, String::from(ACCESS_TOKEN))
+        }
+    }
+
+    /// Scripts a text message from [`USER`], with the reply fallback clients
+    /// add, yielding its event id.
+    pub fn push_message(
+        &self,
+        room_id: &str,
+        text: &str,
+        reply_to: Option<&str>,
+    ) -> String {
+        self.recorder.update(|state| {
+            let mut content = json!({ "msgtype": "m.text", "body": text });
+            let replied = reply_to.and_then(|id| state.events.get(id));
+            if let Some(replied) = replied {
+                let quote = format!(
+                    "> <{}> {}\n\n",
+                    replied["sender"].as_str().unwrap_or_default(),
+                    replied["content"]["body"].as_str().unwrap_or_default()
+                );
+                content["body"] = Value::from(quote + text);
+                content["m.relates_to"] = json!({
+                    "m.in_reply_to": { "event_id": replied["event_id"] },
+                });
+            }
+            state.push_event(room_id, USER, content)
+        })
+    }
+
+    /// Invites the bot to a room.
+    pub fn invite(&self, room_id: &str) {
+        self.recorder
+            .update(|state| state.invites.insert(String::from(room_id)));
+    }
+
+    /// Waits until the bot has called the endpoint `count` times, yielding
+    /// those calls.
+    pub async fn wait_for_calls(
+        &self,
+        endpoint: &str,
+        count: usize,
+    ) -> Vec<Call> {
+        let what = format!("{} call(s) to {}", count, endpoint);
+        self.recorder
+            .wait_for(what, count, |state| {
+                state
+                    .calls
+                    .iter()
+                    .filter(|call| call.endpoint == endpoint)
+                    .cloned()
+                    .collect()
+            })
+            .await
+    }
+
+    async fn serve(&self, request: Request<Body>) -> Response<Body> {
+        let method = request.method().clone();
+        let path = request
+            .uri()
+            .path()
+            .split('/')
+            .skip(4)
+            .map(decode)
+            .collect::<Vec<_>>();
+        let query = request
+            .uri()
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(key, value)| (String::from(key), decode(value)))
+            .collect::<HashMap<_, _>>();
+        let is_authorized = request
+            .headers()
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            == Some(&format!("Bearer {}", ACCESS_TOKEN));
+        let bytes = hyper::body::to_bytes(request.into_body())
+            .await
+            .unwrap_or_default();
+        // Uploads are not JSON, their bodies are recorded as null.
+        let body =
+            serde_json::from_slice::<Value>(&bytes).unwrap_or(Value::Null);
+
+        let segments = path.iter().map(String::as_str).collect::<Vec<_>>();
+        let (endpoint, result) = match (&method, segments.as_slice()) {
+            _ if !is_authorized => (
+                "",
+                Err((StatusCode::UNAUTHORIZED, "M_UNKNOWN_TOKEN", "Bad token")),
+            ),
+            (&Method::GET, ["account", "whoami"]) => {
+                ("whoami", Ok(json!({ "user_id": BOT })))
+            },
+            (&Method::GET, ["sync"]) => ("sync", Ok(self.sync(&query).await)),
+            (&Method::POST, ["join", room_id]) => {
+                self.recorder
+                    .with_state(|state| state.invites.remove(*room_id));
+                ("join", Ok(json!({ "room_id": room_id })))
+            },
+            (&Method::PUT, ["rooms", room_id, "send", _, _]) => {
+                let event_id = self.recorder.with_state(|state| {
+                    state.push_event(room_id, BOT, body.clone())
+                });
+                ("send", Ok(json!({ "event_id": event_id })))
+            },
+            (&Method::PUT, ["rooms", _, "redact", event_id, _]) => (
+                "redact",
+                self.recorder
+                    .with_state(|state| state.redact(event_id))
+                    .map(|event_id| json!({ "event_id": event_id }))
+                    .ok_or((
+                        StatusCode::NOT_FOUND,
+                        "M_NOT_FOUND",
+                        "Event not found",
+                    )),
+            ),
+            (&Method::GET, ["rooms", _, "event", event_id]) => (
+                "event",
+                self.recorder
+                    .with_state(|state| state.events.get(*event_id).cloned())
+                    .ok_or((
+                        StatusCode::NOT_FOUND,
+                        "M_NOT_FOUND",
+                        "Event not found",
+                    )),
+            ),
+            (&Method::POST, ["upload"]) => {
+                ("upload", Ok(json!({ "content_uri": "mxc://fake/1" })))
+            },
+            _ => (
+                "",
+                Err((StatusCode::NOT_FOUND, "M_UNRECOGNIZED", "Unrecognized")),
+            ),
+        };
+        let (status, response) = match result {
+            Ok(response) => (StatusCode::OK, response),
+            Err((status, errcode, error)) => {
+                (status, json!({ "errcode": errcode, "error": error }))
+            },
+        };
+        self.recorder.update(|state| {
+            state.calls.push(Call {
+                endpoint: String::from(endpoint),
+                path,
+                body,
+                response: response.clone(),
+            })
+        });
+
+        let mut http_response = Response::new(Body::from(response.to_string()));
+        *http_response.status_mut() = status;
+        http_response
+    }
+
+    /// Long polls like a homeserver, holding the request until events come
+    /// after the token.
+    async fn sync(&self, query: &HashMap<String, String>) -> Value {
+        let since = query
+            .get("since")
+            .and_then(|since| since.strip_prefix('s')?.parse::<usize>().ok());
+        let timeout = Duration::from_millis(
+            query
+                .get("timeout")
+                .and_then(|timeout| timeout.parse().ok())
+                .unwrap_or(0),
+        );
+
+        let mut changes = self.recorder.subscribe();
+        let waiting = async {
+            loop {
+                let has_news = self.recorder.with_state(|state| {
+                    since.is_none_or(|since| state.timeline.len() > since)
+                        || !state.invites.is_empty()
+                });
+                if has_news || changes.changed().await.is_err() {
+                    break;
+                }
+            }
+        };
+        let _ = time::timeout(timeout, waiting).await;
+        self.recorder.with_state(|state| state.sync_since(since.unwrap_or(0)))
+    }
+}
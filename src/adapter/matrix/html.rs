@@ -0,0 +1,170 @@
+//! Formatted bodies, in the HTML subset Matrix clients render.
+
+use crate::domain::{Id, RichText, Span, Style};
+use std::cmp::Reverse;
+
+pub const FORMAT: &str = "org.matrix.custom.html";
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+/// Link to a user, room or event, as clients render it.
+pub fn permalink(id: &str) -> String {
+    format!("https://matrix.to/#/{}", escape(id))
+}
+
+fn open_tag<U, F>(style: &Style<U>, mention: &F) -> String
+where
+    U: Id,
+    F: Fn(U) -> Option<String>,
+{
+    match style {
+        Style::Bold => String::from("<strong>"),
+        Style::Italic => String::from("<em>"),
+        Style::Underline => String::from("<u>"),
+        Style::Strikethrough => String::from("<del>"),
+        Style::Spoiler => String::from("<span data-mx-spoiler>"),
+        Style::Code => String::from("<code>"),
+        Style::Pre { language: Some(language) } => {
+            format!("<pre><code class=\"language-{}\">", escape(language))
+        },
+        Style::Pre { language: None } => String::from("<pre><code>"),
+        Style::Link { url } => format!("<a href=\"{}\">", escape(url)),
+        Style::Mention { user_id } => match mention(*user_id) {
+            Some(user_id) => format!("<a href=\"{}\">", permalink(&user_id)),
+            None => String::from("<a>"),
+        },
+    }
+}
+
+fn close_tag<U>(style: &Style<U>) -> &'static str
+where
+    U: Id,
+{
+    match style {
+        Style::Bold => "</strong>",
+        Style::Italic => "</em>",
+        Style::Underline => "</u>",
+        Style::Strikethrough => "</del>",
+        Style::Spoiler => "</span>",
+        Style::Code => "</code>",
+        Style::Pre { .. } => "</code></pre>",
+        Style::Link { .. } | Style::Mention { .. } => "</a>",
+    }
+}
+
+/// Renders the text with its spans as tags, closing and reopening spans
+/// that overlap without nesting. Mentions link to the Matrix id given for
+/// the user.
+pub fn render<U, F>(content: &RichText<U>, mention: F) -> String
+where
+    U: Id,
+    F: Fn(U) -> Option<String>,
+{
+    let text = &content.text;
+    let mut boundaries: Vec<usize> = content
+        .spans
+        .iter()
+        .flat_map(|span| [span.start, span.end])
+        .filter(|&offset| offset <= text.len() && text.is_char_boundary(offset))
+        .collect();
+    boundaries.push(text.len());
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    let mut html = String::new();
+    let mut open: Vec<&Span<U>> = Vec::new();
+    let mut last = 0;
+    for boundary in boundaries {
+        let in_pre =
+            open.iter().any(|span| matches!(span.style, Style::Pre { .. }));
+        let chunk = escape(&text[last..boundary]);
+        if in_pre {
+            html.push_str(&chunk);
+        } else {
+            html.push_str(&chunk.replace('\n', "<br />"));
+        }
+        last = boundary;
+
+        if let Some(first_ending) =
+            open.iter().position(|span| span.end <= boundary)
+        {
+            for span in open[first_ending..].iter().rev() {
+                html.push_str(close_tag(&span.style));
+            }
+            let reopened: Vec<_> = open
+                .drain(first_ending..)
+                .filter(|span| span.end > boundary)
+                .collect();
+            for span in reopened {
+                html.push_str(&open_tag(&span.style, &mention));
+                open.push(span);
+            }
+        }
+
+        let mut starting: Vec<_> = content
+            .spans
+            .iter()
+            .filter(|span| span.start == boundary && span.end > boundary)
+            .collect();
+        starting.sort_by_key(|span| Reverse(span.end));
+        for span in starting {
+            html.push_str(&open_tag(&span.style, &mention));
+            open.push(span);
+        }
+    }
+    html
+}
+
+#[cfg(test)]
+mod test {
+    use super::render;
+    use crate::domain::{RichText, Span, Style};
+
+    fn span(start: usize, end: usize, style: Style<u64>) -> Span<u64> {
+        Span { start, end, style }
+    }
+
+    #[test]
+    fn escapes_text_and_breaks_lines() {
+        let content = RichText::<u64>::from(String::from("a < b\nc & d"));
+        assert_eq!(render(&content, |_| None), "a &lt; b<br />c &amp; d");
+    }
+
+    #[test]
+    fn nests_and_reopens_spans() {
+        let content = RichText {
+            text: String::from("bold both italic"),
+            spans: vec![span(0, 9, Style::Bold), span(5, 16, Style::Italic)],
+        };
+        assert_eq!(
+            render(&content, |_| None),
+            "<strong>bold <em>both</em></strong><em> italic</em>"
+        );
+    }
+
+    #[test]
+    fn links_mentions_to_users() {
+        let content = RichText {
+            text: String::from("hi alice"),
+            spans: vec![span(3, 8, Style::Mention { user_id: 7 })],
+        };
+        let html = render(&content, |_| Some(String::from("@alice:example")));
+        assert_eq!(
+            html,
+            "hi <a href=\"https://matrix.to/#/@alice:example\">alice</a>"
+        );
+    }
+}
@@ -0,0 +1,284 @@
+//! Platform living in memory, scripted by tests or typed into the console,
+//! with plain integers as message, chat and user ids.
+
+#[cfg(test)]
+pub mod conversation;
+
+use crate::{
+    domain::{
+        self,
+        Author,
+        Callback,
+        ContentKind,
+        MessageData,
+        ReplyTarget,
+        RichText,
+    },
+    future::DynFuture,
+    port::{Connector, Deleter, Disconnected, Receiver, Receiving, Sender},
+};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::{self, Arc, PoisonError},
+};
+use tokio::sync::{mpsc, Mutex};
+
+pub type Message = domain::Message<u64, u64, u64>;
+pub type NewMessage = domain::NewMessage<u64, u64, u64>;
+pub type Update = domain::Update<u64, u64, u64>;
+
+/// Message sent by the bot, with the id it was given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sent {
+    pub id: u64,
+    pub message: NewMessage,
+}
+
+impl Sent {
+    pub fn text(&self) -> &str {
+        &self.message.data.content.text
+    }
+
+    /// Message replied to, if any.
+    pub fn reply_to(&self) -> Option<u64> {
+        match &self.message.data.reply_target {
+            ReplyTarget::Message(message) => Some(message.id),
+            ReplyTarget::MessageId(message_id) => Some(*message_id),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    next_message_id: u64,
+    /// Every message sent so far, by users or the bot.
+    messages: HashMap<u64, Message>,
+    updates: mpsc::UnboundedSender<Update>,
+    sent: mpsc::UnboundedSender<Sent>,
+    deleted: mpsc::UnboundedSender<u64>,
+}
+
+impl State {
+    /// Stores a message under a new id, with the message it replies to
+    /// resolved when known.
+    fn record(
+        &mut self,
+        author: Author<u64>,
+        mut data: MessageData<u64, u64, u64>,
+    ) -> Message {
+        if let ReplyTarget::MessageId(message_id) = data.reply_target {
+            if let Some(replied) = self.messages.get(&message_id) {
+                data.reply_target =
+                    ReplyTarget::Message(Box::new(replied.clone()));
+            }
+        }
+        self.next_message_id += 1;
+        let message = Message {
+            id: self.next_message_id,
+            author: Some(author),
+            content_kind: ContentKind::Text,
+            data,
+        };
+        self.messages.insert(message.id, message.clone());
+        message
+    }
+}
+
+/// Channel both ends of which are in memory: updates are scripted with
+/// [`say`](Self::say) and [`press`](Self::press), and messages sent by the bot
+/// come out of [`next_sent`](Self::next_sent), the ids of those it deletes
+/// out of [`next_deleted`](Self::next_deleted).
+#[derive(Debug, Clone)]
+pub struct MemoryChannel {
+    state: Arc<sync::Mutex<State>>,
+    updates: Arc<Mutex<mpsc::UnboundedReceiver<Update>>>,
+    sent: Arc<Mutex<mpsc::UnboundedReceiver<Sent>>>,
+    deleted: Arc<Mutex<mpsc::UnboundedReceiver<u64>>>,
+}
+
+impl Default for MemoryChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryChannel {
+    pub fn new() -> Self {
+        let (update_sender, updates) = mpsc::unbounded_channel();
+        let (sent_sender, sent) = mpsc::unbounded_channel();
+        let (deleted_sender, deleted) = mpsc::unbounded_channel();
+        let state = State {
+            next_message_id: 0,
+            messages: HashMap::new(),
+            updates: update_sender,
+            sent: sent_sender,
+            deleted: deleted_sender,
+        };
+        Self {
+            state: Arc::new(sync::Mutex::new(state)),
+            updates: Arc::new(Mutex::new(updates)),
+            sent: Arc::new(Mutex::new(sent)),
+            deleted: Arc::new(Mutex::new(deleted)),
+        }
+    }
+
+    /// Scripts a text message from a user, yielding its id.
+    pub fn say(
+        &self,
+        user_id: u64,
+        chat_id: u64,
+        text: &str,
+        reply_to: Option<u64>,
+    ) -> u64 {
+        self.say_rich(user_id, chat_id, String::from(text).into(), reply_to)
+    }
+
+    /// Scripts a message from a user with styled text, yielding its id.
+    pub fn say_rich(
+        &self,
+        user_id: u64,
+        chat_id: u64,
+        content: RichText<u64>,
+        reply_to: Option<u64>,
+    ) -> u64 {
+        let author = Author {
+            id: Some(user_id),
+            display_name: format!("User {}", user_id),
+            username: None,
+            is_bot: false,
+        };
+        let data = MessageData {
+            chat_id,
+            thread_id: None,
+            content,
+            reply_target: reply_to
+                .map_or(ReplyTarget::NotReplying, ReplyTarget::MessageId),
+        };
+        let mut state = self.lock_state();
+        let message = state.record(author, data);
+        let message_id = message.id;
+        let _ = state.updates.send(Update::Message(message));
+        message_id
+    }
+
+    /// Scripts a press of a keyboard button under a message.
+    ///
+    /// # Panics
+    ///
+    /// If no message has the given id.
+    pub fn press(&self, message_id: u64, data: &str) {
+        let state = self.lock_state();
+        let message = state
+            .messages
+            .get(&message_id)
+            .unwrap_or_else(|| {
+                panic!("no message {} to press under", message_id)
+            })
+            .clone();
+        let callback = Callback { message, data: String::from(data) };
+        let _ = state.updates.send(Update::Callback(callback));
+    }
+
+    /// Waits for the next message sent by the bot.
+    pub async fn next_sent(&self) -> Sent {
+        self.sent
+            .lock()
+            .await
+            .recv()
+            .await
+            .expect("sending half is kept in the state")
+    }
+
+    /// Waits for the bot to delete a message, yielding its id.
+    pub async fn next_deleted(&self) -> u64 {
+        self.deleted
+            .lock()
+            .await
+            .recv()
+            .await
+            .expect("sending half is kept in the state")
+    }
+
+    fn lock_state(&self) -> sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Sender for MemoryChannel {
+    type MessageId = u64;
+    type ChatId = u64;
+    type UserId = u64;
+    type Error = Infallible;
+
+    fn send<'fut>(
+        &'fut self,
+        message: &'fut NewMessage,
+    ) -> DynFuture<'fut, Result<Self::MessageId, Self::Error>> {
+        Box::pin(async move {
+            let author = Author {
+                id: None,
+                display_name: String::from("Bot"),
+                username: None,
+                is_bot: true,
+            };
+            let mut state = self.lock_state();
+            let id = state.record(author, message.data.clone()).id;
+            let _ = state.sent.send(Sent { id, message: message.clone() });
+            Ok(id)
+        })
+    }
+}
+
+/// Deleting a message unknown to the channel does nothing.
+impl Deleter for MemoryChannel {
+    fn can_delete(&self, _chat_id: Self::ChatId) -> bool {
+        true
+    }
+
+    fn delete(
+        &self,
+        _chat_id: Self::ChatId,
+        message_id: Self::MessageId,
+    ) -> DynFuture<'_, Result<(), Self::Error>> {
+        Box::pin(async move {
+            let mut state = self.lock_state();
+            if state.messages.remove(&message_id).is_some() {
+                let _ = state.deleted.send(message_id);
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Receiver for MemoryChannel {
+    type MessageId = u64;
+    type ChatId = u64;
+    type UserId = u64;
+    type Error = Infallible;
+
+    fn receive<'fut>(
+        &'fut self,
+    ) -> Receiving<'fut, Self::MessageId, Self::ChatId, Self::UserId, Self::Error>
+    {
+        Box::pin(async move {
+            Ok(self.updates.lock().await.recv().await.ok_or(Disconnected))
+        })
+    }
+
+    /// Updates are consumed as they are received, so there is nothing to
+    /// commit.
+    fn commit(&self) -> DynFuture<'_, Result<(), Self::Error>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+impl Connector for MemoryChannel {
+    type Receiver = Self;
+    type Error = Infallible;
+
+    fn connect(&self) -> DynFuture<'_, Result<Self::Receiver, Self::Error>> {
+        Box::pin(async move { Ok(self.clone()) })
+    }
+}
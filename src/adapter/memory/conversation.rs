@@ -0,0 +1,148 @@
+//! Conversations with the bot, written as a script of what users say and what
+//! the bot is expected to answer:
+//!
+//! ```ignore
+//! let conversation = Conversation::start(build_app);
+//! let target = conversation.user(1).in_chat(5).says("hello world");
+//! conversation
+//!     .user(1)
+//!     .in_chat(5)
+//!     .replying_to(target)
+//!     .says("s/world/there/");
+//! assert_eq!(conversation.expect_reply(target).await.text(), "hello there");
+//! conversation.finish().await;
+//! ```
+
+use super::{MemoryChannel, Sent};
+use crate::{
+    app::{App, Stopped},
+    middleware::retry::Backoff,
+    shutdown::Shutdown,
+};
+use std::{convert::Infallible, error::Error, time::Duration};
+use tokio::{task::JoinHandle, time};
+
+/// How long the bot may take to answer before an expectation fails.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long the bot has to stay quiet for silence to be assumed.
+const SILENCE: Duration = Duration::from_millis(100);
+
+pub type MemoryApp<E = Infallible> = App<'static, u64, u64, u64, E>;
+
+/// App running against a [`MemoryChannel`].
+#[derive(Debug)]
+pub struct Conversation<E = Infallible> {
+    channel: MemoryChannel,
+    shutdown: Shutdown,
+    app: JoinHandle<Result<Stopped, E>>,
+}
+
+impl<E> Conversation<E>
+where
+    E: Error + Send + 'static,
+{
+    /// Runs the app built around the channel, which is both where its
+    /// handlers send and where it receives from.
+    pub fn start<F>(build: F) -> Self
+    where
+        F: FnOnce(MemoryChannel) -> MemoryApp<E>,
+    {
+        let channel = MemoryChannel::new();
+        let shutdown = Shutdown::new();
+        let app = tokio::spawn(build(channel.clone()).run_supervised(
+            channel.clone(),
+            Backoff::default(),
+            shutdown.clone(),
+        ));
+        Self { channel, shutdown, app }
+    }
+
+    /// Starts a turn of the user, in their private chat unless told
+    /// otherwise.
+    pub fn user(&self, user_id: u64) -> Turn<'_, E> {
+        Turn { conversation: self, user_id, chat_id: user_id, reply_to: None }
+    }
+
+    /// Presses a keyboard button under a message.
+    pub fn press(&self, message_id: u64, data: &str) {
+        self.channel.press(message_id, data);
+    }
+
+    /// Expects the bot to send a message, yielding it.
+    pub async fn expect_sent(&self) -> Sent {
+        time::timeout(REPLY_TIMEOUT, self.channel.next_sent())
+            .await
+            .unwrap_or_else(|_| panic!("bot did not send anything"))
+    }
+
+    /// Expects the next message of the bot to reply to the given message,
+    /// yielding it.
+    pub async fn expect_reply(&self, to: u64) -> Sent {
+        let sent = self.expect_sent().await;
+        assert_eq!(
+            sent.reply_to(),
+            Some(to),
+            "bot replied elsewhere: {:?}",
+            sent
+        );
+        sent
+    }
+
+    /// Expects the bot to delete the given message next.
+    pub async fn expect_deleted(&self, message_id: u64) {
+        let deleted = time::timeout(REPLY_TIMEOUT, self.channel.next_deleted())
+            .await
+            .unwrap_or_else(|_| panic!("bot did not delete anything"));
+        assert_eq!(deleted, message_id, "bot deleted another message");
+    }
+
+    /// Expects the bot to say nothing more for a while.
+    pub async fn expect_silence(&self) {
+        if let Ok(sent) = time::timeout(SILENCE, self.channel.next_sent()).await
+        {
+            panic!("bot unexpectedly said {:?}", sent);
+        }
+    }
+
+    /// Shuts the app down, expecting every update to have been handled.
+    pub async fn finish(self) {
+        self.shutdown.trigger();
+        let stopped = self.app.await.expect("app did not panic");
+        assert!(
+            matches!(stopped, Ok(Stopped::Drained)),
+            "app did not drain: {:?}",
+            stopped
+        );
+    }
+}
+
+/// What a user says next, and where.
+#[derive(Debug)]
+pub struct Turn<'conversation, E> {
+    conversation: &'conversation Conversation<E>,
+    user_id: u64,
+    chat_id: u64,
+    reply_to: Option<u64>,
+}
+
+impl<'conversation, E> Turn<'conversation, E> {
+    pub fn in_chat(mut self, chat_id: u64) -> Self {
+        self.chat_id = chat_id;
+        self
+    }
+
+    pub fn replying_to(mut self, message_id: u64) -> Self {
+        self.reply_to = Some(message_id);
+        self
+    }
+
+    /// Sends the message, yielding its id.
+    pub fn says(self, text: &str) -> u64 {
+        self.conversation.channel.say(
+            self.user_id,
+            self.chat_id,
+            text,
+            self.reply_to,
+        )
+    }
+}
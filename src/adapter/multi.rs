@@ -0,0 +1,1081 @@
+//! Several accounts, of any platforms, behind a single channel whose ids are
+//! tagged with the account they belong to.
+
+use crate::{
+    app::{Bots, ConnectionEvent},
+    domain::{
+        Author,
+        Bot,
+        Callback,
+        Id,
+        Message,
+        MessageData,
+        NewMessage,
+        ReplyTarget,
+        RichText,
+        Span,
+        Style,
+        Update,
+    },
+    future::DynFuture,
+    middleware::retry::Backoff,
+    port::{Connector, Deleter, Disconnected, Receiver, Receiving, Sender},
+};
+use futures::future;
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    error::Error,
+    fmt,
+    hash::Hash,
+    sync::{self, Arc, PoisonError},
+    task::Poll,
+};
+use tokio::{sync::Mutex, time};
+
+/// Ids remembered per account and kind, the least recently used being
+/// forgotten first.
+const ID_CAPACITY: usize = 100_000;
+
+/// Message, chat or user id of one of the accounts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MultiId {
+    /// Index of the account, in the order accounts were added.
+    account: usize,
+    local: u64,
+}
+
+impl fmt::Display for MultiId {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmtr, "{}:{}", self.account, self.local)
+    }
+}
+
+impl Id for MultiId {}
+
+pub type MultiNewMessage = NewMessage<MultiId, MultiId, MultiId>;
+pub type MultiUpdate = Update<MultiId, MultiId, MultiId>;
+
+#[derive(Debug)]
+pub enum MultiError {
+    /// The id belongs to no account, or was forgotten since.
+    UnknownId(MultiId),
+    Account {
+        name: Arc<str>,
+        cause: Box<dyn Error + Send + Sync>,
+    },
+}
+
+impl fmt::Display for MultiError {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownId(id) => write!(fmtr, "unknown id {}", id),
+            Self::Account { name, cause } => {
+                write!(fmtr, "error in account {}: {}", name, cause)
+            },
+        }
+    }
+}
+
+impl Error for MultiError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::UnknownId(_) => None,
+            Self::Account { cause, .. } => Some(&**cause),
+        }
+    }
+}
+
+/// Two-way mapping between ids of a platform and local numbers, forgetting
+/// the least recently used ones.
+#[derive(Debug)]
+struct IdTable<T> {
+    capacity: usize,
+    next: u64,
+    locals: HashMap<T, u64>,
+    /// Native ids by local number, with when they were last used.
+    natives: HashMap<u64, (T, u64)>,
+    /// Local numbers by when they were last used.
+    order: BTreeMap<u64, u64>,
+    uses: u64,
+}
+
+impl<T> IdTable<T>
+where
+    T: Copy + Eq + Hash,
+{
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            next: 0,
+            locals: HashMap::new(),
+            natives: HashMap::new(),
+            order: BTreeMap::new(),
+            uses: 0,
+        }
+    }
+
+    fn local(&mut self, native: T) -> u64 {
+        self.uses += 1;
+        if let Some(&local) = self.locals.get(&native) {
+            if let Some((_, used)) = self.natives.get_mut(&local) {
+                self.order.remove(used);
+                *used = self.uses;
+            }
+            self.order.insert(self.uses, local);
+            return local;
+        }
+        if self.order.len() >= self.capacity {
+            if let Some((_, oldest)) = self.order.pop_first() {
+                if let Some((forgotten, _)) = self.natives.remove(&oldest) {
+                    self.locals.remove(&forgotten);
+                }
+            }
+        }
+        self.next += 1;
+        self.locals.insert(native, self.next);
+        self.natives.insert(self.next, (native, self.uses));
+        self.order.insert(self.uses, self.next);
+        self.next
+    }
+
+    fn native(&self, local: u64) -> Option<T> {
+        self.natives.get(&local).map(|&(native, _)| native)
+    }
+}
+
+#[derive(Debug)]
+struct Tables<M, C, U> {
+    account: usize,
+    messages: IdTable<M>,
+    chats: IdTable<C>,
+    users: IdTable<U>,
+}
+
+/// Conversion of every id found in messages, possibly failing.
+trait Mapping<M, C, U, M2, C2, U2> {
+    fn message(&mut self, id: M) -> Option<M2>;
+    fn chat(&mut self, id: C) -> Option<C2>;
+    fn user(&mut self, id: U) -> Option<U2>;
+}
+
+/// From the ids of the account to tagged ones.
+struct Tagging<'tables, M, C, U>(&'tables mut Tables<M, C, U>);
+
+impl<M, C, U> Mapping<M, C, U, MultiId, MultiId, MultiId>
+    for Tagging<'_, M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    fn message(&mut self, id: M) -> Option<MultiId> {
+        let local = self.0.messages.local(id);
+        Some(MultiId { account: self.0.account, local })
+    }
+
+    fn chat(&mut self, id: C) -> Option<MultiId> {
+        let local = self.0.chats.local(id);
+        Some(MultiId { account: self.0.account, local })
+    }
+
+    fn user(&mut self, id: U) -> Option<MultiId> {
+        let local = self.0.users.local(id);
+        Some(MultiId { account: self.0.account, local })
+    }
+}
+
+/// From tagged ids back to the ids of the account, remembering the first
+/// unknown one.
+struct Untagging<'tables, M, C, U> {
+    tables: &'tables Tables<M, C, U>,
+    unknown: Option<MultiId>,
+}
+
+impl<M, C, U> Untagging<'_, M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    fn lookup<T>(&mut self, id: MultiId, table: &IdTable<T>) -> Option<T>
+    where
+        T: Id,
+    {
+        let native = table
+            .native(id.local)
+            .filter(|_| id.account == self.tables.account);
+        if native.is_none() {
+            self.unknown.get_or_insert(id);
+        }
+        native
+    }
+}
+
+impl<M, C, U> Mapping<MultiId, MultiId, MultiId, M, C, U>
+    for Untagging<'_, M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    fn message(&mut self, id: MultiId) -> Option<M> {
+        let tables = self.tables;
+        self.lookup(id, &tables.messages)
+    }
+
+    fn chat(&mut self, id: MultiId) -> Option<C> {
+        let tables = self.tables;
+        self.lookup(id, &tables.chats)
+    }
+
+    fn user(&mut self, id: MultiId) -> Option<U> {
+        let tables = self.tables;
+        self.lookup(id, &tables.users)
+    }
+}
+
+/// Maps mentions in the text, stressing users that cannot be mentioned.
+fn map_text<U, U2, F>(text: &RichText<U>, mapping: &mut F) -> RichText<U2>
+where
+    U: Id,
+    U2: Id,
+    F: FnMut(U) -> Option<U2>,
+{
+    let spans = text
+        .spans
+        .iter()
+        .map(|span| {
+            let style = match &span.style {
+                Style::Mention { user_id } => match mapping(*user_id) {
+                    Some(user_id) => Style::Mention { user_id },
+                    None => Style::Bold,
+                },
+                Style::Bold => Style::Bold,
+                Style::Italic => Style::Italic,
+                Style::Underline => Style::Underline,
+                Style::Strikethrough => Style::Strikethrough,
+                Style::Spoiler => Style::Spoiler,
+                Style::Code => Style::Code,
+                Style::Pre { language } => {
+                    Style::Pre { language: language.clone() }
+                },
+                Style::Link { url } => Style::Link { url: url.clone() },
+            };
+            Span { start: span.start, end: span.end, style }
+        })
+        .collect();
+    RichText { text: text.text.clone(), spans }
+}
+
+/// Maps the data of a message, failing only when its chat or thread cannot
+/// be mapped. Replies to messages that cannot be mapped are left out.
+fn map_data<M, C, U, M2, C2, U2, P>(
+    data: &MessageData<M, C, U>,
+    mapping: &mut P,
+) -> Option<MessageData<M2, C2, U2>>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+    M2: Id,
+    C2: Id,
+    U2: Id,
+    P: Mapping<M, C, U, M2, C2, U2>,
+{
+    let chat_id = mapping.chat(data.chat_id)?;
+    let thread_id = match data.thread_id {
+        Some(thread_id) => Some(mapping.message(thread_id)?),
+        None => None,
+    };
+    let reply_target = match &data.reply_target {
+        ReplyTarget::Message(message) => match map_message(message, mapping) {
+            Some(message) => ReplyTarget::Message(Box::new(message)),
+            None => ReplyTarget::NotReplying,
+        },
+        ReplyTarget::MessageId(id) => match mapping.message(*id) {
+            Some(id) => ReplyTarget::MessageId(id),
+            None => ReplyTarget::NotReplying,
+        },
+        ReplyTarget::Prunned => ReplyTarget::Prunned,
+        ReplyTarget::NotReplying => ReplyTarget::NotReplying,
+    };
+    Some(MessageData {
+        chat_id,
+        thread_id,
+        content: map_text(&data.content, &mut |id| mapping.user(id)),
+        reply_target,
+    })
+}
+
+fn map_message<M, C, U, M2, C2, U2, P>(
+    message: &Message<M, C, U>,
+    mapping: &mut P,
+) -> Option<Message<M2, C2, U2>>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+    M2: Id,
+    C2: Id,
+    U2: Id,
+    P: Mapping<M, C, U, M2, C2, U2>,
+{
+    // Authors that cannot be mapped are only known by name.
+    let author = match &message.author {
+        Some(author) => Some(Author {
+            id: author.id.and_then(|id| mapping.user(id)),
+            display_name: author.display_name.clone(),
+            username: author.username.clone(),
+            is_bot: author.is_bot,
+        }),
+        None => None,
+    };
+    Some(Message {
+        id: mapping.message(message.id)?,
+        author,
+        content_kind: message.content_kind,
+        data: map_data(&message.data, mapping)?,
+    })
+}
+
+fn map_update<M, C, U, M2, C2, U2, P>(
+    update: &Update<M, C, U>,
+    mapping: &mut P,
+) -> Option<Update<M2, C2, U2>>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+    M2: Id,
+    C2: Id,
+    U2: Id,
+    P: Mapping<M, C, U, M2, C2, U2>,
+{
+    match update {
+        Update::Message(message) => {
+            map_message(message, mapping).map(Update::Message)
+        },
+        Update::Callback(callback) => Some(Update::Callback(Callback {
+            message: map_message(&callback.message, mapping)?,
+            data: callback.data.clone(),
+        })),
+    }
+}
+
+/// Tags ids of an account from outside, e.g. chats known from the
+/// configuration.
+#[derive(Debug, Clone)]
+pub struct Tags<M, C, U> {
+    tables: Arc<sync::Mutex<Tables<M, C, U>>>,
+}
+
+impl<M, C, U> Tags<M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    pub fn chat(&self, chat_id: C) -> MultiId {
+        let mut tables = lock(&self.tables);
+        let local = tables.chats.local(chat_id);
+        MultiId { account: tables.account, local }
+    }
+}
+
+fn lock<T>(mutex: &sync::Mutex<T>) -> sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Account with its ids erased, as the multiplexer sees it.
+trait Link: fmt::Debug + Send + Sync {
+    fn bot(&self) -> &Bot;
+
+    /// Receives the next update, connecting again as often as needed.
+    fn next(self: Arc<Self>) -> DynFuture<'static, MultiUpdate>;
+
+    fn commit(&self) -> DynFuture<'_, Result<(), MultiError>>;
+
+    fn send<'fut>(
+        &'fut self,
+        message: &'fut MultiNewMessage,
+    ) -> DynFuture<'fut, Result<MultiId, MultiError>>;
+
+    fn can_delete(&self, chat_id: MultiId) -> bool;
+
+    fn delete(
+        &self,
+        chat_id: MultiId,
+        message_id: MultiId,
+    ) -> DynFuture<'_, Result<(), MultiError>>;
+}
+
+struct Account<T, S>
+where
+    T: Connector,
+    S: Deleter,
+{
+    name: Arc<str>,
+    bot: Bot,
+    connector: T,
+    sender: S,
+    backoff: Backoff,
+    receiver: sync::Mutex<Option<Arc<T::Receiver>>>,
+    tags: Tags<S::MessageId, S::ChatId, S::UserId>,
+}
+
+impl<T, S> fmt::Debug for Account<T, S>
+where
+    T: Connector,
+    S: Deleter,
+{
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("Account")
+            .field("name", &self.name)
+            .field("bot", &self.bot)
+            .field("connector", &self.connector)
+            .field("sender", &self.sender)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T, S> Account<T, S>
+where
+    T: Connector,
+    S: Deleter,
+{
+    /// Untags the chat and message ids, which belong to this account.
+    fn untag(
+        &self,
+        chat_id: MultiId,
+        message_id: MultiId,
+    ) -> Result<(S::ChatId, S::MessageId), MultiError> {
+        let tables = lock(&self.tags.tables);
+        let mut untagging = Untagging { tables: &tables, unknown: None };
+        let chat =
+            untagging.chat(chat_id).ok_or(MultiError::UnknownId(chat_id));
+        let message = untagging
+            .message(message_id)
+            .ok_or(MultiError::UnknownId(message_id));
+        Ok((chat?, message?))
+    }
+
+    fn notify(&self, event: ConnectionEvent) {
+        eprintln!("Connection of {}: {}", self.name, event);
+    }
+
+    fn error<E>(&self, cause: E) -> MultiError
+    where
+        E: Error + Send + Sync + 'static,
+    {
+        MultiError::Account {
+            name: Arc::clone(&self.name),
+            cause: cause.into(),
+        }
+    }
+}
+
+impl<T, S> Link for Account<T, S>
+where
+    T: Connector + Send + Sync + 'static,
+    T::Error: Send + Sync + 'static,
+    T::Receiver: Receiver<
+            MessageId = S::MessageId,
+            ChatId = S::ChatId,
+            UserId = S::UserId,
+        > + Send
+        + Sync
+        + 'static,
+    <T::Receiver as Receiver>::Error: Send + Sync + 'static,
+    S: Deleter + Send + Sync + 'static,
+    S::MessageId: Send + Sync + 'static,
+    S::ChatId: Send + Sync + 'static,
+    S::UserId: Send + Sync + 'static,
+    S::Error: Send + Sync + 'static,
+{
+    fn bot(&self) -> &Bot {
+        &self.bot
+    }
+
+    fn next(self: Arc<Self>) -> DynFuture<'static, MultiUpdate> {
+        Box::pin(async move {
+            let mut failures = 0;
+            loop {
+                let connected = lock(&self.receiver).clone();
+                let receiver = match connected {
+                    Some(receiver) => receiver,
+                    None => {
+                        if failures > 0 {
+                            let delay = self.backoff.delay(failures - 1);
+                            self.notify(ConnectionEvent::Reconnecting {
+                                delay,
+                            });
+                            time::sleep(delay).await;
+                        }
+                        failures += 1;
+                        self.notify(ConnectionEvent::Connecting);
+                        match self.connector.connect().await {
+                            Ok(receiver) => {
+                                self.notify(ConnectionEvent::Connected);
+                                let receiver = Arc::new(receiver);
+                                *lock(&self.receiver) =
+                                    Some(Arc::clone(&receiver));
+                                receiver
+                            },
+                            Err(error) => {
+                                self.notify(ConnectionEvent::Failed(&error));
+                                continue;
+                            },
+                        }
+                    },
+                };
+
+                match receiver.receive().await {
+                    Ok(Ok(update)) => {
+                        let mut tables = lock(&self.tags.tables);
+                        return map_update(&update, &mut Tagging(&mut tables))
+                            .expect("tagging knows every id");
+                    },
+                    Ok(Err(Disconnected)) => {
+                        self.notify(ConnectionEvent::Disconnected)
+                    },
+                    Err(error) => self.notify(ConnectionEvent::Failed(&error)),
+                }
+                *lock(&self.receiver) = None;
+            }
+        })
+    }
+
+    fn commit(&self) -> DynFuture<'_, Result<(), MultiError>> {
+        Box::pin(async move {
+            let connected = lock(&self.receiver).clone();
+            match connected {
+                Some(receiver) => {
+                    receiver.commit().await.map_err(|error| self.error(error))
+                },
+                None => Ok(()),
+            }
+        })
+    }
+
+    fn send<'fut>(
+        &'fut self,
+        message: &'fut MultiNewMessage,
+    ) -> DynFuture<'fut, Result<MultiId, MultiError>> {
+        Box::pin(async move {
+            let native = {
+                let tables = lock(&self.tags.tables);
+                let mut untagging =
+                    Untagging { tables: &tables, unknown: None };
+                map_data(&message.data, &mut untagging)
+                    .map(|data| NewMessage {
+                        data,
+                        keyboard: message.keyboard.clone(),
+                        attachment: message.attachment.clone(),
+                    })
+                    .ok_or(untagging.unknown)
+            };
+            let native = native.map_err(|unknown| {
+                MultiError::UnknownId(unknown.unwrap_or(message.data.chat_id))
+            })?;
+            let sent = self
+                .sender
+                .send(&native)
+                .await
+                .map_err(|error| self.error(error))?;
+            let mut tables = lock(&self.tags.tables);
+            Ok(MultiId {
+                account: tables.account,
+                local: tables.messages.local(sent),
+            })
+        })
+    }
+
+    fn can_delete(&self, chat_id: MultiId) -> bool {
+        let tables = lock(&self.tags.tables);
+        let mut untagging = Untagging { tables: &tables, unknown: None };
+        untagging
+            .chat(chat_id)
+            .is_some_and(|chat_id| self.sender.can_delete(chat_id))
+    }
+
+    fn delete(
+        &self,
+        chat_id: MultiId,
+        message_id: MultiId,
+    ) -> DynFuture<'_, Result<(), MultiError>> {
+        Box::pin(async move {
+            let (chat_id, message_id) = self.untag(chat_id, message_id)?;
+            self.sender
+                .delete(chat_id, message_id)
+                .await
+                .map_err(|error| self.error(error))
+        })
+    }
+}
+
+/// Receiving of each account, kept across calls so that updates of one
+/// account do not interrupt the others.
+#[derive(Default)]
+struct Pending {
+    receiving: Vec<Option<DynFuture<'static, MultiUpdate>>>,
+    /// Account polled first, taking turns for fairness.
+    first: usize,
+}
+
+/// Channel over several accounts, receiving from all of them and sending
+/// each message through the account of its chat.
+///
+/// Each account connects again on its own when disconnected, the others
+/// going on meanwhile, so receiving never fails.
+#[derive(Clone)]
+pub struct Multiplexer {
+    accounts: Vec<Arc<dyn Link>>,
+    backoff: Backoff,
+    pending: Arc<Mutex<Pending>>,
+}
+
+impl fmt::Debug for Multiplexer {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("Multiplexer")
+            .field("accounts", &self.accounts)
+            .field("backoff", &self.backoff)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Multiplexer {
+    /// Creates a multiplexer without accounts, which reconnect with the
+    /// given backoff.
+    pub fn new(backoff: Backoff) -> Self {
+        Self {
+            accounts: Vec::new(),
+            backoff,
+            pending: Arc::new(Mutex::new(Pending::default())),
+        }
+    }
+
+    /// Adds an account, received from through the connector and sent to
+    /// through the sender, yielding tags of its ids.
+    ///
+    /// Accounts are to be added before the multiplexer is cloned.
+    pub fn add<T, S>(
+        &mut self,
+        name: &str,
+        bot: Bot,
+        connector: T,
+        sender: S,
+    ) -> Tags<S::MessageId, S::ChatId, S::UserId>
+    where
+        T: Connector + Send + Sync + 'static,
+        T::Error: Send + Sync + 'static,
+        T::Receiver: Receiver<
+                MessageId = S::MessageId,
+                ChatId = S::ChatId,
+                UserId = S::UserId,
+            > + Send
+            + Sync
+            + 'static,
+        <T::Receiver as Receiver>::Error: Send + Sync + 'static,
+        S: Deleter + Send + Sync + 'static,
+        S::MessageId: Send + Sync + 'static,
+        S::ChatId: Send + Sync + 'static,
+        S::UserId: Send + Sync + 'static,
+        S::Error: Send + Sync + 'static,
+    {
+        let tables = Tables {
+            account: self.accounts.len(),
+            messages: IdTable::new(ID_CAPACITY),
+            chats: IdTable::new(ID_CAPACITY),
+            users: IdTable::new(ID_CAPACITY),
+        };
+        let tags = Tags { tables: Arc::new(sync::Mutex::new(tables)) };
+        self.accounts.push(Arc::new(Account {
+            name: Arc::from(name),
+            bot,
+            connector,
+            sender,
+            backoff: self.backoff,
+            receiver: sync::Mutex::new(None),
+            tags: tags.clone(),
+        }));
+        tags
+    }
+}
+
+impl Bots<MultiId> for Multiplexer {
+    fn bot_in(&self, chat_id: MultiId) -> Option<&Bot> {
+        self.accounts.get(chat_id.account).map(|account| account.bot())
+    }
+}
+
+impl Sender for Multiplexer {
+    type MessageId = MultiId;
+    type ChatId = MultiId;
+    type UserId = MultiId;
+    type Error = MultiError;
+
+    fn send<'fut>(
+        &'fut self,
+        message: &'fut MultiNewMessage,
+    ) -> DynFuture<'fut, Result<Self::MessageId, Self::Error>> {
+        Box::pin(async move {
+            let chat_id = message.data.chat_id;
+            let account = self
+                .accounts
+                .get(chat_id.account)
+                .ok_or(MultiError::UnknownId(chat_id))?;
+            account.send(message).await
+        })
+    }
+}
+
+impl Deleter for Multiplexer {
+    fn can_delete(&self, chat_id: MultiId) -> bool {
+        self.accounts
+            .get(chat_id.account)
+            .is_some_and(|account| account.can_delete(chat_id))
+    }
+
+    fn delete(
+        &self,
+        chat_id: MultiId,
+        message_id: MultiId,
+    ) -> DynFuture<'_, Result<(), Self::Error>> {
+        Box::pin(async move {
+            let account = self
+                .accounts
+                .get(chat_id.account)
+                .ok_or(MultiError::UnknownId(chat_id))?;
+            account.delete(chat_id, message_id).await
+        })
+    }
+}
+
+impl Receiver for Multiplexer {
+    type MessageId = MultiId;
+    type ChatId = MultiId;
+    type UserId = MultiId;
+    type Error = MultiError;
+
+    fn receive<'fut>(
+        &'fut self,
+    ) -> Receiving<'fut, Self::MessageId, Self::ChatId, Self::UserId, Self::Error>
+    {
+        Box::pin(async move {
+            if self.accounts.is_empty() {
+                return Ok(Err(Disconnected));
+            }
+            let mut pending = self.pending.lock().await;
+            let pending = &mut *pending;
+            pending.receiving.resize_with(self.accounts.len(), || None);
+            let update = future::poll_fn(|context| {
+                let count = pending.receiving.len();
+                for offset in 0..count {
+                    let index = (pending.first + offset) % count;
+                    let slot = &mut pending.receiving[index];
+                    let receiving = slot.get_or_insert_with(|| {
+                        Arc::clone(&self.accounts[index]).next()
+                    });
+                    if let Poll::Ready(update) =
+                        receiving.as_mut().poll(context)
+                    {
+                        *slot = None;
+                        pending.first = (index + 1) % count;
+                        return Poll::Ready(update);
+                    }
+                }
+                Poll::Pending
+            })
+            .await;
+            Ok(Ok(update))
+        })
+    }
+
+    /// Stops receiving, then commits every account, so that nothing is
+    /// committed without being handled. Receivers keep whatever an
+    /// interrupted receive read for the next one, and leave it uncommitted.
+    fn commit(&self) -> DynFuture<'_, Result<(), Self::Error>> {
+        Box::pin(async move {
+            self.pending.lock().await.receiving.clear();
+            let commits = self.accounts.iter().map(|account| account.commit());
+            future::join_all(commits).await.into_iter().collect()
+        })
+    }
+}
+
+impl Connector for Multiplexer {
+    type Receiver = Self;
+    type Error = Infallible;
+
+    fn connect(&self) -> DynFuture<'_, Result<Self::Receiver, Self::Error>> {
+        Box::pin(async move { Ok(self.clone()) })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{IdTable, MultiError, MultiId, Multiplexer};
+    use crate::{
+        adapter::{
+            irc::{
+                fake::{FakeIrcServer, CHANNEL, NICK},
+                IrcChannel,
+            },
+            memory::MemoryChannel,
+        },
+        app::{App, Stopped},
+        commands::{
+            help::{HelpCommand, HelpRequestParser},
+            replace::{ReplaceCommand, RequestParser},
+        },
+        domain::{
+            Bot,
+            MessageData,
+            NewMessage,
+            ReplyTarget,
+            RichText,
+            Span,
+            Style,
+            Update,
+        },
+        future::DynFuture,
+        handler::DefaultHandler,
+        middleware::retry::Backoff,
+        port::{Connector, Receiver, Sender},
+        shutdown::Shutdown,
+    };
+    use std::{io, time::Duration};
+    use tokio::time;
+
+    /// Connector failing every time.
+    #[derive(Debug)]
+    struct Unreachable;
+
+    impl Connector for Unreachable {
+        type Receiver = MemoryChannel;
+        type Error = io::Error;
+
+        fn connect(
+            &self,
+        ) -> DynFuture<'_, Result<Self::Receiver, Self::Error>> {
+            Box::pin(async {
+                Err(io::Error::new(io::ErrorKind::NotFound, "unreachable"))
+            })
+        }
+    }
+
+    fn bot(handle: &str) -> Bot {
+        Bot { handle: String::from(handle) }
+    }
+
+    /// Runs an app over two memory accounts, handled as "first" and
+    /// "second".
+    fn start() -> (MemoryChannel, MemoryChannel, Shutdown) {
+        let first = MemoryChannel::new();
+        let second = MemoryChannel::new();
+        let mut multiplexer = Multiplexer::new(Backoff::default());
+        multiplexer.add("first", bot("first"), first.clone(), first.clone());
+        multiplexer.add(
+            "second",
+            bot("second"),
+            second.clone(),
+            second.clone(),
+        );
+        let app = App::new(bot("regex_bot"))
+            .bots(multiplexer.clone())
+            .handler(DefaultHandler {
+                request_parser: HelpRequestParser,
+                command: HelpCommand,
+                sender: multiplexer.clone(),
+            })
+            .handler(DefaultHandler {
+                request_parser: RequestParser::new(),
+                command: ReplaceCommand,
+                sender: multiplexer.clone(),
+            });
+        let shutdown = Shutdown::new();
+        tokio::spawn(app.run_supervised(
+            multiplexer,
+            Backoff::default(),
+            shutdown.clone(),
+        ));
+        (first, second, shutdown)
+    }
+
+    #[tokio::test]
+    async fn replies_through_the_originating_account() {
+        let (first, second, shutdown) = start();
+        // Both accounts use the same native ids, which must not collide.
+        let target = first.say(1, 5, "hello world", None);
+        let other_target = second.say(1, 5, "bonjour world", None);
+        first.say(1, 5, "s/world/there/", Some(target));
+        let sent = time::timeout(Duration::from_secs(5), first.next_sent())
+            .await
+            .expect("first account replies");
+        assert_eq!(sent.text(), "hello there");
+        assert_eq!(sent.message.data.chat_id, 5);
+        assert_eq!(sent.reply_to(), Some(target));
+
+        second.say(1, 5, "s/world/monde/", Some(other_target));
+        let sent = time::timeout(Duration::from_secs(5), second.next_sent())
+            .await
+            .expect("second account replies");
+        assert_eq!(sent.text(), "bonjour monde");
+        assert_eq!(sent.reply_to(), Some(other_target));
+        shutdown.trigger();
+    }
+
+    #[tokio::test]
+    async fn answers_with_the_handle_of_each_account() {
+        let (first, second, shutdown) = start();
+        second.say(1, 5, "/help@first", None);
+        let command = second.say(1, 5, "/help@second", None);
+        let sent = time::timeout(Duration::from_secs(5), second.next_sent())
+            .await
+            .expect("second account answers its own handle");
+        assert_eq!(sent.reply_to(), Some(command));
+        first.say(1, 5, "/help@second", None);
+        assert!(time::timeout(Duration::from_millis(100), first.next_sent())
+            .await
+            .is_err());
+        shutdown.trigger();
+    }
+
+    #[tokio::test]
+    async fn refuses_chats_it_never_saw() {
+        let channel = MemoryChannel::new();
+        let mut multiplexer = Multiplexer::new(Backoff::default());
+        let tags = multiplexer.add(
+            "memory",
+            bot("memory"),
+            channel.clone(),
+            channel.clone(),
+        );
+        let chat_id = tags.chat(7);
+        let mut message = NewMessage {
+            data: MessageData {
+                chat_id,
+                thread_id: None,
+                content: String::from("hi").into(),
+                reply_target: ReplyTarget::NotReplying,
+            },
+            keyboard: None,
+            attachment: None,
+        };
+        multiplexer.send(&message).await.expect("tagged chats are known");
+        assert_eq!(channel.next_sent().await.message.data.chat_id, 7);
+
+        message.data.chat_id.local += 1;
+        let error = multiplexer.send(&message).await.unwrap_err();
+        assert!(matches!(error, MultiError::UnknownId(_)), "{:?}", error);
+        message.data.chat_id.account += 1;
+        let error = multiplexer.send(&message).await.unwrap_err();
+        assert!(matches!(error, MultiError::UnknownId(_)), "{:?}", error);
+    }
+
+    #[tokio::test]
+    async fn leaves_out_replies_and_mentions_it_cannot_map() {
+        let channel = MemoryChannel::new();
+        let mut multiplexer = Multiplexer::new(Backoff::default());
+        let tags = multiplexer.add(
+            "memory",
+            bot("memory"),
+            channel.clone(),
+            channel.clone(),
+        );
+        let unknown = MultiId { account: 0, local: 99 };
+        let mention = Span {
+            start: 0,
+            end: 5,
+            style: Style::Mention { user_id: unknown },
+        };
+        let message = NewMessage {
+            data: MessageData {
+                chat_id: tags.chat(7),
+                thread_id: None,
+                content: RichText {
+                    text: String::from("alice, hi"),
+                    spans: vec![mention],
+                },
+                reply_target: ReplyTarget::MessageId(unknown),
+            },
+            keyboard: None,
+            attachment: None,
+        };
+        multiplexer.send(&message).await.expect("the chat is known");
+        let sent = channel.next_sent().await.message;
+        assert_eq!(sent.data.reply_target, ReplyTarget::NotReplying);
+        assert_eq!(sent.data.content.text, "alice, hi");
+        assert_eq!(sent.data.content.spans[0].style, Style::Bold);
+    }
+
+    #[test]
+    fn forgets_the_least_recently_used_ids() {
+        let mut table = IdTable::new(2);
+        let first = table.local('a');
+        let second = table.local('b');
+        assert_eq!(table.local('a'), first);
+        table.local('c');
+        assert_eq!(table.native(first), Some('a'));
+        assert_eq!(table.native(second), None);
+        assert_ne!(table.local('b'), second);
+    }
+
+    #[tokio::test]
+    async fn goes_on_while_an_account_is_unreachable() {
+        let channel = MemoryChannel::new();
+        let mut multiplexer = Multiplexer::new(Backoff::default());
+        multiplexer.add("down", bot("down"), Unreachable, channel.clone());
+        multiplexer.add("up", bot("up"), channel.clone(), channel.clone());
+        let shutdown = Shutdown::new();
+        let app = App::new(bot("regex_bot")).handler(DefaultHandler {
+            request_parser: HelpRequestParser,
+            command: HelpCommand,
+            sender: multiplexer.clone(),
+        });
+        let running = tokio::spawn(app.run_supervised(
+            multiplexer,
+            Backoff::default(),
+            shutdown.clone(),
+        ));
+        let command = channel.say(1, 5, "/help", None);
+        let sent = time::timeout(Duration::from_secs(5), channel.next_sent())
+            .await
+            .expect("reachable account answers");
+        assert_eq!(sent.reply_to(), Some(command));
+        shutdown.trigger();
+        let stopped = running.await.expect("app does not panic");
+        assert_eq!(stopped.expect("nothing fails"), Stopped::Drained);
+    }
+
+    #[tokio::test]
+    async fn commits_without_losing_what_receiving_read() {
+        let server = FakeIrcServer::start().await;
+        let channel = IrcChannel::new(server.config());
+        let mut multiplexer = Multiplexer::new(Backoff::default());
+        multiplexer.add("irc", bot(NICK), channel.clone(), channel);
+        let waiting = Duration::from_millis(100);
+        let (_, received) = tokio::join!(
+            server.wait_for("JOIN", 1),
+            time::timeout(waiting, multiplexer.receive()),
+        );
+        assert!(received.is_err());
+
+        server.send_part(&format!(":alice!a@host PRIVMSG {} :hel", CHANNEL));
+        let received = time::timeout(waiting, multiplexer.receive()).await;
+        assert!(received.is_err());
+        multiplexer.commit().await.expect("nothing fails");
+        server.send_part("lo\r\n");
+        let received =
+            time::timeout(Duration::from_secs(5), multiplexer.receive())
+                .await
+                .expect("the line is completed");
+        match received.unwrap().unwrap() {
+            Update::Message(message) => {
+                assert_eq!(message.data.content.text, "hello")
+            },
+            update => panic!("expected a message, got {:?}", update),
+        }
+    }
+}
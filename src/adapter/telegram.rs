@@ -1,79 +1,417 @@
+mod client;
+mod error;
+#[cfg(test)]
+pub mod fake;
+mod requests;
+mod webhook;
+
+use self::requests::{
+    ApiResponse,
+    EntityUser,
+    GetMe,
+    GetRawUpdates,
+    MessageEntity,
+    SendDocument,
+    SendMessage,
+};
+pub use self::{
+    client::{ClientConfig, TgClient},
+    error::TgError,
+    webhook::{TgWebhook, WebhookConfig},
+};
 use crate::{
     domain::{self, MessageData},
     future::DynFuture,
-    port::{Disconnected, Receiver, Sender},
+    history::History,
+    middleware::{
+        rate_limit::{Quota, RateLimits},
+        split::{LengthUnit, PlatformLimits, TextLimit},
+    },
+    port::{Connector, Deleter, Receiver, Receiving, Sender},
 };
 use core::fmt;
-use futures::StreamExt;
+use serde_json::Value;
+use std::{
+    collections::VecDeque,
+    sync::{self, Arc, PoisonError},
+    time::Duration,
+};
 use telegram_bot::{
-    Api,
+    CanAnswerCallbackQuery,
     ChatId,
-    Error,
+    DeleteMessage,
+    InlineKeyboardButton,
+    InlineKeyboardMarkup,
+    Integer,
     MessageId,
     MessageKind,
     MessageOrChannelPost,
-    SendMessage,
+    Request,
+    Update,
     UpdateKind,
+    User,
+    UserId,
+};
+use tokio::sync::Mutex;
+
+const POLL_TIMEOUT_SECS: Integer = 30;
+
+pub const LIMITS: PlatformLimits = PlatformLimits {
+    text: TextLimit { max_len: 4096, unit: LengthUnit::Utf16 },
+    caption: TextLimit { max_len: 1024, unit: LengthUnit::Utf16 },
+};
+
+/// Documented limits are about one message per second in a chat, twenty per
+/// minute in a group and thirty per second overall.
+pub const RATE_LIMITS: RateLimits = RateLimits {
+    per_chat: &[
+        Quota { burst: 1, period: Duration::from_secs(1) },
+        Quota { burst: 20, period: Duration::from_secs(60) },
+    ],
+    global: &[Quota { burst: 30, period: Duration::from_secs(1) }],
 };
+const DEFAULT_REPLY_DEPTH: usize = 1;
+const HISTORY_CAPACITY: usize = 4096;
+
+fn domain_keyboard_to_tg(keyboard: &domain::Keyboard) -> InlineKeyboardMarkup {
+    let rows = keyboard
+        .rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|button| {
+                    InlineKeyboardButton::callback(&button.label, &button.data)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    InlineKeyboardMarkup::from(rows)
+}
+
+fn utf16_to_byte_offset(text: &str, utf16_offset: usize) -> usize {
+    let mut units = 0;
+    for (index, character) in text.char_indices() {
+        if units >= utf16_offset {
+            return index;
+        }
+        units += character.len_utf16();
+    }
+    text.len()
+}
+
+fn byte_to_utf16_offset(text: &str, byte_offset: usize) -> usize {
+    text[..byte_offset].encode_utf16().count()
+}
+
+fn tg_entities_to_domain(
+    text: &str,
+    raw_entities: &Value,
+) -> Vec<domain::Span<UserId>> {
+    let entities = match raw_entities.as_array() {
+        Some(entities) => entities,
+        None => return Vec::new(),
+    };
+    entities
+        .iter()
+        .filter_map(|entity| {
+            let offset = usize::try_from(entity["offset"].as_u64()?).ok()?;
+            let length = usize::try_from(entity["length"].as_u64()?).ok()?;
+            // Mentions, hashtags, URLs and the like are detected by Telegram
+            // itself, so only explicit formatting is kept.
+            let style = match entity["type"].as_str()? {
+                "bold" => domain::Style::Bold,
+                "italic" => domain::Style::Italic,
+                "underline" => domain::Style::Underline,
+                "strikethrough" => domain::Style::Strikethrough,
+                "spoiler" => domain::Style::Spoiler,
+                "code" => domain::Style::Code,
+                "pre" => domain::Style::Pre {
+                    language: entity["language"].as_str().map(String::from),
+                },
+                "text_link" => domain::Style::Link {
+                    url: String::from(entity["url"].as_str()?),
+                },
+                "text_mention" => domain::Style::Mention {
+                    user_id: UserId::new(entity["user"]["id"].as_i64()?),
+                },
+                _ => return None,
+            };
+            Some(domain::Span {
+                start: utf16_to_byte_offset(text, offset),
+                end: utf16_to_byte_offset(text, offset + length),
+                style,
+            })
+        })
+        .collect()
+}
+
+fn domain_spans_to_tg(
+    text: &str,
+    spans: &[domain::Span<UserId>],
+) -> Vec<MessageEntity> {
+    spans
+        .iter()
+        .map(|span| {
+            let offset = byte_to_utf16_offset(text, span.start);
+            let length = byte_to_utf16_offset(text, span.end) - offset;
+            let entity = |kind| MessageEntity::new(kind, offset, length);
+            match &span.style {
+                domain::Style::Bold => entity("bold"),
+                domain::Style::Italic => entity("italic"),
+                domain::Style::Underline => entity("underline"),
+                domain::Style::Strikethrough => entity("strikethrough"),
+                domain::Style::Spoiler => entity("spoiler"),
+                domain::Style::Code => entity("code"),
+                domain::Style::Pre { language } => MessageEntity {
+                    language: language.clone(),
+                    ..entity("pre")
+                },
+                domain::Style::Link { url } => MessageEntity {
+                    url: Some(url.clone()),
+                    ..entity("text_link")
+                },
+                domain::Style::Mention { user_id } => MessageEntity {
+                    user: Some(EntityUser { id: *user_id }),
+                    ..entity("text_mention")
+                },
+            }
+        })
+        .collect()
+}
+
+fn tg_user_to_domain(user: User) -> domain::Author<UserId> {
+    let display_name = match user.last_name {
+        Some(last_name) => format!("{} {}", user.first_name, last_name),
+        None => user.first_name,
+    };
+    domain::Author {
+        id: Some(user.id),
+        display_name,
+        username: user.username,
+        is_bot: user.is_bot,
+    }
+}
+
+fn tg_signature_to_domain(raw: &Value) -> Option<domain::Author<UserId>> {
+    let signature = raw.get("author_signature")?.as_str()?;
+    Some(domain::Author {
+        id: None,
+        display_name: String::from(signature),
+        username: None,
+        is_bot: false,
+    })
+}
+
+fn tg_thread_to_domain(raw: &Value) -> Option<MessageId> {
+    // Replies in plain groups also carry a thread id, but only forum topics
+    // accept it when sending.
+    if raw["is_topic_message"].as_bool() != Some(true) {
+        return None;
+    }
+    raw["message_thread_id"].as_i64().map(MessageId::new)
+}
 
 fn tg_message_to_domain(
     msg_or_post: MessageOrChannelPost,
-) -> Option<domain::Message<MessageId, ChatId>> {
+    raw: &Value,
+) -> Option<domain::Message<MessageId, ChatId, UserId>> {
     fn convert_with_custom_reply(
         msg_or_post: MessageOrChannelPost,
+        raw: &Value,
         convert_reply: bool,
-    ) -> Option<domain::Message<MessageId, ChatId>> {
-        let (id, chat_id, replying_to, kind) = match msg_or_post {
+    ) -> Option<domain::Message<MessageId, ChatId, UserId>> {
+        let (id, author, chat_id, replying_to, kind) = match msg_or_post {
             MessageOrChannelPost::Message(message) => (
                 message.id,
+                Some(tg_user_to_domain(message.from)),
                 message.chat.id(),
                 message.reply_to_message,
                 message.kind,
             ),
             MessageOrChannelPost::ChannelPost(post) => (
                 post.id,
+                tg_signature_to_domain(raw),
                 ChatId::from(post.chat.id),
                 post.reply_to_message,
                 post.kind,
             ),
         };
 
-        if let MessageKind::Text { data, .. } = kind {
-            Some(domain::Message {
-                id,
-                data: MessageData {
-                    chat_id,
-                    content: data,
-                    reply_target: if convert_reply {
-                        match replying_to.and_then(|msg_or_post| {
-                            convert_with_custom_reply(*msg_or_post, false)
-                        }) {
-                            Some(message) => {
-                                domain::ReplyTarget::Message(Box::new(message))
-                            },
-                            None => domain::ReplyTarget::NotReplying,
-                        }
-                    } else {
-                        domain::ReplyTarget::Prunned
-                    },
+        let (content_kind, text, raw_entities) = match kind {
+            MessageKind::Text { data, .. } => {
+                (domain::ContentKind::Text, data, &raw["entities"])
+            },
+            kind => {
+                let media_kind = match kind {
+                    MessageKind::Photo { .. } => domain::MediaKind::Photo,
+                    MessageKind::Video { .. } => domain::MediaKind::Video,
+                    MessageKind::Document { .. } => domain::MediaKind::Document,
+                    MessageKind::Audio { .. } => domain::MediaKind::Audio,
+                    MessageKind::Voice { .. } => domain::MediaKind::Voice,
+                    _ => domain::MediaKind::Other,
+                };
+                let caption = raw["caption"].as_str()?;
+                (
+                    domain::ContentKind::Caption(media_kind),
+                    String::from(caption),
+                    &raw["caption_entities"],
+                )
+            },
+        };
+
+        Some(domain::Message {
+            id,
+            author,
+            content_kind,
+            data: MessageData {
+                chat_id,
+                thread_id: tg_thread_to_domain(raw),
+                content: domain::RichText {
+                    spans: tg_entities_to_domain(&text, raw_entities),
+                    text,
                 },
-            })
-        } else {
-            None
-        }
+                reply_target: if convert_reply {
+                    match replying_to.and_then(|msg_or_post| {
+                        convert_with_custom_reply(
+                            *msg_or_post,
+                            &raw["reply_to_message"],
+                            false,
+                        )
+                    }) {
+                        Some(message) => {
+                            domain::ReplyTarget::Message(Box::new(message))
+                        },
+                        None => domain::ReplyTarget::NotReplying,
+                    }
+                } else {
+                    domain::ReplyTarget::Prunned
+                },
+            },
+        })
     }
 
-    convert_with_custom_reply(msg_or_post, true)
+    convert_with_custom_reply(msg_or_post, raw, true)
+}
+
+#[derive(Debug, Default)]
+struct UpdateQueue {
+    offset: Integer,
+    pending: VecDeque<Value>,
 }
 
 #[derive(Clone)]
 pub struct TgMessageChannel {
-    api: Api,
+    client: TgClient,
+    updates: Arc<Mutex<UpdateQueue>>,
+    history: Arc<sync::Mutex<History<MessageId, ChatId, UserId>>>,
+    reply_depth: usize,
 }
 
 impl TgMessageChannel {
-    pub fn new(token: &str) -> Self {
-        Self { api: Api::new(token) }
+    pub fn new(client: TgClient) -> Self {
+        Self {
+            client,
+            updates: Arc::new(Mutex::new(UpdateQueue::default())),
+            history: Arc::new(sync::Mutex::new(History::new(HISTORY_CAPACITY))),
+            reply_depth: DEFAULT_REPLY_DEPTH,
+        }
+    }
+
+    /// Sets how many levels of replied messages are embedded in received
+    /// messages. Telegram only embeds one, deeper levels come from messages
+    /// previously seen by this channel.
+    pub fn reply_depth(mut self, depth: usize) -> Self {
+        self.reply_depth = depth;
+        self
+    }
+
+    async fn call<R>(&self, request: R) -> Result<Value, TgError>
+    where
+        R: Request<Response = ApiResponse>,
+    {
+        self.call_waiting(request, Duration::ZERO).await
+    }
+
+    /// Calls a method the Bot API may hold for up to `wait` before answering.
+    async fn call_waiting<R>(
+        &self,
+        request: R,
+        wait: Duration,
+    ) -> Result<Value, TgError>
+    where
+        R: Request<Response = ApiResponse>,
+    {
+        self.client.send(request, wait).await?
+    }
+
+    /// Converts a raw update, yielding nothing for updates the bot does not
+    /// handle. Callback queries are acknowledged on the way, and delivered
+    /// even if that fails.
+    async fn update_to_domain(
+        &self,
+        raw_update: Value,
+    ) -> Option<domain::Update<MessageId, ChatId, UserId>> {
+        // Updates telegram_bot cannot represent are skipped instead of failing
+        // the whole batch.
+        let update = match serde_json::from_value::<Update>(raw_update.clone())
+        {
+            Ok(update) => update,
+            Err(error) => {
+                eprintln!(
+                    "Error reading update {}, skipping it...",
+                    raw_update["update_id"]
+                );
+                eprintln!("    {}", error);
+                return None;
+            },
+        };
+        match update.kind {
+            UpdateKind::Message(message) => tg_message_to_domain(
+                MessageOrChannelPost::Message(message),
+                &raw_update["message"],
+            )
+            .map(|message| domain::Update::Message(self.remember(message))),
+            UpdateKind::ChannelPost(post) => tg_message_to_domain(
+                MessageOrChannelPost::ChannelPost(post),
+                &raw_update["channel_post"],
+            )
+            .map(|message| domain::Update::Message(self.remember(message))),
+            UpdateKind::CallbackQuery(query) => {
+                let ack = query.acknowledge();
+                if let Err(error) = self.client.send(ack, Duration::ZERO).await
+                {
+                    eprintln!(
+                        "Error answering callback query of update {}...",
+                        raw_update["update_id"]
+                    );
+                    eprintln!("    {}", error);
+                }
+                match (query.message, query.data) {
+                    (Some(message), Some(data)) => tg_message_to_domain(
+                        message,
+                        &raw_update["callback_query"]["message"],
+                    )
+                    .map(|message| {
+                        domain::Update::Callback(domain::Callback {
+                            message: self.remember(message),
+                            data,
+                        })
+                    }),
+                    _ => None,
+                }
+            },
+            _ => None,
+        }
+    }
+
+    fn remember(
+        &self,
+        message: domain::Message<MessageId, ChatId, UserId>,
+    ) -> domain::Message<MessageId, ChatId, UserId> {
+        let mut history =
+            self.history.lock().unwrap_or_else(PoisonError::into_inner);
+        history.record(&message);
+        history.resolve(message, self.reply_depth)
     }
 }
 
@@ -85,75 +423,266 @@ impl fmt::Debug for TgMessageChannel {
 
 impl domain::Id for MessageId {}
 impl domain::Id for ChatId {}
+impl domain::Id for UserId {}
 
 impl Sender for TgMessageChannel {
-    type Error = Error;
+    type Error = TgError;
     type MessageId = MessageId;
     type ChatId = ChatId;
+    type UserId = UserId;
 
     fn send<'fut>(
         &'fut self,
-        message: &'fut domain::NewMessage<Self::MessageId, Self::ChatId>,
-    ) -> DynFuture<'fut, Result<(), Self::Error>> {
+        message: &'fut domain::NewMessage<
+            Self::MessageId,
+            Self::ChatId,
+            Self::UserId,
+        >,
+    ) -> DynFuture<'fut, Result<Self::MessageId, Self::Error>> {
         Box::pin(async move {
-            let mut request =
-                SendMessage::new(message.data.chat_id, &message.data.content);
-            match &message.data.reply_target {
-                domain::ReplyTarget::Message(message) => {
-                    request.reply_to(message.id);
+            let content = &message.data.content;
+            let entities = domain_spans_to_tg(&content.text, &content.spans);
+            let reply_to = match &message.data.reply_target {
+                domain::ReplyTarget::Message(message) => Some(message.id),
+                domain::ReplyTarget::MessageId(message_id) => Some(*message_id),
+                _ => None,
+            };
+            let reply_markup =
+                message.keyboard.as_ref().map(domain_keyboard_to_tg);
+
+            let sent = match &message.attachment {
+                Some(attachment) => {
+                    let mut request = SendDocument::new(
+                        message.data.chat_id,
+                        &attachment.file_name,
+                        &attachment.bytes,
+                    );
+                    request.caption(&content.text, entities);
+                    if let Some(thread_id) = message.data.thread_id {
+                        request.thread(thread_id);
+                    }
+                    if let Some(message_id) = reply_to {
+                        request.reply_to(message_id);
+                    }
+                    if let Some(reply_markup) = reply_markup {
+                        request.reply_markup(reply_markup);
+                    }
+                    let content_types =
+                        [("document", attachment.mime_type.as_str())];
+                    self.client
+                        .upload(request, &content_types, Duration::ZERO)
+                        .await??
                 },
-                domain::ReplyTarget::MessageId(message_id) => {
-                    request.reply_to(message_id);
+                None => {
+                    let mut request =
+                        SendMessage::new(message.data.chat_id, &content.text);
+                    request.entities(entities);
+                    if let Some(thread_id) = message.data.thread_id {
+                        request.thread(thread_id);
+                    }
+                    if let Some(message_id) = reply_to {
+                        request.reply_to(message_id);
+                    }
+                    if let Some(reply_markup) = reply_markup {
+                        request.reply_markup(reply_markup);
+                    }
+                    self.call(request).await?
                 },
-                _ => (),
+            };
+            let sent_id = sent["message_id"]
+                .as_i64()
+                .map(MessageId::new)
+                .ok_or(TgError::MalformedResponse)?;
+            if let Some(sent_msg) =
+                serde_json::from_value::<MessageOrChannelPost>(sent.clone())
+                    .ok()
+                    .and_then(|msg_or_post| {
+                        tg_message_to_domain(msg_or_post, &sent)
+                    })
+            {
+                self.remember(sent_msg);
             }
-            self.api.send(request).await?;
+            Ok(sent_id)
+        })
+    }
+}
+
+impl Deleter for TgMessageChannel {
+    fn can_delete(&self, _chat_id: Self::ChatId) -> bool {
+        true
+    }
+
+    fn delete(
+        &self,
+        chat_id: Self::ChatId,
+        message_id: Self::MessageId,
+    ) -> DynFuture<'_, Result<(), Self::Error>> {
+        Box::pin(async move {
+            let request = DeleteMessage::new(chat_id, message_id);
+            self.client.send(request, Duration::ZERO).await?;
             Ok(())
         })
     }
 }
 
 impl Receiver for TgMessageChannel {
-    type Error = Error;
+    type Error = TgError;
     type MessageId = MessageId;
     type ChatId = ChatId;
+    type UserId = UserId;
 
     fn receive<'fut>(
         &'fut self,
-    ) -> DynFuture<
-        'fut,
-        Result<
-            Result<
-                domain::Message<Self::MessageId, Self::ChatId>,
-                Disconnected,
-            >,
-            Self::Error,
-        >,
-    > {
+    ) -> Receiving<'fut, Self::MessageId, Self::ChatId, Self::UserId, Self::Error>
+    {
         Box::pin(async move {
+            let mut updates = self.updates.lock().await;
             loop {
-                let update = match self.api.stream().next().await {
-                    Some(result) => result?,
-                    None => break Ok(Err(Disconnected)),
-                };
-                match update.kind {
-                    UpdateKind::Message(message) => {
-                        if let Some(domain_msg) = tg_message_to_domain(
-                            MessageOrChannelPost::Message(message),
-                        ) {
-                            break Ok(Ok(domain_msg));
-                        }
-                    },
-                    UpdateKind::ChannelPost(post) => {
-                        if let Some(domain_msg) = tg_message_to_domain(
-                            MessageOrChannelPost::ChannelPost(post),
-                        ) {
-                            break Ok(Ok(domain_msg));
+                // Only taken once converted, so that an interrupted receive
+                // leaves it for the next one.
+                let raw_update = match updates.pending.front() {
+                    Some(raw_update) => raw_update.clone(),
+                    None => {
+                        let request = GetRawUpdates::new(
+                            updates.offset,
+                            POLL_TIMEOUT_SECS,
+                        );
+                        let wait =
+                            Duration::from_secs(POLL_TIMEOUT_SECS as u64);
+                        let batch = self.call_waiting(request, wait).await?;
+                        match batch {
+                            Value::Array(batch) => {
+                                updates.pending.extend(batch)
+                            },
+                            _ => break Err(TgError::MalformedResponse),
                         }
+                        continue;
                     },
-                    _ => (),
+                };
+                let update_id = raw_update["update_id"].as_i64();
+                let update = self.update_to_domain(raw_update).await;
+                updates.pending.pop_front();
+                if let Some(update_id) = update_id {
+                    updates.offset = updates.offset.max(update_id + 1);
+                }
+                if let Some(update) = update {
+                    break Ok(Ok(update));
                 }
             }
         })
     }
+
+    /// Confirms the updates delivered so far by asking for the ones after
+    /// them, which are left for the next run.
+    fn commit(&self) -> DynFuture<'_, Result<(), Self::Error>> {
+        Box::pin(async move {
+            let updates = self.updates.lock().await;
+            let mut request = GetRawUpdates::new(updates.offset, 0);
+            request.limit(1);
+            self.call(request).await?;
+            Ok(())
+        })
+    }
+}
+
+/// Long polling keeps no connection open, so connecting only checks that the
+/// Bot API is reachable and accepts the token.
+impl Connector for TgMessageChannel {
+    type Receiver = Self;
+    type Error = TgError;
+
+    fn connect(&self) -> DynFuture<'_, Result<Self::Receiver, Self::Error>> {
+        Box::pin(async move {
+            self.call(GetMe).await?;
+            Ok(self.clone())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        domain_keyboard_to_tg,
+        domain_spans_to_tg,
+        tg_entities_to_domain,
+    };
+    use crate::domain::{Button, Keyboard, Span, Style};
+    use serde_json::json;
+    use telegram_bot::UserId;
+
+    /// Each emoji takes 4 bytes and 2 UTF-16 units, "é" 2 bytes and 1 unit.
+    const TEXT: &str = "😀 bold 🎉 café";
+
+    #[test]
+    fn reads_entity_offsets_as_utf16_units() {
+        let entities = json!([
+            { "type": "bold", "offset": 3, "length": 4 },
+            { "type": "text_mention", "offset": 11, "length": 4, "user": { "id": 7 } },
+            { "type": "hashtag", "offset": 0, "length": 2 },
+        ]);
+        assert_eq!(
+            tg_entities_to_domain(TEXT, &entities),
+            [
+                Span { start: 5, end: 9, style: Style::Bold },
+                Span {
+                    start: 15,
+                    end: 20,
+                    style: Style::Mention { user_id: UserId::new(7) },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_entity_offsets_as_utf16_units() {
+        let spans = [
+            Span { start: 0, end: 4, style: Style::Italic },
+            Span { start: 5, end: 14, style: Style::Bold },
+            Span { start: 15, end: 20, style: Style::Code },
+        ];
+        let entities = serde_json::to_value(domain_spans_to_tg(TEXT, &spans));
+        assert_eq!(
+            entities.unwrap(),
+            json!([
+                { "type": "italic", "offset": 0, "length": 2 },
+                { "type": "bold", "offset": 3, "length": 7 },
+                { "type": "code", "offset": 11, "length": 4 },
+            ])
+        );
+    }
+
+    #[test]
+    fn clamps_entities_past_the_text() {
+        let entities = json!([{ "type": "bold", "offset": 11, "length": 10 }]);
+        assert_eq!(
+            tg_entities_to_domain(TEXT, &entities),
+            [Span { start: 15, end: TEXT.len(), style: Style::Bold }]
+        );
+    }
+
+    #[test]
+    fn encodes_button_data_as_callback_data() {
+        let keyboard = Keyboard {
+            rows: vec![vec![
+                Button {
+                    label: String::from("Apply globally"),
+                    data: String::from("s/o/0/g"),
+                },
+                Button {
+                    label: String::from("Case-insensitive"),
+                    data: String::from("s/o/0/i"),
+                },
+            ]],
+        };
+        let markup = serde_json::to_value(domain_keyboard_to_tg(&keyboard));
+        assert_eq!(
+            markup.unwrap(),
+            json!({
+                "inline_keyboard": [[
+                    { "text": "Apply globally", "callback_data": "s/o/0/g" },
+                    { "text": "Case-insensitive", "callback_data": "s/o/0/i" },
+                ]],
+            })
+        );
+    }
 }
@@ -0,0 +1,336 @@
+use crate::middleware::retry::Transient;
+use core::fmt;
+use hyper::{
+    client::HttpConnector,
+    header::CONTENT_TYPE,
+    http::uri::InvalidUri,
+    Body,
+    Client,
+    Method,
+    Uri,
+};
+use hyper_proxy::{Intercept, Proxy, ProxyConnector};
+use hyper_tls::HttpsConnector;
+use std::{
+    collections::hash_map::RandomState,
+    error::Error,
+    hash::{BuildHasher, Hasher},
+    io,
+    sync::Arc,
+    time::Duration,
+};
+use telegram_bot::{
+    types::requests::Error as RawError,
+    Body as RawBody,
+    HttpRequest,
+    HttpResponse,
+    Method as RawMethod,
+    MultipartValue,
+    Request,
+    ResponseType,
+};
+use tokio::{fs, time};
+
+pub const DEFAULT_API_URL: &str = "https://api.telegram.org/";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where and how the Bot API is reached.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientConfig {
+    /// Base URL of the Bot API, e.g. that of a local Bot API server.
+    pub api_url: Uri,
+    /// How long a request may take, on top of the time the Bot API holds it
+    /// while long polling.
+    pub timeout: Duration,
+    pub connect_timeout: Duration,
+    /// HTTP proxy all requests go through.
+    pub proxy: Option<Uri>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            api_url: Uri::from_static(DEFAULT_API_URL),
+            timeout: DEFAULT_TIMEOUT,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            proxy: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The request could not be encoded, or the response decoded.
+    Raw(RawError),
+    /// The method URL built from the base URL is invalid.
+    Url(InvalidUri),
+    /// A file to upload could not be read.
+    File(io::Error),
+    Http(hyper::Error),
+    TimedOut,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Raw(cause) => write!(fmtr, "{}", cause),
+            Self::Url(cause) => write!(fmtr, "invalid Bot API URL: {}", cause),
+            Self::File(cause) => write!(fmtr, "error reading file: {}", cause),
+            Self::Http(cause) => write!(fmtr, "{}", cause),
+            Self::TimedOut => write!(fmtr, "request to the Bot API timed out"),
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Raw(cause) => Some(cause),
+            Self::Url(cause) => Some(cause),
+            Self::File(cause) => Some(cause),
+            Self::Http(cause) => Some(cause),
+            Self::TimedOut => None,
+        }
+    }
+}
+
+impl Transient for ClientError {
+    fn is_transient(&self) -> bool {
+        matches!(self, Self::Http(_) | Self::TimedOut)
+    }
+
+    fn may_have_succeeded(&self) -> bool {
+        match self {
+            Self::Http(cause) => !cause.is_connect(),
+            Self::TimedOut => true,
+            _ => false,
+        }
+    }
+}
+
+/// Sends `telegram_bot` requests over hyper, like `telegram_bot::Api` does,
+/// but on the application's runtime and with configurable endpoint, timeouts
+/// and proxy.
+#[derive(Clone)]
+pub struct TgClient {
+    http: Client<ProxyConnector<HttpsConnector<HttpConnector>>>,
+    /// Base URL and token, to which method names are appended.
+    bot_url: Arc<str>,
+    timeout: Duration,
+}
+
+impl TgClient {
+    /// Fails if TLS cannot be set up for the proxy.
+    pub fn new(token: &str, config: ClientConfig) -> Result<Self, io::Error> {
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        http.set_connect_timeout(Some(config.connect_timeout));
+        let https = HttpsConnector::new_with_connector(http);
+        let connector = match config.proxy {
+            Some(proxy) => ProxyConnector::from_proxy(
+                https,
+                Proxy::new(Intercept::All, proxy),
+            )?,
+            None => ProxyConnector::unsecured(https),
+        };
+
+        let mut bot_url = config.api_url.to_string();
+        if !bot_url.ends_with('/') {
+            bot_url.push('/');
+        }
+        bot_url.push_str("bot");
+        bot_url.push_str(token);
+        bot_url.push('/');
+
+        Ok(Self {
+            http: Client::builder().build(connector),
+            bot_url: Arc::from(bot_url),
+            timeout: config.timeout,
+        })
+    }
+
+    /// Sends a request the Bot API may hold for up to `wait` before
+    /// answering.
+    pub async fn send<R>(
+        &self,
+        request: R,
+        wait: Duration,
+    ) -> Result<<R::Response as ResponseType>::Type, ClientError>
+    where
+        R: Request,
+    {
+        self.upload(request, &[], wait).await
+    }
+
+    /// Same as [`send`](Self::send), with the content type of the files in
+    /// some multipart fields, given by field name. Files in other fields are
+    /// sent as `application/octet-stream`.
+    pub async fn upload<R>(
+        &self,
+        request: R,
+        content_types: &[(&str, &str)],
+        wait: Duration,
+    ) -> Result<<R::Response as ResponseType>::Type, ClientError>
+    where
+        R: Request,
+    {
+        let request = request.serialize().map_err(ClientError::Raw)?;
+        let response = time::timeout(
+            self.timeout + wait,
+            self.send_raw(request, content_types),
+        )
+        .await
+        .map_err(|_| ClientError::TimedOut)??;
+        R::Response::deserialize(response).map_err(ClientError::Raw)
+    }
+
+    async fn send_raw(
+        &self,
+        request: HttpRequest,
+        content_types: &[(&str, &str)],
+    ) -> Result<HttpResponse, ClientError> {
+        let uri = format!("{}{}", self.bot_url, request.name())
+            .parse::<Uri>()
+            .map_err(ClientError::Url)?;
+        let method = match request.method {
+            RawMethod::Get => Method::GET,
+            RawMethod::Post => Method::POST,
+        };
+        let builder = hyper::Request::builder().method(method).uri(uri);
+        let http_request = match request.body {
+            RawBody::Json(json) => builder
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(json)),
+            RawBody::Multipart(parts) => {
+                let (boundary, body) = multipart(parts, content_types).await?;
+                builder
+                    .header(
+                        CONTENT_TYPE,
+                        format!("multipart/form-data; boundary={}", boundary),
+                    )
+                    .body(Body::from(body))
+            },
+            _ => builder.body(Body::empty()),
+        }
+        .expect("method, URL and headers are valid");
+
+        let response =
+            self.http.request(http_request).await.map_err(ClientError::Http)?;
+        // Failures come with a status code, but are described in the body
+        // too, which is all `telegram_bot` looks at.
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(ClientError::Http)?;
+        Ok(HttpResponse { body: Some(body.to_vec()) })
+    }
+}
+
+impl fmt::Debug for TgClient {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("TgClient")
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Encodes the parts as `multipart/form-data`, yielding the boundary and the
+/// body.
+async fn multipart(
+    parts: Vec<(&'static str, MultipartValue)>,
+    content_types: &[(&str, &str)],
+) -> Result<(String, Vec<u8>), ClientError> {
+    let boundary =
+        format!("{:016x}", RandomState::new().build_hasher().finish());
+
+    let mut body = Vec::new();
+    for (name, value) in parts {
+        body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+        let (file_name, data) = match value {
+            MultipartValue::Text(text) => {
+                body.extend_from_slice(
+                    format!(
+                        "Content-Disposition: form-data; name=\"{}\"\r\n\r\n",
+                        name
+                    )
+                    .as_bytes(),
+                );
+                body.extend_from_slice(text.as_str().as_bytes());
+                body.extend_from_slice(b"\r\n");
+                continue;
+            },
+            MultipartValue::Data { file_name, data } => {
+                (String::from(file_name.as_str()), data.to_vec())
+            },
+            MultipartValue::Path { path, file_name } => {
+                let data =
+                    fs::read(path.as_str()).await.map_err(ClientError::File)?;
+                let file_name = match file_name {
+                    Some(file_name) => String::from(file_name.as_str()),
+                    None => path
+                        .as_str()
+                        .rsplit(['/', '\\'])
+                        .next()
+                        .map(String::from)
+                        .unwrap_or_default(),
+                };
+                (file_name, data)
+            },
+        };
+        let file_name = file_name.replace(['"', '\r', '\n'], "_");
+        let content_type =
+            content_types.iter().find(|(field, _)| *field == name).map_or(
+                String::from("application/octet-stream"),
+                |(_, kind)| kind.replace(['\r', '\n'], ""),
+            );
+        body.extend_from_slice(
+            format!(
+                "Content-Disposition: form-data; name=\"{}\"; \
+                 filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+                name, file_name, content_type
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(&data);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    Ok((boundary, body))
+}
+
+#[cfg(test)]
+mod test {
+    use super::multipart;
+    use telegram_bot::MultipartValue;
+
+    fn document() -> Vec<(&'static str, MultipartValue)> {
+        vec![
+            ("chat_id", MultipartValue::Text("1".into())),
+            (
+                "document",
+                MultipartValue::Data {
+                    file_name: "message.txt".into(),
+                    data: b"hello".to_vec().into(),
+                },
+            ),
+        ]
+    }
+
+    #[tokio::test]
+    async fn labels_files_with_their_content_type() {
+        let content_types = [("document", "text/plain")];
+        let (_, body) = multipart(document(), &content_types).await.unwrap();
+        let body = String::from_utf8(body).unwrap();
+        assert!(body.contains(
+            "name=\"document\"; filename=\"message.txt\"\r\n\
+             Content-Type: text/plain\r\n\r\nhello\r\n"
+        ));
+    }
+
+    #[tokio::test]
+    async fn labels_files_of_unknown_type_as_bytes() {
+        let (_, body) = multipart(document(), &[]).await.unwrap();
+        let body = String::from_utf8(body).unwrap();
+        assert!(body.contains("Content-Type: application/octet-stream\r\n"));
+    }
+}
@@ -0,0 +1,83 @@
+use super::client::ClientError;
+use crate::middleware::{rate_limit::RetryAfter, retry::Transient};
+use core::fmt;
+use std::{error::Error, time::Duration};
+
+#[derive(Debug)]
+pub enum TgError {
+    /// Failure of the underlying client, e.g. a connection error.
+    Client(ClientError),
+    /// Failure reported by the Bot API itself.
+    Api {
+        error_code: Option<i64>,
+        description: String,
+        retry_after: Option<u64>,
+    },
+    MalformedResponse,
+}
+
+impl From<ClientError> for TgError {
+    fn from(cause: ClientError) -> Self {
+        Self::Client(cause)
+    }
+}
+
+impl fmt::Display for TgError {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Client(cause) => write!(fmtr, "{}", cause),
+            Self::Api { description, retry_after, .. } => {
+                write!(fmtr, "{}", description)?;
+                if let Some(seconds) = retry_after {
+                    write!(fmtr, ", retry after: {}", seconds)?;
+                }
+                Ok(())
+            },
+            Self::MalformedResponse => {
+                write!(fmtr, "malformed response from the Bot API")
+            },
+        }
+    }
+}
+
+impl Error for TgError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Client(cause) => Some(cause),
+            _ => None,
+        }
+    }
+}
+
+impl RetryAfter for TgError {
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Api { retry_after, .. } => {
+                retry_after.map(Duration::from_secs)
+            },
+            _ => None,
+        }
+    }
+}
+
+impl Transient for TgError {
+    fn is_transient(&self) -> bool {
+        match self {
+            Self::Api { error_code: Some(code), retry_after, .. } => {
+                *code == 429 || *code >= 500 || retry_after.is_some()
+            },
+            Self::Api { retry_after, .. } => retry_after.is_some(),
+            Self::Client(cause) => cause.is_transient(),
+            Self::MalformedResponse => true,
+        }
+    }
+
+    fn may_have_succeeded(&self) -> bool {
+        match self {
+            Self::Client(cause) => cause.may_have_succeeded(),
+            // The Bot API answered, just not in a way we understand.
+            Self::MalformedResponse => true,
+            Self::Api { .. } => false,
+        }
+    }
+}
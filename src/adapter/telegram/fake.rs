@@ -0,0 +1,393 @@
+//! Local stand-in for the Bot API, serving scripted updates and recording the
+//! calls made to it.
+
+use super::client::ClientConfig;
+use crate::adapter::fake::{self, Recorder};
+use hyper::{Body, Request, Response, StatusCode, Uri};
+use serde_json::{json, Value};
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+use tokio::time;
+
+pub const BOT_ID: i64 = 1;
+pub const BOT_HANDLE: &str = "fake_bot";
+pub const USER_ID: i64 = 2;
+
+/// Call made by the bot, with its parameters and the response it got.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    pub method: String,
+    pub params: Value,
+    pub response: Value,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    next_update_id: i64,
+    next_message_id: i64,
+    /// Updates from the confirmed offset on.
+    updates: Vec<Value>,
+    /// Offset of the first update not yet confirmed.
+    offset: i64,
+    /// Every message sent so far, by the user or the bot.
+    messages: HashMap<(i64, i64), Value>,
+    calls: Vec<Call>,
+}
+
+impl State {
+    /// Stores a new message, replying to a stored one if found.
+    fn message(
+        &mut self,
+        chat_id: i64,
+        from: Value,
+        text: &str,
+        reply_to: Option<i64>,
+        reply_markup: &Value,
+    ) -> Value {
+        self.next_message_id += 1;
+        let mut message = json!({
+            "message_id": self.next_message_id,
+            "date": 0,
+            "chat": chat(chat_id),
+            "from": from,
+            "text": text,
+        });
+        if let Some(replied) = reply_to
+            .and_then(|reply_to| self.messages.get(&(chat_id, reply_to)))
+        {
+            message["reply_to_message"] = replied.clone();
+        }
+        if !reply_markup.is_null() {
+            message["reply_markup"] = reply_markup.clone();
+        }
+        self.messages.insert((chat_id, self.next_message_id), message.clone());
+        message
+    }
+
+    fn push_update(&mut self, kind: &str, payload: Value) {
+        let mut update = json!({ "update_id": self.next_update_id });
+        update[kind] = payload;
+        self.next_update_id += 1;
+        self.updates.push(update);
+    }
+
+    fn confirm(&mut self, offset: i64) {
+        self.offset = self.offset.max(offset);
+        let offset = self.offset;
+        self.updates
+            .retain(|update| update["update_id"].as_i64() >= Some(offset));
+    }
+}
+
+fn chat(chat_id: i64) -> Value {
+    if chat_id > 0 {
+        json!({ "id": chat_id, "type": "private", "first_name": "User" })
+    } else {
+        json!({ "id": chat_id, "type": "supergroup", "title": "Group" })
+    }
+}
+
+fn user() -> Value {
+    json!({ "id": USER_ID, "is_bot": false, "first_name": "User" })
+}
+
+fn bot() -> Value {
+    json!({
+        "id": BOT_ID,
+        "is_bot": true,
+        "first_name": "Fake",
+        "username": BOT_HANDLE,
+    })
+}
+
+/// Serves `getMe`, `getUpdates`, `sendMessage`, `editMessageText`,
+/// `deleteMessage` and `answerCallbackQuery` for any token.
+#[derive(Debug, Clone)]
+pub struct FakeBotApi {
+    /// Changed whenever an update is pushed or a call is made.
+    recorder: Recorder<State>,
+    local_addr: SocketAddr,
+}
+
+impl FakeBotApi {
+    pub fn start() -> Self {
+        let (listener, local_addr) = fake::listen();
+        let api = Self { recorder: Recorder::default(), local_addr };
+        let serving = api.clone();
+        fake::serve_http(listener, move |request| {
+            let api = serving.clone();
+            async move { api.serve(request).await }
+        });
+        api
+    }
+
+    /// Client configuration pointing at this server.
+    pub fn client_config(&self) -> ClientConfig {
+        let api_url = format!("http://{}/", self.local_addr)
+            .parse::<Uri>()
+            .expect("socket address makes a valid URL");
+        ClientConfig {
+            api_url,
+            timeout: Duration::from_secs(5),
+            ..ClientConfig::default()
+        }
+    }
+
+    /// Scripts a text message from the user, yielding its id.
+    pub fn push_message(
+        &self,
+        chat_id: i64,
+        text: &str,
+        reply_to: Option<i64>,
+    ) -> i64 {
+        self.recorder.update(|state| {
+            let message =
+                state.message(chat_id, user(), text, reply_to, &Value::Null);
+            state.push_update("message", message.clone());
+            message["message_id"].as_i64().expect("message id was just set")
+        })
+    }
+
+    /// Scripts a press of an inline keyboard button under a message.
+    pub fn push_callback(&self, chat_id: i64, message_id: i64, data: &str) {
+        self.recorder.update(|state| {
+            let message = state.messages.get(&(chat_id, message_id)).cloned();
+            let query = json!({
+                "id": state.next_update_id.to_string(),
+                "from": user(),
+                "message": message,
+                "chat_instance": "0",
+                "data": data,
+            });
+            state.push_update("callback_query", query);
+        });
+    }
+
+    /// Offset of the first update not yet confirmed by the bot.
+    pub fn offset(&self) -> i64 {
+        self.recorder.with_state(|state| state.offset)
+    }
+
+    /// Waits until the bot has called the method `count` times, yielding
+    /// those calls.
+    pub async fn wait_for_calls(
+        &self,
+        method: &str,
+        count: usize,
+    ) -> Vec<Call> {
+        let what = format!("{} call(s) to {}", count, method);
+        self.recorder
+            .wait_for(what, count, |state| {
+                state
+                    .calls
+                    .iter()
+                    .filter(|call| call.method == method)
+                    .cloned()
+                    .collect()
+            })
+            .await
+    }
+
+    async fn serve(&self, request: Request<Body>) -> Response<Body> {
+        let method = request
+            .uri()
+            .path()
+            .rsplit('/')
+            .next()
+            .map(String::from)
+            .unwrap_or_default();
+        // Uploads are multipart and not inspected, their parameters are
+        // recorded as null.
+        let params = hyper::body::to_bytes(request.into_body())
+            .await
+            .ok()
+            .and_then(|body| serde_json::from_slice::<Value>(&body).ok())
+            .unwrap_or(Value::Null);
+
+        let result = match method.as_str() {
+            "getUpdates" => Ok(self.get_updates(&params).await),
+            method => self
+                .recorder
+                .with_state(|state| respond(state, method, &params)),
+        };
+        let (status, body) = match result {
+            Ok(result) => {
+                (StatusCode::OK, json!({ "ok": true, "result": result }))
+            },
+            Err((status, description)) => (
+                status,
+                json!({
+                    "ok": false,
+                    "error_code": status.as_u16(),
+                    "description": description,
+                }),
+            ),
+        };
+        self.recorder.update(|state| {
+            state.calls.push(Call { method, params, response: body.clone() })
+        });
+
+        let mut response = Response::new(Body::from(body.to_string()));
+        *response.status_mut() = status;
+        response
+    }
+
+    /// Long polls like the Bot API, confirming updates before the offset.
+    async fn get_updates(&self, params: &Value) -> Value {
+        let offset = params["offset"].as_i64().unwrap_or(0);
+        let limit = params["limit"].as_u64().unwrap_or(100) as usize;
+        let timeout =
+            Duration::from_secs(params["timeout"].as_u64().unwrap_or(0));
+        self.recorder.with_state(|state| state.confirm(offset));
+
+        let mut changes = self.recorder.subscribe();
+        let waiting = async {
+            loop {
+                let updates = self.recorder.with_state(|state| {
+                    state
+                        .updates
+                        .iter()
+                        .take(limit)
+                        .cloned()
+                        .collect::<Vec<_>>()
+                });
+                if !updates.is_empty() {
+                    break updates;
+                }
+                if changes.changed().await.is_err() {
+                    break Vec::new();
+                }
+            }
+        };
+        Value::from(time::timeout(timeout, waiting).await.unwrap_or_default())
+    }
+}
+
+fn respond(
+    state: &mut State,
+    method: &str,
+    params: &Value,
+) -> Result<Value, (StatusCode, &'static str)> {
+    let chat_id = params["chat_id"].as_i64();
+    let message_id = params["message_id"].as_i64();
+    match method {
+        "getMe" => Ok(bot()),
+        "answerCallbackQuery" => Ok(Value::Bool(true)),
+        "sendMessage" => {
+            let chat_id = chat_id.ok_or((
+                StatusCode::BAD_REQUEST,
+                "Bad Request: chat not found",
+            ))?;
+            let text = params["text"].as_str().unwrap_or_default();
+            let reply_to = params["reply_to_message_id"].as_i64();
+            let reply_markup = &params["reply_markup"];
+            Ok(state.message(chat_id, bot(), text, reply_to, reply_markup))
+        },
+        "editMessageText" => {
+            let key = chat_id.zip(message_id).ok_or((
+                StatusCode::BAD_REQUEST,
+                "Bad Request: message identifier is not specified",
+            ))?;
+            let message = state.messages.get_mut(&key).ok_or((
+                StatusCode::BAD_REQUEST,
+                "Bad Request: message to edit not found",
+            ))?;
+            message["text"] = params["text"].clone();
+            message["edit_date"] = Value::from(0);
+            Ok(message.clone())
+        },
+        "deleteMessage" => {
+            let key = chat_id.zip(message_id).ok_or((
+                StatusCode::BAD_REQUEST,
+                "Bad Request: message identifier is not specified",
+            ))?;
+            state.messages.remove(&key).ok_or((
+                StatusCode::BAD_REQUEST,
+                "Bad Request: message to delete not found",
+            ))?;
+            Ok(Value::Bool(true))
+        },
+        _ => Err((StatusCode::NOT_FOUND, "Not Found")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FakeBotApi;
+    use crate::adapter::telegram::TgClient;
+    use std::time::Duration;
+    use telegram_bot::{
+        ChatId,
+        DeleteMessage,
+        EditMessageText,
+        InlineKeyboardButton,
+        InlineKeyboardMarkup,
+        MessageId,
+        SendMessage,
+    };
+
+    const CHAT_ID: i64 = 42;
+
+    /// Sends a message as the bot, yielding its id and the client used.
+    async fn send(
+        api: &FakeBotApi,
+        message: SendMessage<'_>,
+    ) -> (TgClient, MessageId) {
+        let client = TgClient::new("token", api.client_config()).unwrap();
+        client.send(message, Duration::ZERO).await.unwrap();
+        let sent = api.wait_for_calls("sendMessage", 1).await;
+        let id = sent[0].response["result"]["message_id"].as_i64().unwrap();
+        (client, MessageId::new(id))
+    }
+
+    #[tokio::test]
+    async fn edits_and_deletes_sent_messages() {
+        let api = FakeBotApi::start();
+        let chat = ChatId::new(CHAT_ID);
+        let (client, sent) = send(&api, SendMessage::new(chat, "sent")).await;
+
+        let edit = EditMessageText::new(chat, sent, "edited");
+        let edited = client.send(edit, Duration::ZERO).await.unwrap();
+        assert_eq!(edited.id, sent);
+        let edits = api.wait_for_calls("editMessageText", 1).await;
+        assert_eq!(edits[0].response["result"]["text"], "edited");
+        assert_eq!(edits[0].response["result"]["edit_date"], 0);
+
+        let delete = DeleteMessage::new(chat, sent);
+        client.send(delete, Duration::ZERO).await.unwrap();
+        let edit = EditMessageText::new(chat, sent, "edited again");
+        client.send(edit, Duration::ZERO).await.unwrap_err();
+        let delete = DeleteMessage::new(chat, sent);
+        client.send(delete, Duration::ZERO).await.unwrap_err();
+        let deletes = api.wait_for_calls("deleteMessage", 2).await;
+        assert_eq!(deletes[0].response["result"], true);
+        assert_eq!(
+            deletes[1].response["description"],
+            "Bad Request: message to delete not found"
+        );
+        let edits = api.wait_for_calls("editMessageText", 2).await;
+        assert_eq!(
+            edits[1].response["description"],
+            "Bad Request: message to edit not found"
+        );
+    }
+
+    #[tokio::test]
+    async fn edits_keep_the_keyboard() {
+        let api = FakeBotApi::start();
+        let chat = ChatId::new(CHAT_ID);
+        let mut keyboard = InlineKeyboardMarkup::new();
+        keyboard.add_row(vec![InlineKeyboardButton::callback("Undo", "undo")]);
+        let mut message = SendMessage::new(chat, "sent");
+        message.reply_markup(keyboard);
+        let (client, sent) = send(&api, message).await;
+
+        let edit = EditMessageText::new(chat, sent, "edited");
+        client.send(edit, Duration::ZERO).await.unwrap();
+        let sends = api.wait_for_calls("sendMessage", 1).await;
+        let edits = api.wait_for_calls("editMessageText", 1).await;
+        assert_eq!(
+            edits[0].response["result"]["reply_markup"],
+            sends[0].params["reply_markup"]
+        );
+    }
+}
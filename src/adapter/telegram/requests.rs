@@ -0,0 +1,347 @@
+use super::error::TgError;
+use serde::Serialize;
+use serde_json::Value;
+use telegram_bot::{
+    types::requests::Error,
+    ChatId,
+    HttpRequest,
+    HttpResponse,
+    Integer,
+    JsonRequestType,
+    MessageId,
+    Multipart,
+    MultipartRequestType,
+    MultipartValue,
+    ReplyMarkup,
+    Request,
+    RequestType,
+    RequestUrl,
+    ResponseType,
+    ToMultipart,
+    UserId,
+};
+
+/// Response of a Bot API method as raw JSON, keeping the failure details
+/// that `telegram_bot` does not expose.
+#[derive(Debug, Clone, Copy)]
+pub struct ApiResponse;
+
+impl ResponseType for ApiResponse {
+    type Type = Result<Value, TgError>;
+
+    fn deserialize(response: HttpResponse) -> Result<Self::Type, Error> {
+        let body = response.body.unwrap_or_default();
+        let mut raw = match serde_json::from_slice::<Value>(&body) {
+            Ok(raw) => raw,
+            Err(_) => return Ok(Err(TgError::MalformedResponse)),
+        };
+        if raw["ok"].as_bool() == Some(true) {
+            return Ok(Ok(raw["result"].take()));
+        }
+        Ok(Err(match raw["description"].as_str() {
+            Some(description) => TgError::Api {
+                error_code: raw["error_code"].as_i64(),
+                description: String::from(description),
+                retry_after: raw["parameters"]["retry_after"].as_u64(),
+            },
+            None => TgError::MalformedResponse,
+        }))
+    }
+}
+
+/// Same as `telegram_bot::GetUpdates`, but yields the updates as raw JSON so
+/// fields unknown to `telegram_bot` can still be read.
+#[derive(Debug, Clone, Serialize)]
+pub struct GetRawUpdates {
+    offset: Integer,
+    timeout: Integer,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<Integer>,
+}
+
+impl GetRawUpdates {
+    pub fn new(offset: Integer, timeout: Integer) -> Self {
+        Self { offset, timeout, limit: None }
+    }
+
+    pub fn limit(&mut self, limit: Integer) -> &mut Self {
+        self.limit = Some(limit);
+        self
+    }
+}
+
+impl Request for GetRawUpdates {
+    type Type = JsonRequestType<Self>;
+    type Response = ApiResponse;
+
+    fn serialize(&self) -> Result<HttpRequest, Error> {
+        <Self::Type as RequestType>::serialize(
+            RequestUrl::method("getUpdates"),
+            self,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EntityUser {
+    pub id: UserId,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageEntity {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub offset: usize,
+    pub length: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<EntityUser>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+impl MessageEntity {
+    pub fn new(kind: &'static str, offset: usize, length: usize) -> Self {
+        Self { kind, offset, length, url: None, user: None, language: None }
+    }
+}
+
+/// Same as `telegram_bot::SendMessage`, but with support for entities.
+#[derive(Debug, Clone, Serialize)]
+pub struct SendMessage<'s> {
+    chat_id: ChatId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_thread_id: Option<MessageId>,
+    text: &'s str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    entities: Vec<MessageEntity>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to_message_id: Option<MessageId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_markup: Option<ReplyMarkup>,
+}
+
+impl<'s> SendMessage<'s> {
+    pub fn new(chat_id: ChatId, text: &'s str) -> Self {
+        Self {
+            chat_id,
+            message_thread_id: None,
+            text,
+            entities: Vec::new(),
+            reply_to_message_id: None,
+            reply_markup: None,
+        }
+    }
+
+    pub fn thread(&mut self, thread_id: MessageId) -> &mut Self {
+        self.message_thread_id = Some(thread_id);
+        self
+    }
+
+    pub fn entities(&mut self, entities: Vec<MessageEntity>) -> &mut Self {
+        self.entities = entities;
+        self
+    }
+
+    pub fn reply_to(&mut self, message_id: MessageId) -> &mut Self {
+        self.reply_to_message_id = Some(message_id);
+        self
+    }
+
+    pub fn reply_markup<R>(&mut self, reply_markup: R) -> &mut Self
+    where
+        R: Into<ReplyMarkup>,
+    {
+        self.reply_markup = Some(reply_markup.into());
+        self
+    }
+}
+
+impl<'s> Request for SendMessage<'s> {
+    type Type = JsonRequestType<Self>;
+    type Response = ApiResponse;
+
+    fn serialize(&self) -> Result<HttpRequest, Error> {
+        <Self::Type as RequestType>::serialize(
+            RequestUrl::method("sendMessage"),
+            self,
+        )
+    }
+}
+
+/// Same as `telegram_bot::SendDocument`, but uploads the document from
+/// memory and supports caption entities.
+#[derive(Debug, Clone)]
+pub struct SendDocument<'s> {
+    chat_id: ChatId,
+    message_thread_id: Option<MessageId>,
+    file_name: &'s str,
+    data: &'s [u8],
+    caption: &'s str,
+    caption_entities: Vec<MessageEntity>,
+    reply_to_message_id: Option<MessageId>,
+    reply_markup: Option<ReplyMarkup>,
+}
+
+impl<'s> SendDocument<'s> {
+    pub fn new(chat_id: ChatId, file_name: &'s str, data: &'s [u8]) -> Self {
+        Self {
+            chat_id,
+            message_thread_id: None,
+            file_name,
+            data,
+            caption: "",
+            caption_entities: Vec::new(),
+            reply_to_message_id: None,
+            reply_markup: None,
+        }
+    }
+
+    pub fn thread(&mut self, thread_id: MessageId) -> &mut Self {
+        self.message_thread_id = Some(thread_id);
+        self
+    }
+
+    pub fn caption(
+        &mut self,
+        caption: &'s str,
+        entities: Vec<MessageEntity>,
+    ) -> &mut Self {
+        self.caption = caption;
+        self.caption_entities = entities;
+        self
+    }
+
+    pub fn reply_to(&mut self, message_id: MessageId) -> &mut Self {
+        self.reply_to_message_id = Some(message_id);
+        self
+    }
+
+    pub fn reply_markup<R>(&mut self, reply_markup: R) -> &mut Self
+    where
+        R: Into<ReplyMarkup>,
+    {
+        self.reply_markup = Some(reply_markup.into());
+        self
+    }
+}
+
+impl<'s> ToMultipart for SendDocument<'s> {
+    fn to_multipart(&self) -> Result<Multipart, Error> {
+        let mut fields = vec![
+            ("chat_id", MultipartValue::Text(self.chat_id.to_string().into())),
+            (
+                "document",
+                MultipartValue::Data {
+                    file_name: self.file_name.into(),
+                    data: self.data.to_vec().into(),
+                },
+            ),
+        ];
+        if let Some(thread_id) = self.message_thread_id {
+            fields.push((
+                "message_thread_id",
+                MultipartValue::Text(thread_id.to_string().into()),
+            ));
+        }
+        if !self.caption.is_empty() {
+            fields.push(("caption", MultipartValue::Text(self.caption.into())));
+        }
+        if !self.caption_entities.is_empty() {
+            let entities = serde_json::to_string(&self.caption_entities)
+                .expect("entities are plain data");
+            fields.push((
+                "caption_entities",
+                MultipartValue::Text(entities.into()),
+            ));
+        }
+        if let Some(message_id) = self.reply_to_message_id {
+            fields.push((
+                "reply_to_message_id",
+                MultipartValue::Text(message_id.to_string().into()),
+            ));
+        }
+        if let Some(reply_markup) = &self.reply_markup {
+            let reply_markup = serde_json::to_string(reply_markup)
+                .expect("reply markups are plain data");
+            fields.push((
+                "reply_markup",
+                MultipartValue::Text(reply_markup.into()),
+            ));
+        }
+        Ok(fields)
+    }
+}
+
+impl<'s> Request for SendDocument<'s> {
+    type Type = MultipartRequestType<Self>;
+    type Response = ApiResponse;
+
+    fn serialize(&self) -> Result<HttpRequest, Error> {
+        <Self::Type as RequestType>::serialize(
+            RequestUrl::method("sendDocument"),
+            self,
+        )
+    }
+}
+
+/// Same as `telegram_bot::GetMe`, but keeping failure details.
+#[derive(Debug, Clone, Serialize)]
+pub struct GetMe;
+
+impl Request for GetMe {
+    type Type = JsonRequestType<Self>;
+    type Response = ApiResponse;
+
+    fn serialize(&self) -> Result<HttpRequest, Error> {
+        <Self::Type as RequestType>::serialize(
+            RequestUrl::method("getMe"),
+            self,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SetWebhook<'s> {
+    url: &'s str,
+    secret_token: &'s str,
+    allowed_updates: &'s [&'s str],
+}
+
+impl<'s> SetWebhook<'s> {
+    pub fn new(
+        url: &'s str,
+        secret_token: &'s str,
+        allowed_updates: &'s [&'s str],
+    ) -> Self {
+        Self { url, secret_token, allowed_updates }
+    }
+}
+
+impl<'s> Request for SetWebhook<'s> {
+    type Type = JsonRequestType<Self>;
+    type Response = ApiResponse;
+
+    fn serialize(&self) -> Result<HttpRequest, Error> {
+        <Self::Type as RequestType>::serialize(
+            RequestUrl::method("setWebhook"),
+            self,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeleteWebhook;
+
+impl Request for DeleteWebhook {
+    type Type = JsonRequestType<Self>;
+    type Response = ApiResponse;
+
+    fn serialize(&self) -> Result<HttpRequest, Error> {
+        <Self::Type as RequestType>::serialize(
+            RequestUrl::method("deleteWebhook"),
+            self,
+        )
+    }
+}
@@ -0,0 +1,339 @@
+use super::{
+    requests::{DeleteWebhook, SetWebhook},
+    TgError,
+    TgMessageChannel,
+};
+use crate::{
+    future::DynFuture,
+    port::{Connector, Disconnected, Receiver, Receiving},
+};
+use core::fmt;
+use hyper::{
+    body::HttpBody,
+    service::{make_service_fn, service_fn},
+    Body,
+    Method,
+    Request,
+    Response,
+    Server,
+    StatusCode,
+};
+use serde_json::Value;
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+use telegram_bot::{ChatId, MessageId, UserId};
+use tokio::sync::{mpsc, oneshot, Mutex};
+
+const SECRET_HEADER: &str = "X-Telegram-Bot-Api-Secret-Token";
+const QUEUE_CAPACITY: usize = 256;
+/// Largest update accepted, far above what Telegram posts.
+const MAX_BODY_SIZE: usize = 1 << 20;
+const MAX_SECRET_LEN: usize = 256;
+const ALLOWED_UPDATES: &[&str] = &["message", "channel_post", "callback_query"];
+
+/// Update posted by Telegram, with a way to tell the request it was taken.
+type Posted = (Value, oneshot::Sender<()>);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WebhookConfig {
+    /// Public URL Telegram posts updates to.
+    pub url: String,
+    /// Address the embedded server listens on.
+    pub listen: SocketAddr,
+    /// Token Telegram sends along with each update, proving where it comes
+    /// from.
+    pub secret: String,
+}
+
+impl WebhookConfig {
+    /// Whether Telegram accepts the secret: 1 to 256 characters, each a
+    /// letter, a digit, `_` or `-`.
+    pub fn is_valid_secret(secret: &str) -> bool {
+        (1..=MAX_SECRET_LEN).contains(&secret.len())
+            && secret.bytes().all(|byte| {
+                byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-'
+            })
+    }
+}
+
+/// Receives updates posted by Telegram to an embedded HTTP server, instead of
+/// polling for them.
+#[derive(Clone)]
+pub struct TgWebhook {
+    channel: TgMessageChannel,
+    config: WebhookConfig,
+    local_addr: SocketAddr,
+    updates: Arc<Mutex<mpsc::Receiver<Posted>>>,
+}
+
+impl TgWebhook {
+    /// Starts the server accepting updates. Telegram is only told about it
+    /// when connecting.
+    pub fn bind(
+        channel: TgMessageChannel,
+        config: WebhookConfig,
+    ) -> Result<Self, hyper::Error> {
+        let (queue, updates) = mpsc::channel(QUEUE_CAPACITY);
+        let secret = Arc::<str>::from(config.secret.as_str());
+        let make_service = make_service_fn(move |_| {
+            let queue = queue.clone();
+            let secret = secret.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    accept(request, queue.clone(), secret.clone())
+                }))
+            }
+        });
+
+        let server = Server::try_bind(&config.listen)?.serve(make_service);
+        let local_addr = server.local_addr();
+        tokio::spawn(async move {
+            if let Err(error) = server.await {
+                eprintln!("Error serving webhook...");
+                eprintln!("    {}", error);
+            }
+        });
+
+        Ok(Self {
+            channel,
+            config,
+            local_addr,
+            updates: Arc::new(Mutex::new(updates)),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Tells Telegram to stop posting updates, which are kept until polled or
+    /// posted again.
+    pub async fn remove(&self) -> Result<(), TgError> {
+        self.channel.call(DeleteWebhook).await?;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for TgWebhook {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("TgWebhook")
+            .field("url", &self.config.url)
+            .field("local_addr", &self.local_addr)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Answers Telegram only once the update is received by the bot, so that an
+/// update still queued when the bot stops is posted again later.
+async fn accept(
+    request: Request<Body>,
+    queue: mpsc::Sender<Posted>,
+    secret: Arc<str>,
+) -> Result<Response<Body>, Infallible> {
+    let status = if request.method() != Method::POST {
+        StatusCode::METHOD_NOT_ALLOWED
+    } else if request
+        .headers()
+        .get(SECRET_HEADER)
+        .is_none_or(|token| token.as_bytes() != secret.as_bytes())
+    {
+        StatusCode::UNAUTHORIZED
+    } else {
+        match read_body(request.into_body()).await {
+            Ok(body) => match serde_json::from_slice::<Value>(&body) {
+                Ok(update) => {
+                    let (taken, was_taken) = oneshot::channel();
+                    match queue.send((update, taken)).await {
+                        Ok(()) => match was_taken.await {
+                            Ok(()) => StatusCode::OK,
+                            Err(_) => StatusCode::SERVICE_UNAVAILABLE,
+                        },
+                        Err(_) => StatusCode::SERVICE_UNAVAILABLE,
+                    }
+                },
+                Err(_) => StatusCode::BAD_REQUEST,
+            },
+            Err(status) => status,
+        }
+    };
+
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    Ok(response)
+}
+
+/// Reads a posted body, giving up on it past `MAX_BODY_SIZE`.
+async fn read_body(mut body: Body) -> Result<Vec<u8>, StatusCode> {
+    let mut read = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_| StatusCode::BAD_REQUEST)?;
+        if read.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        read.extend_from_slice(&chunk);
+    }
+    Ok(read)
+}
+
+/// Connecting registers the webhook with Telegram.
+impl Connector for TgWebhook {
+    type Receiver = Self;
+    type Error = TgError;
+
+    fn connect(&self) -> DynFuture<'_, Result<Self::Receiver, Self::Error>> {
+        Box::pin(async move {
+            let request = SetWebhook::new(
+                &self.config.url,
+                &self.config.secret,
+                ALLOWED_UPDATES,
+            );
+            self.channel.call(request).await?;
+            Ok(self.clone())
+        })
+    }
+}
+
+impl Receiver for TgWebhook {
+    type Error = TgError;
+    type MessageId = MessageId;
+    type ChatId = ChatId;
+    type UserId = UserId;
+
+    fn receive<'fut>(
+        &'fut self,
+    ) -> Receiving<'fut, Self::MessageId, Self::ChatId, Self::UserId, Self::Error>
+    {
+        Box::pin(async move {
+            let mut updates = self.updates.lock().await;
+            loop {
+                let (raw_update, taken) = match updates.recv().await {
+                    Some(posted) => posted,
+                    None => break Ok(Err(Disconnected)),
+                };
+                let update = self.channel.update_to_domain(raw_update).await;
+                let _ = taken.send(());
+                if let Some(update) = update {
+                    break Ok(Ok(update));
+                }
+            }
+        })
+    }
+
+    /// Updates are confirmed to Telegram as they are received, so there is
+    /// nothing left to commit.
+    fn commit(&self) -> DynFuture<'_, Result<(), Self::Error>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TgWebhook, WebhookConfig, MAX_BODY_SIZE, SECRET_HEADER};
+    use crate::{
+        adapter::telegram::{ClientConfig, TgClient, TgMessageChannel},
+        domain::{ReplyTarget, Update},
+        port::Receiver,
+    };
+    use hyper::{Body, Client, Method, Request, StatusCode};
+    use std::net::SocketAddr;
+
+    const SECRET: &str = "s3cr3t";
+
+    const UPDATE: &str = r#"{
+        "update_id": 1,
+        "message": {
+            "message_id": 7,
+            "date": 0,
+            "chat": { "id": 42, "type": "private", "first_name": "Ada" },
+            "from": { "id": 42, "is_bot": false, "first_name": "Ada" },
+            "text": "s/foo/bar/"
+        }
+    }"#;
+
+    fn webhook() -> TgWebhook {
+        let config = WebhookConfig {
+            url: String::from("https://example.com/telegram"),
+            listen: ([127, 0, 0, 1], 0).into(),
+            secret: String::from(SECRET),
+        };
+        let client = TgClient::new("token", ClientConfig::default()).unwrap();
+        TgWebhook::bind(TgMessageChannel::new(client), config).unwrap()
+    }
+
+    async fn post(addr: SocketAddr, secret: &str) -> StatusCode {
+        post_body(addr, secret, Body::from(UPDATE)).await
+    }
+
+    async fn post_body(
+        addr: SocketAddr,
+        secret: &str,
+        body: Body,
+    ) -> StatusCode {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(format!("http://{}/", addr))
+            .header(SECRET_HEADER, secret)
+            .body(body)
+            .unwrap();
+        Client::new().request(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn receives_posted_updates() {
+        let webhook = webhook();
+        let (status, received) =
+            tokio::join!(post(webhook.local_addr(), SECRET), webhook.receive());
+        assert_eq!(status, StatusCode::OK);
+
+        let message = match received.unwrap().unwrap() {
+            Update::Message(message) => message,
+            update => panic!("unexpected update {:?}", update),
+        };
+        assert_eq!(message.id.to_string(), "7");
+        assert_eq!(message.data.chat_id.to_string(), "42");
+        assert_eq!(message.data.content.text, "s/foo/bar/");
+        assert_eq!(message.data.reply_target, ReplyTarget::NotReplying);
+    }
+
+    #[tokio::test]
+    async fn rejects_updates_without_the_secret() {
+        let webhook = webhook();
+        assert_eq!(
+            post(webhook.local_addr(), "guess").await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn answers_unavailable_for_updates_never_received() {
+        let webhook = webhook();
+        let posting = tokio::spawn(post(webhook.local_addr(), SECRET));
+        let (update, taken) =
+            webhook.updates.lock().await.recv().await.unwrap();
+        assert_eq!(update["update_id"], 1);
+        assert!(!posting.is_finished());
+        drop(taken);
+        assert_eq!(posting.await.unwrap(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_updates() {
+        let webhook = webhook();
+        let body = Body::from(vec![b' '; MAX_BODY_SIZE + 1]);
+        assert_eq!(
+            post_body(webhook.local_addr(), SECRET, body).await,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+
+    #[test]
+    fn accepts_the_secrets_telegram_does() {
+        assert!(WebhookConfig::is_valid_secret(SECRET));
+        assert!(WebhookConfig::is_valid_secret("A-z_09"));
+        assert!(WebhookConfig::is_valid_secret(&"a".repeat(256)));
+        assert!(!WebhookConfig::is_valid_secret(""));
+        assert!(!WebhookConfig::is_valid_secret(&"a".repeat(257)));
+        assert!(!WebhookConfig::is_valid_secret("s3cr3t!"));
+        assert!(!WebhookConfig::is_valid_secret("sécret"));
+    }
+}
@@ -1,73 +1,388 @@
 use crate::{
-    domain::{Bot, Id},
-    handler::Handler,
-    port::Receiver,
+    clock::{Clock, SystemClock},
+    domain::{Bot, Id, MessageData, NewMessage, ReplyTarget, RichText, Update},
+    future::DynFuture,
+    handler::{CallbackHandler, Handler},
+    middleware::retry::Backoff,
+    port::{Connector, Disconnected, Receiver, Sender},
+    shutdown::Shutdown,
 };
-use std::{error::Error, fmt, sync::Arc};
+use futures::future;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    error::Error,
+    fmt,
+    hash::{Hash, Hasher},
+    num::{NonZeroU32, NonZeroU64, ParseIntError},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::mpsc::{self, error::SendError},
+    task::{JoinError, JoinHandle},
+    time,
+};
+
+const DEFAULT_WORKERS: usize = 8;
+const DEFAULT_QUEUE_CAPACITY: usize = 64;
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_QUARANTINE_FAILURES: u32 = 3;
+const DEFAULT_QUARANTINE_DURATION: Duration = Duration::from_secs(60 * 60);
 
 #[derive(Debug, Clone)]
-pub enum AppError<R, H> {
-    Receiver(R),
-    Handler(H),
+pub enum InvalidErrorPolicy {
+    UnknownPolicy(String),
+    InvalidFailures(ParseIntError),
+    InvalidDuration(ParseIntError),
 }
 
-impl<R, H> fmt::Display for AppError<R, H>
-where
-    R: fmt::Display,
-    H: fmt::Display,
-{
+impl fmt::Display for InvalidErrorPolicy {
     fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Self::Receiver(cause) => write!(fmtr, "{}", cause),
-            Self::Handler(cause) => write!(fmtr, "{}", cause),
+            Self::UnknownPolicy(input) => write!(
+                fmtr,
+                "{:?} is not an error policy, expected \"continue\", \
+                 \"quarantine\", \"quarantine:<failures>\", \
+                 \"quarantine:<failures>:<seconds>\" or \"abort\"",
+                input
+            ),
+            Self::InvalidFailures(cause) => {
+                write!(fmtr, "invalid quarantine failure count: {}", cause)
+            },
+            Self::InvalidDuration(cause) => {
+                write!(fmtr, "invalid quarantine duration: {}", cause)
+            },
         }
     }
 }
 
-impl<R, H> Error for AppError<R, H>
+impl Error for InvalidErrorPolicy {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::UnknownPolicy(_) => None,
+            Self::InvalidFailures(cause) => Some(cause),
+            Self::InvalidDuration(cause) => Some(cause),
+        }
+    }
+}
+
+/// What the app does when a handler fails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ErrorPolicy {
+    /// Logs the error and goes on with the next update.
+    #[default]
+    Continue,
+    /// Logs the error, and ignores a chat for some time once its handling
+    /// failed a number of times in a row.
+    Quarantine { max_failures: u32, duration: Duration },
+    /// Stops the app, yielding the error.
+    Abort,
+}
+
+impl FromStr for ErrorPolicy {
+    type Err = InvalidErrorPolicy;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut parts = input.splitn(3, ':');
+        let name = parts.next().unwrap_or_default();
+        let failures = parts.next();
+        let secs = parts.next();
+        match (name, failures) {
+            ("continue", None) => Ok(Self::Continue),
+            ("abort", None) => Ok(Self::Abort),
+            ("quarantine", failures) => Ok(Self::Quarantine {
+                max_failures: failures
+                    .map(str::parse::<NonZeroU32>)
+                    .transpose()
+                    .map_err(InvalidErrorPolicy::InvalidFailures)?
+                    .map_or(DEFAULT_QUARANTINE_FAILURES, NonZeroU32::get),
+                duration: secs
+                    .map(str::parse::<NonZeroU64>)
+                    .transpose()
+                    .map_err(InvalidErrorPolicy::InvalidDuration)?
+                    .map_or(DEFAULT_QUARANTINE_DURATION, |secs| {
+                        Duration::from_secs(secs.get())
+                    }),
+            }),
+            _ => Err(InvalidErrorPolicy::UnknownPolicy(String::from(input))),
+        }
+    }
+}
+
+/// Notified of every handler error, e.g. to report it somewhere.
+pub trait ErrorHook<C, E>: fmt::Debug
 where
-    R: Error,
-    H: Error,
+    C: Id,
+    E: Error,
 {
-    fn source(&self) -> Option<&(dyn Error + 'static)> {
+    fn on_error<'fut>(
+        &'fut self,
+        chat_id: C,
+        error: &'fut E,
+    ) -> DynFuture<'fut, ()>;
+}
+
+impl<C, E, T> ErrorHook<C, E> for &T
+where
+    C: Id,
+    E: Error,
+    T: ErrorHook<C, E> + ?Sized,
+{
+    fn on_error<'fut>(
+        &'fut self,
+        chat_id: C,
+        error: &'fut E,
+    ) -> DynFuture<'fut, ()> {
+        (**self).on_error(chat_id, error)
+    }
+}
+
+impl<C, E, T> ErrorHook<C, E> for Box<T>
+where
+    C: Id,
+    E: Error,
+    T: ErrorHook<C, E> + ?Sized,
+{
+    fn on_error<'fut>(
+        &'fut self,
+        chat_id: C,
+        error: &'fut E,
+    ) -> DynFuture<'fut, ()> {
+        (**self).on_error(chat_id, error)
+    }
+}
+
+impl<C, E, T> ErrorHook<C, E> for Arc<T>
+where
+    C: Id,
+    E: Error,
+    T: ErrorHook<C, E> + ?Sized,
+{
+    fn on_error<'fut>(
+        &'fut self,
+        chat_id: C,
+        error: &'fut E,
+    ) -> DynFuture<'fut, ()> {
+        (**self).on_error(chat_id, error)
+    }
+}
+
+/// Error hook reporting errors as messages to a chat, e.g. the owner's.
+#[derive(Debug, Clone)]
+pub struct NotifyChat<S>
+where
+    S: Sender,
+{
+    pub sender: S,
+    pub chat_id: S::ChatId,
+}
+
+impl<S, E> ErrorHook<S::ChatId, E> for NotifyChat<S>
+where
+    S: Sender + Send + Sync,
+    S::MessageId: Send + Sync,
+    S::ChatId: Send + Sync,
+    S::UserId: Send + Sync,
+    E: Error + Sync,
+{
+    fn on_error<'fut>(
+        &'fut self,
+        chat_id: S::ChatId,
+        error: &'fut E,
+    ) -> DynFuture<'fut, ()> {
+        Box::pin(async move {
+            let message = NewMessage {
+                data: MessageData {
+                    chat_id: self.chat_id,
+                    thread_id: None,
+                    content: RichText::from(format!(
+                        "Error handling an update in chat {}: {}",
+                        chat_id, error
+                    )),
+                    reply_target: ReplyTarget::NotReplying,
+                },
+                keyboard: None,
+                attachment: None,
+            };
+            if let Err(error) = self.sender.send(&message).await {
+                eprintln!("Error notifying chat {}...", self.chat_id);
+                eprintln!("    {}", error);
+            }
+        })
+    }
+}
+
+/// Identity of the bot in each chat, for apps serving several accounts.
+pub trait Bots<C>: fmt::Debug
+where
+    C: Id,
+{
+    /// Bot answering in the chat, if it differs from the app's own.
+    fn bot_in(&self, chat_id: C) -> Option<&Bot>;
+}
+
+/// Change in the connection of a supervised app.
+#[derive(Debug, Clone, Copy)]
+pub enum ConnectionEvent<'error> {
+    Connecting,
+    Connected,
+    Disconnected,
+    Failed(&'error dyn Error),
+    Reconnecting { delay: Duration },
+}
+
+impl<'error> fmt::Display for ConnectionEvent<'error> {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Self::Receiver(cause) => cause.source(),
-            Self::Handler(cause) => cause.source(),
+            Self::Connecting => write!(fmtr, "connecting"),
+            Self::Connected => write!(fmtr, "connected"),
+            Self::Disconnected => write!(fmtr, "disconnected"),
+            Self::Failed(cause) => write!(fmtr, "failed: {}", cause),
+            Self::Reconnecting { delay } => {
+                write!(fmtr, "reconnecting in {:?}", delay)
+            },
         }
     }
 }
 
+/// How a supervised app stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stopped {
+    /// Every pending update was handled.
+    Drained,
+    /// Pending updates were dropped when the drain timeout expired.
+    Forced,
+    /// A worker died while handling updates, so those received were not
+    /// confirmed and are delivered again on the next run.
+    Crashed,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ChatHealth {
+    failures: u32,
+    quarantined_until: Option<Instant>,
+}
+
+type DynHandler<'handlers, M, C, U, E> = Arc<
+    dyn Handler<MessageId = M, ChatId = C, UserId = U, Error = E>
+        + Send
+        + Sync
+        + 'handlers,
+>;
+
+type DynCallbackHandler<'handlers, M, C, U, E> = Arc<
+    dyn CallbackHandler<MessageId = M, ChatId = C, UserId = U, Error = E>
+        + Send
+        + Sync
+        + 'handlers,
+>;
+
+type DynErrorHook<'handlers, C, E> =
+    Arc<dyn ErrorHook<C, E> + Send + Sync + 'handlers>;
+
+type DynBots<'handlers, C> = Arc<dyn Bots<C> + Send + Sync + 'handlers>;
+
+type DynClock<'handlers> = Arc<dyn Clock + Send + Sync + 'handlers>;
+
 #[derive(Debug, Clone)]
-pub struct App<'handlers, M, C, E>
+pub struct App<'handlers, M, C, U, E>
 where
     M: Id,
     C: Id,
+    U: Id,
     E: Error,
 {
     bot: Bot,
-    handlers: Vec<
-        Arc<
-            dyn Handler<MessageId = M, ChatId = C, Error = E>
-                + Send
-                + Sync
-                + 'handlers,
-        >,
-    >,
+    bots: Option<DynBots<'handlers, C>>,
+    handlers: Vec<DynHandler<'handlers, M, C, U, E>>,
+    callback_handlers: Vec<DynCallbackHandler<'handlers, M, C, U, E>>,
+    workers: usize,
+    queue_capacity: usize,
+    drain_timeout: Duration,
+    error_policy: ErrorPolicy,
+    error_hook: Option<DynErrorHook<'handlers, C, E>>,
+    clock: DynClock<'handlers>,
 }
 
-impl<'handlers, M, C, E> App<'handlers, M, C, E>
+impl<'handlers, M, C, U, E> App<'handlers, M, C, U, E>
 where
     M: Id,
     C: Id,
+    U: Id,
     E: Error,
 {
     pub fn new(bot: Bot) -> Self {
-        Self { bot, handlers: Vec::new() }
+        Self {
+            bot,
+            bots: None,
+            handlers: Vec::new(),
+            callback_handlers: Vec::new(),
+            workers: DEFAULT_WORKERS,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            error_policy: ErrorPolicy::default(),
+            error_hook: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Sets how many updates are handled at once, each by a worker owning a
+    /// share of the chats.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Sets how many updates wait for each worker before receiving is held
+    /// back.
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = capacity.max(1);
+        self
+    }
+
+    /// Sets how long pending updates may take to be handled on shutdown.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    pub fn error_policy(mut self, policy: ErrorPolicy) -> Self {
+        self.error_policy = policy;
+        self
+    }
+
+    pub fn error_hook<H>(mut self, hook: H) -> Self
+    where
+        H: ErrorHook<C, E> + Send + Sync + 'handlers,
+    {
+        self.error_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets the clock by which quarantines expire, for tests to drive.
+    #[cfg(test)]
+    pub fn clock<K>(mut self, clock: K) -> Self
+    where
+        K: Clock + Send + Sync + 'handlers,
+    {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Sets which bot answers in each chat, the app's own answering where
+    /// none is given.
+    pub fn bots<B>(mut self, bots: B) -> Self
+    where
+        B: Bots<C> + Send + Sync + 'handlers,
+    {
+        self.bots = Some(Arc::new(bots));
+        self
     }
 
     pub fn handler<H>(mut self, handler: H) -> Self
     where
-        H: Handler<MessageId = M, ChatId = C, Error = E>
+        H: Handler<MessageId = M, ChatId = C, UserId = U, Error = E>
             + Send
             + Sync
             + 'handlers,
@@ -76,24 +391,800 @@ where
         self
     }
 
-    pub async fn run<R>(self, receiver: R) -> Result<(), AppError<R::Error, E>>
+    pub fn callback_handler<H>(mut self, handler: H) -> Self
     where
-        R: Receiver<MessageId = M, ChatId = C>,
+        H: CallbackHandler<MessageId = M, ChatId = C, UserId = U, Error = E>
+            + Send
+            + Sync
+            + 'handlers,
     {
-        while let Ok(input_message) =
-            receiver.receive().await.map_err(AppError::Receiver)?
-        {
-            for handler in &self.handlers {
-                if handler
-                    .run(&self.bot, &input_message)
-                    .await
-                    .map_err(AppError::Handler)?
-                {
-                    break;
-                }
+        self.callback_handlers.push(Arc::new(handler));
+        self
+    }
+
+    /// Handles an update according to the error policy, only failing when it
+    /// says to abort.
+    async fn process(
+        &self,
+        update: &Update<M, C, U>,
+        health: &mut HashMap<C, ChatHealth>,
+    ) -> Result<(), E> {
+        let chat_id = chat_of(update);
+        let now = self.clock.now();
+        if let Some(chat) = health.get(&chat_id) {
+            if chat.quarantined_until.is_some_and(|until| until > now) {
+                return Ok(());
             }
         }
 
+        let error = match self.handle(update).await {
+            Ok(()) => {
+                health.remove(&chat_id);
+                return Ok(());
+            },
+            Err(error) => error,
+        };
+
+        if let Some(hook) = &self.error_hook {
+            hook.on_error(chat_id, &error).await;
+        }
+        match self.error_policy {
+            ErrorPolicy::Continue => {
+                eprintln!("Error handling update in chat {}...", chat_id);
+                eprintln!("    {}", error);
+            },
+            ErrorPolicy::Quarantine { max_failures, duration } => {
+                eprintln!("Error handling update in chat {}...", chat_id);
+                eprintln!("    {}", error);
+                let chat = health.entry(chat_id).or_default();
+                chat.failures += 1;
+                if chat.failures >= max_failures {
+                    eprintln!(
+                        "Ignoring chat {} for {:?} after {} failures",
+                        chat_id, duration, chat.failures
+                    );
+                    chat.failures = 0;
+                    chat.quarantined_until = Some(now + duration);
+                }
+            },
+            ErrorPolicy::Abort => return Err(error),
+        }
+        Ok(())
+    }
+
+    async fn handle(&self, update: &Update<M, C, U>) -> Result<(), E> {
+        let bot = self
+            .bots
+            .as_ref()
+            .and_then(|bots| bots.bot_in(chat_of(update)))
+            .unwrap_or(&self.bot);
+        match update {
+            Update::Message(input_message) => {
+                for handler in &self.handlers {
+                    if handler.run(bot, input_message).await? {
+                        break;
+                    }
+                }
+            },
+            Update::Callback(callback) => {
+                for handler in &self.callback_handlers {
+                    if handler.run(bot, callback).await? {
+                        break;
+                    }
+                }
+            },
+        }
         Ok(())
     }
 }
+
+impl<M, C, U, E> App<'static, M, C, U, E>
+where
+    M: Id + Send + Sync + 'static,
+    C: Id + Send + Sync + 'static,
+    U: Id + Send + Sync + 'static,
+    E: Error + Send + 'static,
+{
+    /// Runs the app, connecting again whenever the receiver disconnects or
+    /// fails, with backoff between attempts, until shut down or aborted by a
+    /// handler error.
+    ///
+    /// On shutdown, receiving stops and pending updates are given the drain
+    /// timeout to be handled, after which the remaining ones are dropped.
+    ///
+    /// Updates are handled by a set of worker tasks, each owning a share of
+    /// the chats, so updates of a chat are handled in order while other chats
+    /// go on. Receiving waits while the queue of the target worker is full. A
+    /// worker that dies is replaced, and nothing received is confirmed since.
+    pub async fn run_supervised<T>(
+        self,
+        connector: T,
+        backoff: Backoff,
+        shutdown: Shutdown,
+    ) -> Result<Stopped, E>
+    where
+        T: Connector,
+        T::Receiver: Receiver<MessageId = M, ChatId = C, UserId = U>,
+    {
+        let (abort_sender, mut aborts) = mpsc::channel(1);
+        let app = Arc::new(self);
+        let (mut queues, mut workers): (Vec<_>, Vec<_>) = (0..app.workers)
+            .map(|_| Self::spawn_worker(&app, &abort_sender))
+            .collect();
+        let mut crashed = false;
+
+        let connected = async {
+            let mut failures = 0;
+            loop {
+                if failures > 0 {
+                    let delay = backoff.delay(failures - 1);
+                    notify(ConnectionEvent::Reconnecting { delay });
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => (),
+                        _ = shutdown.triggered() => return Ok(None),
+                    }
+                }
+                failures += 1;
+
+                notify(ConnectionEvent::Connecting);
+                let connected = tokio::select! {
+                    connected = connector.connect() => connected,
+                    _ = shutdown.triggered() => return Ok(None),
+                };
+                let receiver = match connected {
+                    Ok(receiver) => receiver,
+                    Err(error) => {
+                        notify(ConnectionEvent::Failed(&error));
+                        continue;
+                    },
+                };
+                notify(ConnectionEvent::Connected);
+
+                loop {
+                    let received = tokio::select! {
+                        received = receiver.receive() => Some(received),
+                        _ = shutdown.triggered() => None,
+                        Some(error) = aborts.recv() => return Err(error),
+                        (died, shard, _) = future::select_all(&mut workers) => {
+                            // Workers stopped by a handler error report it
+                            // before stopping.
+                            if let Ok(error) = aborts.try_recv() {
+                                return Err(error);
+                            }
+                            report_death(died);
+                            crashed = true;
+                            (queues[shard], workers[shard]) =
+                                Self::spawn_worker(&app, &abort_sender);
+                            continue;
+                        },
+                    };
+                    let update = match received {
+                        Some(Ok(Ok(update))) => update,
+                        Some(Ok(Err(Disconnected))) => {
+                            notify(ConnectionEvent::Disconnected);
+                            break;
+                        },
+                        Some(Err(error)) => {
+                            notify(ConnectionEvent::Failed(&error));
+                            break;
+                        },
+                        None => return Ok(Some(receiver)),
+                    };
+                    failures = 0;
+
+                    let shard = shard(chat_of(&update), queues.len());
+                    let sent = tokio::select! {
+                        sent = queues[shard].send(update) => sent,
+                        Some(error) = aborts.recv() => return Err(error),
+                        // Nothing is committed, so that the update dropped
+                        // here is delivered again on the next run, along with
+                        // the others received in this one.
+                        _ = shutdown.triggered() => return Ok(None),
+                    };
+                    if let Err(SendError(update)) = sent {
+                        if let Ok(error) = aborts.try_recv() {
+                            return Err(error);
+                        }
+                        report_death((&mut workers[shard]).await);
+                        crashed = true;
+                        (queues[shard], workers[shard]) =
+                            Self::spawn_worker(&app, &abort_sender);
+                        // A new queue has room for at least this update.
+                        let _ = queues[shard].try_send(update);
+                    }
+                }
+            }
+        }
+        .await;
+
+        let receiver = match connected {
+            Ok(receiver) => receiver,
+            Err(error) => {
+                for worker in workers {
+                    worker.abort();
+                }
+                return Err(error);
+            },
+        };
+
+        eprintln!("Shutting down, finishing pending updates...");
+        drop(queues);
+        let drained = time::timeout(
+            app.drain_timeout,
+            future::join_all(workers.iter_mut()),
+        )
+        .await;
+        if let Ok(error) = aborts.try_recv() {
+            return Err(error);
+        }
+        let joined = match drained {
+            Ok(joined) => joined,
+            Err(_) => {
+                for worker in workers {
+                    worker.abort();
+                }
+                return Ok(Stopped::Forced);
+            },
+        };
+        for died in joined.into_iter().filter(Result::is_err) {
+            report_death(died);
+            crashed = true;
+        }
+        if crashed {
+            return Ok(Stopped::Crashed);
+        }
+
+        // Only committed once everything received was handled, so that an
+        // interrupted update is delivered again on the next run.
+        if let Some(receiver) = receiver {
+            if let Err(error) = receiver.commit().await {
+                eprintln!("Error committing received updates...");
+                eprintln!("    {}", error);
+            }
+        }
+        Ok(Stopped::Drained)
+    }
+
+    fn spawn_worker(
+        app: &Arc<Self>,
+        aborts: &mpsc::Sender<E>,
+    ) -> (mpsc::Sender<Update<M, C, U>>, JoinHandle<()>) {
+        let (queue, updates) = mpsc::channel(app.queue_capacity);
+        let worker =
+            tokio::spawn(Arc::clone(app).work(updates, aborts.clone()));
+        (queue, worker)
+    }
+
+    async fn work(
+        self: Arc<Self>,
+        mut updates: mpsc::Receiver<Update<M, C, U>>,
+        aborts: mpsc::Sender<E>,
+    ) {
+        let mut health = HashMap::new();
+        while let Some(update) = updates.recv().await {
+            if let Err(error) = self.process(&update, &mut health).await {
+                let _ = aborts.send(error).await;
+                break;
+            }
+        }
+    }
+}
+
+/// Logs why a worker died, which is then replaced.
+fn report_death(died: Result<(), JoinError>) {
+    eprintln!("Error in a worker, which stopped handling updates...");
+    match died {
+        Ok(()) => eprintln!("    its queue closed"),
+        Err(error) => eprintln!("    {}", error),
+    }
+}
+
+fn notify(event: ConnectionEvent) {
+    eprintln!("Connection: {}", event);
+}
+
+fn chat_of<M, C, U>(update: &Update<M, C, U>) -> C
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    match update {
+        Update::Message(message) => message.data.chat_id,
+        Update::Callback(callback) => callback.message.data.chat_id,
+    }
+}
+
+/// Picks the worker owning a chat.
+fn shard<C>(chat_id: C, workers: usize) -> usize
+where
+    C: Id,
+{
+    let mut hasher = DefaultHasher::new();
+    chat_id.hash(&mut hasher);
+    (hasher.finish() % workers as u64) as usize
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        shard,
+        App,
+        ErrorPolicy,
+        InvalidErrorPolicy,
+        NotifyChat,
+        Stopped,
+        DEFAULT_QUARANTINE_DURATION,
+        DEFAULT_QUARANTINE_FAILURES,
+    };
+    use crate::{
+        adapter::memory::{
+            conversation::Conversation,
+            MemoryChannel,
+            Message,
+            NewMessage,
+        },
+        clock::{Clock, ManualClock},
+        domain::{Bot, MessageData, ReplyTarget},
+        future::DynFuture,
+        handler::Handler,
+        middleware::{
+            rate_limit::RetryAfter,
+            retry::{
+                Backoff,
+                BreakerConfig,
+                CircuitBreaker,
+                Retrying,
+                Transient,
+            },
+        },
+        port::{Connector, Disconnected, Receiver, Receiving, Sender},
+        shutdown::Shutdown,
+    };
+    use std::{
+        convert::Infallible,
+        error::Error,
+        fmt,
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+    use tokio::{
+        sync::{Notify, Semaphore},
+        time,
+    };
+
+    const BACKOFF: Backoff = Backoff {
+        max_retries: Some(2),
+        initial_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(1),
+    };
+
+    const QUARANTINE: ErrorPolicy = ErrorPolicy::Quarantine {
+        max_failures: 2,
+        duration: Duration::from_secs(60),
+    };
+
+    #[derive(Debug)]
+    struct Failure;
+
+    impl fmt::Display for Failure {
+        fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+            write!(fmtr, "failure")
+        }
+    }
+
+    impl Error for Failure {}
+
+    impl Transient for Failure {
+        fn is_transient(&self) -> bool {
+            true
+        }
+    }
+
+    impl RetryAfter for Failure {
+        fn retry_after(&self) -> Option<Duration> {
+            None
+        }
+    }
+
+    /// Repeats every message, failing on "fail", panicking on "panic" once
+    /// repeated and waiting for a permit of the gate on "wait".
+    #[derive(Debug)]
+    struct Echo {
+        sender: MemoryChannel,
+        gate: Arc<Semaphore>,
+    }
+
+    impl Handler for Echo {
+        type MessageId = u64;
+        type ChatId = u64;
+        type UserId = u64;
+        type Error = Failure;
+
+        fn run<'fut>(
+            &'fut self,
+            _bot: &'fut Bot,
+            input_message: &'fut Message,
+        ) -> DynFuture<'fut, Result<bool, Failure>> {
+            Box::pin(async move {
+                let text = &input_message.data.content.text;
+                match text.as_str() {
+                    "fail" => return Err(Failure),
+                    "wait" => self.gate.acquire().await.unwrap().forget(),
+                    _ => (),
+                }
+                let message = NewMessage {
+                    data: MessageData {
+                        chat_id: input_message.data.chat_id,
+                        thread_id: None,
+                        content: text.clone().into(),
+                        reply_target: ReplyTarget::MessageId(input_message.id),
+                    },
+                    keyboard: None,
+                    attachment: None,
+                };
+                let _ = self.sender.send(&message).await;
+                if text == "panic" {
+                    panic!("asked to panic");
+                }
+                Ok(true)
+            })
+        }
+    }
+
+    #[derive(Debug)]
+    struct FailingSender;
+
+    impl Sender for FailingSender {
+        type MessageId = u64;
+        type ChatId = u64;
+        type UserId = u64;
+        type Error = Failure;
+
+        fn send<'fut>(
+            &'fut self,
+            _message: &'fut NewMessage,
+        ) -> DynFuture<'fut, Result<u64, Failure>> {
+            Box::pin(async { Err(Failure) })
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    enum Breakage {
+        Disconnects,
+        Fails,
+    }
+
+    /// Connection to the channel, which may be broken.
+    #[derive(Debug)]
+    struct Connection {
+        channel: MemoryChannel,
+        breakage: Option<Breakage>,
+    }
+
+    impl Receiver for Connection {
+        type MessageId = u64;
+        type ChatId = u64;
+        type UserId = u64;
+        type Error = Failure;
+
+        fn receive(&self) -> Receiving<'_, u64, u64, u64, Failure> {
+            Box::pin(async move {
+                match self.breakage {
+                    Some(Breakage::Disconnects) => Ok(Err(Disconnected)),
+                    Some(Breakage::Fails) => Err(Failure),
+                    None => match self.channel.receive().await {
+                        Ok(received) => Ok(received),
+                        Err(never) => match never {},
+                    },
+                }
+            })
+        }
+
+        fn commit(&self) -> DynFuture<'_, Result<(), Failure>> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    /// Connects to the channel, only the first connection being broken.
+    #[derive(Debug)]
+    struct Unreliable {
+        channel: MemoryChannel,
+        breakage: Breakage,
+        connections: AtomicU32,
+    }
+
+    impl Unreliable {
+        fn new(channel: MemoryChannel, breakage: Breakage) -> Self {
+            Self { channel, breakage, connections: AtomicU32::new(0) }
+        }
+    }
+
+    impl Connector for Unreliable {
+        type Receiver = Connection;
+        type Error = Failure;
+
+        fn connect(&self) -> DynFuture<'_, Result<Connection, Failure>> {
+            Box::pin(async move {
+                let first =
+                    self.connections.fetch_add(1, Ordering::SeqCst) == 0;
+                Ok(Connection {
+                    channel: self.channel.clone(),
+                    breakage: first.then_some(self.breakage),
+                })
+            })
+        }
+    }
+
+    /// Runs the app until it answers a message, which it can only do once
+    /// connected again.
+    async fn expect_reconnection<T>(channel: MemoryChannel, connector: T)
+    where
+        T: Connector,
+        T::Receiver: Receiver<MessageId = u64, ChatId = u64, UserId = u64>,
+    {
+        let shutdown = Shutdown::new();
+        let app = echo(channel.clone());
+        let (stopped, ()) = tokio::join!(
+            app.run_supervised(connector, BACKOFF, shutdown.clone()),
+            async {
+                let hello = channel.say(1, 1, "hello", None);
+                let sent =
+                    time::timeout(Duration::from_secs(5), channel.next_sent())
+                        .await
+                        .expect("bot did not reconnect");
+                assert_eq!(sent.reply_to(), Some(hello));
+                shutdown.trigger();
+            },
+        );
+        assert!(matches!(stopped, Ok(Stopped::Drained)));
+    }
+
+    /// Counts the updates received from the channel, notifying each time,
+    /// and the commits.
+    #[derive(Debug, Clone, Default)]
+    struct Counting {
+        channel: MemoryChannel,
+        received: Arc<AtomicU32>,
+        counted: Arc<Notify>,
+        commits: Arc<AtomicU32>,
+    }
+
+    impl Receiver for Counting {
+        type MessageId = u64;
+        type ChatId = u64;
+        type UserId = u64;
+        type Error = Infallible;
+
+        fn receive(&self) -> Receiving<'_, u64, u64, u64, Infallible> {
+            Box::pin(async move {
+                let received = self.channel.receive().await;
+                self.received.fetch_add(1, Ordering::SeqCst);
+                self.counted.notify_one();
+                received
+            })
+        }
+
+        fn commit(&self) -> DynFuture<'_, Result<(), Infallible>> {
+            self.commits.fetch_add(1, Ordering::SeqCst);
+            self.channel.commit()
+        }
+    }
+
+    impl Connector for Counting {
+        type Receiver = Self;
+        type Error = Infallible;
+
+        fn connect(&self) -> DynFuture<'_, Result<Self, Infallible>> {
+            Box::pin(async move { Ok(self.clone()) })
+        }
+    }
+
+    fn echo(channel: MemoryChannel) -> App<'static, u64, u64, u64, Failure> {
+        gated_echo(channel, Arc::new(Semaphore::new(0)))
+    }
+
+    fn gated_echo(
+        channel: MemoryChannel,
+        gate: Arc<Semaphore>,
+    ) -> App<'static, u64, u64, u64, Failure> {
+        App::new(Bot { handle: String::from("regex_bot") })
+            .handler(Echo { sender: channel, gate })
+    }
+
+    #[test]
+    fn parses_error_policies() {
+        let parse = |input: &str| input.parse::<ErrorPolicy>().ok();
+        assert_eq!(parse("continue"), Some(ErrorPolicy::Continue));
+        assert_eq!(parse("abort"), Some(ErrorPolicy::Abort));
+        assert_eq!(
+            parse("quarantine"),
+            Some(ErrorPolicy::Quarantine {
+                max_failures: DEFAULT_QUARANTINE_FAILURES,
+                duration: DEFAULT_QUARANTINE_DURATION,
+            })
+        );
+        assert_eq!(
+            parse("quarantine:5"),
+            Some(ErrorPolicy::Quarantine {
+                max_failures: 5,
+                duration: DEFAULT_QUARANTINE_DURATION,
+            })
+        );
+        assert_eq!(
+            parse("quarantine:5:600"),
+            Some(ErrorPolicy::Quarantine {
+                max_failures: 5,
+                duration: Duration::from_secs(600),
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_error_policies() {
+        let parse = str::parse::<ErrorPolicy>;
+        assert!(matches!(
+            parse("quarantine:0"),
+            Err(InvalidErrorPolicy::InvalidFailures(_))
+        ));
+        assert!(matches!(
+            parse("quarantine:x"),
+            Err(InvalidErrorPolicy::InvalidFailures(_))
+        ));
+        assert!(matches!(
+            parse("quarantine:2:0"),
+            Err(InvalidErrorPolicy::InvalidDuration(_))
+        ));
+        for input in ["ignore", "abort:1", "continue:2", ""] {
+            assert!(matches!(
+                parse(input),
+                Err(InvalidErrorPolicy::UnknownPolicy(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn ignores_failing_chats_until_the_quarantine_expires() {
+        let clock = ManualClock::new();
+        let conversation = Conversation::start(|channel| {
+            echo(channel).error_policy(QUARANTINE).clock(clock.clone())
+        });
+        conversation.user(1).says("fail");
+        let hello = conversation.user(1).says("hello");
+        conversation.expect_reply(hello).await;
+        conversation.user(1).says("fail");
+        conversation.user(1).says("fail");
+        conversation.user(1).says("hello");
+        let other = conversation.user(2).says("hello");
+        conversation.expect_reply(other).await;
+        conversation.expect_silence().await;
+
+        clock.sleep(Duration::from_secs(60)).await;
+        let hello = conversation.user(1).says("hello");
+        conversation.expect_reply(hello).await;
+        conversation.finish().await;
+    }
+
+    #[tokio::test]
+    async fn notifies_the_owner_of_errors() {
+        let conversation = Conversation::start(|channel| {
+            let hook = NotifyChat { sender: channel.clone(), chat_id: 99 };
+            echo(channel).error_hook(hook)
+        });
+        conversation.user(1).in_chat(5).says("fail");
+        let notice = conversation.expect_sent().await;
+        assert_eq!(notice.message.data.chat_id, 99);
+        assert_eq!(
+            notice.text(),
+            "Error handling an update in chat 5: failure"
+        );
+        conversation.finish().await;
+    }
+
+    #[tokio::test]
+    async fn goes_on_when_the_owner_cannot_be_notified() {
+        let conversation = Conversation::start(|channel| {
+            let hook = NotifyChat { sender: FailingSender, chat_id: 99 };
+            echo(channel).error_hook(hook)
+        });
+        conversation.user(1).says("fail");
+        let hello = conversation.user(1).says("hello");
+        conversation.expect_reply(hello).await;
+        conversation.finish().await;
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_disconnecting() {
+        let channel = MemoryChannel::new();
+        let connector = Unreliable::new(channel.clone(), Breakage::Disconnects);
+        expect_reconnection(channel, &connector).await;
+        assert_eq!(connector.connections.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn reconnects_once_retries_give_up() {
+        let channel = MemoryChannel::new();
+        let connector = Retrying::with_clock(
+            Unreliable::new(channel.clone(), Breakage::Fails),
+            ManualClock::new(),
+            BACKOFF,
+            CircuitBreaker::new(BreakerConfig::default()),
+        );
+        expect_reconnection(channel, connector).await;
+    }
+
+    #[tokio::test]
+    async fn handles_chats_in_order_without_holding_up_others() {
+        let gate = Arc::new(Semaphore::new(0));
+        let conversation = Conversation::start(|channel| {
+            gated_echo(channel, Arc::clone(&gate)).workers(4)
+        });
+        let other_chat =
+            (2..).find(|&chat_id| shard(chat_id, 4) != shard(1, 4)).unwrap();
+        let wait = conversation.user(1).in_chat(1).says("wait");
+        let after = conversation.user(1).in_chat(1).says("after");
+        let other = conversation.user(2).in_chat(other_chat).says("other");
+        conversation.expect_reply(other).await;
+        conversation.expect_silence().await;
+
+        gate.add_permits(1);
+        conversation.expect_reply(wait).await;
+        conversation.expect_reply(after).await;
+        conversation.finish().await;
+    }
+
+    #[tokio::test]
+    async fn stops_receiving_while_the_queue_is_full() {
+        let connector = Counting::default();
+        let channel = connector.channel.clone();
+        let received = Arc::clone(&connector.received);
+        let counted = Arc::clone(&connector.counted);
+        let shutdown = Shutdown::new();
+        let app = gated_echo(channel.clone(), Arc::new(Semaphore::new(0)))
+            .workers(1)
+            .queue_capacity(1)
+            .drain_timeout(Duration::from_millis(10));
+        let run = app.run_supervised(connector, BACKOFF, shutdown.clone());
+        let (stopped, ()) =
+            tokio::join!(time::timeout(Duration::from_secs(5), run), async {
+                for text in ["wait", "queued", "held", "unreceived"] {
+                    channel.say(1, 1, text, None);
+                }
+                while received.load(Ordering::SeqCst) < 3 {
+                    counted.notified().await;
+                }
+                shutdown.trigger();
+            },);
+        let stopped = stopped.expect("shutdown waited for room in the queue");
+        assert!(matches!(stopped, Ok(Stopped::Forced)));
+        // One was being handled, one was queued and one waited for room in
+        // the queue.
+        assert_eq!(received.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn replaces_dead_workers_without_confirming_updates() {
+        let connector = Counting::default();
+        let channel = connector.channel.clone();
+        let commits = Arc::clone(&connector.commits);
+        let shutdown = Shutdown::new();
+        let app = echo(channel.clone()).workers(1);
+        let run = app.run_supervised(connector, BACKOFF, shutdown.clone());
+        let (stopped, ()) = tokio::join!(run, async {
+            for text in ["panic", "hello"] {
+                let said = channel.say(1, 1, text, None);
+                let sent =
+                    time::timeout(Duration::from_secs(5), channel.next_sent())
+                        .await
+                        .expect("no worker was left to answer");
+                assert_eq!(sent.reply_to(), Some(said));
+            }
+            shutdown.trigger();
+        });
+        assert!(matches!(stopped, Ok(Stopped::Crashed)));
+        assert_eq!(commits.load(Ordering::SeqCst), 0);
+    }
+}
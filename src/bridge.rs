@@ -0,0 +1,625 @@
+//! Mirroring of messages between linked chats, possibly on different
+//! platforms.
+//!
+//! Users' messages are relayed by a [`BridgeHandler`], under the name of their
+//! author, while the bot's own messages are mirrored by sending through
+//! [`Bridged`]. Receiving through [`Bridged`] shows copies as their original,
+//! so that commands replying to a copy see what its author wrote.
+
+use crate::{
+    domain::{
+        Author,
+        Bot,
+        ContentKind,
+        Id,
+        MediaKind,
+        Message,
+        MessageData,
+        NewMessage,
+        ReplyTarget,
+        RichText,
+        Span,
+        Style,
+        Update,
+    },
+    future::DynFuture,
+    handler::Handler,
+    port::{Connector, Deleter, Receiver, Receiving, Sender},
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{self, Arc, PoisonError},
+};
+
+/// Mirrored messages remembered, the oldest being forgotten first.
+const DEFAULT_CAPACITY: usize = 10_000;
+
+/// A message and its copies in the linked chats.
+#[derive(Debug)]
+struct Group<M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    author: Option<Author<U>>,
+    content_kind: ContentKind,
+    content: RichText<U>,
+    /// Where the original is, then where its copies are.
+    members: Vec<(C, M)>,
+}
+
+#[derive(Debug)]
+struct State<M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    capacity: usize,
+    next_group: u64,
+    groups: HashMap<u64, Group<M, C, U>>,
+    /// Group of every original and copy.
+    members: HashMap<(C, M), u64>,
+    order: VecDeque<u64>,
+}
+
+impl<M, C, U> State<M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    fn group(&self, chat_id: C, message_id: M) -> Option<&Group<M, C, U>> {
+        let group = self.members.get(&(chat_id, message_id))?;
+        self.groups.get(group)
+    }
+
+    fn record(&mut self, group: Group<M, C, U>) {
+        self.next_group += 1;
+        for &member in &group.members {
+            self.members.insert(member, self.next_group);
+        }
+        self.groups.insert(self.next_group, group);
+        self.order.push_back(self.next_group);
+        while self.order.len() > self.capacity {
+            let forgotten = self
+                .order
+                .pop_front()
+                .and_then(|oldest| self.groups.remove(&oldest));
+            for member in forgotten.iter().flat_map(|group| &group.members) {
+                self.members.remove(member);
+            }
+        }
+    }
+}
+
+/// Chats linked to each other, along with which message is a copy of which.
+#[derive(Debug, Clone)]
+pub struct Bridge<M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    links: HashMap<C, Vec<C>>,
+    state: Arc<sync::Mutex<State<M, C, U>>>,
+}
+
+impl<M, C, U> Default for Bridge<M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M, C, U> Bridge<M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    pub fn new() -> Self {
+        let state = State {
+            capacity: DEFAULT_CAPACITY,
+            next_group: 0,
+            groups: HashMap::new(),
+            members: HashMap::new(),
+            order: VecDeque::new(),
+        };
+        Self { links: HashMap::new(), state: Arc::new(sync::Mutex::new(state)) }
+    }
+
+    /// Links two chats, mirroring messages of each in the other.
+    pub fn link(mut self, chat_id: C, other_chat_id: C) -> Self {
+        if chat_id != other_chat_id {
+            self.links.entry(chat_id).or_default().push(other_chat_id);
+            self.links.entry(other_chat_id).or_default().push(chat_id);
+        }
+        self
+    }
+
+    fn lock_state(&self) -> sync::MutexGuard<'_, State<M, C, U>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Sends a copy of the message to every chat linked to its own, naming
+    /// its author unless it is the bot.
+    async fn relay<S>(
+        &self,
+        sender: &S,
+        message: &Message<M, C, U>,
+    ) -> Result<(), S::Error>
+    where
+        S: Sender<MessageId = M, ChatId = C, UserId = U>,
+    {
+        let chat_id = message.data.chat_id;
+        let linked = match self.links.get(&chat_id) {
+            Some(linked) => linked,
+            None => return Ok(()),
+        };
+        let content = copy_content(message);
+        let replied_id = match &message.data.reply_target {
+            ReplyTarget::Message(target) => Some(target.id),
+            ReplyTarget::MessageId(target_id) => Some(*target_id),
+            ReplyTarget::Prunned | ReplyTarget::NotReplying => None,
+        };
+
+        let mut members = vec![(chat_id, message.id)];
+        let mut result = Ok(());
+        for &linked_chat_id in linked {
+            let reply_target = replied_id
+                .and_then(|replied_id| {
+                    self.counterpart(chat_id, replied_id, linked_chat_id)
+                })
+                .map_or(ReplyTarget::NotReplying, ReplyTarget::MessageId);
+            let copy = NewMessage {
+                data: MessageData {
+                    chat_id: linked_chat_id,
+                    thread_id: None,
+                    content: content.clone(),
+                    reply_target,
+                },
+                keyboard: None,
+                attachment: None,
+            };
+            match sender.send(&copy).await {
+                Ok(copy_id) => members.push((linked_chat_id, copy_id)),
+                Err(error) => result = result.and(Err(error)),
+            }
+        }
+
+        self.lock_state().record(Group {
+            author: message.author.clone(),
+            content_kind: message.content_kind,
+            content: message.data.content.clone(),
+            members,
+        });
+        result
+    }
+
+    /// Message standing for the given one in another chat, be it the
+    /// original or a copy.
+    fn counterpart(
+        &self,
+        chat_id: C,
+        message_id: M,
+        in_chat_id: C,
+    ) -> Option<M> {
+        let state = self.lock_state();
+        let group = state.group(chat_id, message_id)?;
+        group
+            .members
+            .iter()
+            .find(|(member_chat_id, _)| *member_chat_id == in_chat_id)
+            .map(|&(_, member_id)| member_id)
+    }
+
+    /// Shows copies in the reply chain of the message as their original.
+    fn show_originals(&self, message: &mut Message<M, C, U>) {
+        let state = self.lock_state();
+        let mut current = Some(message);
+        while let Some(message) = current {
+            let key = (message.data.chat_id, message.id);
+            let original = state
+                .group(key.0, key.1)
+                .filter(|group| group.members.first() != Some(&key));
+            if let Some(original) = original {
+                message.author = original.author.clone();
+                message.content_kind = original.content_kind;
+                message.data.content = original.content.clone();
+            }
+            current = match &mut message.data.reply_target {
+                ReplyTarget::Message(target) => Some(&mut **target),
+                _ => None,
+            };
+        }
+    }
+}
+
+/// Content of a copy, led by the name of the author and the kind of media
+/// that could not be copied.
+fn copy_content<M, C, U>(message: &Message<M, C, U>) -> RichText<U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    let mut content = message.data.content.clone();
+    if let ContentKind::Caption(kind) = message.content_kind {
+        let label = match kind {
+            MediaKind::Photo => "[photo]",
+            MediaKind::Video => "[video]",
+            MediaKind::Document => "[document]",
+            MediaKind::Audio => "[audio]",
+            MediaKind::Voice => "[voice message]",
+            MediaKind::Other => "[attachment]",
+        };
+        let separator = if content.text.is_empty() { "" } else { " " };
+        content.prepend(&format!("{}{}", label, separator));
+    }
+    let author = message.author.as_ref().filter(|author| !author.is_bot);
+    if let Some(author) = author {
+        let name = &author.display_name;
+        content.prepend(&format!("{}: ", name));
+        let style = Style::Bold;
+        content.spans.insert(0, Span { start: 0, end: name.len(), style });
+    }
+    content
+}
+
+/// Relays messages of users across the bridge, letting other handlers run
+/// on them too.
+#[derive(Debug, Clone)]
+pub struct BridgeHandler<S>
+where
+    S: Sender,
+{
+    pub bridge: Bridge<S::MessageId, S::ChatId, S::UserId>,
+    /// Sender not mirroring, so that copies are not copied again.
+    pub sender: S,
+}
+
+impl<S> Handler for BridgeHandler<S>
+where
+    S: Sender + Send + Sync,
+    S::MessageId: Send + Sync,
+    S::ChatId: Send + Sync,
+    S::UserId: Send + Sync,
+    S::Error: Send,
+{
+    type MessageId = S::MessageId;
+    type ChatId = S::ChatId;
+    type UserId = S::UserId;
+    type Error = S::Error;
+
+    fn run<'fut>(
+        &'fut self,
+        _bot: &'fut Bot,
+        input_message: &'fut Message<
+            Self::MessageId,
+            Self::ChatId,
+            Self::UserId,
+        >,
+    ) -> DynFuture<'fut, Result<bool, Self::Error>> {
+        Box::pin(async move {
+            // A side being down must not keep commands from running.
+            if let Err(error) =
+                self.bridge.relay(&self.sender, input_message).await
+            {
+                eprintln!(
+                    "Error relaying message from chat {}...",
+                    input_message.data.chat_id
+                );
+                eprintln!("    {}", error);
+            }
+            Ok(false)
+        })
+    }
+}
+
+/// Channel seen through a bridge: messages sent to a linked chat are
+/// mirrored in the others, and copies are received as their original.
+#[derive(Debug, Clone)]
+pub struct Bridged<T, M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    inner: T,
+    bridge: Bridge<M, C, U>,
+}
+
+impl<T, M, C, U> Bridged<T, M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    pub fn new(inner: T, bridge: Bridge<M, C, U>) -> Self {
+        Self { inner, bridge }
+    }
+}
+
+impl<S> Sender for Bridged<S, S::MessageId, S::ChatId, S::UserId>
+where
+    S: Sender + Send + Sync,
+    S::MessageId: Send + Sync,
+    S::ChatId: Send + Sync,
+    S::UserId: Send + Sync,
+    S::Error: Send,
+{
+    type MessageId = S::MessageId;
+    type ChatId = S::ChatId;
+    type UserId = S::UserId;
+    type Error = S::Error;
+
+    /// Sends the message, then mirrors it, failing only if the message itself
+    /// could not be sent.
+    fn send<'fut>(
+        &'fut self,
+        message: &'fut NewMessage<Self::MessageId, Self::ChatId, Self::UserId>,
+    ) -> DynFuture<'fut, Result<Self::MessageId, Self::Error>> {
+        Box::pin(async move {
+            let message_id = self.inner.send(message).await?;
+            let sent = Message {
+                id: message_id,
+                author: None,
+                content_kind: ContentKind::Text,
+                data: message.data.clone(),
+            };
+            if let Err(error) = self.bridge.relay(&self.inner, &sent).await {
+                eprintln!(
+                    "Error mirroring message sent to chat {}...",
+                    message.data.chat_id
+                );
+                eprintln!("    {}", error);
+            }
+            Ok(message_id)
+        })
+    }
+}
+
+impl<D> Deleter for Bridged<D, D::MessageId, D::ChatId, D::UserId>
+where
+    D: Deleter + Send + Sync,
+    D::MessageId: Send + Sync,
+    D::ChatId: Send + Sync,
+    D::UserId: Send + Sync,
+    D::Error: Send,
+{
+    fn can_delete(&self, chat_id: Self::ChatId) -> bool {
+        self.inner.can_delete(chat_id)
+    }
+
+    /// Deletes the message, leaving its copies in the linked chats.
+    fn delete(
+        &self,
+        chat_id: Self::ChatId,
+        message_id: Self::MessageId,
+    ) -> DynFuture<'_, Result<(), Self::Error>> {
+        self.inner.delete(chat_id, message_id)
+    }
+}
+
+impl<R> Receiver for Bridged<R, R::MessageId, R::ChatId, R::UserId>
+where
+    R: Receiver + Send + Sync,
+    R::MessageId: Send + Sync,
+    R::ChatId: Send + Sync,
+    R::UserId: Send + Sync,
+{
+    type MessageId = R::MessageId;
+    type ChatId = R::ChatId;
+    type UserId = R::UserId;
+    type Error = R::Error;
+
+    fn receive<'fut>(
+        &'fut self,
+    ) -> Receiving<'fut, Self::MessageId, Self::ChatId, Self::UserId, Self::Error>
+    {
+        Box::pin(async move {
+            let mut received = self.inner.receive().await?;
+            match &mut received {
+                Ok(Update::Message(message)) => {
+                    self.bridge.show_originals(message)
+                },
+                Ok(Update::Callback(callback)) => {
+                    self.bridge.show_originals(&mut callback.message)
+                },
+                Err(_) => (),
+            }
+            Ok(received)
+        })
+    }
+
+    fn commit(&self) -> DynFuture<'_, Result<(), Self::Error>> {
+        self.inner.commit()
+    }
+}
+
+impl<T> Connector
+    for Bridged<
+        T,
+        <T::Receiver as Receiver>::MessageId,
+        <T::Receiver as Receiver>::ChatId,
+        <T::Receiver as Receiver>::UserId,
+    >
+where
+    T: Connector + Send + Sync,
+    T::Receiver: Send + Sync,
+    <T::Receiver as Receiver>::MessageId: Send + Sync,
+    <T::Receiver as Receiver>::ChatId: Send + Sync,
+    <T::Receiver as Receiver>::UserId: Send + Sync,
+{
+    type Receiver = Bridged<
+        T::Receiver,
+        <T::Receiver as Receiver>::MessageId,
+        <T::Receiver as Receiver>::ChatId,
+        <T::Receiver as Receiver>::UserId,
+    >;
+    type Error = T::Error;
+
+    fn connect(&self) -> DynFuture<'_, Result<Self::Receiver, Self::Error>> {
+        Box::pin(async move {
+            let receiver = self.inner.connect().await?;
+            Ok(Bridged::new(receiver, self.bridge.clone()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Bridge, BridgeHandler, Bridged};
+    use crate::{
+        adapter::{
+            memory::{MemoryChannel, Sent},
+            multi::Multiplexer,
+        },
+        app::App,
+        commands::replace::{ReplaceCommand, RequestParser},
+        domain::{Bot, RichText, Span, Style},
+        handler::DefaultHandler,
+        middleware::retry::Backoff,
+        shutdown::Shutdown,
+    };
+    use std::time::Duration;
+    use tokio::time;
+
+    /// Runs an app bridging chats 5 and 6 of the channel.
+    fn start() -> (MemoryChannel, Shutdown) {
+        let channel = MemoryChannel::new();
+        let bridge = Bridge::new().link(5, 6);
+        let bridged = Bridged::new(channel.clone(), bridge.clone());
+        let app = App::new(Bot { handle: String::from("regex_bot") })
+            .handler(BridgeHandler { bridge, sender: channel.clone() })
+            .handler(DefaultHandler {
+                request_parser: RequestParser::new(),
+                command: ReplaceCommand,
+                sender: bridged.clone(),
+            });
+        let shutdown = Shutdown::new();
+        tokio::spawn(app.run_supervised(
+            bridged,
+            Backoff::default(),
+            shutdown.clone(),
+        ));
+        (channel, shutdown)
+    }
+
+    async fn next_sent(channel: &MemoryChannel) -> Sent {
+        time::timeout(Duration::from_secs(5), channel.next_sent())
+            .await
+            .expect("bot sends a message")
+    }
+
+    #[tokio::test]
+    async fn mirrors_messages_under_the_name_of_their_author() {
+        let (channel, shutdown) = start();
+        channel.say(1, 5, "hello", None);
+        let copy = next_sent(&channel).await;
+        assert_eq!(copy.message.data.chat_id, 6);
+        assert_eq!(copy.text(), "User 1: hello");
+        assert_eq!(
+            copy.message.data.content.spans,
+            vec![Span { start: 0, end: 6, style: Style::Bold }]
+        );
+
+        channel.say(2, 6, "hi", None);
+        let copy = next_sent(&channel).await;
+        assert_eq!(copy.message.data.chat_id, 5);
+        assert_eq!(copy.text(), "User 2: hi");
+        shutdown.trigger();
+    }
+
+    #[tokio::test]
+    async fn keeps_replies_across_the_bridge() {
+        let (channel, shutdown) = start();
+        let original = channel.say(1, 5, "hello", None);
+        let copy = next_sent(&channel).await;
+        let message = channel.say(2, 6, "hi there", Some(copy.id));
+        let reply = next_sent(&channel).await;
+        assert_eq!(reply.message.data.chat_id, 5);
+        assert_eq!(reply.reply_to(), Some(original));
+
+        channel.say(1, 5, "how are you?", Some(reply.id));
+        let answer = next_sent(&channel).await;
+        assert_eq!(answer.message.data.chat_id, 6);
+        assert_eq!(answer.reply_to(), Some(message));
+        shutdown.trigger();
+    }
+
+    #[tokio::test]
+    async fn commands_see_copies_as_their_original() {
+        let (channel, shutdown) = start();
+        let original = channel.say(1, 5, "hello world", None);
+        let copy = next_sent(&channel).await;
+        channel.say(2, 6, "s/world/there/", Some(copy.id));
+
+        let relayed = next_sent(&channel).await;
+        assert_eq!(relayed.text(), "User 2: s/world/there/");
+        assert_eq!(relayed.reply_to(), Some(original));
+        let result = next_sent(&channel).await;
+        assert_eq!(result.message.data.chat_id, 6);
+        assert_eq!(result.text(), "hello there");
+        assert_eq!(result.reply_to(), Some(copy.id));
+        let mirrored = next_sent(&channel).await;
+        assert_eq!(mirrored.message.data.chat_id, 5);
+        assert_eq!(mirrored.text(), "hello there");
+        assert_eq!(mirrored.reply_to(), Some(original));
+        shutdown.trigger();
+    }
+
+    #[tokio::test]
+    async fn stresses_mentions_it_cannot_carry_to_another_account() {
+        let first = MemoryChannel::new();
+        let second = MemoryChannel::new();
+        let bot = |handle: &str| Bot { handle: String::from(handle) };
+        let mut multiplexer = Multiplexer::new(Backoff::default());
+        let first_tags = multiplexer.add(
+            "first",
+            bot("first"),
+            first.clone(),
+            first.clone(),
+        );
+        let second_tags = multiplexer.add(
+            "second",
+            bot("second"),
+            second.clone(),
+            second.clone(),
+        );
+        let bridge =
+            Bridge::new().link(first_tags.chat(5), second_tags.chat(6));
+        let app = App::new(bot("regex_bot"))
+            .handler(BridgeHandler { bridge, sender: multiplexer.clone() });
+        let shutdown = Shutdown::new();
+        tokio::spawn(app.run_supervised(
+            multiplexer,
+            Backoff::default(),
+            shutdown.clone(),
+        ));
+
+        let mention = Style::Mention { user_id: 2 };
+        let content = RichText {
+            text: String::from("hi bob"),
+            spans: vec![Span { start: 3, end: 6, style: mention }],
+        };
+        first.say_rich(1, 5, content, None);
+        let copy = next_sent(&second).await;
+        assert_eq!(copy.message.data.chat_id, 6);
+        assert_eq!(copy.text(), "User 1: hi bob");
+        assert_eq!(
+            copy.message.data.content.spans,
+            vec![
+                Span { start: 0, end: 6, style: Style::Bold },
+                Span { start: 11, end: 14, style: Style::Bold },
+            ]
+        );
+        shutdown.trigger();
+    }
+}
@@ -0,0 +1,71 @@
+use std::{error::Error, fmt, path::PathBuf};
+
+pub const USAGE: &str =
+    "usage: rustgex-bot [--console | --irc | --matrix | --discord | --multi]
+       rustgex-bot apply COMMAND [FILE...]";
+
+/// Where the bot talks to users.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Telegram,
+    /// Chat in the terminal, for trying the bot locally.
+    Console,
+    Irc,
+    Matrix,
+    Discord,
+    /// Serve every account listed in the environment at once.
+    Multi,
+    /// Run a single command against the files, or stdin if there are none.
+    Apply {
+        command: String,
+        files: Vec<PathBuf>,
+    },
+}
+
+impl Mode {
+    pub fn from_args<I>(args: I) -> Result<Self, CliError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut args = args.into_iter();
+        let mut mode = Self::Telegram;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--console" => mode = Self::Console,
+                "--irc" => mode = Self::Irc,
+                "--matrix" => mode = Self::Matrix,
+                "--discord" => mode = Self::Discord,
+                "--multi" => mode = Self::Multi,
+                "apply" if mode == Self::Telegram => {
+                    let command =
+                        args.next().ok_or(CliError::MissingCommand)?;
+                    let files = args.by_ref().map(PathBuf::from).collect();
+                    mode = Self::Apply { command, files };
+                },
+                _ => return Err(CliError::UnknownArgument(arg)),
+            }
+        }
+        Ok(mode)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CliError {
+    UnknownArgument(String),
+    MissingCommand,
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownArgument(arg) => {
+                write!(fmtr, "unknown argument {:?}\n{}", arg, USAGE)
+            },
+            Self::MissingCommand => {
+                write!(fmtr, "missing command to apply\n{}", USAGE)
+            },
+        }
+    }
+}
+
+impl Error for CliError {}
@@ -0,0 +1,127 @@
+use crate::future::DynFuture;
+use core::fmt;
+use std::{
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Source of time, so that time-dependent behaviour can be driven by tests.
+pub trait Clock: fmt::Debug {
+    fn now(&self) -> Instant;
+
+    fn sleep(&self, duration: Duration) -> DynFuture<'_, ()>;
+}
+
+impl<T> Clock for &T
+where
+    T: Clock + ?Sized,
+{
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn sleep(&self, duration: Duration) -> DynFuture<'_, ()> {
+        (**self).sleep(duration)
+    }
+}
+
+impl<T> Clock for &mut T
+where
+    T: Clock + ?Sized,
+{
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn sleep(&self, duration: Duration) -> DynFuture<'_, ()> {
+        (**self).sleep(duration)
+    }
+}
+
+impl<T> Clock for Box<T>
+where
+    T: Clock + ?Sized,
+{
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn sleep(&self, duration: Duration) -> DynFuture<'_, ()> {
+        (**self).sleep(duration)
+    }
+}
+
+impl<T> Clock for Rc<T>
+where
+    T: Clock + ?Sized,
+{
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn sleep(&self, duration: Duration) -> DynFuture<'_, ()> {
+        (**self).sleep(duration)
+    }
+}
+
+impl<T> Clock for Arc<T>
+where
+    T: Clock + ?Sized,
+{
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn sleep(&self, duration: Duration) -> DynFuture<'_, ()> {
+        (**self).sleep(duration)
+    }
+}
+
+/// Clock backed by the system time and the tokio timer.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> DynFuture<'_, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Clock whose time only moves when slept on, so that tests run instantly
+/// and observe exact delays.
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    start: Instant,
+    now: Arc<std::sync::Mutex<Instant>>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new() -> Self {
+        let start = Instant::now();
+        Self { start, now: Arc::new(std::sync::Mutex::new(start)) }
+    }
+
+    /// Time slept since the clock was created.
+    pub fn elapsed(&self) -> Duration {
+        self.now() - self.start
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) -> DynFuture<'_, ()> {
+        *self.now.lock().unwrap() += duration;
+        Box::pin(async {})
+    }
+}
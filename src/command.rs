@@ -1,77 +1,94 @@
 use crate::domain::{Id, NewMessage};
 use std::{error::Error, fmt, rc::Rc, sync::Arc};
 
-pub trait Command<R, M, C>: fmt::Debug
+#[derive(Debug)]
+pub enum Unfallible {}
+
+impl fmt::Display for Unfallible {
+    fn fmt(&self, _fmtr: &mut fmt::Formatter) -> fmt::Result {
+        match *self {}
+    }
+}
+
+impl Error for Unfallible {}
+
+pub trait Command<R, M, C, U>: fmt::Debug
 where
     M: Id,
     C: Id,
+    U: Id,
 {
     type Error: Error;
 
-    fn execute(&self, request: R) -> Result<NewMessage<M, C>, Self::Error>;
+    fn execute(&self, request: R) -> Result<NewMessage<M, C, U>, Self::Error>;
 }
 
-impl<'this, Co, R, M, C> Command<R, M, C> for &'this Co
+impl<Co, R, M, C, U> Command<R, M, C, U> for &Co
 where
-    Co: Command<R, M, C> + ?Sized,
+    Co: Command<R, M, C, U> + ?Sized,
     M: Id,
     C: Id,
+    U: Id,
 {
     type Error = Co::Error;
 
-    fn execute(&self, request: R) -> Result<NewMessage<M, C>, Self::Error> {
+    fn execute(&self, request: R) -> Result<NewMessage<M, C, U>, Self::Error> {
         (**self).execute(request)
     }
 }
 
-impl<'this, Co, R, M, C> Command<R, M, C> for &'this mut Co
+impl<Co, R, M, C, U> Command<R, M, C, U> for &mut Co
 where
-    Co: Command<R, M, C> + ?Sized,
+    Co: Command<R, M, C, U> + ?Sized,
     M: Id,
     C: Id,
+    U: Id,
 {
     type Error = Co::Error;
 
-    fn execute(&self, request: R) -> Result<NewMessage<M, C>, Self::Error> {
+    fn execute(&self, request: R) -> Result<NewMessage<M, C, U>, Self::Error> {
         (**self).execute(request)
     }
 }
 
-impl<'this, Co, R, M, C> Command<R, M, C> for Box<Co>
+impl<Co, R, M, C, U> Command<R, M, C, U> for Box<Co>
 where
-    Co: Command<R, M, C> + ?Sized,
+    Co: Command<R, M, C, U> + ?Sized,
     M: Id,
     C: Id,
+    U: Id,
 {
     type Error = Co::Error;
 
-    fn execute(&self, request: R) -> Result<NewMessage<M, C>, Self::Error> {
+    fn execute(&self, request: R) -> Result<NewMessage<M, C, U>, Self::Error> {
         (**self).execute(request)
     }
 }
 
-impl<'this, Co, R, M, C> Command<R, M, C> for Rc<Co>
+impl<Co, R, M, C, U> Command<R, M, C, U> for Rc<Co>
 where
-    Co: Command<R, M, C> + ?Sized,
+    Co: Command<R, M, C, U> + ?Sized,
     M: Id,
     C: Id,
+    U: Id,
 {
     type Error = Co::Error;
 
-    fn execute(&self, request: R) -> Result<NewMessage<M, C>, Self::Error> {
+    fn execute(&self, request: R) -> Result<NewMessage<M, C, U>, Self::Error> {
         (**self).execute(request)
     }
 }
 
-impl<'this, Co, R, M, C> Command<R, M, C> for Arc<Co>
+impl<Co, R, M, C, U> Command<R, M, C, U> for Arc<Co>
 where
-    Co: Command<R, M, C> + ?Sized,
+    Co: Command<R, M, C, U> + ?Sized,
     M: Id,
     C: Id,
+    U: Id,
 {
     type Error = Co::Error;
 
-    fn execute(&self, request: R) -> Result<NewMessage<M, C>, Self::Error> {
+    fn execute(&self, request: R) -> Result<NewMessage<M, C, U>, Self::Error> {
         (**self).execute(request)
     }
 }
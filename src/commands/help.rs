@@ -1,20 +1,8 @@
 use crate::{
-    command::Command,
+    command::{Command, Unfallible},
     domain::{Bot, Id, Message, MessageData, NewMessage, ReplyTarget},
     request,
 };
-use std::{error::Error, fmt};
-
-#[derive(Debug)]
-pub enum Unfallible {}
-
-impl fmt::Display for Unfallible {
-    fn fmt(&self, _fmtr: &mut fmt::Formatter) -> fmt::Result {
-        match *self {}
-    }
-}
-
-impl Error for Unfallible {}
 
 #[derive(Debug, Clone, Copy)]
 pub struct HelpRequestParser;
@@ -27,12 +15,14 @@ where
 {
     original_message_id: M,
     chat_id: C,
+    thread_id: Option<M>,
 }
 
-impl<M, C> request::Parser<M, C> for HelpRequestParser
+impl<M, C, U> request::Parser<M, C, U> for HelpRequestParser
 where
     M: Id,
     C: Id,
+    U: Id,
 {
     type Request = HelpRequest<M, C>;
     type Error = Unfallible;
@@ -40,12 +30,14 @@ where
     fn parse(
         &self,
         bot: &Bot,
-        message: &Message<M, C>,
+        message: &Message<M, C, U>,
     ) -> Option<Result<Self::Request, Self::Error>> {
-        let matches_without_handle = message.data.content.trim() == "/help";
+        let matches_without_handle =
+            message.data.content.text.trim() == "/help";
         let matches_with_handle = message
             .data
             .content
+            .text
             .split_once("@")
             .map(|(head, tail)| head == "/help" && tail == bot.handle)
             .unwrap_or(false);
@@ -53,6 +45,7 @@ where
             Some(Ok(HelpRequest {
                 original_message_id: message.id,
                 chat_id: message.data.chat_id,
+                thread_id: message.data.thread_id,
             }))
         } else {
             None
@@ -63,31 +56,79 @@ where
 #[derive(Debug, Clone, Copy)]
 pub struct HelpCommand;
 
-impl<M, C> Command<HelpRequest<M, C>, M, C> for HelpCommand
+impl<M, C, U> Command<HelpRequest<M, C>, M, C, U> for HelpCommand
 where
     M: Id,
     C: Id,
+    U: Id,
 {
     type Error = Unfallible;
 
     fn execute(
         &self,
         request: HelpRequest<M, C>,
-    ) -> Result<NewMessage<M, C>, Self::Error> {
+    ) -> Result<NewMessage<M, C, U>, Self::Error> {
         Ok(NewMessage {
             data: MessageData {
                 chat_id: request.chat_id,
+                thread_id: request.thread_id,
                 content: String::from(
                     "This bot performs replacements on messages based on \
                      regular expressions.\n\n- /help -- shows this \
                      message\n\n- s/regex/replacement/flags -- performs a \
                      replacement in the previous message or in the message \
                      you're replying to.",
-                ),
+                )
+                .into(),
                 reply_target: ReplyTarget::MessageId(
                     request.original_message_id,
                 ),
             },
+            keyboard: None,
+            attachment: None,
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{HelpCommand, HelpRequestParser};
+    use crate::{
+        adapter::memory::conversation::Conversation,
+        app::App,
+        domain::Bot,
+        handler::DefaultHandler,
+    };
+
+    fn conversation() -> Conversation {
+        Conversation::start(|channel| {
+            App::new(Bot { handle: String::from("regex_bot") }).handler(
+                DefaultHandler {
+                    request_parser: HelpRequestParser,
+                    command: HelpCommand,
+                    sender: channel,
+                },
+            )
+        })
+    }
+
+    #[tokio::test]
+    async fn replies_to_help() {
+        let conversation = conversation();
+        let command = conversation.user(1).in_chat(5).says("/help");
+        let reply = conversation.expect_reply(command).await;
+        assert_eq!(reply.message.data.chat_id, 5);
+        assert!(reply.text().contains("s/regex/replacement/flags"));
+        conversation.finish().await;
+    }
+
+    #[tokio::test]
+    async fn replies_to_help_addressed_to_the_bot() {
+        let conversation = conversation();
+        let command = conversation.user(1).says("/help@regex_bot");
+        conversation.expect_reply(command).await;
+        conversation.user(1).says("/help@other_bot");
+        conversation.expect_silence().await;
+        conversation.finish().await;
+    }
+}
@@ -1,12 +1,18 @@
-use std::{error::Error, fmt};
+use std::{
+    error::Error,
+    fmt,
+    mem,
+    sync::{Arc, Mutex, PoisonError},
+};
 
-use regex::{Regex, RegexBuilder};
+use regex::{Captures, Regex, RegexBuilder, Replacer};
 
-use crate::{domain, domain::Id, request};
+use crate::{command::Command, domain, domain::Id, history::History, request};
 
 #[derive(Debug, Clone)]
 pub enum ParseError {
     MissingQuery,
+    MissingTarget,
     UnrecognizedFlag(char),
     DuplicatedFlag(char),
     InvalidRegex(regex::Error),
@@ -24,6 +30,12 @@ impl fmt::Display for ParseError {
             Self::MissingQuery => {
                 write!(fmtr, "missing query regex in rule")
             },
+            Self::MissingTarget => {
+                write!(
+                    fmtr,
+                    "no message to replace in, reply to the one you want"
+                )
+            },
             Self::UnrecognizedFlag(flag) => {
                 write!(fmtr, "{:?} is an unrecognized flag", flag)
             },
@@ -58,24 +70,39 @@ struct Flags {
     global: bool,
 }
 
+/// Case conversion of what follows in a replacement, set by `\U`, `\L` and
+/// `\E` as in sed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum CaseConversion {
+    #[default]
+    Unchanged,
+    Upper,
+    Lower,
+}
+
+impl CaseConversion {
+    fn push_str(self, dst: &mut String, text: &str) {
+        match self {
+            Self::Unchanged => dst.push_str(text),
+            Self::Upper => dst.push_str(&text.to_uppercase()),
+            Self::Lower => dst.push_str(&text.to_lowercase()),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum ReplacementNode {
     Text(String),
     GroupIndex(usize),
     GroupName(String),
+    Case(CaseConversion),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Replacement {
     pub nodes: Vec<ReplacementNode>,
 }
 
-impl Default for Replacement {
-    fn default() -> Self {
-        Self { nodes: Vec::new() }
-    }
-}
-
 impl Replacement {
     fn parse(replacement_str: &str) -> Self {
         let mut this = Self::default();
@@ -127,13 +154,14 @@ impl Replacement {
                                     }
                                 }
 
-                                if curr_text.len() > 1 {
+                                if !curr_text.is_empty() {
                                     this.nodes
                                         .push(ReplacementNode::Text(curr_text));
                                     curr_text = String::new();
                                 }
-                                this.nodes
-                                    .push(ReplacementNode::GroupName(index));
+                                this.nodes.push(ReplacementNode::GroupName(
+                                    group_name,
+                                ));
                             },
                             _ => curr_text.push('g'),
                         }
@@ -141,8 +169,21 @@ impl Replacement {
                     Some('{') => {
                         char_stream.next();
                     },
+                    Some(&letter @ ('U' | 'L' | 'E')) => {
+                        char_stream.next();
+                        if !curr_text.is_empty() {
+                            this.nodes.push(ReplacementNode::Text(curr_text));
+                            curr_text = String::new();
+                        }
+                        let case = match letter {
+                            'U' => CaseConversion::Upper,
+                            'L' => CaseConversion::Lower,
+                            _ => CaseConversion::Unchanged,
+                        };
+                        this.nodes.push(ReplacementNode::Case(case));
+                    },
                     Some(_) => {
-                        if curr_text.len() > 1 {
+                        if !curr_text.is_empty() {
                             this.nodes.push(ReplacementNode::Text(curr_text));
                             curr_text = String::new();
                         }
@@ -166,21 +207,218 @@ impl Replacement {
             }
         }
 
+        if !curr_text.is_empty() {
+            this.nodes.push(ReplacementNode::Text(curr_text));
+        }
+
         this
     }
 }
 
+impl Replacer for &Replacement {
+    fn replace_append(&mut self, captures: &Captures, dst: &mut String) {
+        let mut case = CaseConversion::default();
+        for node in &self.nodes {
+            match node {
+                ReplacementNode::Text(text) => case.push_str(dst, text),
+                ReplacementNode::GroupIndex(index) => {
+                    if let Some(group) = captures.get(*index) {
+                        case.push_str(dst, group.as_str());
+                    }
+                },
+                ReplacementNode::GroupName(name) => {
+                    if let Some(group) = captures.name(name) {
+                        case.push_str(dst, group.as_str());
+                    }
+                },
+                ReplacementNode::Case(conversion) => case = *conversion,
+            }
+        }
+    }
+}
+
+/// A replaced region, in byte offsets of the original and of the new text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Edit {
+    start: usize,
+    end: usize,
+    new_start: usize,
+    new_end: usize,
+}
+
+impl Edit {
+    /// Maps a span boundary of the original text into the new text. Spans
+    /// covering a whole replaced region keep covering the replacement, while
+    /// spans partially overlapping one are shrunk to exclude it.
+    fn map_position(edits: &[Self], position: usize, is_start: bool) -> usize {
+        let edit = match edits.iter().rev().find(|edit| edit.start <= position)
+        {
+            Some(edit) => edit,
+            None => return position,
+        };
+        if position == edit.start {
+            edit.new_start
+        } else if position >= edit.end {
+            edit.new_end + (position - edit.end)
+        } else if is_start {
+            edit.new_end
+        } else {
+            edit.new_start
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Expression {
+    query: String,
+    replacement: String,
+    flags: Flags,
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmtr, "s/{}/{}/{}", self.query, self.replacement, self.flags)
+    }
+}
+
 #[derive(Debug, Clone)]
-pub struct Request {
+pub struct Request<M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
     pub query: Regex,
     pub replacement: Replacement,
     pub is_global: bool,
+    pub target: domain::Message<M, C, U>,
+    pub thread_id: Option<M>,
+    expression: Expression,
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct RequestParser;
+impl<M, C, U> Request<M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    pub fn apply(&self) -> Option<domain::RichText<U>> {
+        let content = &self.target.data.content;
+        let max_edits = if self.is_global { usize::MAX } else { 1 };
+        let mut text = String::new();
+        let mut edits = Vec::new();
+        let mut last_end = 0;
+
+        for captures in self.query.captures_iter(&content.text).take(max_edits)
+        {
+            let matched = captures.get(0).expect("group 0 always matches");
+            text.push_str(&content.text[last_end..matched.start()]);
+            let new_start = text.len();
+            (&self.replacement).replace_append(&captures, &mut text);
+            edits.push(Edit {
+                start: matched.start(),
+                end: matched.end(),
+                new_start,
+                new_end: text.len(),
+            });
+            last_end = matched.end();
+        }
+
+        if edits.is_empty() {
+            return None;
+        }
+        text.push_str(&content.text[last_end..]);
+
+        let spans = content
+            .spans
+            .iter()
+            .filter_map(|span| {
+                let start = Edit::map_position(&edits, span.start, true);
+                let end = Edit::map_position(&edits, span.end, false);
+                if start < end {
+                    Some(domain::Span { start, end, style: span.style.clone() })
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        Some(domain::RichText { text, spans })
+    }
+
+    fn variant_button(
+        &self,
+        label: &str,
+        set_flag: impl FnOnce(&mut Flags) -> bool,
+    ) -> Option<domain::Button> {
+        let mut expression = self.expression.clone();
+        if !set_flag(&mut expression.flags) {
+            return None;
+        }
+        let data = expression.to_string();
+        if data.len() > domain::Button::MAX_DATA_LEN {
+            return None;
+        }
+        Some(domain::Button { label: String::from(label), data })
+    }
+
+    fn keyboard(&self) -> Option<domain::Keyboard> {
+        let buttons = [
+            self.variant_button("Apply globally", |flags| {
+                !mem::replace(&mut flags.global, true)
+            }),
+            self.variant_button("Case-insensitive", |flags| {
+                !mem::replace(&mut flags.case_insensitive, true)
+            }),
+        ];
+        let row: Vec<_> = buttons.into_iter().flatten().collect();
+        if row.is_empty() {
+            None
+        } else {
+            Some(domain::Keyboard { rows: vec![row] })
+        }
+    }
+}
+
+/// Messages remembered to find the previous message of a chat and thread.
+const PREVIOUS_CAPACITY: usize = 10_000;
+
+/// Parses `s/regex/replacement/flags` commands. Commands not replying to a
+/// message apply to the previous message of the same chat and thread, so the
+/// parser keeps a history of the messages that were not commands. Clones share
+/// that history.
+#[derive(Debug, Clone)]
+pub struct RequestParser<M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    previous: Arc<Mutex<History<M, C, U>>>,
+}
+
+impl<M, C, U> Default for RequestParser<M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M, C, U> RequestParser<M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    pub fn new() -> Self {
+        let history = History::new(PREVIOUS_CAPACITY);
+        Self { previous: Arc::new(Mutex::new(history)) }
+    }
 
-impl RequestParser {
     fn split_bar_escaping<'input>(
         &self,
         input: &'input str,
@@ -197,22 +435,14 @@ impl RequestParser {
             }
         })
     }
-}
 
-impl<M, C> request::Parser<M, C> for RequestParser
-where
-    M: Id,
-    C: Id,
-{
-    type Error = ParseError;
-    type Request = Request;
-
-    fn parse(
+    fn parse_expression(
         &self,
-        _bot: &domain::Bot,
-        message: &domain::Message<M, C>,
-    ) -> Option<Result<Self::Request, Self::Error>> {
-        let (_, mut tail) = message.data.content.split_once("s/")?;
+        input: &str,
+        thread_id: Option<M>,
+        target: Option<domain::Message<M, C, U>>,
+    ) -> Option<Result<Request<M, C, U>, ParseError>> {
+        let (_, mut tail) = input.split_once("s/")?;
         let (query_str, new_tail) = match self.split_bar_escaping(tail) {
             Some(split) => split,
             None => return Some(Err(ParseError::MissingQuery)),
@@ -226,6 +456,10 @@ where
             Ok(flags) => flags,
             Err(error) => return Some(Err(error)),
         };
+        let target = match target {
+            Some(target) => target,
+            None => return Some(Err(ParseError::MissingTarget)),
+        };
         let query = match RegexBuilder::new(query_str)
             .case_insensitive(flags.case_insensitive)
             .multi_line(flags.multi_line)
@@ -240,7 +474,81 @@ where
             Err(error) => return Some(Err(ParseError::InvalidRegex(error))),
         };
         let replacement = Replacement::parse(replacement_str);
-        Some(Ok(Request { query, replacement, is_global: flags.global }))
+        Some(Ok(Request {
+            query,
+            replacement,
+            is_global: flags.global,
+            target,
+            thread_id,
+            expression: Expression {
+                query: String::from(query_str),
+                replacement: String::from(replacement_str),
+                flags,
+            },
+        }))
+    }
+}
+
+impl<M, C, U> request::Parser<M, C, U> for RequestParser<M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    type Error = ParseError;
+    type Request = Request<M, C, U>;
+
+    fn parse(
+        &self,
+        _bot: &domain::Bot,
+        message: &domain::Message<M, C, U>,
+    ) -> Option<Result<Self::Request, Self::Error>> {
+        let data = &message.data;
+        let mut previous =
+            self.previous.lock().unwrap_or_else(PoisonError::into_inner);
+        if !data.content.text.contains("s/") {
+            previous.record_latest(message);
+            return None;
+        }
+        let target = match &data.reply_target {
+            domain::ReplyTarget::Message(target) => Some((**target).clone()),
+            domain::ReplyTarget::NotReplying => {
+                previous.latest(data.chat_id, data.thread_id).cloned()
+            },
+            _ => None,
+        };
+        drop(previous);
+        self.parse_expression(
+            &message.data.content.text,
+            message.data.thread_id,
+            target,
+        )
+    }
+}
+
+impl<M, C, U> request::CallbackParser<M, C, U> for RequestParser<M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    type Error = ParseError;
+    type Request = Request<M, C, U>;
+
+    fn parse(
+        &self,
+        _bot: &domain::Bot,
+        callback: &domain::Callback<M, C, U>,
+    ) -> Option<Result<Self::Request, Self::Error>> {
+        let target = match &callback.message.data.reply_target {
+            domain::ReplyTarget::Message(target) => Some((**target).clone()),
+            _ => None,
+        };
+        self.parse_expression(
+            &callback.data,
+            callback.message.data.thread_id,
+            target,
+        )
     }
 }
 
@@ -252,7 +560,9 @@ impl Default for Flags {
             dot_matches_new_line: false,
             swap_greed: false,
             ignore_whitespace: false,
-            unicode: false,
+            // Without Unicode, `.`, negated classes and any non-ASCII literal
+            // are rejected as able to match invalid UTF-8.
+            unicode: true,
             octal: false,
             global: false,
         }
@@ -288,3 +598,336 @@ impl Flags {
         }
     }
 }
+
+impl fmt::Display for Flags {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        let flags = [
+            (self.case_insensitive, 'i'),
+            (self.multi_line, 'm'),
+            (self.dot_matches_new_line, 's'),
+            (self.swap_greed, 'U'),
+            (self.ignore_whitespace, 'x'),
+            (self.octal, 'o'),
+            (self.global, 'g'),
+        ];
+        for (is_set, flag_char) in flags {
+            if is_set {
+                write!(fmtr, "{}", flag_char)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NoMatch;
+
+impl fmt::Display for NoMatch {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmtr, "query regex did not match the message")
+    }
+}
+
+impl Error for NoMatch {}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReplaceCommand;
+
+impl<M, C, U> Command<Request<M, C, U>, M, C, U> for ReplaceCommand
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    type Error = NoMatch;
+
+    fn execute(
+        &self,
+        request: Request<M, C, U>,
+    ) -> Result<domain::NewMessage<M, C, U>, Self::Error> {
+        let mut content = request.apply().ok_or(NoMatch)?;
+        if let domain::ContentKind::Caption(_) = request.target.content_kind {
+            content.prepend("Corrected caption:\n");
+        }
+        Ok(domain::NewMessage {
+            data: domain::MessageData {
+                chat_id: request.target.data.chat_id,
+                thread_id: request.thread_id,
+                content,
+                reply_target: domain::ReplyTarget::MessageId(request.target.id),
+            },
+            keyboard: request.keyboard(),
+            attachment: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Edit, ParseError, ReplaceCommand, RequestParser};
+    use crate::{
+        adapter::memory::{conversation::Conversation, Message},
+        app::App,
+        command::Command,
+        domain::{
+            Bot,
+            Button,
+            Callback,
+            ContentKind,
+            MessageData,
+            ReplyTarget,
+            RichText,
+            Span,
+            Style,
+        },
+        handler::{DefaultCallbackHandler, DefaultHandler},
+        request::{CallbackParser, Parser},
+    };
+
+    fn bot() -> Bot {
+        Bot { handle: String::from("regex_bot") }
+    }
+
+    fn message(
+        id: u64,
+        text: &str,
+        reply_target: ReplyTarget<u64, u64, u64>,
+    ) -> Message {
+        Message {
+            id,
+            author: None,
+            content_kind: ContentKind::Text,
+            data: MessageData {
+                chat_id: 1,
+                thread_id: None,
+                content: String::from(text).into(),
+                reply_target,
+            },
+        }
+    }
+
+    /// Buttons under the correction of `target` by `command`.
+    fn buttons(command: &str, target: &str) -> Vec<Button> {
+        let target = message(1, target, ReplyTarget::NotReplying);
+        let command =
+            message(2, command, ReplyTarget::Message(Box::new(target)));
+        let request =
+            Parser::parse(&RequestParser::new(), &bot(), &command).unwrap();
+        let correction = ReplaceCommand.execute(request.unwrap()).unwrap();
+        correction
+            .keyboard
+            .map_or(Vec::new(), |keyboard| keyboard.rows.concat())
+    }
+
+    /// Press of a button with the given data under a correction.
+    fn callback(
+        data: &str,
+        target: ReplyTarget<u64, u64, u64>,
+    ) -> Callback<u64, u64, u64> {
+        Callback { message: message(3, "", target), data: String::from(data) }
+    }
+
+    fn conversation() -> Conversation {
+        Conversation::start(|channel| {
+            let request_parser = RequestParser::new();
+            App::new(Bot { handle: String::from("regex_bot") })
+                .handler(DefaultHandler {
+                    request_parser: request_parser.clone(),
+                    command: ReplaceCommand,
+                    sender: channel.clone(),
+                })
+                .callback_handler(DefaultCallbackHandler {
+                    request_parser,
+                    command: ReplaceCommand,
+                    sender: channel,
+                })
+        })
+    }
+
+    #[tokio::test]
+    async fn corrects_the_replied_message() {
+        let conversation = conversation();
+        let target = conversation.user(1).in_chat(5).says("hello world");
+        conversation.user(2).in_chat(5).replying_to(target).says("s/o/0/g");
+        let reply = conversation.expect_reply(target).await;
+        assert_eq!(reply.text(), "hell0 w0rld");
+        assert_eq!(reply.message.data.chat_id, 5);
+        conversation.finish().await;
+    }
+
+    #[tokio::test]
+    async fn corrects_the_previous_message() {
+        let conversation = conversation();
+        let target = conversation.user(1).in_chat(5).says("hello world");
+        conversation.user(2).in_chat(6).says("goodbye world");
+        conversation.user(2).in_chat(5).says("s/world/there/");
+        let reply = conversation.expect_reply(target).await;
+        assert_eq!(reply.text(), "hello there");
+        conversation.finish().await;
+    }
+
+    #[tokio::test]
+    async fn finds_commands_inside_text() {
+        let conversation = conversation();
+        let target = conversation.user(1).in_chat(5).says("hello world");
+        conversation
+            .user(2)
+            .in_chat(5)
+            .replying_to(target)
+            .says("I think you meant s/world/there/");
+        let reply = conversation.expect_reply(target).await;
+        assert_eq!(reply.text(), "hello there");
+        conversation.finish().await;
+    }
+
+    #[tokio::test]
+    async fn matches_any_character_and_non_ascii_text() {
+        let conversation = conversation();
+        let target = conversation.user(1).says("café au lait");
+        conversation.user(1).replying_to(target).says(r"s/é(.)/e\1/");
+        let reply = conversation.expect_reply(target).await;
+        assert_eq!(reply.text(), "cafe au lait");
+        conversation.finish().await;
+    }
+
+    #[tokio::test]
+    async fn explains_a_missing_target() {
+        let conversation = conversation();
+        let command = conversation.user(1).says("s/a/b/");
+        let reply = conversation.expect_reply(command).await;
+        assert!(reply.text().contains("no message to replace in"));
+        conversation.finish().await;
+    }
+
+    /// "hello world !" with "world" replaced by "all".
+    const EDITS: [Edit; 1] =
+        [Edit { start: 6, end: 11, new_start: 6, new_end: 9 }];
+
+    #[test]
+    fn keeps_positions_before_a_replacement() {
+        assert_eq!(Edit::map_position(&EDITS, 0, true), 0);
+        assert_eq!(Edit::map_position(&EDITS, 5, false), 5);
+    }
+
+    #[test]
+    fn shifts_positions_after_a_replacement() {
+        assert_eq!(Edit::map_position(&EDITS, 11, true), 9);
+        assert_eq!(Edit::map_position(&EDITS, 13, false), 11);
+    }
+
+    #[test]
+    fn keeps_replaced_regions_covered() {
+        assert_eq!(Edit::map_position(&EDITS, 6, true), 6);
+        assert_eq!(Edit::map_position(&EDITS, 11, false), 9);
+    }
+
+    #[test]
+    fn shrinks_spans_inside_or_across_a_replacement() {
+        // Inside: the span collapses and is dropped.
+        assert_eq!(Edit::map_position(&EDITS, 7, true), 9);
+        assert_eq!(Edit::map_position(&EDITS, 9, false), 6);
+        // Across its start or its end: the replacement is excluded.
+        assert_eq!(Edit::map_position(&EDITS, 8, false), 6);
+        assert_eq!(Edit::map_position(&EDITS, 8, true), 9);
+    }
+
+    #[test]
+    fn maps_spans_through_replacements() {
+        let span = |start, end| Span { start, end, style: Style::Bold };
+        let mut target = message(1, "", ReplyTarget::NotReplying);
+        target.data.content = RichText {
+            text: String::from("hello world !"),
+            spans: vec![span(0, 5), span(6, 11), span(7, 9), span(3, 8)],
+        };
+        let command =
+            message(2, "s/world/all/", ReplyTarget::Message(Box::new(target)));
+        let request =
+            Parser::parse(&RequestParser::new(), &bot(), &command).unwrap();
+        let correction = ReplaceCommand.execute(request.unwrap()).unwrap();
+        assert_eq!(
+            correction.data.content,
+            RichText {
+                text: String::from("hello all !"),
+                spans: vec![span(0, 5), span(6, 9), span(3, 6)],
+            }
+        );
+    }
+
+    #[test]
+    fn encodes_variants_as_callback_data() {
+        let variants = buttons("s/o/0/", "foo");
+        let data: Vec<_> =
+            variants.iter().map(|button| button.data.as_str()).collect();
+        assert_eq!(data, ["s/o/0/g", "s/o/0/i"]);
+        assert!(buttons("s/o/0/gi", "foo").is_empty());
+    }
+
+    #[test]
+    fn omits_variants_over_the_data_limit() {
+        let query = "o".repeat(Button::MAX_DATA_LEN);
+        let command = format!("s/{}/0/", query);
+        assert!(buttons(&command, &query).is_empty());
+    }
+
+    #[test]
+    fn parses_callback_data() {
+        let target = message(1, "Foo foo", ReplyTarget::NotReplying);
+        let callback =
+            callback("s/foo/bar/gi", ReplyTarget::Message(Box::new(target)));
+        let request =
+            CallbackParser::parse(&RequestParser::new(), &bot(), &callback);
+        let correction = ReplaceCommand.execute(request.unwrap().unwrap());
+        assert_eq!(correction.unwrap().data.content.text, "bar bar");
+    }
+
+    #[test]
+    fn callback_needs_a_known_target() {
+        let callback = callback("s/foo/bar/g", ReplyTarget::MessageId(1));
+        let request =
+            CallbackParser::parse(&RequestParser::new(), &bot(), &callback);
+        assert!(matches!(request, Some(Err(ParseError::MissingTarget))));
+    }
+
+    #[test]
+    fn ignores_other_callback_data() {
+        let callback = callback("vote:1", ReplyTarget::NotReplying);
+        let request =
+            CallbackParser::parse(&RequestParser::new(), &bot(), &callback);
+        assert!(request.is_none());
+    }
+
+    #[tokio::test]
+    async fn keyboard_applies_variants() {
+        let conversation = conversation();
+        let target = conversation.user(1).says("Hello hello hello");
+        conversation.user(1).replying_to(target).says("s/hello/bye/");
+        let reply = conversation.expect_reply(target).await;
+        assert_eq!(reply.text(), "Hello bye hello");
+
+        let keyboard = reply.message.keyboard.clone().unwrap();
+        let button = keyboard.rows[0]
+            .iter()
+            .find(|button| button.label == "Apply globally")
+            .unwrap();
+        conversation.press(reply.id, &button.data);
+        let variant = conversation.expect_reply(target).await;
+        assert_eq!(variant.text(), "Hello bye bye");
+
+        let button = keyboard.rows[0]
+            .iter()
+            .find(|button| button.label == "Case-insensitive")
+            .unwrap();
+        conversation.press(reply.id, &button.data);
+        let variant = conversation.expect_reply(target).await;
+        assert_eq!(variant.text(), "bye hello hello");
+        conversation.finish().await;
+    }
+
+    #[tokio::test]
+    async fn ignores_other_messages() {
+        let conversation = conversation();
+        conversation.user(1).says("just chatting");
+        conversation.expect_silence().await;
+        conversation.finish().await;
+    }
+}
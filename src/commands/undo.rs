@@ -0,0 +1,138 @@
+//! Taking back what a command sent, through an "Undo" button under it.
+
+use crate::{
+    command::Command,
+    domain::{Bot, Button, Callback, Keyboard, NewMessage},
+    future::DynFuture,
+    handler::CallbackHandler,
+    port::Deleter,
+};
+
+/// Callback data of the "Undo" button.
+pub const UNDO_DATA: &str = "undo";
+
+/// Command whose messages get an "Undo" button in chats where the deleter
+/// can delete them.
+#[derive(Debug, Clone)]
+pub struct Undoable<Co, D> {
+    pub command: Co,
+    pub deleter: D,
+}
+
+impl<Co, R, D> Command<R, D::MessageId, D::ChatId, D::UserId>
+    for Undoable<Co, D>
+where
+    Co: Command<R, D::MessageId, D::ChatId, D::UserId>,
+    D: Deleter,
+{
+    type Error = Co::Error;
+
+    fn execute(
+        &self,
+        request: R,
+    ) -> Result<NewMessage<D::MessageId, D::ChatId, D::UserId>, Self::Error>
+    {
+        let mut message = self.command.execute(request)?;
+        if self.deleter.can_delete(message.data.chat_id) {
+            let undo = Button {
+                label: String::from("Undo"),
+                data: String::from(UNDO_DATA),
+            };
+            message
+                .keyboard
+                .get_or_insert_with(Keyboard::default)
+                .rows
+                .push(vec![undo]);
+        }
+        Ok(message)
+    }
+}
+
+/// Deletes the message whose "Undo" button was pressed.
+#[derive(Debug, Clone)]
+pub struct UndoHandler<D> {
+    pub deleter: D,
+}
+
+impl<D> CallbackHandler for UndoHandler<D>
+where
+    D: Deleter + Send + Sync,
+    D::MessageId: Send + Sync,
+    D::ChatId: Send + Sync,
+    D::UserId: Send + Sync,
+{
+    type MessageId = D::MessageId;
+    type ChatId = D::ChatId;
+    type UserId = D::UserId;
+    type Error = D::Error;
+
+    fn run<'fut>(
+        &'fut self,
+        _bot: &'fut Bot,
+        callback: &'fut Callback<Self::MessageId, Self::ChatId, Self::UserId>,
+    ) -> DynFuture<'fut, Result<bool, Self::Error>> {
+        Box::pin(async move {
+            if callback.data != UNDO_DATA {
+                return Ok(false);
+            }
+            let message = &callback.message;
+            self.deleter.delete(message.data.chat_id, message.id).await?;
+            Ok(true)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{UndoHandler, Undoable, UNDO_DATA};
+    use crate::{
+        adapter::memory::conversation::Conversation,
+        app::App,
+        commands::replace::{ReplaceCommand, RequestParser},
+        domain::Bot,
+        handler::DefaultHandler,
+    };
+
+    fn conversation() -> Conversation {
+        Conversation::start(|channel| {
+            App::new(Bot { handle: String::from("regex_bot") })
+                .handler(DefaultHandler {
+                    request_parser: RequestParser::new(),
+                    command: Undoable {
+                        command: ReplaceCommand,
+                        deleter: channel.clone(),
+                    },
+                    sender: channel.clone(),
+                })
+                .callback_handler(UndoHandler { deleter: channel })
+        })
+    }
+
+    #[tokio::test]
+    async fn deletes_corrections_when_undone() {
+        let conversation = conversation();
+        let target = conversation.user(1).says("hello world");
+        conversation.user(1).replying_to(target).says("s/world/there/");
+        let reply = conversation.expect_reply(target).await;
+        let keyboard = reply.message.keyboard.clone().unwrap();
+        let undo = keyboard.rows.last().unwrap();
+        assert_eq!(undo[0].label, "Undo");
+        conversation.press(reply.id, &undo[0].data);
+        conversation.expect_deleted(reply.id).await;
+        conversation.finish().await;
+    }
+
+    #[tokio::test]
+    async fn leaves_other_buttons_alone() {
+        let conversation = conversation();
+        let target = conversation.user(1).says("hello world");
+        conversation.user(1).replying_to(target).says("s/world/there/");
+        let first = conversation.expect_reply(target).await;
+        conversation.user(1).replying_to(target).says("s/hello/bye/");
+        let second = conversation.expect_reply(target).await;
+        conversation.press(first.id, &format!("{}/", UNDO_DATA));
+        conversation.press(second.id, UNDO_DATA);
+        conversation.expect_deleted(second.id).await;
+        conversation.finish().await;
+    }
+}
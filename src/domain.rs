@@ -13,46 +13,194 @@ pub trait Id:
 {
 }
 
+/// Plain integer ids, for platforms living in this process.
+impl Id for u64 {}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub enum ReplyTarget<M, C>
+pub enum ReplyTarget<M, C, U>
 where
     M: Id,
     C: Id,
+    U: Id,
 {
-    Message(Box<Message<M, C>>),
+    Message(Box<Message<M, C, U>>),
     MessageId(M),
     Prunned,
     NotReplying,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct MessageData<M, C>
+pub enum Style<U>
+where
+    U: Id,
+{
+    Bold,
+    Italic,
+    Underline,
+    Strikethrough,
+    Spoiler,
+    Code,
+    Pre { language: Option<String> },
+    Link { url: String },
+    Mention { user_id: U },
+}
+
+/// A styled range of a [`RichText`], in byte offsets into its text.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Span<U>
+where
+    U: Id,
+{
+    pub start: usize,
+    pub end: usize,
+    pub style: Style<U>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RichText<U>
+where
+    U: Id,
+{
+    pub text: String,
+    pub spans: Vec<Span<U>>,
+}
+
+impl<U> RichText<U>
+where
+    U: Id,
+{
+    pub fn prepend(&mut self, prefix: &str) {
+        self.text.insert_str(0, prefix);
+        for span in &mut self.spans {
+            span.start += prefix.len();
+            span.end += prefix.len();
+        }
+    }
+}
+
+impl<U> From<String> for RichText<U>
+where
+    U: Id,
+{
+    fn from(text: String) -> Self {
+        Self { text, spans: Vec::new() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MessageData<M, C, U>
 where
     M: Id,
     C: Id,
+    U: Id,
 {
     pub chat_id: C,
-    pub content: String,
-    pub reply_target: ReplyTarget<M, C>,
+    /// Thread or forum topic within the chat, identified by its root message.
+    pub thread_id: Option<M>,
+    pub content: RichText<U>,
+    pub reply_target: ReplyTarget<M, C, U>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct Message<M, C>
+pub struct Author<U>
+where
+    U: Id,
+{
+    /// Platform identifier of the author, absent when the platform only
+    /// exposes a name, e.g. for signed channel posts.
+    pub id: Option<U>,
+    pub display_name: String,
+    pub username: Option<String>,
+    pub is_bot: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MediaKind {
+    Photo,
+    Video,
+    Document,
+    Audio,
+    Voice,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ContentKind {
+    Text,
+    Caption(MediaKind),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Message<M, C, U>
 where
     M: Id,
     C: Id,
+    U: Id,
 {
     pub id: M,
-    pub data: MessageData<M, C>,
+    pub author: Option<Author<U>>,
+    pub content_kind: ContentKind,
+    pub data: MessageData<M, C, U>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Button {
+    pub label: String,
+    pub data: String,
+}
+
+impl Button {
+    /// Maximum length in bytes of callback data accepted by every supported
+    /// platform.
+    pub const MAX_DATA_LEN: usize = 64;
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Keyboard {
+    pub rows: Vec<Vec<Button>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Attachment {
+    pub file_name: String,
+    pub mime_type: String,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NewMessage<M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    pub data: MessageData<M, C, U>,
+    pub keyboard: Option<Keyboard>,
+    /// File sent with the message, in which case the content of the message
+    /// becomes its caption.
+    pub attachment: Option<Attachment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Callback<M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    pub message: Message<M, C, U>,
+    pub data: String,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct NewMessage<M, C>
+pub enum Update<M, C, U>
 where
     M: Id,
     C: Id,
+    U: Id,
 {
-    pub data: MessageData<M, C>,
+    Message(Message<M, C, U>),
+    Callback(Callback<M, C, U>),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
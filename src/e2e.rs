@@ -0,0 +1,163 @@
+//! Runs the app with the Telegram adapter against a fake Bot API.
+
+use crate::{
+    adapter::telegram::{
+        fake::{FakeBotApi, BOT_HANDLE},
+        TgClient,
+        TgError,
+        TgMessageChannel,
+    },
+    app::{App, Stopped},
+    commands::{
+        help::{HelpCommand, HelpRequestParser},
+        replace::{ReplaceCommand, RequestParser as ReplaceRequestParser},
+        undo::{UndoHandler, Undoable},
+    },
+    domain,
+    handler::{DefaultCallbackHandler, DefaultHandler},
+    middleware::retry::Backoff,
+    shutdown::Shutdown,
+};
+use tokio::task::JoinHandle;
+
+const CHAT_ID: i64 = 42;
+
+struct Running {
+    api: FakeBotApi,
+    shutdown: Shutdown,
+    app: JoinHandle<Result<Stopped, TgError>>,
+}
+
+impl Running {
+    fn start() -> Self {
+        let api = FakeBotApi::start();
+        let client = TgClient::new("token", api.client_config()).unwrap();
+        let channel = TgMessageChannel::new(client);
+        let replace_parser = ReplaceRequestParser::new();
+        let replace =
+            Undoable { command: ReplaceCommand, deleter: channel.clone() };
+        let app = App::new(domain::Bot { handle: String::from(BOT_HANDLE) })
+            .handler(DefaultHandler {
+                request_parser: HelpRequestParser,
+                command: HelpCommand,
+                sender: channel.clone(),
+            })
+            .handler(DefaultHandler {
+                request_parser: replace_parser.clone(),
+                command: replace.clone(),
+                sender: channel.clone(),
+            })
+            .callback_handler(UndoHandler { deleter: channel.clone() })
+            .callback_handler(DefaultCallbackHandler {
+                request_parser: replace_parser,
+                command: replace,
+                sender: channel.clone(),
+            });
+        let shutdown = Shutdown::new();
+        let app = tokio::spawn(app.run_supervised(
+            channel,
+            Backoff::default(),
+            shutdown.clone(),
+        ));
+        Self { api, shutdown, app }
+    }
+
+    async fn stop(self) -> Stopped {
+        self.shutdown.trigger();
+        self.app.await.unwrap().unwrap()
+    }
+}
+
+#[tokio::test]
+async fn help_replies_to_the_command() {
+    let running = Running::start();
+    let command = running.api.push_message(CHAT_ID, "/help", None);
+
+    let sent = running.api.wait_for_calls("sendMessage", 1).await;
+    assert_eq!(sent[0].params["chat_id"], CHAT_ID);
+    assert_eq!(sent[0].params["reply_to_message_id"], command);
+    assert!(sent[0].params["text"].as_str().unwrap().contains("/help"));
+
+    assert_eq!(running.stop().await, Stopped::Drained);
+}
+
+#[tokio::test]
+async fn replace_corrects_the_replied_message() {
+    let running = Running::start();
+    let target = running.api.push_message(CHAT_ID, "hello world", None);
+    running.api.push_message(CHAT_ID, "s/world/there/", Some(target));
+
+    let sent = running.api.wait_for_calls("sendMessage", 1).await;
+    assert_eq!(sent[0].params["text"], "hello there");
+    assert_eq!(sent[0].params["reply_to_message_id"], target);
+
+    assert_eq!(running.stop().await, Stopped::Drained);
+}
+
+#[tokio::test]
+async fn keyboard_buttons_apply_variants() {
+    let running = Running::start();
+    let target = running.api.push_message(CHAT_ID, "Hello hello", None);
+    running.api.push_message(CHAT_ID, "s/hello/bye/", Some(target));
+
+    let sent = running.api.wait_for_calls("sendMessage", 1).await;
+    assert_eq!(sent[0].params["text"], "Hello bye");
+    let reply = sent[0].response["result"]["message_id"].as_i64().unwrap();
+    let buttons = &sent[0].params["reply_markup"]["inline_keyboard"][0];
+    let data = buttons
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|button| button["text"] == "Case-insensitive")
+        .and_then(|button| button["callback_data"].as_str())
+        .unwrap();
+    running.api.push_callback(CHAT_ID, reply, data);
+
+    running.api.wait_for_calls("answerCallbackQuery", 1).await;
+    let sent = running.api.wait_for_calls("sendMessage", 2).await;
+    assert_eq!(sent[1].params["text"], "bye hello");
+    assert_eq!(sent[1].params["reply_to_message_id"], target);
+
+    assert_eq!(running.stop().await, Stopped::Drained);
+}
+
+#[tokio::test]
+async fn handled_updates_are_confirmed_on_shutdown() {
+    let running = Running::start();
+    running.api.push_message(CHAT_ID, "/help", None);
+    running.api.push_message(CHAT_ID, "not a command", None);
+    running.api.wait_for_calls("sendMessage", 1).await;
+
+    let api = running.api.clone();
+    assert_eq!(running.stop().await, Stopped::Drained);
+    let polls = api.wait_for_calls("getUpdates", 1).await;
+    let commit = polls.iter().find(|call| call.params["limit"] == 1).unwrap();
+    assert_eq!(commit.params["offset"], 2);
+    assert_eq!(api.offset(), 2);
+}
+
+#[tokio::test]
+async fn undo_deletes_the_correction() {
+    let running = Running::start();
+    let target = running.api.push_message(CHAT_ID, "hello world", None);
+    running.api.push_message(CHAT_ID, "s/world/there/", Some(target));
+
+    let sent = running.api.wait_for_calls("sendMessage", 1).await;
+    let reply = sent[0].response["result"]["message_id"].as_i64().unwrap();
+    let rows =
+        sent[0].params["reply_markup"]["inline_keyboard"].as_array().unwrap();
+    let undo = &rows.last().unwrap()[0];
+    assert_eq!(undo["text"], "Undo");
+    running.api.push_callback(
+        CHAT_ID,
+        reply,
+        undo["callback_data"].as_str().unwrap(),
+    );
+
+    let deletes = running.api.wait_for_calls("deleteMessage", 1).await;
+    assert_eq!(deletes[0].params["chat_id"], CHAT_ID);
+    assert_eq!(deletes[0].params["message_id"], reply);
+    assert_eq!(deletes[0].response["result"], true);
+
+    assert_eq!(running.stop().await, Stopped::Drained);
+}
@@ -1,13 +1,111 @@
-use std::{env, error::Error, fmt};
+use crate::{
+    adapter::{
+        discord::DiscordConfig,
+        irc::{self, InvalidName, IrcConfig, IrcName},
+        matrix::MatrixConfig,
+        telegram::{ClientConfig, WebhookConfig},
+    },
+    app::{ErrorPolicy, InvalidErrorPolicy},
+    middleware::{
+        rate_limit::{InvalidRateLimitPolicy, RateLimitPolicy},
+        retry::{Backoff, BreakerConfig},
+        split::{InvalidOverflowPolicy, OverflowPolicy},
+    },
+};
+use hyper::{http::uri::InvalidUri, Uri};
+use std::{
+    env,
+    error::Error,
+    fmt,
+    net::{AddrParseError, SocketAddr},
+    num::ParseIntError,
+    str::ParseBoolError,
+    time::Duration,
+};
 
 const TOKEN_VAR: &str = "TELEGRAM_BOT_TOKEN";
 const HANDLE_VAR: &str = "TELEGRAM_BOT_HANDLE";
+const REPLY_DEPTH_VAR: &str = "BOT_REPLY_DEPTH";
+const OVERFLOW_POLICY_VAR: &str = "BOT_OVERFLOW_POLICY";
+const RATE_LIMIT_POLICY_VAR: &str = "BOT_RATE_LIMIT_POLICY";
+const MAX_RETRIES_VAR: &str = "BOT_MAX_RETRIES";
+const RETRY_DELAY_VAR: &str = "BOT_RETRY_DELAY_MS";
+const MAX_RETRY_DELAY_VAR: &str = "BOT_MAX_RETRY_DELAY_SECS";
+const BREAKER_THRESHOLD_VAR: &str = "BOT_BREAKER_THRESHOLD";
+const BREAKER_COOLDOWN_VAR: &str = "BOT_BREAKER_COOLDOWN_SECS";
+const ERROR_POLICY_VAR: &str = "BOT_ERROR_POLICY";
+const OWNER_CHAT_VAR: &str = "BOT_OWNER_CHAT_ID";
+const WORKERS_VAR: &str = "BOT_WORKERS";
+const QUEUE_CAPACITY_VAR: &str = "BOT_QUEUE_CAPACITY";
+const DRAIN_TIMEOUT_VAR: &str = "BOT_DRAIN_TIMEOUT_SECS";
+const WEBHOOK_URL_VAR: &str = "BOT_WEBHOOK_URL";
+const WEBHOOK_LISTEN_VAR: &str = "BOT_WEBHOOK_LISTEN";
+const WEBHOOK_SECRET_VAR: &str = "BOT_WEBHOOK_SECRET";
+const DEFAULT_WEBHOOK_LISTEN: &str = "0.0.0.0:8080";
+const API_URL_VAR: &str = "TELEGRAM_API_URL";
+const TIMEOUT_VAR: &str = "TELEGRAM_TIMEOUT_SECS";
+const CONNECT_TIMEOUT_VAR: &str = "TELEGRAM_CONNECT_TIMEOUT_SECS";
+const PROXY_VAR: &str = "TELEGRAM_PROXY";
+const IRC_HOST_VAR: &str = "IRC_HOST";
+const IRC_PORT_VAR: &str = "IRC_PORT";
+const IRC_TLS_VAR: &str = "IRC_TLS";
+const IRC_NICK_VAR: &str = "IRC_NICK";
+const IRC_USER_VAR: &str = "IRC_USER";
+const IRC_PASSWORD_VAR: &str = "IRC_PASSWORD";
+const IRC_CHANNELS_VAR: &str = "IRC_CHANNELS";
+const MATRIX_HOMESERVER_VAR: &str = "MATRIX_HOMESERVER_URL";
+const MATRIX_TOKEN_VAR: &str = "MATRIX_ACCESS_TOKEN";
+const MATRIX_ACCEPT_INVITES_VAR: &str = "MATRIX_ACCEPT_INVITES";
+const DISCORD_TOKEN_VAR: &str = "DISCORD_TOKEN";
+const DISCORD_API_URL_VAR: &str = "DISCORD_API_URL";
+const DISCORD_GATEWAY_URL_VAR: &str = "DISCORD_GATEWAY_URL";
+const DISCORD_PING_REPLIES_VAR: &str = "DISCORD_PING_REPLIES";
+const ACCOUNTS_VAR: &str = "BOT_ACCOUNTS";
+const BRIDGES_VAR: &str = "BOT_BRIDGES";
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 #[non_exhaustive]
 pub enum EnvError {
     MissingToken(env::VarError),
     MissingHandle(env::VarError),
+    InvalidReplyDepth(ParseIntError),
+    InvalidOverflowPolicy(InvalidOverflowPolicy),
+    InvalidRateLimitPolicy(InvalidRateLimitPolicy),
+    InvalidMaxRetries(ParseIntError),
+    InvalidRetryDelay(ParseIntError),
+    InvalidMaxRetryDelay(ParseIntError),
+    InvalidBreakerThreshold(ParseIntError),
+    InvalidBreakerCooldown(ParseIntError),
+    InvalidErrorPolicy(InvalidErrorPolicy),
+    InvalidOwnerChat(ParseIntError),
+    InvalidWorkers(ParseIntError),
+    InvalidQueueCapacity(ParseIntError),
+    InvalidDrainTimeout(ParseIntError),
+    InvalidWebhookListen(AddrParseError),
+    MissingWebhookSecret(env::VarError),
+    InvalidWebhookSecret,
+    InvalidApiUrl(InvalidUri),
+    InvalidTimeout(ParseIntError),
+    InvalidConnectTimeout(ParseIntError),
+    InvalidProxy(InvalidUri),
+    MissingIrcHost(env::VarError),
+    InvalidIrcPort(ParseIntError),
+    InvalidIrcTls(ParseBoolError),
+    MissingIrcNick(env::VarError),
+    InvalidIrcNick(InvalidName),
+    InvalidIrcChannel(InvalidName),
+    MissingMatrixHomeserver(env::VarError),
+    InvalidMatrixHomeserver(InvalidUri),
+    MissingMatrixToken(env::VarError),
+    InvalidMatrixAcceptInvites(ParseBoolError),
+    MissingDiscordToken(env::VarError),
+    InvalidDiscordApiUrl(InvalidUri),
+    InvalidDiscordPingReplies(ParseBoolError),
+    MissingAccounts(env::VarError),
+    UnknownPlatform(String),
+    InAccount { account: String, cause: Box<EnvError> },
+    InvalidBridge(String),
+    UnknownBridgeAccount(String),
 }
 
 impl fmt::Display for EnvError {
@@ -23,6 +121,200 @@ impl fmt::Display for EnvError {
                 "error finding environment variable {}: {}",
                 HANDLE_VAR, cause
             ),
+            Self::InvalidReplyDepth(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                REPLY_DEPTH_VAR, cause
+            ),
+            Self::InvalidOverflowPolicy(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                OVERFLOW_POLICY_VAR, cause
+            ),
+            Self::InvalidRateLimitPolicy(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                RATE_LIMIT_POLICY_VAR, cause
+            ),
+            Self::InvalidMaxRetries(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                MAX_RETRIES_VAR, cause
+            ),
+            Self::InvalidRetryDelay(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                RETRY_DELAY_VAR, cause
+            ),
+            Self::InvalidMaxRetryDelay(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                MAX_RETRY_DELAY_VAR, cause
+            ),
+            Self::InvalidBreakerThreshold(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                BREAKER_THRESHOLD_VAR, cause
+            ),
+            Self::InvalidBreakerCooldown(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                BREAKER_COOLDOWN_VAR, cause
+            ),
+            Self::InvalidErrorPolicy(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                ERROR_POLICY_VAR, cause
+            ),
+            Self::InvalidOwnerChat(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                OWNER_CHAT_VAR, cause
+            ),
+            Self::InvalidWorkers(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                WORKERS_VAR, cause
+            ),
+            Self::InvalidQueueCapacity(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                QUEUE_CAPACITY_VAR, cause
+            ),
+            Self::InvalidDrainTimeout(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                DRAIN_TIMEOUT_VAR, cause
+            ),
+            Self::InvalidWebhookListen(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                WEBHOOK_LISTEN_VAR, cause
+            ),
+            Self::MissingWebhookSecret(cause) => write!(
+                fmtr,
+                "error finding environment variable {}, required along with \
+                 {}: {}",
+                WEBHOOK_SECRET_VAR, WEBHOOK_URL_VAR, cause
+            ),
+            Self::InvalidWebhookSecret => write!(
+                fmtr,
+                "error parsing environment variable {}: expected 1 to 256 \
+                 letters, digits, \"_\" or \"-\"",
+                WEBHOOK_SECRET_VAR
+            ),
+            Self::InvalidApiUrl(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                API_URL_VAR, cause
+            ),
+            Self::InvalidTimeout(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                TIMEOUT_VAR, cause
+            ),
+            Self::InvalidConnectTimeout(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                CONNECT_TIMEOUT_VAR, cause
+            ),
+            Self::InvalidProxy(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                PROXY_VAR, cause
+            ),
+            Self::MissingIrcHost(cause) => write!(
+                fmtr,
+                "error finding environment variable {}: {}",
+                IRC_HOST_VAR, cause
+            ),
+            Self::InvalidIrcPort(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                IRC_PORT_VAR, cause
+            ),
+            Self::InvalidIrcTls(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                IRC_TLS_VAR, cause
+            ),
+            Self::MissingIrcNick(cause) => write!(
+                fmtr,
+                "error finding environment variable {}: {}",
+                IRC_NICK_VAR, cause
+            ),
+            Self::InvalidIrcNick(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                IRC_NICK_VAR, cause
+            ),
+            Self::InvalidIrcChannel(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                IRC_CHANNELS_VAR, cause
+            ),
+            Self::MissingMatrixHomeserver(cause) => write!(
+                fmtr,
+                "error finding environment variable {}: {}",
+                MATRIX_HOMESERVER_VAR, cause
+            ),
+            Self::InvalidMatrixHomeserver(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                MATRIX_HOMESERVER_VAR, cause
+            ),
+            Self::MissingMatrixToken(cause) => write!(
+                fmtr,
+                "error finding environment variable {}: {}",
+                MATRIX_TOKEN_VAR, cause
+            ),
+            Self::InvalidMatrixAcceptInvites(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                MATRIX_ACCEPT_INVITES_VAR, cause
+            ),
+            Self::MissingDiscordToken(cause) => write!(
+                fmtr,
+                "error finding environment variable {}: {}",
+                DISCORD_TOKEN_VAR, cause
+            ),
+            Self::InvalidDiscordApiUrl(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                DISCORD_API_URL_VAR, cause
+            ),
+            Self::InvalidDiscordPingReplies(cause) => write!(
+                fmtr,
+                "error parsing environment variable {}: {}",
+                DISCORD_PING_REPLIES_VAR, cause
+            ),
+            Self::MissingAccounts(cause) => write!(
+                fmtr,
+                "error finding environment variable {}: {}",
+                ACCOUNTS_VAR, cause
+            ),
+            Self::UnknownPlatform(platform) => write!(
+                fmtr,
+                "error parsing environment variable {}: {:?} is not a \
+                 platform, expected \"telegram\", \"irc\", \"matrix\" or \
+                 \"discord\"",
+                ACCOUNTS_VAR, platform
+            ),
+            Self::InAccount { account, cause } => {
+                write!(fmtr, "error in account {}: {}", account, cause)
+            },
+            Self::InvalidBridge(bridge) => write!(
+                fmtr,
+                "error parsing environment variable {}: {:?} is not a bridge, \
+                 expected \"ACCOUNT/CHAT=ACCOUNT/CHAT\"",
+                BRIDGES_VAR, bridge
+            ),
+            Self::UnknownBridgeAccount(account) => write!(
+                fmtr,
+                "error parsing environment variable {}: {:?} is not an \
+                 account listed in {}",
+                BRIDGES_VAR, account, ACCOUNTS_VAR
+            ),
         }
     }
 }
@@ -32,20 +324,456 @@ impl Error for EnvError {
         match self {
             Self::MissingToken(cause) => Some(cause),
             Self::MissingHandle(cause) => Some(cause),
+            Self::InvalidReplyDepth(cause) => Some(cause),
+            Self::InvalidOverflowPolicy(cause) => Some(cause),
+            Self::InvalidRateLimitPolicy(cause) => Some(cause),
+            Self::InvalidMaxRetries(cause) => Some(cause),
+            Self::InvalidRetryDelay(cause) => Some(cause),
+            Self::InvalidMaxRetryDelay(cause) => Some(cause),
+            Self::InvalidBreakerThreshold(cause) => Some(cause),
+            Self::InvalidBreakerCooldown(cause) => Some(cause),
+            Self::InvalidErrorPolicy(cause) => Some(cause),
+            Self::InvalidOwnerChat(cause) => Some(cause),
+            Self::InvalidWorkers(cause) => Some(cause),
+            Self::InvalidQueueCapacity(cause) => Some(cause),
+            Self::InvalidDrainTimeout(cause) => Some(cause),
+            Self::InvalidWebhookListen(cause) => Some(cause),
+            Self::MissingWebhookSecret(cause) => Some(cause),
+            Self::InvalidWebhookSecret => None,
+            Self::InvalidApiUrl(cause) => Some(cause),
+            Self::InvalidTimeout(cause) => Some(cause),
+            Self::InvalidConnectTimeout(cause) => Some(cause),
+            Self::InvalidProxy(cause) => Some(cause),
+            Self::MissingIrcHost(cause) => Some(cause),
+            Self::InvalidIrcPort(cause) => Some(cause),
+            Self::InvalidIrcTls(cause) => Some(cause),
+            Self::MissingIrcNick(cause) => Some(cause),
+            Self::InvalidIrcNick(cause) => Some(cause),
+            Self::InvalidIrcChannel(cause) => Some(cause),
+            Self::MissingMatrixHomeserver(cause) => Some(cause),
+            Self::InvalidMatrixHomeserver(cause) => Some(cause),
+            Self::MissingMatrixToken(cause) => Some(cause),
+            Self::InvalidMatrixAcceptInvites(cause) => Some(cause),
+            Self::MissingDiscordToken(cause) => Some(cause),
+            Self::InvalidDiscordApiUrl(cause) => Some(cause),
+            Self::InvalidDiscordPingReplies(cause) => Some(cause),
+            Self::MissingAccounts(cause) => Some(cause),
+            Self::UnknownPlatform(_) => None,
+            Self::InAccount { cause, .. } => Some(&**cause),
+            Self::InvalidBridge(_) => None,
+            Self::UnknownBridgeAccount(_) => None,
         }
     }
 }
 
+/// Reads a variable, its name followed by the suffix.
+fn var(name: &str, suffix: &str) -> Result<String, env::VarError> {
+    env::var(format!("{}{}", name, suffix))
+}
+
+/// Reads how deep reply chains are resolved, from the variable followed by
+/// the suffix.
+fn reply_depth(suffix: &str) -> Result<Option<usize>, EnvError> {
+    var(REPLY_DEPTH_VAR, suffix)
+        .ok()
+        .map(|depth| depth.parse())
+        .transpose()
+        .map_err(EnvError::InvalidReplyDepth)
+}
+
+/// Settings of the app, whatever platform it runs on.
 #[derive(Debug, Clone)]
 pub struct Environment {
+    pub overflow_policy: OverflowPolicy,
+    pub rate_limit_policy: RateLimitPolicy,
+    pub error_policy: ErrorPolicy,
+    pub workers: Option<usize>,
+    pub queue_capacity: Option<usize>,
+    pub drain_timeout: Option<Duration>,
+    /// How sending, receiving and connecting are retried.
+    pub backoff: Backoff,
+    /// When each platform is given a rest after repeated failures.
+    pub breaker: BreakerConfig,
+}
+
+impl Environment {
+    pub fn load() -> Result<Self, EnvError> {
+        let overflow_policy = env::var(OVERFLOW_POLICY_VAR)
+            .ok()
+            .map(|policy| policy.parse())
+            .transpose()
+            .map_err(EnvError::InvalidOverflowPolicy)?
+            .unwrap_or_default();
+        let rate_limit_policy = env::var(RATE_LIMIT_POLICY_VAR)
+            .ok()
+            .map(|policy| policy.parse())
+            .transpose()
+            .map_err(EnvError::InvalidRateLimitPolicy)?
+            .unwrap_or_default();
+        let defaults = Backoff::default();
+        let backoff = Backoff {
+            max_retries: env::var(MAX_RETRIES_VAR)
+                .ok()
+                .map(|retries| retries.parse())
+                .transpose()
+                .map_err(EnvError::InvalidMaxRetries)?
+                .or(defaults.max_retries),
+            initial_delay: env::var(RETRY_DELAY_VAR)
+                .ok()
+                .map(|millis| millis.parse().map(Duration::from_millis))
+                .transpose()
+                .map_err(EnvError::InvalidRetryDelay)?
+                .unwrap_or(defaults.initial_delay),
+            max_delay: env::var(MAX_RETRY_DELAY_VAR)
+                .ok()
+                .map(|secs| secs.parse().map(Duration::from_secs))
+                .transpose()
+                .map_err(EnvError::InvalidMaxRetryDelay)?
+                .unwrap_or(defaults.max_delay),
+        };
+        let defaults = BreakerConfig::default();
+        let breaker = BreakerConfig {
+            failure_threshold: env::var(BREAKER_THRESHOLD_VAR)
+                .ok()
+                .map(|failures| failures.parse())
+                .transpose()
+                .map_err(EnvError::InvalidBreakerThreshold)?
+                .unwrap_or(defaults.failure_threshold),
+            cooldown: env::var(BREAKER_COOLDOWN_VAR)
+                .ok()
+                .map(|secs| secs.parse().map(Duration::from_secs))
+                .transpose()
+                .map_err(EnvError::InvalidBreakerCooldown)?
+                .unwrap_or(defaults.cooldown),
+        };
+        let error_policy = env::var(ERROR_POLICY_VAR)
+            .ok()
+            .map(|policy| policy.parse())
+            .transpose()
+            .map_err(EnvError::InvalidErrorPolicy)?
+            .unwrap_or_default();
+        let workers = env::var(WORKERS_VAR)
+            .ok()
+            .map(|workers| workers.parse())
+            .transpose()
+            .map_err(EnvError::InvalidWorkers)?;
+        let queue_capacity = env::var(QUEUE_CAPACITY_VAR)
+            .ok()
+            .map(|capacity| capacity.parse())
+            .transpose()
+            .map_err(EnvError::InvalidQueueCapacity)?;
+        let drain_timeout = env::var(DRAIN_TIMEOUT_VAR)
+            .ok()
+            .map(|secs| secs.parse().map(Duration::from_secs))
+            .transpose()
+            .map_err(EnvError::InvalidDrainTimeout)?;
+        Ok(Self {
+            overflow_policy,
+            rate_limit_policy,
+            error_policy,
+            workers,
+            queue_capacity,
+            drain_timeout,
+            backoff,
+            breaker,
+        })
+    }
+}
+
+/// Settings of the Telegram bot.
+#[derive(Debug, Clone)]
+pub struct TelegramEnvironment {
     pub token: String,
     pub handle: String,
+    pub reply_depth: Option<usize>,
+    /// Chat to which handler errors are reported.
+    pub owner_chat_id: Option<i64>,
+    /// Webhook to receive updates through, instead of polling.
+    pub webhook: Option<WebhookConfig>,
+    /// How the Bot API is reached.
+    pub client: ClientConfig,
 }
 
-impl Environment {
+impl TelegramEnvironment {
+    pub fn load() -> Result<Self, EnvError> {
+        Self::load_suffixed("")
+    }
+
+    /// Loads the settings from variables named with the suffix, e.g. for
+    /// one of several accounts.
+    fn load_suffixed(suffix: &str) -> Result<Self, EnvError> {
+        let token = var(TOKEN_VAR, suffix).map_err(EnvError::MissingToken)?;
+        let handle =
+            var(HANDLE_VAR, suffix).map_err(EnvError::MissingHandle)?;
+        let reply_depth = reply_depth(suffix)?;
+        let owner_chat_id = var(OWNER_CHAT_VAR, suffix)
+            .ok()
+            .map(|chat_id| chat_id.parse())
+            .transpose()
+            .map_err(EnvError::InvalidOwnerChat)?;
+        let webhook = match var(WEBHOOK_URL_VAR, suffix) {
+            Ok(url) => {
+                let listen = var(WEBHOOK_LISTEN_VAR, suffix)
+                    .unwrap_or_else(|_| String::from(DEFAULT_WEBHOOK_LISTEN))
+                    .parse::<SocketAddr>()
+                    .map_err(EnvError::InvalidWebhookListen)?;
+                let secret = var(WEBHOOK_SECRET_VAR, suffix)
+                    .map_err(EnvError::MissingWebhookSecret)?;
+                if !WebhookConfig::is_valid_secret(&secret) {
+                    return Err(EnvError::InvalidWebhookSecret);
+                }
+                Some(WebhookConfig { url, listen, secret })
+            },
+            Err(_) => None,
+        };
+        let mut client = ClientConfig::default();
+        if let Ok(api_url) = var(API_URL_VAR, suffix) {
+            client.api_url =
+                api_url.parse::<Uri>().map_err(EnvError::InvalidApiUrl)?;
+        }
+        if let Ok(secs) = var(TIMEOUT_VAR, suffix) {
+            client.timeout = secs
+                .parse()
+                .map(Duration::from_secs)
+                .map_err(EnvError::InvalidTimeout)?;
+        }
+        if let Ok(secs) = var(CONNECT_TIMEOUT_VAR, suffix) {
+            client.connect_timeout = secs
+                .parse()
+                .map(Duration::from_secs)
+                .map_err(EnvError::InvalidConnectTimeout)?;
+        }
+        client.proxy = var(PROXY_VAR, suffix)
+            .ok()
+            .map(|proxy| proxy.parse::<Uri>())
+            .transpose()
+            .map_err(EnvError::InvalidProxy)?;
+        Ok(Self { token, handle, reply_depth, owner_chat_id, webhook, client })
+    }
+}
+
+/// Settings of the IRC bot.
+#[derive(Debug, Clone)]
+pub struct IrcEnvironment {
+    pub reply_depth: Option<usize>,
+    pub config: IrcConfig,
+}
+
+impl IrcEnvironment {
+    pub fn load() -> Result<Self, EnvError> {
+        Self::load_suffixed("")
+    }
+
+    /// Loads the settings from variables named with the suffix, e.g. for
+    /// one of several accounts.
+    fn load_suffixed(suffix: &str) -> Result<Self, EnvError> {
+        let host =
+            var(IRC_HOST_VAR, suffix).map_err(EnvError::MissingIrcHost)?;
+        let nick = var(IRC_NICK_VAR, suffix)
+            .map_err(EnvError::MissingIrcNick)?
+            .parse::<IrcName>()
+            .map_err(EnvError::InvalidIrcNick)?;
+        let reply_depth = reply_depth(suffix)?;
+
+        let mut config = IrcConfig::new(host, nick);
+        if let Ok(tls) = var(IRC_TLS_VAR, suffix) {
+            config.tls = tls.parse().map_err(EnvError::InvalidIrcTls)?;
+        }
+        config.port = match var(IRC_PORT_VAR, suffix) {
+            Ok(port) => port.parse().map_err(EnvError::InvalidIrcPort)?,
+            Err(_) if config.tls => irc::DEFAULT_TLS_PORT,
+            Err(_) => irc::DEFAULT_PORT,
+        };
+        if let Ok(user) = var(IRC_USER_VAR, suffix) {
+            config.user = user;
+        }
+        config.password = var(IRC_PASSWORD_VAR, suffix).ok();
+        if let Ok(channels) = var(IRC_CHANNELS_VAR, suffix) {
+            config.channels = channels
+                .split(',')
+                .filter(|channel| !channel.is_empty())
+                .map(|channel| channel.parse())
+                .collect::<Result<_, _>>()
+                .map_err(EnvError::InvalidIrcChannel)?;
+        }
+        Ok(Self { reply_depth, config })
+    }
+}
+
+/// Settings of the Matrix bot.
+#[derive(Debug, Clone)]
+pub struct MatrixEnvironment {
+    pub reply_depth: Option<usize>,
+    /// Whether the account joins any room it is invited to.
+    pub accept_invites: bool,
+    pub config: MatrixConfig,
+}
+
+impl MatrixEnvironment {
     pub fn load() -> Result<Self, EnvError> {
-        let token = env::var(TOKEN_VAR).map_err(EnvError::MissingToken)?;
-        let handle = env::var(HANDLE_VAR).map_err(EnvError::MissingHandle)?;
-        Ok(Self { token, handle })
+        Self::load_suffixed("")
+    }
+
+    /// Loads the settings from variables named with the suffix, e.g. for
+    /// one of several accounts.
+    fn load_suffixed(suffix: &str) -> Result<Self, EnvError> {
+        let homeserver_url = var(MATRIX_HOMESERVER_VAR, suffix)
+            .map_err(EnvError::MissingMatrixHomeserver)?
+            .parse::<Uri>()
+            .map_err(EnvError::InvalidMatrixHomeserver)?;
+        let access_token = var(MATRIX_TOKEN_VAR, suffix)
+            .map_err(EnvError::MissingMatrixToken)?;
+        let reply_depth = reply_depth(suffix)?;
+        let accept_invites = var(MATRIX_ACCEPT_INVITES_VAR, suffix)
+            .ok()
+            .map(|accept| accept.parse())
+            .transpose()
+            .map_err(EnvError::InvalidMatrixAcceptInvites)?
+            .unwrap_or(false);
+        let config = MatrixConfig::new(homeserver_url, access_token);
+        Ok(Self { reply_depth, accept_invites, config })
     }
 }
+
+/// Settings of the Discord bot.
+#[derive(Debug, Clone)]
+pub struct DiscordEnvironment {
+    pub reply_depth: Option<usize>,
+    /// Whether replies ping the author of the message replied to.
+    pub ping_replies: bool,
+    pub config: DiscordConfig,
+}
+
+impl DiscordEnvironment {
+    pub fn load() -> Result<Self, EnvError> {
+        Self::load_suffixed("")
+    }
+
+    /// Loads the settings from variables named with the suffix, e.g. for
+    /// one of several accounts.
+    fn load_suffixed(suffix: &str) -> Result<Self, EnvError> {
+        let token = var(DISCORD_TOKEN_VAR, suffix)
+            .map_err(EnvError::MissingDiscordToken)?;
+        let reply_depth = reply_depth(suffix)?;
+        let ping_replies = var(DISCORD_PING_REPLIES_VAR, suffix)
+            .ok()
+            .map(|ping| ping.parse())
+            .transpose()
+            .map_err(EnvError::InvalidDiscordPingReplies)?
+            .unwrap_or(false);
+
+        let mut config = DiscordConfig::new(token);
+        if let Ok(api_url) = var(DISCORD_API_URL_VAR, suffix) {
+            config.api_url = api_url
+                .parse::<Uri>()
+                .map_err(EnvError::InvalidDiscordApiUrl)?;
+        }
+        config.gateway_url = var(DISCORD_GATEWAY_URL_VAR, suffix).ok();
+        Ok(Self { reply_depth, ping_replies, config })
+    }
+}
+
+/// Settings of an account on some platform.
+#[derive(Debug, Clone)]
+pub enum AccountEnvironment {
+    Telegram(TelegramEnvironment),
+    Irc(IrcEnvironment),
+    Matrix(MatrixEnvironment),
+    Discord(DiscordEnvironment),
+}
+
+/// Account among several served at once.
+#[derive(Debug, Clone)]
+pub struct Account {
+    /// Platform, followed by the name of the account if any, e.g.
+    /// `telegram:ALT`.
+    pub label: String,
+    pub environment: AccountEnvironment,
+}
+
+impl Account {
+    /// Loads the accounts listed in `BOT_ACCOUNTS`, separated by commas, each
+    /// as its platform optionally followed by a colon and a name. Settings of
+    /// a named account are read from the usual variables followed by an
+    /// underscore and the name, e.g. `TELEGRAM_BOT_TOKEN_ALT` for
+    /// `telegram:ALT`.
+    pub fn load_all() -> Result<Vec<Self>, EnvError> {
+        let accounts =
+            env::var(ACCOUNTS_VAR).map_err(EnvError::MissingAccounts)?;
+        accounts
+            .split(',')
+            .map(str::trim)
+            .filter(|label| !label.is_empty())
+            .map(Self::load)
+            .collect()
+    }
+
+    /// Loads the pairs of chats linked by bridges, listed in `BOT_BRIDGES`
+    /// separated by commas, each as `ACCOUNT/CHAT=ACCOUNT/CHAT` where accounts
+    /// are named by their label, e.g. `telegram/-1001234=irc/#rust`.
+    pub fn load_bridges(
+        accounts: &[Self],
+    ) -> Result<Vec<(BridgedChat, BridgedChat)>, EnvError> {
+        let bridges = match env::var(BRIDGES_VAR) {
+            Ok(bridges) => bridges,
+            Err(_) => return Ok(Vec::new()),
+        };
+        let chat = |bridge: &str, side: &str| {
+            let (label, chat) = side
+                .trim()
+                .split_once('/')
+                .filter(|(_, chat)| !chat.is_empty())
+                .ok_or_else(|| EnvError::InvalidBridge(String::from(bridge)))?;
+            let account = accounts
+                .iter()
+                .position(|account| account.label == label)
+                .ok_or_else(|| {
+                    EnvError::UnknownBridgeAccount(String::from(label))
+                })?;
+            Ok(BridgedChat { account, chat: String::from(chat) })
+        };
+        bridges
+            .split(',')
+            .map(str::trim)
+            .filter(|bridge| !bridge.is_empty())
+            .map(|bridge| {
+                let (side, other_side) =
+                    bridge.split_once('=').ok_or_else(|| {
+                        EnvError::InvalidBridge(String::from(bridge))
+                    })?;
+                Ok((chat(bridge, side)?, chat(bridge, other_side)?))
+            })
+            .collect()
+    }
+
+    fn load(label: &str) -> Result<Self, EnvError> {
+        let (platform, suffix) = match label.split_once(':') {
+            Some((platform, name)) => (platform, format!("_{}", name)),
+            None => (label, String::new()),
+        };
+        let environment = match platform {
+            "telegram" => TelegramEnvironment::load_suffixed(&suffix)
+                .map(AccountEnvironment::Telegram),
+            "irc" => IrcEnvironment::load_suffixed(&suffix)
+                .map(AccountEnvironment::Irc),
+            "matrix" => MatrixEnvironment::load_suffixed(&suffix)
+                .map(AccountEnvironment::Matrix),
+            "discord" => DiscordEnvironment::load_suffixed(&suffix)
+                .map(AccountEnvironment::Discord),
+            _ => return Err(EnvError::UnknownPlatform(String::from(platform))),
+        }
+        .map_err(|cause| EnvError::InAccount {
+            account: String::from(label),
+            cause: Box::new(cause),
+        })?;
+        Ok(Self { label: String::from(label), environment })
+    }
+}
+
+/// Chat linked by a bridge, named as on its platform.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BridgedChat {
+    /// Index of the account among those loaded.
+    pub account: usize,
+    pub chat: String,
+}
@@ -2,7 +2,15 @@ use std::{error::Error, fmt, rc::Rc, sync::Arc};
 
 use crate::{
     command::Command,
-    domain::{Bot, Id, Message, MessageData, NewMessage, ReplyTarget},
+    domain::{
+        Bot,
+        Callback,
+        Id,
+        Message,
+        MessageData,
+        NewMessage,
+        ReplyTarget,
+    },
     future::DynFuture,
     port::Sender,
     request,
@@ -11,44 +19,59 @@ use crate::{
 pub trait Handler: fmt::Debug {
     type MessageId: Id;
     type ChatId: Id;
+    type UserId: Id;
     type Error: Error;
 
     fn run<'fut>(
         &'fut self,
         bot: &'fut Bot,
-        input_message: &'fut Message<Self::MessageId, Self::ChatId>,
+        input_message: &'fut Message<
+            Self::MessageId,
+            Self::ChatId,
+            Self::UserId,
+        >,
     ) -> DynFuture<'fut, Result<bool, Self::Error>>;
 }
 
-impl<'this, H> Handler for &'this H
+impl<H> Handler for &H
 where
     H: Handler + ?Sized,
 {
     type MessageId = H::MessageId;
     type ChatId = H::ChatId;
+    type UserId = H::UserId;
     type Error = H::Error;
 
     fn run<'fut>(
         &'fut self,
         bot: &'fut Bot,
-        input_message: &'fut Message<Self::MessageId, Self::ChatId>,
+        input_message: &'fut Message<
+            Self::MessageId,
+            Self::ChatId,
+            Self::UserId,
+        >,
     ) -> DynFuture<'fut, Result<bool, Self::Error>> {
         (**self).run(bot, input_message)
     }
 }
 
-impl<'this, H> Handler for &'this mut H
+impl<H> Handler for &mut H
 where
     H: Handler + ?Sized,
 {
     type MessageId = H::MessageId;
     type ChatId = H::ChatId;
+    type UserId = H::UserId;
     type Error = H::Error;
 
     fn run<'fut>(
         &'fut self,
         bot: &'fut Bot,
-        input_message: &'fut Message<Self::MessageId, Self::ChatId>,
+        input_message: &'fut Message<
+            Self::MessageId,
+            Self::ChatId,
+            Self::UserId,
+        >,
     ) -> DynFuture<'fut, Result<bool, Self::Error>> {
         (**self).run(bot, input_message)
     }
@@ -60,12 +83,17 @@ where
 {
     type MessageId = H::MessageId;
     type ChatId = H::ChatId;
+    type UserId = H::UserId;
     type Error = H::Error;
 
     fn run<'fut>(
         &'fut self,
         bot: &'fut Bot,
-        input_message: &'fut Message<Self::MessageId, Self::ChatId>,
+        input_message: &'fut Message<
+            Self::MessageId,
+            Self::ChatId,
+            Self::UserId,
+        >,
     ) -> DynFuture<'fut, Result<bool, Self::Error>> {
         (**self).run(bot, input_message)
     }
@@ -77,12 +105,17 @@ where
 {
     type MessageId = H::MessageId;
     type ChatId = H::ChatId;
+    type UserId = H::UserId;
     type Error = H::Error;
 
     fn run<'fut>(
         &'fut self,
         bot: &'fut Bot,
-        input_message: &'fut Message<Self::MessageId, Self::ChatId>,
+        input_message: &'fut Message<
+            Self::MessageId,
+            Self::ChatId,
+            Self::UserId,
+        >,
     ) -> DynFuture<'fut, Result<bool, Self::Error>> {
         (**self).run(bot, input_message)
     }
@@ -94,22 +127,152 @@ where
 {
     type MessageId = H::MessageId;
     type ChatId = H::ChatId;
+    type UserId = H::UserId;
     type Error = H::Error;
 
     fn run<'fut>(
         &'fut self,
         bot: &'fut Bot,
-        input_message: &'fut Message<Self::MessageId, Self::ChatId>,
+        input_message: &'fut Message<
+            Self::MessageId,
+            Self::ChatId,
+            Self::UserId,
+        >,
     ) -> DynFuture<'fut, Result<bool, Self::Error>> {
         (**self).run(bot, input_message)
     }
 }
 
+pub trait CallbackHandler: fmt::Debug {
+    type MessageId: Id;
+    type ChatId: Id;
+    type UserId: Id;
+    type Error: Error;
+
+    fn run<'fut>(
+        &'fut self,
+        bot: &'fut Bot,
+        callback: &'fut Callback<Self::MessageId, Self::ChatId, Self::UserId>,
+    ) -> DynFuture<'fut, Result<bool, Self::Error>>;
+}
+
+impl<H> CallbackHandler for &H
+where
+    H: CallbackHandler + ?Sized,
+{
+    type MessageId = H::MessageId;
+    type ChatId = H::ChatId;
+    type UserId = H::UserId;
+    type Error = H::Error;
+
+    fn run<'fut>(
+        &'fut self,
+        bot: &'fut Bot,
+        callback: &'fut Callback<Self::MessageId, Self::ChatId, Self::UserId>,
+    ) -> DynFuture<'fut, Result<bool, Self::Error>> {
+        (**self).run(bot, callback)
+    }
+}
+
+impl<H> CallbackHandler for &mut H
+where
+    H: CallbackHandler + ?Sized,
+{
+    type MessageId = H::MessageId;
+    type ChatId = H::ChatId;
+    type UserId = H::UserId;
+    type Error = H::Error;
+
+    fn run<'fut>(
+        &'fut self,
+        bot: &'fut Bot,
+        callback: &'fut Callback<Self::MessageId, Self::ChatId, Self::UserId>,
+    ) -> DynFuture<'fut, Result<bool, Self::Error>> {
+        (**self).run(bot, callback)
+    }
+}
+
+impl<H> CallbackHandler for Box<H>
+where
+    H: CallbackHandler + ?Sized,
+{
+    type MessageId = H::MessageId;
+    type ChatId = H::ChatId;
+    type UserId = H::UserId;
+    type Error = H::Error;
+
+    fn run<'fut>(
+        &'fut self,
+        bot: &'fut Bot,
+        callback: &'fut Callback<Self::MessageId, Self::ChatId, Self::UserId>,
+    ) -> DynFuture<'fut, Result<bool, Self::Error>> {
+        (**self).run(bot, callback)
+    }
+}
+
+impl<H> CallbackHandler for Rc<H>
+where
+    H: CallbackHandler + ?Sized,
+{
+    type MessageId = H::MessageId;
+    type ChatId = H::ChatId;
+    type UserId = H::UserId;
+    type Error = H::Error;
+
+    fn run<'fut>(
+        &'fut self,
+        bot: &'fut Bot,
+        callback: &'fut Callback<Self::MessageId, Self::ChatId, Self::UserId>,
+    ) -> DynFuture<'fut, Result<bool, Self::Error>> {
+        (**self).run(bot, callback)
+    }
+}
+
+impl<H> CallbackHandler for Arc<H>
+where
+    H: CallbackHandler + ?Sized,
+{
+    type MessageId = H::MessageId;
+    type ChatId = H::ChatId;
+    type UserId = H::UserId;
+    type Error = H::Error;
+
+    fn run<'fut>(
+        &'fut self,
+        bot: &'fut Bot,
+        callback: &'fut Callback<Self::MessageId, Self::ChatId, Self::UserId>,
+    ) -> DynFuture<'fut, Result<bool, Self::Error>> {
+        (**self).run(bot, callback)
+    }
+}
+
+fn error_reply<M, C, U, E>(
+    error: E,
+    replying_to: &Message<M, C, U>,
+) -> NewMessage<M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+    E: Error,
+{
+    NewMessage {
+        data: MessageData {
+            content: error.to_string().into(),
+            chat_id: replying_to.data.chat_id,
+            thread_id: replying_to.data.thread_id,
+            reply_target: ReplyTarget::MessageId(replying_to.id),
+        },
+        keyboard: None,
+        attachment: None,
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DefaultHandler<R, C, S>
 where
-    R: request::Parser<S::MessageId, S::ChatId>,
-    C: Command<R::Request, S::MessageId, S::ChatId>,
+    R: request::Parser<S::MessageId, S::ChatId, S::UserId>,
+    C: Command<R::Request, S::MessageId, S::ChatId, S::UserId>,
     S: Sender,
 {
     pub request_parser: R,
@@ -119,24 +282,30 @@ where
 
 impl<R, C, S> Handler for DefaultHandler<R, C, S>
 where
-    R: request::Parser<S::MessageId, S::ChatId> + Send + Sync,
-    C: Command<R::Request, S::MessageId, S::ChatId> + Send + Sync,
+    R: request::Parser<S::MessageId, S::ChatId, S::UserId> + Send + Sync,
+    C: Command<R::Request, S::MessageId, S::ChatId, S::UserId> + Send + Sync,
     S: Sender + Send + Sync,
     R::Request: Send,
     S::MessageId: Send + Sync,
     S::ChatId: Send + Sync,
+    S::UserId: Send + Sync,
     R::Error: Send,
     C::Error: Send,
     S::Error: Send,
 {
     type MessageId = S::MessageId;
     type ChatId = S::ChatId;
+    type UserId = S::UserId;
     type Error = S::Error;
 
     fn run<'fut>(
         &'fut self,
         bot: &'fut Bot,
-        input_message: &'fut Message<Self::MessageId, Self::ChatId>,
+        input_message: &'fut Message<
+            Self::MessageId,
+            Self::ChatId,
+            Self::UserId,
+        >,
     ) -> DynFuture<'fut, Result<bool, Self::Error>> {
         Box::pin(async move {
             match self.request_parser.parse(bot, input_message) {
@@ -144,25 +313,66 @@ where
                     let output_message = match parse_result {
                         Ok(request) => match self.command.execute(request) {
                             Ok(message) => message,
-                            Err(error) => NewMessage {
-                                data: MessageData {
-                                    content: error.to_string(),
-                                    chat_id: input_message.data.chat_id,
-                                    reply_target: ReplyTarget::MessageId(
-                                        input_message.id,
-                                    ),
-                                },
-                            },
+                            Err(error) => error_reply(error, input_message),
                         },
-                        Err(error) => NewMessage {
-                            data: MessageData {
-                                content: error.to_string(),
-                                chat_id: input_message.data.chat_id,
-                                reply_target: ReplyTarget::MessageId(
-                                    input_message.id,
-                                ),
-                            },
+                        Err(error) => error_reply(error, input_message),
+                    };
+
+                    self.sender.send(&output_message).await?;
+                    Ok(true)
+                },
+                None => Ok(false),
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DefaultCallbackHandler<R, C, S>
+where
+    R: request::CallbackParser<S::MessageId, S::ChatId, S::UserId>,
+    C: Command<R::Request, S::MessageId, S::ChatId, S::UserId>,
+    S: Sender,
+{
+    pub request_parser: R,
+    pub command: C,
+    pub sender: S,
+}
+
+impl<R, C, S> CallbackHandler for DefaultCallbackHandler<R, C, S>
+where
+    R: request::CallbackParser<S::MessageId, S::ChatId, S::UserId>
+        + Send
+        + Sync,
+    C: Command<R::Request, S::MessageId, S::ChatId, S::UserId> + Send + Sync,
+    S: Sender + Send + Sync,
+    R::Request: Send,
+    S::MessageId: Send + Sync,
+    S::ChatId: Send + Sync,
+    S::UserId: Send + Sync,
+    R::Error: Send,
+    C::Error: Send,
+    S::Error: Send,
+{
+    type MessageId = S::MessageId;
+    type ChatId = S::ChatId;
+    type UserId = S::UserId;
+    type Error = S::Error;
+
+    fn run<'fut>(
+        &'fut self,
+        bot: &'fut Bot,
+        callback: &'fut Callback<Self::MessageId, Self::ChatId, Self::UserId>,
+    ) -> DynFuture<'fut, Result<bool, Self::Error>> {
+        Box::pin(async move {
+            match self.request_parser.parse(bot, callback) {
+                Some(parse_result) => {
+                    let output_message = match parse_result {
+                        Ok(request) => match self.command.execute(request) {
+                            Ok(message) => message,
+                            Err(error) => error_reply(error, &callback.message),
                         },
+                        Err(error) => error_reply(error, &callback.message),
                     };
 
                     self.sender.send(&output_message).await?;
@@ -0,0 +1,259 @@
+use crate::domain::{Id, Message, ReplyTarget};
+use std::collections::{HashMap, VecDeque};
+
+/// Bounded record of recently seen messages, used to resolve reply chains
+/// deeper than what a platform embeds in its updates.
+#[derive(Debug, Clone)]
+pub struct History<M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    capacity: usize,
+    messages: HashMap<(C, M), Message<M, C, U>>,
+    order: VecDeque<(C, M)>,
+    /// Latest message recorded as such in each chat and thread.
+    latest: HashMap<(C, Option<M>), M>,
+}
+
+impl<M, C, U> History<M, C, U>
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            messages: HashMap::new(),
+            order: VecDeque::new(),
+            latest: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, chat_id: C, message_id: M) -> Option<&Message<M, C, U>> {
+        self.messages.get(&(chat_id, message_id))
+    }
+
+    /// Latest message of the chat and thread, unless forgotten since.
+    pub fn latest(
+        &self,
+        chat_id: C,
+        thread_id: Option<M>,
+    ) -> Option<&Message<M, C, U>> {
+        let message_id = self.latest.get(&(chat_id, thread_id))?;
+        self.get(chat_id, *message_id)
+    }
+
+    /// Records a message as the latest of its chat and thread.
+    pub fn record_latest(&mut self, message: &Message<M, C, U>) {
+        self.record(message);
+        let key = (message.data.chat_id, message.data.thread_id);
+        self.latest.insert(key, message.id);
+    }
+
+    /// Records a message and every message embedded in its reply chain. Only
+    /// the id of a reply target is stored, so each entry is a single level.
+    pub fn record(&mut self, message: &Message<M, C, U>) {
+        let mut stored = message.clone();
+        let mut knows_reply = true;
+        if let ReplyTarget::Message(target) = &message.data.reply_target {
+            self.record(target);
+            stored.data.reply_target = ReplyTarget::MessageId(target.id);
+        } else if message.data.reply_target == ReplyTarget::Prunned {
+            knows_reply = false;
+        }
+
+        let key = (message.data.chat_id, message.id);
+        if let Some(existing) = self.messages.get_mut(&key) {
+            if !knows_reply {
+                stored.data.reply_target = existing.data.reply_target.clone();
+            }
+            *existing = stored;
+        } else {
+            self.messages.insert(key, stored);
+            self.order.push_back(key);
+            while self.order.len() > self.capacity {
+                let forgotten = self
+                    .order
+                    .pop_front()
+                    .and_then(|oldest| self.messages.remove(&oldest));
+                if let Some(forgotten) = forgotten {
+                    let key =
+                        (forgotten.data.chat_id, forgotten.data.thread_id);
+                    if self.latest.get(&key) == Some(&forgotten.id) {
+                        self.latest.remove(&key);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Expands the reply chain of a message so that up to `depth` levels of
+    /// replied messages are embedded, looking up missing levels by id.
+    pub fn resolve(
+        &self,
+        mut message: Message<M, C, U>,
+        depth: usize,
+    ) -> Message<M, C, U> {
+        let chat_id = message.data.chat_id;
+        let mut target = message.data.reply_target;
+        if target == ReplyTarget::Prunned {
+            if let Some(stored) = self.get(chat_id, message.id) {
+                target = stored.data.reply_target.clone();
+            }
+        }
+
+        message.data.reply_target = match target {
+            ReplyTarget::NotReplying => ReplyTarget::NotReplying,
+            _ if depth == 0 => ReplyTarget::Prunned,
+            ReplyTarget::Message(target) => {
+                ReplyTarget::Message(Box::new(self.resolve(*target, depth - 1)))
+            },
+            ReplyTarget::MessageId(target_id) => {
+                match self.get(chat_id, target_id) {
+                    Some(target) => ReplyTarget::Message(Box::new(
+                        self.resolve(target.clone(), depth - 1),
+                    )),
+                    None => ReplyTarget::MessageId(target_id),
+                }
+            },
+            ReplyTarget::Prunned => ReplyTarget::Prunned,
+        };
+
+        message
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::History;
+    use crate::domain::{ContentKind, Message, MessageData, ReplyTarget};
+
+    fn message(
+        id: u64,
+        reply_target: ReplyTarget<u64, u64, u64>,
+    ) -> Message<u64, u64, u64> {
+        Message {
+            id,
+            author: None,
+            content_kind: ContentKind::Text,
+            data: MessageData {
+                chat_id: 1,
+                thread_id: None,
+                content: format!("message {}", id).into(),
+                reply_target,
+            },
+        }
+    }
+
+    /// Chain of messages 1 to `len`, each replying to the one before.
+    fn chain(len: u64) -> History<u64, u64, u64> {
+        let mut history = History::new(16);
+        history.record(&message(1, ReplyTarget::NotReplying));
+        for id in 2..=len {
+            history.record(&message(id, ReplyTarget::MessageId(id - 1)));
+        }
+        history
+    }
+
+    /// Ids along the reply chain of a message, with how the chain ends.
+    fn ids(
+        message: &Message<u64, u64, u64>,
+    ) -> (Vec<u64>, ReplyTarget<u64, u64, u64>) {
+        let mut ids = vec![message.id];
+        let mut target = &message.data.reply_target;
+        while let ReplyTarget::Message(replied) = target {
+            ids.push(replied.id);
+            target = &replied.data.reply_target;
+        }
+        (ids, target.clone())
+    }
+
+    #[test]
+    fn prunes_at_depth_zero() {
+        let history = chain(2);
+        let resolved =
+            history.resolve(message(3, ReplyTarget::MessageId(2)), 0);
+        assert_eq!(ids(&resolved), (vec![3], ReplyTarget::Prunned));
+        let resolved = history.resolve(message(3, ReplyTarget::NotReplying), 0);
+        assert_eq!(ids(&resolved), (vec![3], ReplyTarget::NotReplying));
+    }
+
+    #[test]
+    fn resolves_one_level() {
+        let history = chain(2);
+        let resolved =
+            history.resolve(message(3, ReplyTarget::MessageId(2)), 1);
+        assert_eq!(ids(&resolved), (vec![3, 2], ReplyTarget::Prunned));
+    }
+
+    #[test]
+    fn resolves_many_levels() {
+        let history = chain(4);
+        let resolved =
+            history.resolve(message(5, ReplyTarget::MessageId(4)), 3);
+        assert_eq!(ids(&resolved), (vec![5, 4, 3, 2], ReplyTarget::Prunned));
+        let resolved =
+            history.resolve(message(5, ReplyTarget::MessageId(4)), 9);
+        assert_eq!(
+            ids(&resolved),
+            (vec![5, 4, 3, 2, 1], ReplyTarget::NotReplying)
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_ids() {
+        let history = chain(2);
+        let resolved =
+            history.resolve(message(3, ReplyTarget::MessageId(9)), 2);
+        assert_eq!(ids(&resolved), (vec![3], ReplyTarget::MessageId(9)));
+    }
+
+    #[test]
+    fn records_embedded_replies_one_level_each() {
+        let mut history = History::new(16);
+        let first = message(1, ReplyTarget::NotReplying);
+        let second = message(2, ReplyTarget::Message(Box::new(first)));
+        history.record(&message(3, ReplyTarget::Message(Box::new(second))));
+        let stored = history.get(1, 3).unwrap();
+        assert_eq!(stored.data.reply_target, ReplyTarget::MessageId(2));
+        let stored = history.get(1, 2).unwrap();
+        assert_eq!(stored.data.reply_target, ReplyTarget::MessageId(1));
+        assert!(history.get(1, 1).is_some());
+    }
+
+    #[test]
+    fn keeps_known_replies_of_prunned_messages() {
+        let mut history = chain(2);
+        history.record(&message(2, ReplyTarget::Prunned));
+        let stored = history.get(1, 2).unwrap();
+        assert_eq!(stored.data.reply_target, ReplyTarget::MessageId(1));
+        let resolved = history.resolve(message(2, ReplyTarget::Prunned), 1);
+        assert_eq!(ids(&resolved), (vec![2, 1], ReplyTarget::NotReplying));
+    }
+
+    #[test]
+    fn forgets_the_oldest_messages() {
+        let mut history = History::new(2);
+        history.record(&message(1, ReplyTarget::NotReplying));
+        history.record(&message(2, ReplyTarget::MessageId(1)));
+        history.record(&message(3, ReplyTarget::MessageId(2)));
+        assert!(history.get(1, 1).is_none());
+        let resolved =
+            history.resolve(message(4, ReplyTarget::MessageId(3)), 3);
+        assert_eq!(ids(&resolved), (vec![4, 3, 2], ReplyTarget::MessageId(1)));
+    }
+
+    #[test]
+    fn forgets_the_latest_message_with_the_others() {
+        let mut history = History::new(2);
+        history.record_latest(&message(1, ReplyTarget::NotReplying));
+        assert_eq!(history.latest(1, None).map(|latest| latest.id), Some(1));
+        history.record(&message(2, ReplyTarget::NotReplying));
+        history.record(&message(3, ReplyTarget::NotReplying));
+        assert!(history.latest(1, None).is_none());
+        assert!(history.latest.is_empty());
+    }
+}
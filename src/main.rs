@@ -1,12 +1,44 @@
-use std::process;
+use std::{fmt, path::PathBuf, process};
+use telegram_bot::ChatId;
 
-use adapter::telegram::TgMessageChannel;
-use app::App;
-use commands::help::{HelpCommand, HelpRequestParser};
-use env::Environment;
-use handler::DefaultHandler;
+use adapter::{
+    console,
+    discord::{self, DiscordChannel},
+    irc::{self, IrcChannel},
+    matrix::{self, MatrixChannel, MatrixClient},
+    memory::MemoryChannel,
+    multi::{MultiId, Multiplexer},
+    telegram::{self, TgClient, TgMessageChannel, TgWebhook},
+};
+use app::{App, NotifyChat, Stopped};
+use bridge::{Bridge, BridgeHandler, Bridged};
+use cli::Mode;
+use commands::{
+    help::{HelpCommand, HelpRequestParser},
+    replace::{ReplaceCommand, RequestParser as ReplaceRequestParser},
+    undo::{UndoHandler, Undoable},
+};
+use env::{
+    Account,
+    AccountEnvironment,
+    DiscordEnvironment,
+    Environment,
+    IrcEnvironment,
+    MatrixEnvironment,
+    TelegramEnvironment,
+};
+use handler::{DefaultCallbackHandler, DefaultHandler};
+use middleware::{
+    rate_limit::{RateLimitingSender, RateLimits, RetryAfter},
+    retry::{CircuitBreaker, Retrying, Transient},
+    split::{PlatformLimits, SplittingSender},
+};
+use port::{Deleter, Sender};
+use shutdown::Shutdown;
 
 mod future;
+mod cli;
+mod clock;
 mod env;
 mod domain;
 mod port;
@@ -14,34 +46,546 @@ mod adapter;
 mod request;
 mod command;
 mod handler;
+mod history;
+mod bridge;
+mod middleware;
 mod commands;
+mod offline;
+mod shutdown;
 mod app;
+#[cfg(test)]
+mod e2e;
+
+const FAILURE_EXIT_CODE: i32 = 1;
+const FORCED_SHUTDOWN_EXIT_CODE: i32 = 2;
 
 #[tokio::main]
 async fn main() {
-    let environment = Environment::load().unwrap_or_else(|error| {
+    let mode =
+        Mode::from_args(std::env::args().skip(1)).unwrap_or_else(|error| {
+            eprintln!("Error with arguments...");
+            eprintln!("    {}", error);
+            process::exit(FAILURE_EXIT_CODE);
+        });
+    let stopped = match mode {
+        Mode::Telegram => run_telegram(load_environment()).await,
+        Mode::Console => run_console(load_environment()).await,
+        Mode::Irc => run_irc(load_environment()).await,
+        Mode::Matrix => run_matrix(load_environment()).await,
+        Mode::Discord => run_discord(load_environment()).await,
+        Mode::Multi => run_multi(load_environment()).await,
+        Mode::Apply { command, files } => {
+            run_apply(&command, &files);
+            return;
+        },
+    };
+    match stopped {
+        Stopped::Drained => (),
+        Stopped::Forced => {
+            eprintln!("Shutdown timed out, pending updates were dropped");
+            process::exit(FORCED_SHUTDOWN_EXIT_CODE);
+        },
+        Stopped::Crashed => {
+            eprintln!("A worker died, received updates were not confirmed");
+            process::exit(FAILURE_EXIT_CODE);
+        },
+    }
+}
+
+fn load_environment() -> Environment {
+    Environment::load().unwrap_or_else(|error| {
         eprintln!("Error with environment...");
         eprintln!("    {}", error);
-        process::exit(1);
-    });
-
-    let Environment { token, handle } = environment;
+        process::exit(FAILURE_EXIT_CODE);
+    })
+}
 
-    let bot = domain::Bot { handle };
-    let channel = TgMessageChannel::new(&token);
+/// Builds the app with every command, replying through the given sender.
+fn build_app<S>(
+    bot: domain::Bot,
+    sender: S,
+    environment: &Environment,
+) -> App<'static, S::MessageId, S::ChatId, S::UserId, S::Error>
+where
+    S: Deleter + Clone + Send + Sync + 'static,
+    S::MessageId: Send + Sync,
+    S::ChatId: Send + Sync,
+    S::UserId: Send + Sync,
+    S::Error: Send,
+{
+    with_commands(App::new(bot), sender, environment)
+}
 
-    let result = App::new(bot)
+/// Adds the commands of the bot, and the settings of the environment, after
+/// whatever handlers the app already has.
+fn with_commands<S>(
+    app: App<'static, S::MessageId, S::ChatId, S::UserId, S::Error>,
+    sender: S,
+    environment: &Environment,
+) -> App<'static, S::MessageId, S::ChatId, S::UserId, S::Error>
+where
+    S: Deleter + Clone + Send + Sync + 'static,
+    S::MessageId: Send + Sync,
+    S::ChatId: Send + Sync,
+    S::UserId: Send + Sync,
+    S::Error: Send,
+{
+    let replace_parser = ReplaceRequestParser::new();
+    let replace = Undoable { command: ReplaceCommand, deleter: sender.clone() };
+    let mut app = app
         .handler(DefaultHandler {
             request_parser: HelpRequestParser,
             command: HelpCommand,
-            sender: channel.clone(),
+            sender: sender.clone(),
+        })
+        .handler(DefaultHandler {
+            request_parser: replace_parser.clone(),
+            command: replace.clone(),
+            sender: sender.clone(),
+        })
+        .callback_handler(UndoHandler { deleter: sender.clone() })
+        .callback_handler(DefaultCallbackHandler {
+            request_parser: replace_parser,
+            command: replace,
+            sender,
         })
-        .run(channel)
-        .await;
+        .error_policy(environment.error_policy);
+    if let Some(workers) = environment.workers {
+        app = app.workers(workers);
+    }
+    if let Some(capacity) = environment.queue_capacity {
+        app = app.queue_capacity(capacity);
+    }
+    if let Some(timeout) = environment.drain_timeout {
+        app = app.drain_timeout(timeout);
+    }
+    app
+}
 
-    if let Err(error) = result {
-        eprintln!("Error running application...");
+/// Finds a chat of an account from its name on the platform.
+type ChatParser = Box<dyn Fn(&str) -> Option<MultiId>>;
+
+/// Wraps the channel of a platform in the middlewares every sender goes
+/// through: splitting, retrying and rate limiting.
+fn sender_stack<S>(
+    channel: S,
+    limits: PlatformLimits,
+    rate_limits: RateLimits,
+    environment: &Environment,
+    breaker: CircuitBreaker,
+) -> SplittingSender<Retrying<RateLimitingSender<S>>>
+where
+    S: Sender + Send + Sync,
+    S::MessageId: Send + Sync,
+    S::ChatId: Send + Sync,
+    S::UserId: Send + Sync,
+    S::Error: Transient + RetryAfter + Send + 'static,
+{
+    SplittingSender::new(
+        Retrying::new(
+            RateLimitingSender::new(
+                channel,
+                rate_limits,
+                environment.rate_limit_policy,
+            ),
+            environment.backoff,
+            breaker,
+        ),
+        limits,
+        environment.overflow_policy,
+    )
+}
+
+async fn run_telegram(environment: Environment) -> Stopped {
+    let TelegramEnvironment {
+        token,
+        handle,
+        reply_depth,
+        owner_chat_id,
+        webhook,
+        client,
+    } = TelegramEnvironment::load().unwrap_or_else(|error| {
+        eprintln!("Error with environment...");
+        eprintln!("    {}", error);
+        process::exit(FAILURE_EXIT_CODE);
+    });
+
+    let client = TgClient::new(&token, client).unwrap_or_else(|error| {
+        eprintln!("Error setting up Telegram client...");
         eprintln!("    {}", error);
-        process::exit(1);
+        process::exit(FAILURE_EXIT_CODE);
+    });
+    let mut channel = TgMessageChannel::new(client);
+    if let Some(depth) = reply_depth {
+        channel = channel.reply_depth(depth);
+    }
+    let breaker = CircuitBreaker::new(environment.breaker);
+    let sender = sender_stack(
+        channel.clone(),
+        telegram::LIMITS,
+        telegram::RATE_LIMITS,
+        &environment,
+        breaker.clone(),
+    );
+
+    let mut app =
+        build_app(domain::Bot { handle }, sender.clone(), &environment);
+    if let Some(owner_chat_id) = owner_chat_id {
+        app = app.error_hook(NotifyChat {
+            sender,
+            chat_id: ChatId::new(owner_chat_id),
+        });
+    }
+
+    let shutdown = Shutdown::on_signals();
+    match webhook {
+        Some(config) => {
+            let webhook =
+                TgWebhook::bind(channel, config).unwrap_or_else(|error| {
+                    eprintln!("Error starting webhook server...");
+                    eprintln!("    {}", error);
+                    process::exit(FAILURE_EXIT_CODE);
+                });
+            let connector =
+                Retrying::new(webhook.clone(), environment.backoff, breaker);
+            let result = app
+                .run_supervised(connector, environment.backoff, shutdown)
+                .await;
+            if let Err(error) = webhook.remove().await {
+                eprintln!("Error removing webhook...");
+                eprintln!("    {}", error);
+            }
+            stopped_or_exit(result)
+        },
+        None => {
+            let connector =
+                Retrying::new(channel, environment.backoff, breaker);
+            let result = app
+                .run_supervised(connector, environment.backoff, shutdown)
+                .await;
+            stopped_or_exit(result)
+        },
+    }
+}
+
+async fn run_irc(environment: Environment) -> Stopped {
+    let IrcEnvironment { reply_depth, config } = IrcEnvironment::load()
+        .unwrap_or_else(|error| {
+            eprintln!("Error with environment...");
+            eprintln!("    {}", error);
+            process::exit(FAILURE_EXIT_CODE);
+        });
+
+    let handle = config.nick.to_string();
+    let mut channel = IrcChannel::new(config);
+    if let Some(depth) = reply_depth {
+        channel = channel.reply_depth(depth);
     }
+    let breaker = CircuitBreaker::new(environment.breaker);
+    let sender = sender_stack(
+        channel.clone(),
+        irc::LIMITS,
+        irc::RATE_LIMITS,
+        &environment,
+        breaker.clone(),
+    );
+    let app = build_app(domain::Bot { handle }, sender, &environment);
+
+    let connector = Retrying::new(channel, environment.backoff, breaker);
+    let shutdown = Shutdown::on_signals();
+    let result =
+        app.run_supervised(connector, environment.backoff, shutdown).await;
+    stopped_or_exit(result)
+}
+
+async fn run_matrix(environment: Environment) -> Stopped {
+    let MatrixEnvironment { reply_depth, accept_invites, config } =
+        MatrixEnvironment::load().unwrap_or_else(|error| {
+            eprintln!("Error with environment...");
+            eprintln!("    {}", error);
+            process::exit(FAILURE_EXIT_CODE);
+        });
+
+    let mut channel = MatrixChannel::new(MatrixClient::new(config))
+        .accept_invites(accept_invites);
+    if let Some(depth) = reply_depth {
+        channel = channel.reply_depth(depth);
+    }
+    let breaker = CircuitBreaker::new(environment.breaker);
+    let sender = sender_stack(
+        channel.clone(),
+        matrix::LIMITS,
+        matrix::RATE_LIMITS,
+        &environment,
+        breaker.clone(),
+    );
+    let bot = domain::Bot { handle: String::from(matrix::HANDLE) };
+    let app = build_app(bot, sender, &environment);
+
+    let connector = Retrying::new(channel, environment.backoff, breaker);
+    let shutdown = Shutdown::on_signals();
+    let result =
+        app.run_supervised(connector, environment.backoff, shutdown).await;
+    stopped_or_exit(result)
+}
+
+async fn run_discord(environment: Environment) -> Stopped {
+    let DiscordEnvironment { reply_depth, ping_replies, config } =
+        DiscordEnvironment::load().unwrap_or_else(|error| {
+            eprintln!("Error with environment...");
+            eprintln!("    {}", error);
+            process::exit(FAILURE_EXIT_CODE);
+        });
+
+    let mut channel =
+        DiscordChannel::new(config).ping_replied_users(ping_replies);
+    if let Some(depth) = reply_depth {
+        channel = channel.reply_depth(depth);
+    }
+    let breaker = CircuitBreaker::new(environment.breaker);
+    let sender = sender_stack(
+        channel.clone(),
+        discord::LIMITS,
+        discord::RATE_LIMITS,
+        &environment,
+        breaker.clone(),
+    );
+    let bot = domain::Bot { handle: String::from(discord::HANDLE) };
+    let app = build_app(bot, sender, &environment);
+
+    let connector = Retrying::new(channel, environment.backoff, breaker);
+    let shutdown = Shutdown::on_signals();
+    let result =
+        app.run_supervised(connector, environment.backoff, shutdown).await;
+    stopped_or_exit(result)
+}
+
+/// Serves every account at once, each reconnecting on its own, with replies
+/// going out through the account they answer.
+async fn run_multi(environment: Environment) -> Stopped {
+    let accounts = Account::load_all().unwrap_or_else(|error| {
+        eprintln!("Error with environment...");
+        eprintln!("    {}", error);
+        process::exit(FAILURE_EXIT_CODE);
+    });
+
+    let bridges = Account::load_bridges(&accounts).unwrap_or_else(|error| {
+        eprintln!("Error with environment...");
+        eprintln!("    {}", error);
+        process::exit(FAILURE_EXIT_CODE);
+    });
+
+    let labels: Vec<_> =
+        accounts.iter().map(|account| account.label.clone()).collect();
+
+    let mut multiplexer = Multiplexer::new(environment.backoff);
+    let mut owner_chat_id = None;
+    let mut webhooks = Vec::new();
+    // Per account, finds the chat named in the configuration of bridges.
+    let mut chat_parsers: Vec<ChatParser> = Vec::new();
+    for Account { label, environment: account } in accounts {
+        let breaker = CircuitBreaker::new(environment.breaker);
+        match account {
+            AccountEnvironment::Telegram(account) => {
+                let client = TgClient::new(&account.token, account.client)
+                    .unwrap_or_else(|error| {
+                        eprintln!("Error setting up Telegram client...");
+                        eprintln!("    {}", error);
+                        process::exit(FAILURE_EXIT_CODE);
+                    });
+                let mut channel = TgMessageChannel::new(client);
+                if let Some(depth) = account.reply_depth {
+                    channel = channel.reply_depth(depth);
+                }
+                let sender = sender_stack(
+                    channel.clone(),
+                    telegram::LIMITS,
+                    telegram::RATE_LIMITS,
+                    &environment,
+                    breaker.clone(),
+                );
+                let bot = domain::Bot { handle: account.handle };
+                let tags = match account.webhook {
+                    Some(config) => {
+                        let webhook = TgWebhook::bind(channel, config)
+                            .unwrap_or_else(|error| {
+                                eprintln!("Error starting webhook server...");
+                                eprintln!("    {}", error);
+                                process::exit(FAILURE_EXIT_CODE);
+                            });
+                        webhooks.push(webhook.clone());
+                        let connector = Retrying::new(
+                            webhook,
+                            environment.backoff,
+                            breaker,
+                        );
+                        multiplexer.add(&label, bot, connector, sender)
+                    },
+                    None => {
+                        let connector = Retrying::new(
+                            channel,
+                            environment.backoff,
+                            breaker,
+                        );
+                        multiplexer.add(&label, bot, connector, sender)
+                    },
+                };
+                // Errors are reported to a single chat, the first one given.
+                if let Some(chat_id) = account.owner_chat_id {
+                    owner_chat_id
+                        .get_or_insert_with(|| tags.chat(ChatId::new(chat_id)));
+                }
+                chat_parsers.push(Box::new(move |chat| {
+                    let chat_id = chat.parse().ok()?;
+                    Some(tags.chat(ChatId::new(chat_id)))
+                }));
+            },
+            AccountEnvironment::Irc(IrcEnvironment { reply_depth, config }) => {
+                let handle = config.nick.to_string();
+                let mut channel = IrcChannel::new(config);
+                if let Some(depth) = reply_depth {
+                    channel = channel.reply_depth(depth);
+                }
+                let sender = sender_stack(
+                    channel.clone(),
+                    irc::LIMITS,
+                    irc::RATE_LIMITS,
+                    &environment,
+                    breaker.clone(),
+                );
+                let bot = domain::Bot { handle };
+                let connector =
+                    Retrying::new(channel, environment.backoff, breaker);
+                let tags = multiplexer.add(&label, bot, connector, sender);
+                chat_parsers.push(Box::new(move |chat| {
+                    Some(tags.chat(chat.parse().ok()?))
+                }));
+            },
+            AccountEnvironment::Matrix(MatrixEnvironment {
+                reply_depth,
+                accept_invites,
+                config,
+            }) => {
+                let mut channel = MatrixChannel::new(MatrixClient::new(config))
+                    .accept_invites(accept_invites);
+                if let Some(depth) = reply_depth {
+                    channel = channel.reply_depth(depth);
+                }
+                let sender = sender_stack(
+                    channel.clone(),
+                    matrix::LIMITS,
+                    matrix::RATE_LIMITS,
+                    &environment,
+                    breaker.clone(),
+                );
+                let bot = domain::Bot { handle: String::from(matrix::HANDLE) };
+                let connector = Retrying::new(
+                    channel.clone(),
+                    environment.backoff,
+                    breaker,
+                );
+                let tags = multiplexer.add(&label, bot, connector, sender);
+                chat_parsers.push(Box::new(move |chat| {
+                    Some(tags.chat(channel.room(chat)))
+                }));
+            },
+            AccountEnvironment::Discord(DiscordEnvironment {
+                reply_depth,
+                ping_replies,
+                config,
+            }) => {
+                let mut channel = DiscordChannel::new(config)
+                    .ping_replied_users(ping_replies);
+                if let Some(depth) = reply_depth {
+                    channel = channel.reply_depth(depth);
+                }
+                let sender = sender_stack(
+                    channel.clone(),
+                    discord::LIMITS,
+                    discord::RATE_LIMITS,
+                    &environment,
+                    breaker.clone(),
+                );
+                let bot = domain::Bot { handle: String::from(discord::HANDLE) };
+                let connector =
+                    Retrying::new(channel, environment.backoff, breaker);
+                let tags = multiplexer.add(&label, bot, connector, sender);
+                chat_parsers.push(Box::new(move |chat| {
+                    Some(tags.chat(chat.parse().ok()?))
+                }));
+            },
+        }
+    }
+
+    let mut bridge = Bridge::new();
+    for (chat, other_chat) in bridges {
+        let [chat_id, other_chat_id] = [chat, other_chat].map(|chat| {
+            chat_parsers[chat.account](&chat.chat).unwrap_or_else(|| {
+                eprintln!("Error with environment...");
+                eprintln!(
+                    "    {:?} is not a valid chat of account {}",
+                    chat.chat, labels[chat.account]
+                );
+                process::exit(FAILURE_EXIT_CODE);
+            })
+        });
+        bridge = bridge.link(chat_id, other_chat_id);
+    }
+
+    // Every chat belongs to an account, whose bot answers in it. Messages
+    // are mirrored across bridges before commands handle them, and what
+    // commands send is mirrored as well.
+    let bot = domain::Bot { handle: String::new() };
+    let app = App::new(bot).handler(BridgeHandler {
+        bridge: bridge.clone(),
+        sender: multiplexer.clone(),
+    });
+    let sender = Bridged::new(multiplexer.clone(), bridge.clone());
+    let mut app =
+        with_commands(app, sender, &environment).bots(multiplexer.clone());
+    if let Some(chat_id) = owner_chat_id {
+        app =
+            app.error_hook(NotifyChat { sender: multiplexer.clone(), chat_id });
+    }
+    let shutdown = Shutdown::on_signals();
+    let connector = Bridged::new(multiplexer, bridge);
+    let result =
+        app.run_supervised(connector, environment.backoff, shutdown).await;
+    for webhook in webhooks {
+        if let Err(error) = webhook.remove().await {
+            eprintln!("Error removing webhook...");
+            eprintln!("    {}", error);
+        }
+    }
+    stopped_or_exit(result)
+}
+
+async fn run_console(environment: Environment) -> Stopped {
+    let channel = MemoryChannel::new();
+    let bot = domain::Bot { handle: String::from(console::HANDLE) };
+    let app = build_app(bot, channel.clone(), &environment);
+    let shutdown = Shutdown::on_signals();
+    console::attach(channel.clone(), shutdown.clone());
+    let result =
+        app.run_supervised(channel, environment.backoff, shutdown).await;
+    stopped_or_exit(result)
+}
+
+fn run_apply(command: &str, files: &[PathBuf]) {
+    if let Err(error) = offline::apply_to_files(command, files) {
+        eprintln!("Error applying command...");
+        eprintln!("    {}", error);
+        process::exit(FAILURE_EXIT_CODE);
+    }
+}
+
+fn stopped_or_exit<E>(result: Result<Stopped, E>) -> Stopped
+where
+    E: fmt::Display,
+{
+    result.unwrap_or_else(|error| {
+        eprintln!("Error running application...");
+        eprintln!("    {}", error);
+        process::exit(FAILURE_EXIT_CODE);
+    })
 }
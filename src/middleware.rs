@@ -0,0 +1,3 @@
+pub mod split;
+pub mod rate_limit;
+pub mod retry;
@@ -0,0 +1,645 @@
+use crate::{
+    clock::{Clock, SystemClock},
+    domain::{Id, NewMessage},
+    future::DynFuture,
+    port::{Deleter, Sender},
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    future::Future,
+    str::FromStr,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Amount of chat states kept before idle ones are discarded. Past it, they
+/// are only discarded once twice as many as after the last time are kept.
+const MAX_IDLE_CHATS: usize = 1024;
+/// Times a message is tried when queueing while the platform keeps asking to
+/// wait, before its error is returned.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Errors which tell how long to wait before trying again.
+pub trait RetryAfter {
+    fn retry_after(&self) -> Option<Duration>;
+}
+
+/// At most `burst` messages within any `period`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Quota {
+    pub burst: u32,
+    pub period: Duration,
+}
+
+impl Quota {
+    fn interval(self) -> Duration {
+        self.period / self.burst.max(1)
+    }
+}
+
+/// Rate limits of a platform, applied to each chat and to all chats
+/// together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RateLimits {
+    pub per_chat: &'static [Quota],
+    pub global: &'static [Quota],
+}
+
+#[derive(Debug, Clone)]
+pub struct InvalidRateLimitPolicy(String);
+
+impl fmt::Display for InvalidRateLimitPolicy {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmtr,
+            "{:?} is not a rate limit policy, expected \"queue\" or \"drop\"",
+            self.0
+        )
+    }
+}
+
+impl Error for InvalidRateLimitPolicy {}
+
+/// What to do with a message sent when the rate limit is exhausted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum RateLimitPolicy {
+    /// Waits until the message can be sent, giving up when the platform
+    /// keeps asking to wait anyway.
+    #[default]
+    Queue,
+    /// Fails immediately with `RateLimitError::Dropped`.
+    Drop,
+}
+
+impl FromStr for RateLimitPolicy {
+    type Err = InvalidRateLimitPolicy;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "queue" => Ok(Self::Queue),
+            "drop" => Ok(Self::Drop),
+            _ => Err(InvalidRateLimitPolicy(String::from(input))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RateLimitError<E> {
+    /// The message was dropped, another one could be sent after the given
+    /// time.
+    Dropped {
+        retry_in: Duration,
+    },
+    Sender(E),
+}
+
+impl<E> fmt::Display for RateLimitError<E>
+where
+    E: fmt::Display,
+{
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Dropped { retry_in } => write!(
+                fmtr,
+                "message dropped by rate limiting, retry in {:?}",
+                retry_in
+            ),
+            Self::Sender(cause) => write!(fmtr, "{}", cause),
+        }
+    }
+}
+
+impl<E> Error for RateLimitError<E>
+where
+    E: Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Dropped { .. } => None,
+            Self::Sender(cause) => Some(cause),
+        }
+    }
+}
+
+impl<E> RetryAfter for RateLimitError<E>
+where
+    E: RetryAfter,
+{
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Dropped { retry_in } => Some(*retry_in),
+            Self::Sender(cause) => cause.retry_after(),
+        }
+    }
+}
+
+/// Token bucket for a quota, tracked as the theoretical arrival time of the
+/// next message (GCRA).
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    quota: Quota,
+    arrival: Instant,
+}
+
+impl Bucket {
+    fn new(quota: Quota, now: Instant) -> Self {
+        Self { quota, arrival: now }
+    }
+
+    fn wait(&self, now: Instant) -> Duration {
+        let tolerance = self.quota.period - self.quota.interval();
+        self.arrival.saturating_duration_since(now).saturating_sub(tolerance)
+    }
+
+    fn take(&mut self, now: Instant) {
+        self.arrival = self.arrival.max(now) + self.quota.interval();
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        self.arrival <= now
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Buckets {
+    buckets: Vec<Bucket>,
+    blocked_until: Instant,
+}
+
+impl Buckets {
+    fn new(quotas: &[Quota], now: Instant) -> Self {
+        let buckets =
+            quotas.iter().map(|&quota| Bucket::new(quota, now)).collect();
+        Self { buckets, blocked_until: now }
+    }
+
+    fn wait(&self, now: Instant) -> Duration {
+        self.buckets.iter().map(|bucket| bucket.wait(now)).fold(
+            self.blocked_until.saturating_duration_since(now),
+            Duration::max,
+        )
+    }
+
+    fn take(&mut self, now: Instant) {
+        for bucket in &mut self.buckets {
+            bucket.take(now);
+        }
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        self.blocked_until <= now
+            && self.buckets.iter().all(|bucket| bucket.is_idle(now))
+    }
+}
+
+#[derive(Debug)]
+struct State<C> {
+    global: Buckets,
+    chats: HashMap<C, Buckets>,
+    /// Amount of chat states left by the last discarding of idle ones.
+    swept_len: usize,
+}
+
+impl<C> State<C>
+where
+    C: Id,
+{
+    /// Takes a token from every bucket of the chat if all of them have one,
+    /// otherwise yields how long to wait until they do.
+    fn try_take(
+        &mut self,
+        chat_id: C,
+        limits: RateLimits,
+        now: Instant,
+    ) -> Option<Duration> {
+        if self.chats.len() >= MAX_IDLE_CHATS.max(self.swept_len * 2) {
+            self.chats.retain(|_, chat| !chat.is_idle(now));
+            self.swept_len = self.chats.len();
+        }
+        let chat = self
+            .chats
+            .entry(chat_id)
+            .or_insert_with(|| Buckets::new(limits.per_chat, now));
+
+        let wait = chat.wait(now).max(self.global.wait(now));
+        if wait > Duration::ZERO {
+            return Some(wait);
+        }
+        chat.take(now);
+        self.global.take(now);
+        None
+    }
+
+    fn block(&mut self, chat_id: C, until: Instant) {
+        if let Some(chat) = self.chats.get_mut(&chat_id) {
+            chat.blocked_until = chat.blocked_until.max(until);
+        }
+    }
+}
+
+/// Sender that keeps within the rate limits of a platform, and stops sending
+/// to a chat for as long as the platform asks to when they are exceeded
+/// anyway.
+#[derive(Debug)]
+pub struct RateLimitingSender<S, K = SystemClock>
+where
+    S: Sender,
+{
+    inner: S,
+    clock: K,
+    limits: RateLimits,
+    policy: RateLimitPolicy,
+    state: Arc<Mutex<State<S::ChatId>>>,
+}
+
+impl<S> RateLimitingSender<S>
+where
+    S: Sender,
+{
+    pub fn new(inner: S, limits: RateLimits, policy: RateLimitPolicy) -> Self {
+        Self::with_clock(inner, SystemClock, limits, policy)
+    }
+}
+
+impl<S, K> RateLimitingSender<S, K>
+where
+    S: Sender,
+    K: Clock,
+{
+    pub fn with_clock(
+        inner: S,
+        clock: K,
+        limits: RateLimits,
+        policy: RateLimitPolicy,
+    ) -> Self {
+        let state = State {
+            global: Buckets::new(limits.global, clock.now()),
+            chats: HashMap::new(),
+            swept_len: 0,
+        };
+        Self {
+            inner,
+            clock,
+            limits,
+            policy,
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    fn try_take(&self, chat_id: S::ChatId) -> Option<Duration> {
+        let mut state =
+            self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.try_take(chat_id, self.limits, self.clock.now())
+    }
+
+    fn block(&self, chat_id: S::ChatId, duration: Duration) {
+        let mut state =
+            self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.block(chat_id, self.clock.now() + duration);
+    }
+
+    /// Makes an attempt at a request to the chat once within the limits,
+    /// again when the platform asks to wait if the policy is to queue.
+    async fn limit<F, A, O>(
+        &self,
+        chat_id: S::ChatId,
+        mut attempt: F,
+    ) -> Result<O, RateLimitError<S::Error>>
+    where
+        F: FnMut() -> A,
+        A: Future<Output = Result<O, S::Error>>,
+        S::Error: RetryAfter,
+    {
+        let mut attempts = 0;
+        loop {
+            while let Some(retry_in) = self.try_take(chat_id) {
+                match self.policy {
+                    RateLimitPolicy::Queue => self.clock.sleep(retry_in).await,
+                    RateLimitPolicy::Drop => {
+                        return Err(RateLimitError::Dropped { retry_in })
+                    },
+                }
+            }
+
+            attempts += 1;
+            match attempt().await {
+                Err(error) => match error.retry_after() {
+                    Some(retry_after) => {
+                        self.block(chat_id, retry_after);
+                        if self.policy == RateLimitPolicy::Drop
+                            || attempts >= MAX_ATTEMPTS
+                        {
+                            break Err(RateLimitError::Sender(error));
+                        }
+                    },
+                    None => break Err(RateLimitError::Sender(error)),
+                },
+                Ok(output) => break Ok(output),
+            }
+        }
+    }
+}
+
+impl<S, K> Clone for RateLimitingSender<S, K>
+where
+    S: Sender + Clone,
+    K: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            clock: self.clock.clone(),
+            limits: self.limits,
+            policy: self.policy,
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<S, K> Sender for RateLimitingSender<S, K>
+where
+    S: Sender + Send + Sync,
+    S::MessageId: Send + Sync,
+    S::ChatId: Send + Sync,
+    S::UserId: Send + Sync,
+    S::Error: RetryAfter + 'static,
+    K: Clock + Send + Sync,
+{
+    type MessageId = S::MessageId;
+    type ChatId = S::ChatId;
+    type UserId = S::UserId;
+    type Error = RateLimitError<S::Error>;
+
+    fn send<'fut>(
+        &'fut self,
+        message: &'fut NewMessage<Self::MessageId, Self::ChatId, Self::UserId>,
+    ) -> DynFuture<'fut, Result<Self::MessageId, Self::Error>> {
+        Box::pin(
+            self.limit(message.data.chat_id, move || self.inner.send(message)),
+        )
+    }
+}
+
+impl<S, K> Deleter for RateLimitingSender<S, K>
+where
+    S: Deleter + Send + Sync,
+    S::MessageId: Send + Sync,
+    S::ChatId: Send + Sync,
+    S::UserId: Send + Sync,
+    S::Error: RetryAfter + 'static,
+    K: Clock + Send + Sync,
+{
+    fn can_delete(&self, chat_id: Self::ChatId) -> bool {
+        self.inner.can_delete(chat_id)
+    }
+
+    fn delete(
+        &self,
+        chat_id: Self::ChatId,
+        message_id: Self::MessageId,
+    ) -> DynFuture<'_, Result<(), Self::Error>> {
+        Box::pin(
+            self.limit(chat_id, move || self.inner.delete(chat_id, message_id)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Buckets,
+        Quota,
+        RateLimitError,
+        RateLimitPolicy,
+        RateLimitingSender,
+        RateLimits,
+        RetryAfter,
+        State,
+        MAX_ATTEMPTS,
+        MAX_IDLE_CHATS,
+    };
+    use crate::{
+        clock::{Clock, ManualClock},
+        domain::{Id, MessageData, NewMessage, ReplyTarget, RichText},
+        future::DynFuture,
+        port::Sender,
+    };
+    use std::{
+        collections::HashMap,
+        error::Error,
+        fmt,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    const LIMITS: RateLimits = RateLimits {
+        per_chat: &[Quota { burst: 1, period: Duration::from_secs(1) }],
+        global: &[Quota { burst: 2, period: Duration::from_secs(1) }],
+    };
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    struct TestId(u64);
+
+    impl fmt::Display for TestId {
+        fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+            write!(fmtr, "{}", self.0)
+        }
+    }
+
+    impl Id for TestId {}
+
+    #[derive(Debug)]
+    struct TooManyRequests(Duration);
+
+    impl fmt::Display for TooManyRequests {
+        fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+            write!(fmtr, "too many requests")
+        }
+    }
+
+    impl Error for TooManyRequests {}
+
+    impl RetryAfter for TooManyRequests {
+        fn retry_after(&self) -> Option<Duration> {
+            Some(self.0)
+        }
+    }
+
+    /// Records when each message was sent, failing the ones listed in
+    /// `rejections` with the given retry delay.
+    #[derive(Debug)]
+    struct RecordingSender {
+        clock: ManualClock,
+        sent: Mutex<Vec<(TestId, Duration)>>,
+        rejections: Mutex<Vec<Duration>>,
+    }
+
+    impl RecordingSender {
+        fn new(clock: ManualClock) -> Self {
+            Self {
+                clock,
+                sent: Mutex::new(Vec::new()),
+                rejections: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn sent(&self) -> Vec<(TestId, Duration)> {
+            self.sent.lock().unwrap().clone()
+        }
+    }
+
+    impl Sender for RecordingSender {
+        type MessageId = TestId;
+        type ChatId = TestId;
+        type UserId = TestId;
+        type Error = TooManyRequests;
+
+        fn send<'fut>(
+            &'fut self,
+            message: &'fut NewMessage<TestId, TestId, TestId>,
+        ) -> DynFuture<'fut, Result<TestId, TooManyRequests>> {
+            Box::pin(async move {
+                if let Some(retry_after) = self.rejections.lock().unwrap().pop()
+                {
+                    return Err(TooManyRequests(retry_after));
+                }
+                let mut sent = self.sent.lock().unwrap();
+                sent.push((message.data.chat_id, self.clock.elapsed()));
+                Ok(TestId(sent.len() as u64))
+            })
+        }
+    }
+
+    fn message(chat_id: u64) -> NewMessage<TestId, TestId, TestId> {
+        NewMessage {
+            data: MessageData {
+                chat_id: TestId(chat_id),
+                thread_id: None,
+                content: RichText::from(String::from("hi")),
+                reply_target: ReplyTarget::NotReplying,
+            },
+            keyboard: None,
+            attachment: None,
+        }
+    }
+
+    fn rate_limited(
+        policy: RateLimitPolicy,
+    ) -> (ManualClock, RateLimitingSender<Arc<RecordingSender>, ManualClock>)
+    {
+        let clock = ManualClock::new();
+        let inner = Arc::new(RecordingSender::new(clock.clone()));
+        let sender = RateLimitingSender::with_clock(
+            inner,
+            clock.clone(),
+            LIMITS,
+            policy,
+        );
+        (clock, sender)
+    }
+
+    #[tokio::test]
+    async fn queues_messages_to_the_same_chat() {
+        let (_, sender) = rate_limited(RateLimitPolicy::Queue);
+        for _ in 0..3 {
+            sender.send(&message(1)).await.unwrap();
+        }
+        assert_eq!(
+            sender.inner.sent(),
+            vec![
+                (TestId(1), Duration::ZERO),
+                (TestId(1), Duration::from_secs(1)),
+                (TestId(1), Duration::from_secs(2)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn applies_the_global_quota_across_chats() {
+        let (_, sender) = rate_limited(RateLimitPolicy::Queue);
+        for chat_id in 1..=3 {
+            sender.send(&message(chat_id)).await.unwrap();
+        }
+        assert_eq!(
+            sender.inner.sent(),
+            vec![
+                (TestId(1), Duration::ZERO),
+                (TestId(2), Duration::ZERO),
+                (TestId(3), Duration::from_millis(500)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn drops_messages_over_the_limit() {
+        let (clock, sender) = rate_limited(RateLimitPolicy::Drop);
+        sender.send(&message(1)).await.unwrap();
+        let error = sender.send(&message(1)).await.unwrap_err();
+        assert!(matches!(
+            error,
+            RateLimitError::Dropped { retry_in } if retry_in == Duration::from_secs(1)
+        ));
+        clock.sleep(Duration::from_secs(1)).await;
+        sender.send(&message(1)).await.unwrap();
+        assert_eq!(sender.inner.sent().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn waits_as_long_as_the_platform_asks() {
+        let (_, sender) = rate_limited(RateLimitPolicy::Queue);
+        sender.inner.rejections.lock().unwrap().push(Duration::from_secs(5));
+        sender.send(&message(1)).await.unwrap();
+        sender.send(&message(1)).await.unwrap();
+        assert_eq!(
+            sender.inner.sent(),
+            vec![
+                (TestId(1), Duration::from_secs(5)),
+                (TestId(1), Duration::from_secs(6)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn gives_up_when_the_platform_keeps_asking_to_wait() {
+        let (_, sender) = rate_limited(RateLimitPolicy::Queue);
+        let rejections = vec![Duration::from_secs(5); MAX_ATTEMPTS as usize];
+        *sender.inner.rejections.lock().unwrap() = rejections;
+        let error = sender.send(&message(1)).await.unwrap_err();
+        assert!(matches!(error, RateLimitError::Sender(_)));
+        assert!(sender.inner.sent().is_empty());
+        assert!(sender.inner.rejections.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn discards_idle_chats_once_their_number_doubled() {
+        let clock = ManualClock::new();
+        let mut state = State {
+            global: Buckets::new(&[], clock.now()),
+            chats: HashMap::new(),
+            swept_len: 0,
+        };
+        let take = |state: &mut State<TestId>, chat_id| {
+            state.try_take(TestId(chat_id), LIMITS, clock.now());
+        };
+        for chat_id in 0..MAX_IDLE_CHATS as u64 {
+            take(&mut state, chat_id);
+        }
+        // None is idle yet, so all of them are kept.
+        take(&mut state, u64::MAX);
+        assert_eq!(state.swept_len, MAX_IDLE_CHATS);
+        assert_eq!(state.chats.len(), MAX_IDLE_CHATS + 1);
+
+        // Once idle, they are kept until their number doubled.
+        clock.sleep(Duration::from_secs(1)).await;
+        for chat_id in MAX_IDLE_CHATS as u64..2 * MAX_IDLE_CHATS as u64 - 1 {
+            take(&mut state, chat_id);
+        }
+        assert_eq!(state.chats.len(), 2 * MAX_IDLE_CHATS);
+        clock.sleep(Duration::from_secs(1)).await;
+        take(&mut state, u64::MAX - 1);
+        assert_eq!(state.chats.len(), 1);
+        assert_eq!(state.swept_len, 0);
+    }
+}
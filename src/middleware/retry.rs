@@ -0,0 +1,698 @@
+use super::rate_limit::{RateLimitError, RetryAfter};
+use crate::{
+    clock::{Clock, SystemClock},
+    domain::NewMessage,
+    future::DynFuture,
+    port::{Connector, Deleter, Receiver, Receiving, Sender},
+};
+use std::{
+    collections::hash_map::RandomState,
+    error::Error,
+    fmt,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Errors which can tell whether trying again could succeed.
+pub trait Transient {
+    fn is_transient(&self) -> bool;
+
+    /// Whether the request may have been carried out despite failing, e.g.
+    /// when only its response was lost. Sending is not retried after such
+    /// failures, since the message would then be posted twice.
+    fn may_have_succeeded(&self) -> bool {
+        false
+    }
+}
+
+impl<E> Transient for RateLimitError<E>
+where
+    E: Transient,
+{
+    fn is_transient(&self) -> bool {
+        match self {
+            Self::Dropped { .. } => false,
+            Self::Sender(cause) => cause.is_transient(),
+        }
+    }
+
+    fn may_have_succeeded(&self) -> bool {
+        match self {
+            Self::Dropped { .. } => false,
+            Self::Sender(cause) => cause.may_have_succeeded(),
+        }
+    }
+}
+
+/// How many times and how long apart to retry. Delays double after each
+/// attempt, with a random half of each delay taken off to spread retries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Backoff {
+    /// Retries after the first attempt, `None` meaning no limit.
+    pub max_retries: Option<u32>,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            max_retries: Some(3),
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay before the given retry, counting from zero.
+    pub fn delay(self, retry: u32) -> Duration {
+        let delay = self
+            .initial_delay
+            .saturating_mul(1 << retry.min(16))
+            .min(self.max_delay);
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(retry);
+        let jitter = hasher.finish() % (delay.as_nanos() as u64 / 2 + 1);
+        delay - Duration::from_nanos(jitter)
+    }
+
+    fn allows(self, retry: u32) -> bool {
+        self.max_retries.is_none_or(|max_retries| retry < max_retries)
+    }
+}
+
+/// After how many consecutive transient failures the circuit opens, and for
+/// how long it then stays open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BreakerConfig {
+    pub failure_threshold: u32,
+    pub cooldown: Duration,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self { failure_threshold: 5, cooldown: Duration::from_secs(60) }
+    }
+}
+
+#[derive(Debug)]
+struct BreakerState {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// Circuit breaker, shared by its clones so that a sender and a receiver
+/// talking to the same service can trip it together. Once the cooldown is
+/// over, calls go through again and a single failure reopens the circuit.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    config: BreakerConfig,
+    state: Arc<Mutex<BreakerState>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: BreakerConfig) -> Self {
+        let state = BreakerState { failures: 0, open_until: None };
+        Self { config, state: Arc::new(Mutex::new(state)) }
+    }
+
+    /// How long the circuit stays open, if it is.
+    fn open_for(&self, now: Instant) -> Option<Duration> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state
+            .open_until
+            .filter(|&open_until| open_until > now)
+            .map(|open_until| open_until - now)
+    }
+
+    fn record_success(&self) {
+        let mut state =
+            self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.failures = 0;
+        state.open_until = None;
+    }
+
+    fn record_failure(&self, now: Instant) {
+        let mut state =
+            self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.failures = state.failures.saturating_add(1);
+        if state.failures >= self.config.failure_threshold {
+            state.open_until = Some(now + self.config.cooldown);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RetryError<E> {
+    /// The circuit is open, so nothing was attempted.
+    CircuitOpen { retry_in: Duration },
+    /// The error was permanent, or retries were exhausted.
+    Failed(E),
+}
+
+impl<E> fmt::Display for RetryError<E>
+where
+    E: fmt::Display,
+{
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::CircuitOpen { retry_in } => write!(
+                fmtr,
+                "too many failures, not trying again for {:?}",
+                retry_in
+            ),
+            Self::Failed(cause) => write!(fmtr, "{}", cause),
+        }
+    }
+}
+
+impl<E> Error for RetryError<E>
+where
+    E: Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::CircuitOpen { .. } => None,
+            Self::Failed(cause) => Some(cause),
+        }
+    }
+}
+
+impl<E> Transient for RetryError<E>
+where
+    E: Transient,
+{
+    fn is_transient(&self) -> bool {
+        match self {
+            Self::CircuitOpen { .. } => true,
+            Self::Failed(cause) => cause.is_transient(),
+        }
+    }
+
+    fn may_have_succeeded(&self) -> bool {
+        match self {
+            Self::CircuitOpen { .. } => false,
+            Self::Failed(cause) => cause.may_have_succeeded(),
+        }
+    }
+}
+
+impl<E> RetryAfter for RetryError<E>
+where
+    E: RetryAfter,
+{
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::CircuitOpen { retry_in } => Some(*retry_in),
+            Self::Failed(cause) => cause.retry_after(),
+        }
+    }
+}
+
+/// What is being retried, which decides how failures are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    Send,
+    /// Deleting a sent message, which fails once it is gone.
+    Delete,
+    Commit,
+    /// Receiving or connecting.
+    Receive,
+}
+
+impl Operation {
+    /// A receiver or connector waits for the circuit to close, since there
+    /// is nothing else it could do.
+    fn waits_while_open(self) -> bool {
+        self == Self::Receive
+    }
+
+    /// Whether doing it twice is harmless, so that it can be retried even
+    /// if the failed attempt may have gone through.
+    fn is_idempotent(self) -> bool {
+        !matches!(self, Self::Send | Self::Delete)
+    }
+}
+
+/// Sender, receiver or connector that retries operations failing with
+/// transient errors, backing off exponentially and respecting a circuit
+/// breaker.
+///
+/// A sender fails right away while the circuit is open, whereas a receiver
+/// or connector waits for it to close. A sender does not retry failures
+/// after which the message may have been posted, or deleted, anyway, such as
+/// timeouts.
+#[derive(Debug, Clone)]
+pub struct Retrying<T, K = SystemClock> {
+    inner: T,
+    clock: K,
+    backoff: Backoff,
+    breaker: CircuitBreaker,
+}
+
+impl<T> Retrying<T> {
+    pub fn new(inner: T, backoff: Backoff, breaker: CircuitBreaker) -> Self {
+        Self::with_clock(inner, SystemClock, backoff, breaker)
+    }
+}
+
+impl<T, K> Retrying<T, K>
+where
+    K: Clock,
+{
+    pub fn with_clock(
+        inner: T,
+        clock: K,
+        backoff: Backoff,
+        breaker: CircuitBreaker,
+    ) -> Self {
+        Self { inner, clock, backoff, breaker }
+    }
+
+    async fn retry<'fut, F, A, O, E>(
+        &'fut self,
+        operation: Operation,
+        mut attempt: F,
+    ) -> Result<O, RetryError<E>>
+    where
+        F: FnMut(&'fut T) -> A,
+        A: Future<Output = Result<O, E>>,
+        E: Transient + RetryAfter,
+    {
+        let mut retry = 0;
+        loop {
+            while let Some(retry_in) = self.breaker.open_for(self.clock.now()) {
+                if !operation.waits_while_open() {
+                    return Err(RetryError::CircuitOpen { retry_in });
+                }
+                self.clock.sleep(retry_in).await;
+            }
+
+            match attempt(&self.inner).await {
+                Ok(output) => {
+                    self.breaker.record_success();
+                    break Ok(output);
+                },
+                Err(error) if error.is_transient() => {
+                    self.breaker.record_failure(self.clock.now());
+                    let repeatable = operation.is_idempotent()
+                        || !error.may_have_succeeded();
+                    if !repeatable || !self.backoff.allows(retry) {
+                        break Err(RetryError::Failed(error));
+                    }
+                    let delay = self.backoff.delay(retry);
+                    let delay = error
+                        .retry_after()
+                        .map_or(delay, |after| after.max(delay));
+                    self.clock.sleep(delay).await;
+                    retry += 1;
+                },
+                Err(error) => break Err(RetryError::Failed(error)),
+            }
+        }
+    }
+}
+
+impl<S, K> Sender for Retrying<S, K>
+where
+    S: Sender + Send + Sync,
+    S::MessageId: Send + Sync,
+    S::ChatId: Send + Sync,
+    S::UserId: Send + Sync,
+    S::Error: Transient + RetryAfter + Send + 'static,
+    K: Clock + Send + Sync,
+{
+    type MessageId = S::MessageId;
+    type ChatId = S::ChatId;
+    type UserId = S::UserId;
+    type Error = RetryError<S::Error>;
+
+    fn send<'fut>(
+        &'fut self,
+        message: &'fut NewMessage<Self::MessageId, Self::ChatId, Self::UserId>,
+    ) -> DynFuture<'fut, Result<Self::MessageId, Self::Error>> {
+        Box::pin(self.retry(Operation::Send, move |inner| inner.send(message)))
+    }
+}
+
+impl<S, K> Deleter for Retrying<S, K>
+where
+    S: Deleter + Send + Sync,
+    S::MessageId: Send + Sync,
+    S::ChatId: Send + Sync,
+    S::UserId: Send + Sync,
+    S::Error: Transient + RetryAfter + Send + 'static,
+    K: Clock + Send + Sync,
+{
+    fn can_delete(&self, chat_id: Self::ChatId) -> bool {
+        self.inner.can_delete(chat_id)
+    }
+
+    fn delete(
+        &self,
+        chat_id: Self::ChatId,
+        message_id: Self::MessageId,
+    ) -> DynFuture<'_, Result<(), Self::Error>> {
+        Box::pin(self.retry(Operation::Delete, move |inner| {
+            inner.delete(chat_id, message_id)
+        }))
+    }
+}
+
+impl<R, K> Receiver for Retrying<R, K>
+where
+    R: Receiver + Send + Sync,
+    R::MessageId: Send + Sync,
+    R::ChatId: Send + Sync,
+    R::UserId: Send + Sync,
+    R::Error: Transient + RetryAfter + Send + 'static,
+    K: Clock + Send + Sync,
+{
+    type MessageId = R::MessageId;
+    type ChatId = R::ChatId;
+    type UserId = R::UserId;
+    type Error = RetryError<R::Error>;
+
+    fn receive<'fut>(
+        &'fut self,
+    ) -> Receiving<'fut, Self::MessageId, Self::ChatId, Self::UserId, Self::Error>
+    {
+        Box::pin(self.retry(Operation::Receive, |inner| inner.receive()))
+    }
+
+    fn commit(&self) -> DynFuture<'_, Result<(), Self::Error>> {
+        Box::pin(self.retry(Operation::Commit, |inner| inner.commit()))
+    }
+}
+
+/// Connects with retries, handing out receivers which retry with the same
+/// backoff and circuit breaker. Retries should be bounded, so that a receiver
+/// which keeps failing gives up and can be connected again.
+impl<C, K> Connector for Retrying<C, K>
+where
+    C: Connector + Send + Sync,
+    C::Error: Transient + RetryAfter + Send + 'static,
+    C::Receiver: Send + Sync,
+    <C::Receiver as Receiver>::MessageId: Send + Sync,
+    <C::Receiver as Receiver>::ChatId: Send + Sync,
+    <C::Receiver as Receiver>::UserId: Send + Sync,
+    <C::Receiver as Receiver>::Error: Transient + RetryAfter + Send + 'static,
+    K: Clock + Clone + Send + Sync,
+{
+    type Receiver = Retrying<C::Receiver, K>;
+    type Error = RetryError<C::Error>;
+
+    fn connect(&self) -> DynFuture<'_, Result<Self::Receiver, Self::Error>> {
+        Box::pin(async move {
+            let receiver =
+                self.retry(Operation::Receive, |inner| inner.connect()).await?;
+            Ok(Retrying::with_clock(
+                receiver,
+                self.clock.clone(),
+                self.backoff,
+                self.breaker.clone(),
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Backoff,
+        BreakerConfig,
+        CircuitBreaker,
+        RetryError,
+        Retrying,
+        Transient,
+    };
+    use crate::{
+        clock::{Clock, ManualClock},
+        domain::{MessageData, NewMessage, ReplyTarget, RichText},
+        future::DynFuture,
+        middleware::rate_limit::RetryAfter,
+        port::{Disconnected, Receiver, Receiving, Sender},
+    };
+    use std::{
+        error::Error,
+        fmt,
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Mutex,
+        },
+        time::Duration,
+    };
+
+    const BACKOFF: Backoff = Backoff {
+        max_retries: Some(2),
+        initial_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(4),
+    };
+
+    const BREAKER: BreakerConfig = BreakerConfig {
+        failure_threshold: 3,
+        cooldown: Duration::from_secs(60),
+    };
+
+    #[derive(Debug, Clone, Copy)]
+    struct Failure {
+        transient: bool,
+        may_have_succeeded: bool,
+    }
+
+    const TRANSIENT: Failure =
+        Failure { transient: true, may_have_succeeded: false };
+    const PERMANENT: Failure =
+        Failure { transient: false, may_have_succeeded: false };
+    const LOST_RESPONSE: Failure =
+        Failure { transient: true, may_have_succeeded: true };
+
+    impl fmt::Display for Failure {
+        fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+            write!(fmtr, "failure")
+        }
+    }
+
+    impl Error for Failure {}
+
+    impl Transient for Failure {
+        fn is_transient(&self) -> bool {
+            self.transient
+        }
+
+        fn may_have_succeeded(&self) -> bool {
+            self.may_have_succeeded
+        }
+    }
+
+    impl RetryAfter for Failure {
+        fn retry_after(&self) -> Option<Duration> {
+            None
+        }
+    }
+
+    /// Fails with the queued failures, in order, then succeeds, counting
+    /// attempts.
+    #[derive(Debug, Default)]
+    struct Flaky {
+        failures: Mutex<Vec<Failure>>,
+        attempts: AtomicU32,
+    }
+
+    impl Flaky {
+        fn new(mut failures: Vec<Failure>) -> Self {
+            failures.reverse();
+            Self { failures: Mutex::new(failures), ..Self::default() }
+        }
+
+        fn attempt(&self) -> Result<(), Failure> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            self.failures.lock().unwrap().pop().map_or(Ok(()), Err)
+        }
+
+        fn attempts(&self) -> u32 {
+            self.attempts.load(Ordering::SeqCst)
+        }
+    }
+
+    impl Sender for Flaky {
+        type MessageId = u64;
+        type ChatId = u64;
+        type UserId = u64;
+        type Error = Failure;
+
+        fn send<'fut>(
+            &'fut self,
+            _message: &'fut NewMessage<u64, u64, u64>,
+        ) -> DynFuture<'fut, Result<u64, Failure>> {
+            Box::pin(async move { self.attempt().map(|()| 1) })
+        }
+    }
+
+    /// Disconnects once it stops failing.
+    impl Receiver for Flaky {
+        type MessageId = u64;
+        type ChatId = u64;
+        type UserId = u64;
+        type Error = Failure;
+
+        fn receive<'fut>(
+            &'fut self,
+        ) -> Receiving<'fut, u64, u64, u64, Failure> {
+            Box::pin(async move { self.attempt().map(|()| Err(Disconnected)) })
+        }
+
+        fn commit(&self) -> DynFuture<'_, Result<(), Failure>> {
+            Box::pin(async move { self.attempt() })
+        }
+    }
+
+    fn message() -> NewMessage<u64, u64, u64> {
+        NewMessage {
+            data: MessageData {
+                chat_id: 1,
+                thread_id: None,
+                content: RichText::from(String::from("hi")),
+                reply_target: ReplyTarget::NotReplying,
+            },
+            keyboard: None,
+            attachment: None,
+        }
+    }
+
+    fn retrying(
+        failures: Vec<Failure>,
+        breaker: BreakerConfig,
+    ) -> (ManualClock, Retrying<Flaky, ManualClock>) {
+        let clock = ManualClock::new();
+        let retrying = Retrying::with_clock(
+            Flaky::new(failures),
+            clock.clone(),
+            BACKOFF,
+            CircuitBreaker::new(breaker),
+        );
+        (clock, retrying)
+    }
+
+    #[test]
+    fn doubles_delays_up_to_the_maximum() {
+        let expected = [1, 2, 4, 4, 4].map(Duration::from_secs);
+        for (retry, expected) in (0..).zip(expected) {
+            let delay = BACKOFF.delay(retry);
+            assert!(delay <= expected, "{:?} > {:?}", delay, expected);
+            assert!(delay >= expected / 2, "{:?} < {:?}", delay, expected / 2);
+        }
+        assert!(BACKOFF.delay(u32::MAX) <= BACKOFF.max_delay);
+    }
+
+    #[test]
+    fn limits_retries_unless_unbounded() {
+        assert!(BACKOFF.allows(1));
+        assert!(!BACKOFF.allows(2));
+        let unbounded = Backoff { max_retries: None, ..BACKOFF };
+        assert!(unbounded.allows(u32::MAX));
+    }
+
+    #[tokio::test]
+    async fn opens_the_circuit_after_consecutive_failures() {
+        let clock = ManualClock::new();
+        let breaker = CircuitBreaker::new(BREAKER);
+        breaker.record_failure(clock.now());
+        breaker.record_failure(clock.now());
+        breaker.record_success();
+        breaker.record_failure(clock.now());
+        breaker.record_failure(clock.now());
+        assert_eq!(breaker.open_for(clock.now()), None);
+        breaker.record_failure(clock.now());
+        assert_eq!(breaker.open_for(clock.now()), Some(BREAKER.cooldown));
+
+        clock.sleep(Duration::from_secs(45)).await;
+        assert_eq!(
+            breaker.open_for(clock.now()),
+            Some(Duration::from_secs(15))
+        );
+    }
+
+    #[tokio::test]
+    async fn reopens_the_circuit_on_a_failure_after_the_cooldown() {
+        let clock = ManualClock::new();
+        let breaker = CircuitBreaker::new(BREAKER);
+        for _ in 0..BREAKER.failure_threshold {
+            breaker.record_failure(clock.now());
+        }
+        clock.sleep(BREAKER.cooldown).await;
+        assert_eq!(breaker.open_for(clock.now()), None);
+
+        breaker.record_failure(clock.now());
+        assert_eq!(breaker.open_for(clock.now()), Some(BREAKER.cooldown));
+
+        clock.sleep(BREAKER.cooldown).await;
+        breaker.record_success();
+        breaker.record_failure(clock.now());
+        assert_eq!(breaker.open_for(clock.now()), None);
+    }
+
+    #[tokio::test]
+    async fn retries_transient_failures_with_backoff() {
+        let (clock, sender) = retrying(vec![TRANSIENT, TRANSIENT], BREAKER);
+        assert_eq!(sender.send(&message()).await.unwrap(), 1);
+        assert_eq!(sender.inner.attempts(), 3);
+        let elapsed = clock.elapsed();
+        assert!(elapsed >= Duration::from_millis(1500), "{:?}", elapsed);
+        assert!(elapsed <= Duration::from_secs(3), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn gives_up_when_retries_run_out() {
+        let (_, sender) = retrying(vec![TRANSIENT; 3], BREAKER);
+        let error = sender.send(&message()).await.unwrap_err();
+        assert!(matches!(error, RetryError::Failed(_)));
+        assert_eq!(sender.inner.attempts(), 3);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_permanent_failures() {
+        let (clock, sender) = retrying(vec![PERMANENT], BREAKER);
+        let error = sender.send(&message()).await.unwrap_err();
+        assert!(matches!(error, RetryError::Failed(_)));
+        assert_eq!(sender.inner.attempts(), 1);
+        assert_eq!(clock.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn does_not_resend_messages_that_may_have_been_sent() {
+        let (_, sender) = retrying(vec![LOST_RESPONSE], BREAKER);
+        let error = sender.send(&message()).await.unwrap_err();
+        assert!(matches!(error, RetryError::Failed(_)));
+        assert_eq!(sender.inner.attempts(), 1);
+
+        let (_, receiver) = retrying(vec![LOST_RESPONSE], BREAKER);
+        assert!(receiver.receive().await.unwrap().is_err());
+        assert_eq!(receiver.inner.attempts(), 2);
+    }
+
+    #[tokio::test]
+    async fn sender_fails_fast_while_the_circuit_is_open() {
+        let breaker = BreakerConfig { failure_threshold: 1, ..BREAKER };
+        let (clock, sender) = retrying(vec![TRANSIENT], breaker);
+        sender.send(&message()).await.unwrap_err();
+        let error = sender.send(&message()).await.unwrap_err();
+        assert!(matches!(error, RetryError::CircuitOpen { .. }));
+        assert_eq!(sender.inner.attempts(), 1);
+
+        clock.sleep(BREAKER.cooldown).await;
+        assert_eq!(sender.send(&message()).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn receiver_waits_for_the_circuit_to_close() {
+        let breaker = BreakerConfig { failure_threshold: 1, ..BREAKER };
+        let (clock, receiver) = retrying(vec![TRANSIENT], breaker);
+        assert!(receiver.receive().await.unwrap().is_err());
+        assert_eq!(receiver.inner.attempts(), 2);
+        assert!(clock.elapsed() >= BREAKER.cooldown);
+    }
+}
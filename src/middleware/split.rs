@@ -0,0 +1,471 @@
+use crate::{
+    domain::{
+        Attachment,
+        Id,
+        MessageData,
+        NewMessage,
+        ReplyTarget,
+        RichText,
+        Span,
+    },
+    future::DynFuture,
+    port::{Deleter, Sender},
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+use unicode_segmentation::UnicodeSegmentation;
+
+const ELLIPSIS: &str = "…";
+const ATTACHMENT_NAME: &str = "message.txt";
+const ATTACHMENT_MIME_TYPE: &str = "text/plain";
+/// Split messages whose parts are remembered, for deleting them whole.
+const PARTS_CAPACITY: usize = 1024;
+
+/// Unit in which a platform measures the length of a text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LengthUnit {
+    /// UTF-16 code units, as counted by Telegram.
+    Utf16,
+    /// UTF-8 bytes, as counted by IRC.
+    Bytes,
+    /// Unicode scalar values, as counted by Discord.
+    Chars,
+}
+
+impl LengthUnit {
+    fn measure(self, text: &str) -> usize {
+        match self {
+            Self::Utf16 => text.encode_utf16().count(),
+            Self::Bytes => text.len(),
+            Self::Chars => text.chars().count(),
+        }
+    }
+}
+
+/// Maximum length of the text of a single message on a platform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TextLimit {
+    pub max_len: usize,
+    pub unit: LengthUnit,
+}
+
+impl TextLimit {
+    fn fits(self, text: &str) -> bool {
+        self.unit.measure(text) <= self.max_len
+    }
+
+    /// Finds the end of the longest prefix of the text within this limit,
+    /// preferring to break after a line, then after a word, then between
+    /// graphemes. Line and word breaks are only taken when they keep at least
+    /// half of the limit, so that an early break does not waste the rest. At
+    /// least one grapheme is always taken.
+    pub fn split_point(self, text: &str) -> usize {
+        let min_break_len = self.max_len / 2;
+        let mut len = 0;
+        let mut grapheme_end = 0;
+        let mut word_end = None;
+        let mut line_end = None;
+
+        for (index, grapheme) in text.grapheme_indices(true) {
+            len += self.unit.measure(grapheme);
+            if len > self.max_len {
+                if grapheme_end == 0 {
+                    grapheme_end = index + grapheme.len();
+                }
+                return line_end.or(word_end).unwrap_or(grapheme_end);
+            }
+            grapheme_end = index + grapheme.len();
+            if len < min_break_len {
+                continue;
+            }
+            if grapheme == "\n" || grapheme == "\r\n" {
+                line_end = Some(grapheme_end);
+            } else if grapheme.chars().all(char::is_whitespace) {
+                word_end = Some(grapheme_end);
+            }
+        }
+
+        text.len()
+    }
+}
+
+/// Text limits of a platform, for plain messages and for captions of
+/// attachments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PlatformLimits {
+    pub text: TextLimit,
+    pub caption: TextLimit,
+}
+
+#[derive(Debug, Clone)]
+pub struct InvalidOverflowPolicy(String);
+
+impl fmt::Display for InvalidOverflowPolicy {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmtr,
+            "{:?} is not an overflow policy, expected \"split\", \
+             \"truncate\" or \"attach\"",
+            self.0
+        )
+    }
+}
+
+impl Error for InvalidOverflowPolicy {}
+
+/// What to do with a message whose text exceeds the platform limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum OverflowPolicy {
+    /// Sends the text as a chain of messages, each replying to the previous.
+    #[default]
+    Split,
+    /// Sends only the beginning of the text, ending with an ellipsis.
+    Truncate,
+    /// Sends the full text as a file, captioned with its beginning.
+    Attach,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = InvalidOverflowPolicy;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "split" => Ok(Self::Split),
+            "truncate" => Ok(Self::Truncate),
+            "attach" => Ok(Self::Attach),
+            _ => Err(InvalidOverflowPolicy(String::from(input))),
+        }
+    }
+}
+
+fn slice<U>(content: &RichText<U>, start: usize, end: usize) -> RichText<U>
+where
+    U: Id,
+{
+    let spans = content
+        .spans
+        .iter()
+        .filter(|span| span.start < end && span.end > start)
+        .map(|span| Span {
+            start: span.start.max(start) - start,
+            end: span.end.min(end) - start,
+            style: span.style.clone(),
+        })
+        .collect();
+    RichText { text: String::from(&content.text[start..end]), spans }
+}
+
+fn truncate<U>(content: &RichText<U>, limit: TextLimit) -> RichText<U>
+where
+    U: Id,
+{
+    if limit.fits(&content.text) {
+        return content.clone();
+    }
+    let prefix_limit = TextLimit {
+        max_len: limit.max_len.saturating_sub(limit.unit.measure(ELLIPSIS)),
+        unit: limit.unit,
+    };
+    let end = prefix_limit.split_point(&content.text);
+    let mut truncated = slice(content, 0, end);
+    truncated.text.push_str(ELLIPSIS);
+    truncated
+}
+
+/// Earlier parts of recently split messages, by chat and id of the last
+/// part, which is the one sending yields.
+#[derive(Debug)]
+struct Parts<M, C> {
+    earlier: HashMap<(C, M), Vec<M>>,
+    order: VecDeque<(C, M)>,
+}
+
+impl<M, C> Parts<M, C>
+where
+    M: Id,
+    C: Id,
+{
+    fn insert(&mut self, chat_id: C, last: M, earlier: Vec<M>) {
+        self.earlier.insert((chat_id, last), earlier);
+        self.order.push_back((chat_id, last));
+        while self.order.len() > PARTS_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.earlier.remove(&oldest);
+            }
+        }
+    }
+
+    fn remove(&mut self, chat_id: C, last: M) -> Vec<M> {
+        self.earlier.remove(&(chat_id, last)).unwrap_or_default()
+    }
+}
+
+/// Sender that keeps messages within the text limits of a platform, either
+/// splitting, truncating or attaching the ones that exceed them. Captions of
+/// attachments are always truncated.
+///
+/// Deleting a split message deletes every part of it, as long as it is among
+/// the last few split.
+#[derive(Debug, Clone)]
+pub struct SplittingSender<S>
+where
+    S: Sender,
+{
+    inner: S,
+    limits: PlatformLimits,
+    policy: OverflowPolicy,
+    parts: Arc<Mutex<Parts<S::MessageId, S::ChatId>>>,
+}
+
+impl<S> SplittingSender<S>
+where
+    S: Sender,
+{
+    pub fn new(
+        inner: S,
+        limits: PlatformLimits,
+        policy: OverflowPolicy,
+    ) -> Self {
+        let parts = Parts { earlier: HashMap::new(), order: VecDeque::new() };
+        Self { inner, limits, policy, parts: Arc::new(Mutex::new(parts)) }
+    }
+
+    fn lock_parts(&self) -> MutexGuard<'_, Parts<S::MessageId, S::ChatId>> {
+        self.parts.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    async fn send_split(
+        &self,
+        message: &NewMessage<S::MessageId, S::ChatId, S::UserId>,
+    ) -> Result<S::MessageId, S::Error> {
+        let content = &message.data.content;
+        let mut reply_target = message.data.reply_target.clone();
+        let mut start = 0;
+        let mut earlier = Vec::new();
+
+        loop {
+            let end =
+                start + self.limits.text.split_point(&content.text[start..]);
+            let is_last = end >= content.text.len();
+            let chunk = NewMessage {
+                data: MessageData {
+                    chat_id: message.data.chat_id,
+                    thread_id: message.data.thread_id,
+                    content: slice(content, start, end),
+                    reply_target,
+                },
+                keyboard: if is_last { message.keyboard.clone() } else { None },
+                attachment: None,
+            };
+            let sent_id = self.inner.send(&chunk).await?;
+            if is_last {
+                self.lock_parts().insert(
+                    message.data.chat_id,
+                    sent_id,
+                    earlier,
+                );
+                break Ok(sent_id);
+            }
+            earlier.push(sent_id);
+            reply_target = ReplyTarget::MessageId(sent_id);
+            start = end;
+        }
+    }
+
+    async fn send_truncated(
+        &self,
+        message: &NewMessage<S::MessageId, S::ChatId, S::UserId>,
+        limit: TextLimit,
+    ) -> Result<S::MessageId, S::Error> {
+        let message = NewMessage {
+            data: MessageData {
+                content: truncate(&message.data.content, limit),
+                ..message.data.clone()
+            },
+            ..message.clone()
+        };
+        self.inner.send(&message).await
+    }
+
+    async fn send_attached(
+        &self,
+        message: &NewMessage<S::MessageId, S::ChatId, S::UserId>,
+    ) -> Result<S::MessageId, S::Error> {
+        let content = &message.data.content;
+        let message = NewMessage {
+            data: MessageData {
+                content: truncate(content, self.limits.caption),
+                ..message.data.clone()
+            },
+            keyboard: message.keyboard.clone(),
+            attachment: Some(Attachment {
+                file_name: String::from(ATTACHMENT_NAME),
+                mime_type: String::from(ATTACHMENT_MIME_TYPE),
+                bytes: content.text.clone().into_bytes(),
+            }),
+        };
+        self.inner.send(&message).await
+    }
+}
+
+impl<S> Sender for SplittingSender<S>
+where
+    S: Sender + Send + Sync,
+    S::MessageId: Send + Sync,
+    S::ChatId: Send + Sync,
+    S::UserId: Send + Sync,
+{
+    type MessageId = S::MessageId;
+    type ChatId = S::ChatId;
+    type UserId = S::UserId;
+    type Error = S::Error;
+
+    fn send<'fut>(
+        &'fut self,
+        message: &'fut NewMessage<Self::MessageId, Self::ChatId, Self::UserId>,
+    ) -> DynFuture<'fut, Result<Self::MessageId, Self::Error>> {
+        Box::pin(async move {
+            if message.attachment.is_some() {
+                return self.send_truncated(message, self.limits.caption).await;
+            }
+            if self.limits.text.fits(&message.data.content.text) {
+                return self.inner.send(message).await;
+            }
+            match self.policy {
+                OverflowPolicy::Split => self.send_split(message).await,
+                OverflowPolicy::Truncate => {
+                    self.send_truncated(message, self.limits.text).await
+                },
+                OverflowPolicy::Attach => self.send_attached(message).await,
+            }
+        })
+    }
+}
+
+impl<S> Deleter for SplittingSender<S>
+where
+    S: Deleter + Send + Sync,
+    S::MessageId: Send + Sync,
+    S::ChatId: Send + Sync,
+    S::UserId: Send + Sync,
+{
+    fn can_delete(&self, chat_id: Self::ChatId) -> bool {
+        self.inner.can_delete(chat_id)
+    }
+
+    fn delete(
+        &self,
+        chat_id: Self::ChatId,
+        message_id: Self::MessageId,
+    ) -> DynFuture<'_, Result<(), Self::Error>> {
+        Box::pin(async move {
+            let earlier = self.lock_parts().remove(chat_id, message_id);
+            self.inner.delete(chat_id, message_id).await?;
+            for part_id in earlier.into_iter().rev() {
+                self.inner.delete(chat_id, part_id).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{slice, truncate, LengthUnit, TextLimit};
+    use crate::domain::{RichText, Span, Style};
+
+    fn limit(max_len: usize, unit: LengthUnit) -> TextLimit {
+        TextLimit { max_len, unit }
+    }
+
+    fn bold(start: usize, end: usize) -> Span<u64> {
+        Span { start, end, style: Style::Bold }
+    }
+
+    #[test]
+    fn breaks_after_lines_then_words() {
+        let text = "aaaa bbb\ncc dd ee";
+        assert_eq!(limit(12, LengthUnit::Bytes).split_point(text), 9);
+        assert_eq!(limit(8, LengthUnit::Bytes).split_point(text), 5);
+        assert_eq!(limit(4, LengthUnit::Bytes).split_point(text), 4);
+        assert_eq!(limit(20, LengthUnit::Bytes).split_point(text), text.len());
+    }
+
+    #[test]
+    fn ignores_breaks_early_in_the_limit() {
+        let text = format!("Hi\n{}", "x".repeat(20));
+        assert_eq!(limit(10, LengthUnit::Bytes).split_point(&text), 10);
+        let text = format!("Hi {}", "x".repeat(20));
+        assert_eq!(limit(10, LengthUnit::Bytes).split_point(&text), 10);
+    }
+
+    #[test]
+    fn measures_multi_byte_text_in_its_unit() {
+        // "é" is 2 bytes, 1 UTF-16 unit and 1 char.
+        let text = "éééé";
+        assert_eq!(limit(3, LengthUnit::Bytes).split_point(text), 2);
+        assert_eq!(limit(3, LengthUnit::Chars).split_point(text), 6);
+        assert_eq!(limit(3, LengthUnit::Utf16).split_point(text), 6);
+    }
+
+    #[test]
+    fn measures_astral_characters_as_two_utf16_units() {
+        let text = "😀😀😀";
+        assert_eq!(limit(5, LengthUnit::Utf16).split_point(text), 8);
+        assert_eq!(limit(5, LengthUnit::Chars).split_point(text), 12);
+    }
+
+    #[test]
+    fn takes_one_grapheme_over_the_limit() {
+        // Family emoji: several chars joined into a single grapheme.
+        let family = "👨\u{200d}👩\u{200d}👧";
+        let text = format!("{}a", family);
+        let split_point = limit(2, LengthUnit::Utf16).split_point(&text);
+        assert_eq!(split_point, family.len());
+    }
+
+    #[test]
+    fn slices_spans_crossing_the_cut() {
+        let content = RichText {
+            text: String::from("hello world"),
+            spans: vec![bold(0, 3), bold(2, 8), bold(8, 11)],
+        };
+        assert_eq!(
+            slice(&content, 0, 6),
+            RichText {
+                text: String::from("hello "),
+                spans: vec![bold(0, 3), bold(2, 6)],
+            }
+        );
+        assert_eq!(
+            slice(&content, 6, 11),
+            RichText {
+                text: String::from("world"),
+                spans: vec![bold(0, 2), bold(2, 5)],
+            }
+        );
+    }
+
+    #[test]
+    fn truncates_with_an_ellipsis_within_the_limit() {
+        let content = RichText {
+            text: format!("Hi\n{}", "x".repeat(20)),
+            spans: vec![bold(0, 23)],
+        };
+        let truncated = truncate(&content, limit(10, LengthUnit::Utf16));
+        assert_eq!(
+            truncated,
+            RichText {
+                text: format!("Hi\n{}…", "x".repeat(6)),
+                spans: vec![bold(0, 9)],
+            }
+        );
+        let short = RichText::<u64>::from(String::from("short"));
+        assert_eq!(truncate(&short, limit(10, LengthUnit::Utf16)), short);
+    }
+}
@@ -0,0 +1,170 @@
+//! Text commands of the bot applied outside of any chat, so that its dialect
+//! can be scripted and regression tested.
+
+use crate::{
+    command::Command,
+    commands::{
+        help::{HelpCommand, HelpRequestParser},
+        replace::{NoMatch, ParseError, ReplaceCommand, RequestParser},
+    },
+    domain::{Bot, ContentKind, Message, MessageData, NewMessage, ReplyTarget},
+    request::Parser,
+};
+use std::{
+    error::Error,
+    fmt,
+    fs,
+    io::{self, Read, Write},
+    path::PathBuf,
+};
+
+const HANDLE: &str = "offline_bot";
+
+#[derive(Debug)]
+pub enum ApplyError {
+    NotACommand(String),
+    Parse(ParseError),
+    Read(PathBuf, io::Error),
+    Write(io::Error),
+}
+
+impl From<ParseError> for ApplyError {
+    fn from(cause: ParseError) -> Self {
+        Self::Parse(cause)
+    }
+}
+
+impl fmt::Display for ApplyError {
+    fn fmt(&self, fmtr: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotACommand(command) => {
+                write!(fmtr, "{:?} is not a command of the bot", command)
+            },
+            Self::Parse(cause) => write!(fmtr, "{}", cause),
+            Self::Read(path, cause) => {
+                write!(fmtr, "could not read {}: {}", path.display(), cause)
+            },
+            Self::Write(cause) => {
+                write!(fmtr, "could not write output: {}", cause)
+            },
+        }
+    }
+}
+
+impl Error for ApplyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::NotACommand(_) => None,
+            Self::Parse(cause) => Some(cause),
+            Self::Read(_, cause) | Self::Write(cause) => Some(cause),
+        }
+    }
+}
+
+/// Runs the command as if it replied to a message with the given text,
+/// yielding the text of the answer. Text a replacement does not match is
+/// yielded unchanged.
+pub fn apply(command: &str, text: &str) -> Result<String, ApplyError> {
+    let bot = &Bot { handle: String::from(HANDLE) };
+    let target = Message::<u64, u64, u64> {
+        id: 1,
+        author: None,
+        content_kind: ContentKind::Text,
+        data: MessageData {
+            chat_id: 1,
+            thread_id: None,
+            content: String::from(text).into(),
+            reply_target: ReplyTarget::NotReplying,
+        },
+    };
+    let message = Message {
+        id: 2,
+        author: None,
+        content_kind: ContentKind::Text,
+        data: MessageData {
+            chat_id: 1,
+            thread_id: None,
+            content: String::from(command).into(),
+            reply_target: ReplyTarget::Message(Box::new(target)),
+        },
+    };
+
+    if let Some(Ok(request)) = HelpRequestParser.parse(bot, &message) {
+        let answer: NewMessage<u64, u64, u64> =
+            HelpCommand.execute(request).unwrap_or_else(|error| match error {});
+        return Ok(answer.data.content.text);
+    }
+    match RequestParser::new().parse(bot, &message) {
+        Some(request) => match ReplaceCommand.execute(request?) {
+            Ok(answer) => Ok(answer.data.content.text),
+            Err(NoMatch) => Ok(String::from(text)),
+        },
+        None => Err(ApplyError::NotACommand(String::from(command))),
+    }
+}
+
+/// Applies the command to each file in turn, or to stdin if there are none,
+/// writing the answers to stdout.
+pub fn apply_to_files(
+    command: &str,
+    files: &[PathBuf],
+) -> Result<(), ApplyError> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    if files.is_empty() {
+        let mut text = String::new();
+        io::stdin()
+            .read_to_string(&mut text)
+            .map_err(|error| ApplyError::Read(PathBuf::from("stdin"), error))?;
+        let answer = apply(command, &text)?;
+        stdout.write_all(answer.as_bytes()).map_err(ApplyError::Write)?;
+    }
+    for path in files {
+        let text = fs::read_to_string(path)
+            .map_err(|error| ApplyError::Read(path.clone(), error))?;
+        let answer = apply(command, &text)?;
+        stdout.write_all(answer.as_bytes()).map_err(ApplyError::Write)?;
+    }
+    stdout.flush().map_err(ApplyError::Write)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{apply, ApplyError};
+
+    #[test]
+    fn replaces_in_text() {
+        let answer = apply(r"s/(\w+)@(\w+)/\2 at \1/g", "a@b c@d\n");
+        assert_eq!(answer.unwrap(), "b at a d at c\n");
+    }
+
+    #[test]
+    fn converts_case_in_replacements() {
+        let answer = apply(r"s/(\w+) (\w+)/\U\1\E \2 \L\1!/", "Hello World");
+        assert_eq!(answer.unwrap(), "HELLO World hello!");
+        let answer = apply(r"s/(\w+)/\U\1/g", "ab cd\n");
+        assert_eq!(answer.unwrap(), "AB CD\n");
+    }
+
+    #[test]
+    fn leaves_unmatched_text_unchanged() {
+        let answer = apply("s/x/y/", "abc\n");
+        assert_eq!(answer.unwrap(), "abc\n");
+    }
+
+    #[test]
+    fn answers_help() {
+        let answer = apply("/help", "").unwrap();
+        assert!(answer.contains("s/regex/replacement/flags"));
+    }
+
+    #[test]
+    fn fails_on_invalid_commands() {
+        assert!(matches!(apply("s/x/y/q", "abc"), Err(ApplyError::Parse(_))));
+        assert!(matches!(apply("s/(/y/", "abc"), Err(ApplyError::Parse(_))));
+        assert!(matches!(
+            apply("hello", "abc"),
+            Err(ApplyError::NotACommand(_))
+        ));
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{
-    domain::{Id, Message, NewMessage},
+    domain::{Id, NewMessage, Update},
     future::DynFuture,
 };
 use core::fmt;
@@ -16,45 +16,52 @@ impl fmt::Display for Disconnected {
 
 impl Error for Disconnected {}
 
+pub type Receiving<'fut, M, C, U, E> =
+    DynFuture<'fut, Result<Result<Update<M, C, U>, Disconnected>, E>>;
+
+/// Sends new messages, yielding the id of the message sent.
 pub trait Sender: fmt::Debug {
     type MessageId: Id;
     type ChatId: Id;
+    type UserId: Id;
     type Error: Error;
 
     fn send<'fut>(
         &'fut self,
-        message: &'fut NewMessage<Self::MessageId, Self::ChatId>,
-    ) -> DynFuture<'fut, Result<(), Self::Error>>;
+        message: &'fut NewMessage<Self::MessageId, Self::ChatId, Self::UserId>,
+    ) -> DynFuture<'fut, Result<Self::MessageId, Self::Error>>;
 }
 
-impl<'this, S> Sender for &'this S
+impl<S> Sender for &S
 where
     S: Sender + ?Sized,
 {
     type MessageId = S::MessageId;
     type ChatId = S::ChatId;
+    type UserId = S::UserId;
     type Error = S::Error;
 
     fn send<'fut>(
         &'fut self,
-        message: &'fut NewMessage<Self::MessageId, Self::ChatId>,
-    ) -> DynFuture<'fut, Result<(), Self::Error>> {
+        message: &'fut NewMessage<Self::MessageId, Self::ChatId, Self::UserId>,
+    ) -> DynFuture<'fut, Result<Self::MessageId, Self::Error>> {
         (**self).send(message)
     }
 }
 
-impl<'this, S> Sender for &'this mut S
+impl<S> Sender for &mut S
 where
     S: Sender + ?Sized,
 {
     type MessageId = S::MessageId;
     type ChatId = S::ChatId;
+    type UserId = S::UserId;
     type Error = S::Error;
 
     fn send<'fut>(
         &'fut self,
-        message: &'fut NewMessage<Self::MessageId, Self::ChatId>,
-    ) -> DynFuture<'fut, Result<(), Self::Error>> {
+        message: &'fut NewMessage<Self::MessageId, Self::ChatId, Self::UserId>,
+    ) -> DynFuture<'fut, Result<Self::MessageId, Self::Error>> {
         (**self).send(message)
     }
 }
@@ -65,12 +72,13 @@ where
 {
     type MessageId = S::MessageId;
     type ChatId = S::ChatId;
+    type UserId = S::UserId;
     type Error = S::Error;
 
     fn send<'fut>(
         &'fut self,
-        message: &'fut NewMessage<Self::MessageId, Self::ChatId>,
-    ) -> DynFuture<'fut, Result<(), Self::Error>> {
+        message: &'fut NewMessage<Self::MessageId, Self::ChatId, Self::UserId>,
+    ) -> DynFuture<'fut, Result<Self::MessageId, Self::Error>> {
         (**self).send(message)
     }
 }
@@ -81,12 +89,13 @@ where
 {
     type MessageId = S::MessageId;
     type ChatId = S::ChatId;
+    type UserId = S::UserId;
     type Error = S::Error;
 
     fn send<'fut>(
         &'fut self,
-        message: &'fut NewMessage<Self::MessageId, Self::ChatId>,
-    ) -> DynFuture<'fut, Result<(), Self::Error>> {
+        message: &'fut NewMessage<Self::MessageId, Self::ChatId, Self::UserId>,
+    ) -> DynFuture<'fut, Result<Self::MessageId, Self::Error>> {
         (**self).send(message)
     }
 }
@@ -97,72 +106,171 @@ where
 {
     type MessageId = S::MessageId;
     type ChatId = S::ChatId;
+    type UserId = S::UserId;
     type Error = S::Error;
 
     fn send<'fut>(
         &'fut self,
-        message: &'fut NewMessage<Self::MessageId, Self::ChatId>,
-    ) -> DynFuture<'fut, Result<(), Self::Error>> {
+        message: &'fut NewMessage<Self::MessageId, Self::ChatId, Self::UserId>,
+    ) -> DynFuture<'fut, Result<Self::MessageId, Self::Error>> {
         (**self).send(message)
     }
 }
 
+/// Sender able to delete what it sent, e.g. to take back a correction.
+pub trait Deleter: Sender {
+    /// Whether messages sent to the chat can be deleted at all.
+    fn can_delete(&self, chat_id: Self::ChatId) -> bool;
+
+    fn delete(
+        &self,
+        chat_id: Self::ChatId,
+        message_id: Self::MessageId,
+    ) -> DynFuture<'_, Result<(), Self::Error>>;
+}
+
+impl<D> Deleter for &D
+where
+    D: Deleter + ?Sized,
+{
+    fn can_delete(&self, chat_id: Self::ChatId) -> bool {
+        (**self).can_delete(chat_id)
+    }
+
+    fn delete(
+        &self,
+        chat_id: Self::ChatId,
+        message_id: Self::MessageId,
+    ) -> DynFuture<'_, Result<(), Self::Error>> {
+        (**self).delete(chat_id, message_id)
+    }
+}
+
+impl<D> Deleter for &mut D
+where
+    D: Deleter + ?Sized,
+{
+    fn can_delete(&self, chat_id: Self::ChatId) -> bool {
+        (**self).can_delete(chat_id)
+    }
+
+    fn delete(
+        &self,
+        chat_id: Self::ChatId,
+        message_id: Self::MessageId,
+    ) -> DynFuture<'_, Result<(), Self::Error>> {
+        (**self).delete(chat_id, message_id)
+    }
+}
+
+impl<D> Deleter for Box<D>
+where
+    D: Deleter + ?Sized,
+{
+    fn can_delete(&self, chat_id: Self::ChatId) -> bool {
+        (**self).can_delete(chat_id)
+    }
+
+    fn delete(
+        &self,
+        chat_id: Self::ChatId,
+        message_id: Self::MessageId,
+    ) -> DynFuture<'_, Result<(), Self::Error>> {
+        (**self).delete(chat_id, message_id)
+    }
+}
+
+impl<D> Deleter for Rc<D>
+where
+    D: Deleter + ?Sized,
+{
+    fn can_delete(&self, chat_id: Self::ChatId) -> bool {
+        (**self).can_delete(chat_id)
+    }
+
+    fn delete(
+        &self,
+        chat_id: Self::ChatId,
+        message_id: Self::MessageId,
+    ) -> DynFuture<'_, Result<(), Self::Error>> {
+        (**self).delete(chat_id, message_id)
+    }
+}
+
+impl<D> Deleter for Arc<D>
+where
+    D: Deleter + ?Sized,
+{
+    fn can_delete(&self, chat_id: Self::ChatId) -> bool {
+        (**self).can_delete(chat_id)
+    }
+
+    fn delete(
+        &self,
+        chat_id: Self::ChatId,
+        message_id: Self::MessageId,
+    ) -> DynFuture<'_, Result<(), Self::Error>> {
+        (**self).delete(chat_id, message_id)
+    }
+}
+
 pub trait Receiver: fmt::Debug {
     type MessageId: Id;
     type ChatId: Id;
+    type UserId: Id;
     type Error: Error;
 
+    /// Receives the next update. Dropping the future before it is ready must
+    /// not lose anything read so far, which the next call goes on with.
     fn receive<'fut>(
         &'fut self,
-    ) -> DynFuture<
-        'fut,
-        Result<
-            Result<Message<Self::MessageId, Self::ChatId>, Disconnected>,
-            Self::Error,
-        >,
-    >;
+    ) -> Receiving<'fut, Self::MessageId, Self::ChatId, Self::UserId, Self::Error>;
+
+    /// Tells the platform which updates were received, so that they are not
+    /// delivered again, e.g. after a restart.
+    fn commit(&self) -> DynFuture<'_, Result<(), Self::Error>>;
 }
 
-impl<'this, R> Receiver for &'this R
+impl<R> Receiver for &R
 where
     R: Receiver + ?Sized,
 {
     type MessageId = R::MessageId;
     type ChatId = R::ChatId;
+    type UserId = R::UserId;
     type Error = R::Error;
 
     fn receive<'fut>(
         &'fut self,
-    ) -> DynFuture<
-        'fut,
-        Result<
-            Result<Message<Self::MessageId, Self::ChatId>, Disconnected>,
-            Self::Error,
-        >,
-    > {
+    ) -> Receiving<'fut, Self::MessageId, Self::ChatId, Self::UserId, Self::Error>
+    {
         (**self).receive()
     }
+
+    fn commit(&self) -> DynFuture<'_, Result<(), Self::Error>> {
+        (**self).commit()
+    }
 }
 
-impl<'this, R> Receiver for &'this mut R
+impl<R> Receiver for &mut R
 where
     R: Receiver + ?Sized,
 {
     type MessageId = R::MessageId;
     type ChatId = R::ChatId;
+    type UserId = R::UserId;
     type Error = R::Error;
 
     fn receive<'fut>(
         &'fut self,
-    ) -> DynFuture<
-        'fut,
-        Result<
-            Result<Message<Self::MessageId, Self::ChatId>, Disconnected>,
-            Self::Error,
-        >,
-    > {
+    ) -> Receiving<'fut, Self::MessageId, Self::ChatId, Self::UserId, Self::Error>
+    {
         (**self).receive()
     }
+
+    fn commit(&self) -> DynFuture<'_, Result<(), Self::Error>> {
+        (**self).commit()
+    }
 }
 
 impl<R> Receiver for Box<R>
@@ -171,19 +279,19 @@ where
 {
     type MessageId = R::MessageId;
     type ChatId = R::ChatId;
+    type UserId = R::UserId;
     type Error = R::Error;
 
     fn receive<'fut>(
         &'fut self,
-    ) -> DynFuture<
-        'fut,
-        Result<
-            Result<Message<Self::MessageId, Self::ChatId>, Disconnected>,
-            Self::Error,
-        >,
-    > {
+    ) -> Receiving<'fut, Self::MessageId, Self::ChatId, Self::UserId, Self::Error>
+    {
         (**self).receive()
     }
+
+    fn commit(&self) -> DynFuture<'_, Result<(), Self::Error>> {
+        (**self).commit()
+    }
 }
 
 impl<R> Receiver for Rc<R>
@@ -192,19 +300,19 @@ where
 {
     type MessageId = R::MessageId;
     type ChatId = R::ChatId;
+    type UserId = R::UserId;
     type Error = R::Error;
 
     fn receive<'fut>(
         &'fut self,
-    ) -> DynFuture<
-        'fut,
-        Result<
-            Result<Message<Self::MessageId, Self::ChatId>, Disconnected>,
-            Self::Error,
-        >,
-    > {
+    ) -> Receiving<'fut, Self::MessageId, Self::ChatId, Self::UserId, Self::Error>
+    {
         (**self).receive()
     }
+
+    fn commit(&self) -> DynFuture<'_, Result<(), Self::Error>> {
+        (**self).commit()
+    }
 }
 
 impl<R> Receiver for Arc<R>
@@ -213,17 +321,86 @@ where
 {
     type MessageId = R::MessageId;
     type ChatId = R::ChatId;
+    type UserId = R::UserId;
     type Error = R::Error;
 
     fn receive<'fut>(
         &'fut self,
-    ) -> DynFuture<
-        'fut,
-        Result<
-            Result<Message<Self::MessageId, Self::ChatId>, Disconnected>,
-            Self::Error,
-        >,
-    > {
+    ) -> Receiving<'fut, Self::MessageId, Self::ChatId, Self::UserId, Self::Error>
+    {
         (**self).receive()
     }
+
+    fn commit(&self) -> DynFuture<'_, Result<(), Self::Error>> {
+        (**self).commit()
+    }
+}
+
+/// Establishes receivers, so that a lost connection can be established
+/// again.
+pub trait Connector: fmt::Debug {
+    type Receiver: Receiver;
+    type Error: Error;
+
+    fn connect(&self) -> DynFuture<'_, Result<Self::Receiver, Self::Error>>;
+}
+
+impl<T> Connector for &T
+where
+    T: Connector + ?Sized,
+{
+    type Receiver = T::Receiver;
+    type Error = T::Error;
+
+    fn connect(&self) -> DynFuture<'_, Result<Self::Receiver, Self::Error>> {
+        (**self).connect()
+    }
+}
+
+impl<T> Connector for &mut T
+where
+    T: Connector + ?Sized,
+{
+    type Receiver = T::Receiver;
+    type Error = T::Error;
+
+    fn connect(&self) -> DynFuture<'_, Result<Self::Receiver, Self::Error>> {
+        (**self).connect()
+    }
+}
+
+impl<T> Connector for Box<T>
+where
+    T: Connector + ?Sized,
+{
+    type Receiver = T::Receiver;
+    type Error = T::Error;
+
+    fn connect(&self) -> DynFuture<'_, Result<Self::Receiver, Self::Error>> {
+        (**self).connect()
+    }
+}
+
+impl<T> Connector for Rc<T>
+where
+    T: Connector + ?Sized,
+{
+    type Receiver = T::Receiver;
+    type Error = T::Error;
+
+    fn connect(&self) -> DynFuture<'_, Result<Self::Receiver, Self::Error>> {
+        (**self).connect()
+    }
+}
+
+impl<T> Connector for Arc<T>
+where
+    T: Connector + ?Sized,
+{
+    type Receiver = T::Receiver;
+    type Error = T::Error;
+
+    fn connect(&self) -> DynFuture<'_, Result<Self::Receiver, Self::Error>> {
+        (**self).connect()
+    }
 }
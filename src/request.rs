@@ -1,10 +1,11 @@
-use crate::domain::{Bot, Id, Message};
+use crate::domain::{Bot, Callback, Id, Message};
 use std::{error::Error, fmt, rc::Rc, sync::Arc};
 
-pub trait Parser<M, C>: fmt::Debug
+pub trait Parser<M, C, U>: fmt::Debug
 where
     M: Id,
     C: Id,
+    U: Id,
 {
     type Error: Error;
     type Request;
@@ -12,15 +13,16 @@ where
     fn parse(
         &self,
         bot: &Bot,
-        message: &Message<M, C>,
+        message: &Message<M, C, U>,
     ) -> Option<Result<Self::Request, Self::Error>>;
 }
 
-impl<'this, P, M, C> Parser<M, C> for &'this P
+impl<P, M, C, U> Parser<M, C, U> for &P
 where
-    P: Parser<M, C> + ?Sized,
+    P: Parser<M, C, U> + ?Sized,
     M: Id,
     C: Id,
+    U: Id,
 {
     type Error = P::Error;
     type Request = P::Request;
@@ -28,17 +30,18 @@ where
     fn parse(
         &self,
         bot: &Bot,
-        message: &Message<M, C>,
+        message: &Message<M, C, U>,
     ) -> Option<Result<Self::Request, Self::Error>> {
         (**self).parse(bot, message)
     }
 }
 
-impl<'this, P, M, C> Parser<M, C> for &'this mut P
+impl<P, M, C, U> Parser<M, C, U> for &mut P
 where
-    P: Parser<M, C> + ?Sized,
+    P: Parser<M, C, U> + ?Sized,
     M: Id,
     C: Id,
+    U: Id,
 {
     type Error = P::Error;
     type Request = P::Request;
@@ -46,17 +49,18 @@ where
     fn parse(
         &self,
         bot: &Bot,
-        message: &Message<M, C>,
+        message: &Message<M, C, U>,
     ) -> Option<Result<Self::Request, Self::Error>> {
         (**self).parse(bot, message)
     }
 }
 
-impl<P, M, C> Parser<M, C> for Box<P>
+impl<P, M, C, U> Parser<M, C, U> for Box<P>
 where
-    P: Parser<M, C> + ?Sized,
+    P: Parser<M, C, U> + ?Sized,
     M: Id,
     C: Id,
+    U: Id,
 {
     type Error = P::Error;
     type Request = P::Request;
@@ -64,17 +68,18 @@ where
     fn parse(
         &self,
         bot: &Bot,
-        message: &Message<M, C>,
+        message: &Message<M, C, U>,
     ) -> Option<Result<Self::Request, Self::Error>> {
         (**self).parse(bot, message)
     }
 }
 
-impl<P, M, C> Parser<M, C> for Rc<P>
+impl<P, M, C, U> Parser<M, C, U> for Rc<P>
 where
-    P: Parser<M, C> + ?Sized,
+    P: Parser<M, C, U> + ?Sized,
     M: Id,
     C: Id,
+    U: Id,
 {
     type Error = P::Error;
     type Request = P::Request;
@@ -82,17 +87,18 @@ where
     fn parse(
         &self,
         bot: &Bot,
-        message: &Message<M, C>,
+        message: &Message<M, C, U>,
     ) -> Option<Result<Self::Request, Self::Error>> {
         (**self).parse(bot, message)
     }
 }
 
-impl<P, M, C> Parser<M, C> for Arc<P>
+impl<P, M, C, U> Parser<M, C, U> for Arc<P>
 where
-    P: Parser<M, C> + ?Sized,
+    P: Parser<M, C, U> + ?Sized,
     M: Id,
     C: Id,
+    U: Id,
 {
     type Error = P::Error;
     type Request = P::Request;
@@ -100,8 +106,119 @@ where
     fn parse(
         &self,
         bot: &Bot,
-        message: &Message<M, C>,
+        message: &Message<M, C, U>,
     ) -> Option<Result<Self::Request, Self::Error>> {
         (**self).parse(bot, message)
     }
 }
+
+pub trait CallbackParser<M, C, U>: fmt::Debug
+where
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    type Error: Error;
+    type Request;
+
+    fn parse(
+        &self,
+        bot: &Bot,
+        callback: &Callback<M, C, U>,
+    ) -> Option<Result<Self::Request, Self::Error>>;
+}
+
+impl<P, M, C, U> CallbackParser<M, C, U> for &P
+where
+    P: CallbackParser<M, C, U> + ?Sized,
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    type Error = P::Error;
+    type Request = P::Request;
+
+    fn parse(
+        &self,
+        bot: &Bot,
+        callback: &Callback<M, C, U>,
+    ) -> Option<Result<Self::Request, Self::Error>> {
+        (**self).parse(bot, callback)
+    }
+}
+
+impl<P, M, C, U> CallbackParser<M, C, U> for &mut P
+where
+    P: CallbackParser<M, C, U> + ?Sized,
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    type Error = P::Error;
+    type Request = P::Request;
+
+    fn parse(
+        &self,
+        bot: &Bot,
+        callback: &Callback<M, C, U>,
+    ) -> Option<Result<Self::Request, Self::Error>> {
+        (**self).parse(bot, callback)
+    }
+}
+
+impl<P, M, C, U> CallbackParser<M, C, U> for Box<P>
+where
+    P: CallbackParser<M, C, U> + ?Sized,
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    type Error = P::Error;
+    type Request = P::Request;
+
+    fn parse(
+        &self,
+        bot: &Bot,
+        callback: &Callback<M, C, U>,
+    ) -> Option<Result<Self::Request, Self::Error>> {
+        (**self).parse(bot, callback)
+    }
+}
+
+impl<P, M, C, U> CallbackParser<M, C, U> for Rc<P>
+where
+    P: CallbackParser<M, C, U> + ?Sized,
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    type Error = P::Error;
+    type Request = P::Request;
+
+    fn parse(
+        &self,
+        bot: &Bot,
+        callback: &Callback<M, C, U>,
+    ) -> Option<Result<Self::Request, Self::Error>> {
+        (**self).parse(bot, callback)
+    }
+}
+
+impl<P, M, C, U> CallbackParser<M, C, U> for Arc<P>
+where
+    P: CallbackParser<M, C, U> + ?Sized,
+    M: Id,
+    C: Id,
+    U: Id,
+{
+    type Error = P::Error;
+    type Request = P::Request;
+
+    fn parse(
+        &self,
+        bot: &Bot,
+        callback: &Callback<M, C, U>,
+    ) -> Option<Result<Self::Request, Self::Error>> {
+        (**self).parse(bot, callback)
+    }
+}
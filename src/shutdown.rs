@@ -0,0 +1,71 @@
+use std::{future, sync::Arc};
+use tokio::{signal, sync::watch};
+
+/// Token telling when to shut down, shared by its clones.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(false);
+        Self { sender: Arc::new(sender) }
+    }
+
+    /// Creates a token triggered when the process receives SIGINT or SIGTERM.
+    pub fn on_signals() -> Self {
+        let shutdown = Self::new();
+        let trigger = shutdown.clone();
+        tokio::spawn(async move {
+            wait_for_signal().await;
+            trigger.trigger();
+        });
+        shutdown
+    }
+
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    /// Completes once the token is triggered.
+    pub async fn triggered(&self) {
+        let mut receiver = self.sender.subscribe();
+        while !*receiver.borrow_and_update() {
+            if receiver.changed().await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use signal::unix::{self, SignalKind};
+
+    match unix::signal(SignalKind::terminate()) {
+        Ok(mut terminate) => tokio::select! {
+            _ = ctrl_c() => (),
+            _ = terminate.recv() => (),
+        },
+        Err(_) => ctrl_c().await,
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    ctrl_c().await
+}
+
+/// Completes on Ctrl+C, or never if it cannot be listened for.
+async fn ctrl_c() {
+    if signal::ctrl_c().await.is_err() {
+        future::pending().await
+    }
+}